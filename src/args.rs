@@ -1,20 +1,57 @@
 use crate::cli::LdkUserInfo;
+use crate::disk::LogFormat;
+use crate::{InboundChannelKind, InboundChannelPolicy};
 use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::PublicKey;
 use bitcoin::PrivateKey;
+use lightning::chain::chaininterface::ConfirmationTarget;
 use lightning::ln::msgs::SocketAddress;
+use lightning::util::logger::Level;
 use std::collections::HashMap;
 use std::env;
 use std::fs;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_YUV_POLL_INTERVAL_MS: u64 = 1000;
+/// Polling any faster would only flood the YUV node with requests.
+const MIN_YUV_POLL_INTERVAL_MS: u64 = 100;
+const DEFAULT_YUV_RPC_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_YUV_TX_CACHE_SIZE: usize = 1000;
+const DEFAULT_YUV_TXIDS_CHUNK_SIZE: usize = 100;
+const DEFAULT_YUV_MAX_CONCURRENT_CHUNKS: usize = 8;
+const DEFAULT_PERSIST_INTERVAL_SECS: u64 = 600;
+const DEFAULT_ANNOUNCEMENT_INTERVAL_SECS: u64 = 3600;
+const DEFAULT_ANNOUNCEMENT_WARMUP_SECS: u64 = 60;
+const DEFAULT_MAX_SWEEP_REJECTIONS: u32 = 10;
+const DEFAULT_HTLC_MINIMUM_MSAT: u64 = 4_000_000;
 
 // TODO: Rewrite with config crate
 pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
-	if env::args().len() < 4 {
-		println!("\rldk-tutorial-node requires at least 3 arguments: `cargo run [<bitcoind-rpc-username>:<bitcoind-rpc-password>@]<bitcoind-rpc-host>:<bitcoind-rpc-port> <ldk_storage_directory_path> <private-key> [<ldk-incoming-peer-listening-port>] [bitcoin-network] [announced-node-name announced-listen-addr*] [enable_yuv_payment]`");
+	let (args, mut named_args) = split_named_args(env::args().collect())?;
+	if let Some(config_path) = named_args.remove("config") {
+		if args.len() > 1 {
+			println!("\rERROR: positional arguments can't be combined with --config");
+			return Err(());
+		}
+		let config = match fs::read_to_string(&config_path) {
+			Ok(config) => config,
+			Err(e) => {
+				println!("\rERROR: failed to read config file {}: {}", config_path, e);
+				return Err(());
+			}
+		};
+		return parse_config_file(&config, named_args);
+	}
+	let bitcoind_rpc_cookie = parse_cookie_arg(&mut named_args)?;
+
+	if args.len() < 4 {
+		println!("\rldk-tutorial-node requires at least 3 arguments: `cargo run [<bitcoind-rpc-username>:<bitcoind-rpc-password>@]<bitcoind-rpc-host>:<bitcoind-rpc-port> <ldk_storage_directory_path> <private-key> [<ldk-incoming-peer-listening-port>] [bitcoin-network] [announced-node-name announced-listen-addr*] [enable_yuv_payment] [--yuv-poll-interval-ms <ms>] [--yuv-rpc-timeout-secs <secs>] [--yuv-txids-chunk-size <n>] [--yuv-max-concurrent-chunks <n>] [--yuv-subscribe <true|false>] [--sweep-conf-target <background|normal|high-priority>] [--sweep-destination <pubkey>] [--max-sweep-rejections <n>] [--min-funding-confs <n>] [--min-channel-size-sat <n>] [--inbound-channels <all|yuv|bitcoin>] [--manually-accept-channels <true|false>] [--anchors <true|false>] [--htlc-minimum-msat <msat>] [--intercept-htlcs <true|false>] [--persist-interval-secs <secs>] [--rpc-listen <addr:port>] [--rpc-allow-remote <true|false>] [--proxy <addr:port>] [--bitcoind-cookie <path>] [--import-channel-backup <path>]`, or `cargo run --config <path>`");
 		return Err(());
 	}
-	let bitcoind_rpc_info = env::args().nth(1).unwrap();
+	let bitcoind_rpc_info = args[1].clone();
 	let bitcoind_rpc_info_parts: Vec<&str> = bitcoind_rpc_info.rsplitn(2, '@').collect();
 
 	// Parse rpc auth after getting network for default .cookie location
@@ -26,12 +63,12 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 	let bitcoind_rpc_host = bitcoind_rpc_path[0].to_string();
 	let bitcoind_rpc_port = bitcoind_rpc_path[1].parse::<u16>().unwrap();
 
-	let ldk_storage_dir_path = env::args().nth(2).unwrap();
+	let ldk_storage_dir_path = args[2].clone();
 
-	let private_key = PrivateKey::from_str(env::args().nth(3).unwrap().as_str()).unwrap();
+	let private_key = parse_private_key(args[3].as_str())?;
 
 	let mut ldk_peer_port_set = true;
-	let ldk_peer_listening_port: u16 = match env::args().nth(4).map(|p| p.parse()) {
+	let ldk_peer_listening_port: u16 = match args.get(4).map(|p| p.parse()) {
 		Some(Ok(p)) => p,
 		Some(Err(_)) => {
 			ldk_peer_port_set = false;
@@ -47,52 +84,42 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 		true => 5,
 		false => 4,
 	};
-	let network: Network = match env::args().nth(arg_idx).as_deref() {
-		Some("testnet") => Network::Testnet,
-		Some("regtest") => Network::Regtest,
-		Some("signet") => Network::Signet,
-		Some(net) => {
-			panic!("Unsupported network provided. Options are: `regtest`, `testnet`, and `signet`. Got {}", net);
-		}
+	let network: Network = match args.get(arg_idx) {
+		Some(net) => parse_network(net)?,
 		None => Network::Testnet,
 	};
 
-	let (bitcoind_rpc_username, bitcoind_rpc_password) = if bitcoind_rpc_info_parts.len() == 1 {
-		get_rpc_auth_from_env_vars()
-			.or(get_rpc_auth_from_env_file(None))
-			.or(get_rpc_auth_from_cookie(None, Some(network), None))
-			.or({
-				println!("\rERROR: unable to get bitcoind RPC username and password");
-				print_rpc_auth_help();
-				Err(())
-			})?
-	} else if bitcoind_rpc_info_parts.len() == 2 {
-		parse_rpc_auth(bitcoind_rpc_info_parts[1])?
-	} else {
-		println!("\rERROR: bad bitcoind RPC URL provided");
-		return Err(());
-	};
+	let (bitcoind_rpc_username, bitcoind_rpc_password) =
+		if let Some((_, auth)) = &bitcoind_rpc_cookie {
+			if bitcoind_rpc_info_parts.len() != 1 {
+				println!("\rERROR: bitcoind RPC username and password can't be combined with --bitcoind-cookie");
+				return Err(());
+			}
+			auth.clone()
+		} else if bitcoind_rpc_info_parts.len() == 1 {
+			get_default_rpc_auth(network)?
+		} else if bitcoind_rpc_info_parts.len() == 2 {
+			parse_rpc_auth(bitcoind_rpc_info_parts[1])?
+		} else {
+			println!("\rERROR: bad bitcoind RPC URL provided");
+			return Err(());
+		};
 
-	let ldk_announced_node_name = match env::args().nth(arg_idx + 1).as_ref() {
+	let ldk_announced_node_name = match args.get(arg_idx + 1) {
 		Some(s) => {
-			if s.len() > 32 {
-				panic!("Node Alias can not be longer than 32 bytes");
-			}
 			arg_idx += 1;
-			let mut bytes = [0; 32];
-			bytes[..s.len()].copy_from_slice(s.as_bytes());
-			bytes
+			parse_node_name(s)?
 		}
 		None => [0; 32],
 	};
 
-	let yuv_rpc_url: Option<String> = env::args().nth(arg_idx + 1);
+	let yuv_rpc_url: Option<String> = args.get(arg_idx + 1).cloned();
 	if yuv_rpc_url.is_some() {
 		arg_idx += 1;
 	}
 
 	let mut ldk_announced_listen_addr = Vec::new();
-	while let Some(s) = env::args().nth(arg_idx + 1).as_ref() {
+	while let Some(s) = args.get(arg_idx + 1) {
 		match SocketAddress::from_str(s) {
 			Ok(sa) => {
 				ldk_announced_listen_addr.push(sa);
@@ -102,10 +129,11 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 		}
 	}
 
-	Ok(LdkUserInfo {
+	let mut user_info = LdkUserInfo {
 		bitcoind_rpc_username,
 		private_key,
 		bitcoind_rpc_password,
+		bitcoind_rpc_cookie: bitcoind_rpc_cookie.map(|(path, _)| path),
 		bitcoind_rpc_host,
 		bitcoind_rpc_port,
 		ldk_storage_dir_path,
@@ -114,9 +142,550 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 		ldk_announced_node_name,
 		network,
 		yuv_rpc_url,
+		yuv_poll_interval: Duration::from_millis(DEFAULT_YUV_POLL_INTERVAL_MS),
+		yuv_rpc_timeout: Duration::from_secs(DEFAULT_YUV_RPC_TIMEOUT_SECS),
+		yuv_tx_cache_size: DEFAULT_YUV_TX_CACHE_SIZE,
+		yuv_txids_chunk_size: DEFAULT_YUV_TXIDS_CHUNK_SIZE,
+		yuv_max_concurrent_chunks: DEFAULT_YUV_MAX_CONCURRENT_CHUNKS,
+		yuv_subscribe: false,
+		sweep_conf_target: ConfirmationTarget::ChannelCloseMinimum,
+		sweep_destination: None,
+		max_sweep_rejections: DEFAULT_MAX_SWEEP_REJECTIONS,
+		min_funding_confs: None,
+		inbound_channel_policy: InboundChannelPolicy::default(),
+		manually_accept_inbound_channels: true,
+		negotiate_anchors: true,
+		htlc_minimum_msat: DEFAULT_HTLC_MINIMUM_MSAT,
+		intercept_htlcs: false,
+		persist_interval: Duration::from_secs(DEFAULT_PERSIST_INTERVAL_SECS),
+		announcement_interval: Duration::from_secs(DEFAULT_ANNOUNCEMENT_INTERVAL_SECS),
+		announcement_warmup: Duration::from_secs(DEFAULT_ANNOUNCEMENT_WARMUP_SECS),
+		log_format: LogFormat::Text,
+		log_level: Level::Gossip,
+		log_max_bytes: None,
+		rpc_listen: None,
+		metrics_listen: None,
+		proxy: None,
+		import_channel_backup: None,
+	};
+	apply_named_args(&mut user_info, named_args)?;
+
+	Ok(user_info)
+}
+
+/// Keys of the config file which can also be given as named args, in which case the latter take
+/// precedence. The rest of the keys mirror the [`LdkUserInfo`] fields.
+const CONFIG_FILE_NAMED_ARGS: [&str; 27] = [
+	"yuv_poll_interval_ms",
+	"yuv_rpc_timeout_secs",
+	"yuv_tx_cache_size",
+	"yuv_txids_chunk_size",
+	"yuv_max_concurrent_chunks",
+	"yuv_subscribe",
+	"sweep_conf_target",
+	"sweep_destination",
+	"max_sweep_rejections",
+	"min_funding_confs",
+	"min_channel_size_sat",
+	"inbound_channels",
+	"manually_accept_channels",
+	"anchors",
+	"htlc_minimum_msat",
+	"intercept_htlcs",
+	"persist_interval_secs",
+	"announcement_interval_secs",
+	"announcement_warmup_secs",
+	"log_format",
+	"log_level",
+	"log_max_bytes",
+	"rpc_listen",
+	"rpc_allow_remote",
+	"metrics_listen",
+	"proxy",
+	"bitcoind_cookie",
+];
+
+/// Parses a TOML config file whose keys mirror the fields of [`LdkUserInfo`], validating them the
+/// same way as the positional args.
+fn parse_config_file(
+	config: &str, mut named_args: HashMap<String, String>,
+) -> Result<LdkUserInfo, ()> {
+	let mut config = match config.parse::<toml::Table>() {
+		Ok(config) => config,
+		Err(e) => {
+			println!("\rERROR: invalid config file: {}", e);
+			return Err(());
+		}
+	};
+
+	for key in CONFIG_FILE_NAMED_ARGS {
+		if let Some(value) = config.remove(key) {
+			let value = match value {
+				toml::Value::String(value) => value,
+				value => value.to_string(),
+			};
+			named_args.entry(key.replace('_', "-")).or_insert(value);
+		}
+	}
+	let bitcoind_rpc_cookie = parse_cookie_arg(&mut named_args)?;
+
+	let mut take_str = |key: &str| -> Result<Option<String>, ()> {
+		match config.remove(key) {
+			Some(toml::Value::String(value)) => Ok(Some(value)),
+			Some(toml::Value::Integer(value)) => Ok(Some(value.to_string())),
+			Some(_) => {
+				println!("\rERROR: invalid value for config key {}", key);
+				Err(())
+			}
+			None => Ok(None),
+		}
+	};
+	let require = |key: &str, value: Option<String>| {
+		value.ok_or_else(|| println!("\rERROR: missing config key {}", key))
+	};
+
+	let bitcoind_rpc_host = require("bitcoind_rpc_host", take_str("bitcoind_rpc_host")?)?;
+	let bitcoind_rpc_port = require("bitcoind_rpc_port", take_str("bitcoind_rpc_port")?)?
+		.parse::<u16>()
+		.map_err(|_| println!("\rERROR: invalid value for config key bitcoind_rpc_port"))?;
+	let ldk_storage_dir_path = require("ldk_storage_dir_path", take_str("ldk_storage_dir_path")?)?;
+	let private_key = parse_private_key(&require("private_key", take_str("private_key")?)?)?;
+	let ldk_peer_listening_port = match take_str("ldk_peer_listening_port")? {
+		Some(port) => port.parse::<u16>().map_err(|_| {
+			println!("\rERROR: invalid value for config key ldk_peer_listening_port")
+		})?,
+		None => 9735,
+	};
+	let network = match take_str("network")? {
+		Some(network) => parse_network(&network)?,
+		None => Network::Testnet,
+	};
+	let (bitcoind_rpc_username, bitcoind_rpc_password) =
+		match (take_str("bitcoind_rpc_username")?, take_str("bitcoind_rpc_password")?) {
+			(Some(_), Some(_)) if bitcoind_rpc_cookie.is_some() => {
+				println!("\rERROR: bitcoind RPC username and password can't be combined with bitcoind_cookie");
+				return Err(());
+			}
+			(Some(username), Some(password)) => (username, password),
+			(None, None) => match &bitcoind_rpc_cookie {
+				Some((_, auth)) => auth.clone(),
+				None => get_default_rpc_auth(network)?,
+			},
+			_ => {
+				println!(
+					"\rERROR: bitcoind_rpc_username and bitcoind_rpc_password must be set together"
+				);
+				return Err(());
+			}
+		};
+	let ldk_announced_node_name = match take_str("ldk_announced_node_name")? {
+		Some(name) => parse_node_name(&name)?,
+		None => [0; 32],
+	};
+	let yuv_rpc_url = take_str("yuv_rpc_url")?;
+
+	let mut ldk_announced_listen_addr = Vec::new();
+	match config.remove("ldk_announced_listen_addr") {
+		Some(toml::Value::Array(addrs)) => {
+			for addr in addrs {
+				match addr.as_str().map(SocketAddress::from_str) {
+					Some(Ok(addr)) => ldk_announced_listen_addr.push(addr),
+					_ => {
+						println!("\rERROR: invalid announced listen address: {}", addr);
+						return Err(());
+					}
+				}
+			}
+		}
+		Some(_) => {
+			println!("\rERROR: ldk_announced_listen_addr must be an array of addresses");
+			return Err(());
+		}
+		None => {}
+	}
+
+	if let Some(key) = config.keys().next() {
+		println!("\rERROR: unknown config key: {}", key);
+		return Err(());
+	}
+
+	let mut user_info = LdkUserInfo {
+		bitcoind_rpc_username,
+		private_key,
+		bitcoind_rpc_password,
+		bitcoind_rpc_cookie: bitcoind_rpc_cookie.map(|(path, _)| path),
+		bitcoind_rpc_host,
+		bitcoind_rpc_port,
+		ldk_storage_dir_path,
+		ldk_peer_listening_port,
+		ldk_announced_listen_addr,
+		ldk_announced_node_name,
+		network,
+		yuv_rpc_url,
+		yuv_poll_interval: Duration::from_millis(DEFAULT_YUV_POLL_INTERVAL_MS),
+		yuv_rpc_timeout: Duration::from_secs(DEFAULT_YUV_RPC_TIMEOUT_SECS),
+		yuv_tx_cache_size: DEFAULT_YUV_TX_CACHE_SIZE,
+		yuv_txids_chunk_size: DEFAULT_YUV_TXIDS_CHUNK_SIZE,
+		yuv_max_concurrent_chunks: DEFAULT_YUV_MAX_CONCURRENT_CHUNKS,
+		yuv_subscribe: false,
+		sweep_conf_target: ConfirmationTarget::ChannelCloseMinimum,
+		sweep_destination: None,
+		max_sweep_rejections: DEFAULT_MAX_SWEEP_REJECTIONS,
+		min_funding_confs: None,
+		inbound_channel_policy: InboundChannelPolicy::default(),
+		manually_accept_inbound_channels: true,
+		negotiate_anchors: true,
+		htlc_minimum_msat: DEFAULT_HTLC_MINIMUM_MSAT,
+		intercept_htlcs: false,
+		persist_interval: Duration::from_secs(DEFAULT_PERSIST_INTERVAL_SECS),
+		announcement_interval: Duration::from_secs(DEFAULT_ANNOUNCEMENT_INTERVAL_SECS),
+		announcement_warmup: Duration::from_secs(DEFAULT_ANNOUNCEMENT_WARMUP_SECS),
+		log_format: LogFormat::Text,
+		log_level: Level::Gossip,
+		log_max_bytes: None,
+		rpc_listen: None,
+		metrics_listen: None,
+		proxy: None,
+		import_channel_backup: None,
+	};
+	apply_named_args(&mut user_info, named_args)?;
+
+	Ok(user_info)
+}
+
+/// Sets the optional settings from the named args, erroring on any unknown one.
+fn apply_named_args(
+	user_info: &mut LdkUserInfo, mut named_args: HashMap<String, String>,
+) -> Result<(), ()> {
+	let yuv_poll_interval_ms =
+		parse_named_arg(&mut named_args, "yuv-poll-interval-ms", DEFAULT_YUV_POLL_INTERVAL_MS)?;
+	if yuv_poll_interval_ms < MIN_YUV_POLL_INTERVAL_MS {
+		println!("\rERROR: --yuv-poll-interval-ms must be at least {}", MIN_YUV_POLL_INTERVAL_MS);
+		return Err(());
+	}
+	user_info.yuv_poll_interval = Duration::from_millis(yuv_poll_interval_ms);
+
+	let yuv_rpc_timeout_secs =
+		parse_named_arg(&mut named_args, "yuv-rpc-timeout-secs", DEFAULT_YUV_RPC_TIMEOUT_SECS)?;
+	if yuv_rpc_timeout_secs == 0 {
+		println!("\rERROR: --yuv-rpc-timeout-secs must be greater than 0");
+		return Err(());
+	}
+	user_info.yuv_rpc_timeout = Duration::from_secs(yuv_rpc_timeout_secs);
+
+	user_info.yuv_tx_cache_size =
+		parse_named_arg(&mut named_args, "yuv-tx-cache-size", DEFAULT_YUV_TX_CACHE_SIZE)?;
+
+	let yuv_txids_chunk_size =
+		parse_named_arg(&mut named_args, "yuv-txids-chunk-size", DEFAULT_YUV_TXIDS_CHUNK_SIZE)?;
+	if yuv_txids_chunk_size == 0 {
+		println!("\rERROR: --yuv-txids-chunk-size must be greater than 0");
+		return Err(());
+	}
+	user_info.yuv_txids_chunk_size = yuv_txids_chunk_size;
+
+	let yuv_max_concurrent_chunks = parse_named_arg(
+		&mut named_args,
+		"yuv-max-concurrent-chunks",
+		DEFAULT_YUV_MAX_CONCURRENT_CHUNKS,
+	)?;
+	if yuv_max_concurrent_chunks == 0 {
+		println!("\rERROR: --yuv-max-concurrent-chunks must be greater than 0");
+		return Err(());
+	}
+	user_info.yuv_max_concurrent_chunks = yuv_max_concurrent_chunks;
+
+	user_info.yuv_subscribe = parse_named_arg(&mut named_args, "yuv-subscribe", false)?;
+
+	let persist_interval_secs =
+		parse_named_arg(&mut named_args, "persist-interval-secs", DEFAULT_PERSIST_INTERVAL_SECS)?;
+	if persist_interval_secs == 0 {
+		println!("\rERROR: --persist-interval-secs must be greater than 0");
+		return Err(());
+	}
+	user_info.persist_interval = Duration::from_secs(persist_interval_secs);
+
+	let announcement_interval_secs = parse_named_arg(
+		&mut named_args,
+		"announcement-interval-secs",
+		DEFAULT_ANNOUNCEMENT_INTERVAL_SECS,
+	)?;
+	if announcement_interval_secs == 0 {
+		println!("\rERROR: --announcement-interval-secs must be greater than 0");
+		return Err(());
+	}
+	user_info.announcement_interval = Duration::from_secs(announcement_interval_secs);
+
+	let announcement_warmup_secs = parse_named_arg(
+		&mut named_args,
+		"announcement-warmup-secs",
+		DEFAULT_ANNOUNCEMENT_WARMUP_SECS,
+	)?;
+	user_info.announcement_warmup = Duration::from_secs(announcement_warmup_secs);
+
+	user_info.rpc_listen = match named_args.remove("rpc-listen") {
+		Some(addr) => match SocketAddr::from_str(&addr) {
+			Ok(addr) => Some(addr),
+			Err(_) => {
+				println!("\rERROR: invalid --rpc-listen address: {}", addr);
+				return Err(());
+			}
+		},
+		None => None,
+	};
+	// The RPC server can spend the node's funds, so it's only reachable from other hosts if that's
+	// explicitly asked for.
+	let rpc_allow_remote = parse_named_arg(&mut named_args, "rpc-allow-remote", false)?;
+	if let Some(addr) = user_info.rpc_listen {
+		if !addr.ip().is_loopback() && !rpc_allow_remote {
+			println!(
+				"\rERROR: --rpc-listen address {} is not a loopback address, set --rpc-allow-remote true to serve the RPC API on it anyway",
+				addr
+			);
+			return Err(());
+		}
+	}
+
+	user_info.metrics_listen = match named_args.remove("metrics-listen") {
+		Some(addr) => match SocketAddr::from_str(&addr) {
+			Ok(addr) => Some(addr),
+			Err(_) => {
+				println!("\rERROR: invalid --metrics-listen address: {}", addr);
+				return Err(());
+			}
+		},
+		None => None,
+	};
+
+	user_info.proxy = match named_args.remove("proxy") {
+		Some(addr) => match SocketAddr::from_str(&addr) {
+			Ok(addr) => Some(addr),
+			Err(_) => {
+				println!("\rERROR: invalid --proxy address: {}", addr);
+				return Err(());
+			}
+		},
+		None => None,
+	};
+
+	user_info.log_format = match named_args.remove("log-format").as_deref() {
+		None | Some("text") => LogFormat::Text,
+		Some("json") => LogFormat::Json,
+		Some(format) => {
+			println!(
+				"\rERROR: unsupported log format provided. Options are: `text` and `json`. Got {}",
+				format
+			);
+			return Err(());
+		}
+	};
+
+	// Everything, including gossip, is logged unless a level is given.
+	user_info.log_level = match named_args.remove("log-level").as_deref() {
+		None => Level::Gossip,
+		Some("error") => Level::Error,
+		Some("warn") => Level::Warn,
+		Some("info") => Level::Info,
+		Some("debug") => Level::Debug,
+		Some("trace") => Level::Trace,
+		Some(level) => {
+			println!("\rERROR: unsupported log level provided. Options are: `error`, `warn`, `info`, `debug`, and `trace`. Got {}", level);
+			return Err(());
+		}
+	};
+
+	user_info.log_max_bytes = match named_args.remove("log-max-bytes") {
+		Some(max_bytes) => match max_bytes.parse::<u64>() {
+			Ok(max_bytes) if max_bytes > 0 => Some(max_bytes),
+			_ => {
+				println!("\rERROR: invalid value for argument --log-max-bytes: {}", max_bytes);
+				return Err(());
+			}
+		},
+		None => None,
+	};
+
+	user_info.sweep_conf_target = match named_args.remove("sweep-conf-target").as_deref() {
+		None | Some("background") => ConfirmationTarget::ChannelCloseMinimum,
+		Some("normal") => ConfirmationTarget::NonAnchorChannelFee,
+		Some("high-priority") => ConfirmationTarget::OnChainSweep,
+		Some(target) => {
+			println!("\rERROR: unsupported sweep confirmation target provided. Options are: `background`, `normal`, and `high-priority`. Got {}", target);
+			return Err(());
+		}
+	};
+
+	user_info.sweep_destination = match named_args.remove("sweep-destination") {
+		Some(pubkey) => match PublicKey::from_str(&pubkey) {
+			Ok(pubkey) => Some(pubkey),
+			Err(_) => {
+				println!("\rERROR: invalid --sweep-destination pubkey: {}", pubkey);
+				return Err(());
+			}
+		},
+		None => None,
+	};
+
+	user_info.max_sweep_rejections =
+		parse_named_arg(&mut named_args, "max-sweep-rejections", DEFAULT_MAX_SWEEP_REJECTIONS)?;
+	if user_info.max_sweep_rejections == 0 {
+		println!("\rERROR: --max-sweep-rejections must be greater than 0");
+		return Err(());
+	}
+
+	user_info.min_funding_confs = match named_args.remove("min-funding-confs") {
+		Some(confs) => match confs.parse::<u32>() {
+			Ok(confs) if confs > 0 => Some(confs),
+			_ => {
+				println!("\rERROR: invalid value for argument --min-funding-confs: {}", confs);
+				return Err(());
+			}
+		},
+		None => None,
+	};
+
+	user_info.inbound_channel_policy.min_channel_size_sat = match named_args
+		.remove("min-channel-size-sat")
+	{
+		Some(size) => match size.parse::<u64>() {
+			Ok(size) => Some(size),
+			Err(_) => {
+				println!("\rERROR: invalid value for argument --min-channel-size-sat: {}", size);
+				return Err(());
+			}
+		},
+		None => None,
+	};
+
+	user_info.inbound_channel_policy.kind = match named_args.remove("inbound-channels").as_deref() {
+		None | Some("all") => InboundChannelKind::All,
+		Some("yuv") => InboundChannelKind::Yuv,
+		Some("bitcoin") => InboundChannelKind::Bitcoin,
+		Some(kind) => {
+			println!("\rERROR: unsupported inbound channels provided. Options are: `all`, `yuv`, and `bitcoin`. Got {}", kind);
+			return Err(());
+		}
+	};
+
+	user_info.manually_accept_inbound_channels =
+		parse_named_arg(&mut named_args, "manually-accept-channels", true)?;
+	user_info.negotiate_anchors = parse_named_arg(&mut named_args, "anchors", true)?;
+	// LDK rejects inbound anchor channels unless it's left to us to accept them, so announcing
+	// support for anchors would only have peers open channels which are then turned away.
+	if user_info.negotiate_anchors && !user_info.manually_accept_inbound_channels {
+		println!("\rERROR: --anchors true requires --manually-accept-channels true, as LDK rejects inbound anchor channels otherwise");
+		return Err(());
+	}
+	user_info.htlc_minimum_msat =
+		parse_named_arg(&mut named_args, "htlc-minimum-msat", DEFAULT_HTLC_MINIMUM_MSAT)?;
+	user_info.intercept_htlcs = parse_named_arg(&mut named_args, "intercept-htlcs", false)?;
+
+	user_info.import_channel_backup = named_args.remove("import-channel-backup").map(PathBuf::from);
+
+	if let Some(name) = named_args.keys().next() {
+		println!("\rERROR: unknown argument: --{}", name);
+		return Err(());
+	}
+
+	Ok(())
+}
+
+fn parse_private_key(private_key: &str) -> Result<PrivateKey, ()> {
+	PrivateKey::from_str(private_key).map_err(|e| {
+		println!("\rERROR: invalid private key: {}", e);
 	})
 }
 
+fn parse_network(network: &str) -> Result<Network, ()> {
+	match network {
+		"testnet" => Ok(Network::Testnet),
+		"regtest" => Ok(Network::Regtest),
+		"signet" => Ok(Network::Signet),
+		net => {
+			println!("\rERROR: unsupported network provided. Options are: `regtest`, `testnet`, and `signet`. Got {}", net);
+			Err(())
+		}
+	}
+}
+
+pub(crate) fn parse_node_name(name: &str) -> Result<[u8; 32], ()> {
+	if name.len() > 32 {
+		println!("\rERROR: node alias can not be longer than 32 bytes");
+		return Err(());
+	}
+	let mut bytes = [0; 32];
+	bytes[..name.len()].copy_from_slice(name.as_bytes());
+	Ok(bytes)
+}
+
+/// Reads the bitcoind RPC credentials from the cookie file given with `--bitcoind-cookie`, if any.
+fn parse_cookie_arg(
+	named_args: &mut HashMap<String, String>,
+) -> Result<Option<(PathBuf, (String, String))>, ()> {
+	let Some(path) = named_args.remove("bitcoind-cookie") else {
+		return Ok(None);
+	};
+	let path = PathBuf::from(path);
+	let cookie_contents = fs::read_to_string(&path).map_err(|e| {
+		println!("\rERROR: failed to read bitcoind cookie file {}: {}", path.display(), e);
+	})?;
+	let auth = parse_rpc_auth(cookie_contents.trim())?;
+
+	Ok(Some((path, auth)))
+}
+
+/// Looks for the bitcoind RPC credentials in the environment, a .env file, and then the default
+/// cookie file, in that order.
+fn get_default_rpc_auth(network: Network) -> Result<(String, String), ()> {
+	get_rpc_auth_from_env_vars()
+		.or(get_rpc_auth_from_env_file(None))
+		.or(get_rpc_auth_from_cookie(None, Some(network), None))
+		.or({
+			println!("\rERROR: unable to get bitcoind RPC username and password");
+			print_rpc_auth_help();
+			Err(())
+		})
+}
+
+/// Splits the `--name value` and `--name=value` arguments out of the positional ones.
+fn split_named_args(args: Vec<String>) -> Result<(Vec<String>, HashMap<String, String>), ()> {
+	let mut positional_args = Vec::new();
+	let mut named_args = HashMap::new();
+
+	let mut args = args.into_iter();
+	while let Some(arg) = args.next() {
+		let Some(name) = arg.strip_prefix("--") else {
+			positional_args.push(arg);
+			continue;
+		};
+
+		let (name, value) = match name.split_once('=') {
+			Some((name, value)) => (name.to_string(), value.to_string()),
+			None => match args.next() {
+				Some(value) => (name.to_string(), value),
+				None => {
+					println!("\rERROR: missing value for argument --{}", name);
+					return Err(());
+				}
+			},
+		};
+		named_args.insert(name, value);
+	}
+
+	Ok((positional_args, named_args))
+}
+
+fn parse_named_arg<T: FromStr>(
+	named_args: &mut HashMap<String, String>, name: &str, default: T,
+) -> Result<T, ()> {
+	match named_args.remove(name) {
+		Some(value) => value.parse().map_err(|_| {
+			println!("\rERROR: invalid value for argument --{}: {}", name, value);
+		}),
+		None => Ok(default),
+	}
+}
+
 // Default datadir relative to home directory
 #[cfg(target_os = "windows")]
 const DEFAULT_BITCOIN_DATADIR: &str = "AppData/Roaming/Bitcoin";
@@ -363,3 +932,317 @@ mod rpc_auth_tests {
 		assert_eq!(password, EXPECTED_PASSWORD);
 	}
 }
+
+#[cfg(test)]
+mod named_args_tests {
+	use super::*;
+
+	fn to_args(args: &[&str]) -> Vec<String> {
+		args.iter().map(|arg| arg.to_string()).collect()
+	}
+
+	#[test]
+	fn test_split_named_args_success() {
+		let (positional_args, named_args) = split_named_args(to_args(&[
+			"yuv-ln-node",
+			"--yuv-poll-interval-ms",
+			"500",
+			"127.0.0.1:18443",
+			"--foo=bar",
+			"./data",
+		]))
+		.unwrap();
+
+		assert_eq!(positional_args, to_args(&["yuv-ln-node", "127.0.0.1:18443", "./data"]));
+		assert_eq!(named_args.get("yuv-poll-interval-ms").unwrap(), "500");
+		assert_eq!(named_args.get("foo").unwrap(), "bar");
+	}
+
+	#[test]
+	fn test_split_named_args_missing_value() {
+		let result = split_named_args(to_args(&["yuv-ln-node", "--yuv-poll-interval-ms"]));
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn test_parse_named_arg() {
+		let mut named_args = HashMap::new();
+		named_args.insert("yuv-poll-interval-ms".to_string(), "500".to_string());
+		named_args.insert("bad".to_string(), "not-a-number".to_string());
+
+		assert_eq!(parse_named_arg(&mut named_args, "yuv-poll-interval-ms", 1000u64), Ok(500));
+		assert_eq!(parse_named_arg(&mut named_args, "yuv-poll-interval-ms", 1000u64), Ok(1000));
+		assert!(parse_named_arg(&mut named_args, "bad", 1000u64).is_err());
+		assert!(named_args.is_empty());
+	}
+}
+
+#[cfg(test)]
+mod config_file_tests {
+	use super::*;
+
+	const TEST_PRIVATE_KEY: &str = "cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9";
+
+	fn test_config() -> String {
+		format!(
+			r#"
+			bitcoind_rpc_host = "127.0.0.1"
+			bitcoind_rpc_port = 18443
+			bitcoind_rpc_username = "testuser"
+			bitcoind_rpc_password = "testpassword"
+			ldk_storage_dir_path = "./data"
+			private_key = "{}"
+			network = "regtest"
+			ldk_announced_node_name = "alice"
+			ldk_announced_listen_addr = ["127.0.0.1:9735"]
+			yuv_rpc_url = "http://127.0.0.1:18333"
+			yuv_poll_interval_ms = 500
+			sweep_conf_target = "normal"
+			announcement_interval_secs = 7200
+			"#,
+			TEST_PRIVATE_KEY
+		)
+	}
+
+	#[test]
+	fn test_parse_config_file_success() {
+		let user_info = parse_config_file(&test_config(), HashMap::new()).unwrap();
+
+		assert_eq!(user_info.bitcoind_rpc_host, "127.0.0.1");
+		assert_eq!(user_info.bitcoind_rpc_port, 18443);
+		assert_eq!(user_info.bitcoind_rpc_username, "testuser");
+		assert_eq!(user_info.bitcoind_rpc_password, "testpassword");
+		assert_eq!(user_info.ldk_storage_dir_path, "./data");
+		assert_eq!(user_info.private_key.to_wif(), TEST_PRIVATE_KEY);
+		assert_eq!(user_info.ldk_peer_listening_port, 9735);
+		assert_eq!(user_info.network, Network::Regtest);
+		assert_eq!(&user_info.ldk_announced_node_name[..5], b"alice");
+		assert_eq!(user_info.ldk_announced_listen_addr.len(), 1);
+		assert_eq!(user_info.yuv_rpc_url.as_deref(), Some("http://127.0.0.1:18333"));
+		assert_eq!(user_info.yuv_poll_interval, Duration::from_millis(500));
+		assert_eq!(user_info.yuv_rpc_timeout, Duration::from_secs(30));
+		assert_eq!(user_info.sweep_conf_target, ConfirmationTarget::NonAnchorChannelFee);
+		assert_eq!(user_info.announcement_interval, Duration::from_secs(7200));
+		assert_eq!(user_info.announcement_warmup, Duration::from_secs(60));
+		assert_eq!(user_info.rpc_listen, None);
+		assert_eq!(user_info.proxy, None);
+		assert_eq!(user_info.sweep_destination, None);
+		assert_eq!(user_info.max_sweep_rejections, 10);
+		assert_eq!(user_info.min_funding_confs, None);
+		assert_eq!(user_info.inbound_channel_policy, InboundChannelPolicy::default());
+		assert!(user_info.manually_accept_inbound_channels);
+		assert!(user_info.negotiate_anchors);
+		assert_eq!(user_info.htlc_minimum_msat, 4_000_000);
+		assert!(!user_info.intercept_htlcs);
+	}
+
+	#[test]
+	fn test_parse_config_file_channel_defaults() {
+		let config = format!(
+			"{}\nmanually_accept_channels = false\nanchors = false\nhtlc_minimum_msat = 1000\nintercept_htlcs = true",
+			test_config()
+		);
+		let user_info = parse_config_file(&config, HashMap::new()).unwrap();
+		assert!(!user_info.manually_accept_inbound_channels);
+		assert!(!user_info.negotiate_anchors);
+		assert_eq!(user_info.htlc_minimum_msat, 1000);
+		assert!(user_info.intercept_htlcs);
+
+		let bad_anchors = format!("{}\nanchors = \"maybe\"", test_config());
+		assert!(parse_config_file(&bad_anchors, HashMap::new()).is_err());
+
+		// Inbound anchor channels can only be accepted manually.
+		let auto_anchors = format!("{}\nmanually_accept_channels = false", test_config());
+		assert!(parse_config_file(&auto_anchors, HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_inbound_channel_policy() {
+		let config =
+			format!("{}\nmin_channel_size_sat = 100000\ninbound_channels = \"yuv\"", test_config());
+		let user_info = parse_config_file(&config, HashMap::new()).unwrap();
+		assert_eq!(user_info.inbound_channel_policy.min_channel_size_sat, Some(100_000));
+		assert_eq!(user_info.inbound_channel_policy.kind, InboundChannelKind::Yuv);
+
+		let bad_kind = format!("{}\ninbound_channels = \"some\"", test_config());
+		assert!(parse_config_file(&bad_kind, HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_min_funding_confs() {
+		let config = format!("{}\nmin_funding_confs = 1", test_config());
+		let user_info = parse_config_file(&config, HashMap::new()).unwrap();
+		assert_eq!(user_info.min_funding_confs, Some(1));
+
+		let zero_confs = format!("{}\nmin_funding_confs = 0", test_config());
+		assert!(parse_config_file(&zero_confs, HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_max_sweep_rejections() {
+		let config = format!("{}\nmax_sweep_rejections = 3", test_config());
+		let user_info = parse_config_file(&config, HashMap::new()).unwrap();
+		assert_eq!(user_info.max_sweep_rejections, 3);
+
+		let zero_rejections = format!("{}\nmax_sweep_rejections = 0", test_config());
+		assert!(parse_config_file(&zero_rejections, HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_yuv_poll_interval() {
+		let min_interval = test_config().replace("= 500", "= 100");
+		let user_info = parse_config_file(&min_interval, HashMap::new()).unwrap();
+		assert_eq!(user_info.yuv_poll_interval, Duration::from_millis(100));
+
+		let zero_interval = test_config().replace("= 500", "= 0");
+		assert!(parse_config_file(&zero_interval, HashMap::new()).is_err());
+
+		let mut named_args = HashMap::new();
+		named_args.insert("yuv-poll-interval-ms".to_string(), "10".to_string());
+		assert!(parse_config_file(&test_config(), named_args).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_yuv_txids_chunk_size() {
+		let user_info = parse_config_file(&test_config(), HashMap::new()).unwrap();
+		assert_eq!(user_info.yuv_txids_chunk_size, 100);
+
+		let config = format!("{}\nyuv_txids_chunk_size = 20", test_config());
+		let user_info = parse_config_file(&config, HashMap::new()).unwrap();
+		assert_eq!(user_info.yuv_txids_chunk_size, 20);
+
+		let zero_chunk_size = format!("{}\nyuv_txids_chunk_size = 0", test_config());
+		assert!(parse_config_file(&zero_chunk_size, HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_yuv_max_concurrent_chunks() {
+		let user_info = parse_config_file(&test_config(), HashMap::new()).unwrap();
+		assert_eq!(user_info.yuv_max_concurrent_chunks, 8);
+
+		let config = format!("{}\nyuv_max_concurrent_chunks = 2", test_config());
+		let user_info = parse_config_file(&config, HashMap::new()).unwrap();
+		assert_eq!(user_info.yuv_max_concurrent_chunks, 2);
+
+		let zero_chunks = format!("{}\nyuv_max_concurrent_chunks = 0", test_config());
+		assert!(parse_config_file(&zero_chunks, HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_yuv_subscribe() {
+		let user_info = parse_config_file(&test_config(), HashMap::new()).unwrap();
+		assert!(!user_info.yuv_subscribe);
+
+		let config = format!("{}\nyuv_subscribe = true", test_config());
+		let user_info = parse_config_file(&config, HashMap::new()).unwrap();
+		assert!(user_info.yuv_subscribe);
+
+		let mut named_args = HashMap::new();
+		named_args.insert("yuv-subscribe".to_string(), "false".to_string());
+		let user_info = parse_config_file(&config, named_args).unwrap();
+		assert!(!user_info.yuv_subscribe);
+
+		let invalid = format!("{}\nyuv_subscribe = \"sometimes\"", test_config());
+		assert!(parse_config_file(&invalid, HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_sweep_destination() {
+		let pubkey = "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619";
+		let config = format!("{}\nsweep_destination = \"{}\"", test_config(), pubkey);
+
+		let user_info = parse_config_file(&config, HashMap::new()).unwrap();
+		assert_eq!(user_info.sweep_destination, Some(PublicKey::from_str(pubkey).unwrap()));
+
+		let bad_destination = format!("{}\nsweep_destination = \"not-a-pubkey\"", test_config());
+		assert!(parse_config_file(&bad_destination, HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_proxy() {
+		let onion_addr = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion:9735";
+		let config = format!("{}\nproxy = \"127.0.0.1:9050\"", test_config())
+			.replace("[\"127.0.0.1:9735\"]", &format!("[\"127.0.0.1:9735\", \"{}\"]", onion_addr));
+
+		let user_info = parse_config_file(&config, HashMap::new()).unwrap();
+		assert_eq!(user_info.proxy, Some(SocketAddr::from_str("127.0.0.1:9050").unwrap()));
+		assert_eq!(
+			user_info.ldk_announced_listen_addr[1],
+			SocketAddress::from_str(onion_addr).unwrap()
+		);
+
+		let bad_proxy = format!("{}\nproxy = \"not-an-address\"", test_config());
+		assert!(parse_config_file(&bad_proxy, HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_rpc_listen() {
+		let config = format!("{}\nrpc_listen = \"127.0.0.1:9000\"", test_config());
+		let user_info = parse_config_file(&config, HashMap::new()).unwrap();
+		assert_eq!(user_info.rpc_listen, Some(SocketAddr::from_str("127.0.0.1:9000").unwrap()));
+
+		// Other hosts can only reach the RPC server when that's explicitly allowed.
+		let remote = format!("{}\nrpc_listen = \"0.0.0.0:9000\"", test_config());
+		assert!(parse_config_file(&remote, HashMap::new()).is_err());
+		let allowed = format!("{}\nrpc_allow_remote = true", remote);
+		let user_info = parse_config_file(&allowed, HashMap::new()).unwrap();
+		assert_eq!(user_info.rpc_listen, Some(SocketAddr::from_str("0.0.0.0:9000").unwrap()));
+	}
+
+	#[test]
+	fn test_parse_config_file_named_args_override() {
+		let mut named_args = HashMap::new();
+		named_args.insert("yuv-poll-interval-ms".to_string(), "2000".to_string());
+
+		let user_info = parse_config_file(&test_config(), named_args).unwrap();
+		assert_eq!(user_info.yuv_poll_interval, Duration::from_millis(2000));
+		assert_eq!(user_info.sweep_conf_target, ConfirmationTarget::NonAnchorChannelFee);
+	}
+
+	#[test]
+	fn test_parse_config_file_cookie() {
+		let config = test_config()
+			.replace("bitcoind_rpc_username = \"testuser\"", "")
+			.replace("bitcoind_rpc_password = \"testpassword\"", "");
+		let mut named_args = HashMap::new();
+		named_args.insert("bitcoind-cookie".to_string(), "test_data/test_cookie".to_string());
+
+		let user_info = parse_config_file(&config, named_args.clone()).unwrap();
+		assert_eq!(user_info.bitcoind_rpc_username, "testuser");
+		assert_eq!(user_info.bitcoind_rpc_password, "testpassword");
+		assert_eq!(user_info.bitcoind_rpc_cookie, Some(PathBuf::from("test_data/test_cookie")));
+
+		// The cookie can't be combined with an explicit username and password.
+		assert!(parse_config_file(&test_config(), named_args).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_import_channel_backup() {
+		let mut named_args = HashMap::new();
+		named_args.insert("import-channel-backup".to_string(), "channels.bak".to_string());
+		let user_info = parse_config_file(&test_config(), named_args).unwrap();
+		assert_eq!(user_info.import_channel_backup, Some(PathBuf::from("channels.bak")));
+
+		// A backup is only ever imported once, so it can't be set in the config file.
+		let config = format!("{}\nimport_channel_backup = \"channels.bak\"", test_config());
+		assert!(parse_config_file(&config, HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn test_parse_config_file_fail() {
+		let bad_network = test_config().replace("regtest", "mainnet");
+		assert!(parse_config_file(&bad_network, HashMap::new()).is_err());
+
+		let bad_private_key = test_config().replace(TEST_PRIVATE_KEY, "not-a-key");
+		assert!(parse_config_file(&bad_private_key, HashMap::new()).is_err());
+
+		let bad_addr = test_config().replace("127.0.0.1:9735", "not-an-address");
+		assert!(parse_config_file(&bad_addr, HashMap::new()).is_err());
+
+		let unknown_key = format!("{}\nfoo = 1", test_config());
+		assert!(parse_config_file(&unknown_key, HashMap::new()).is_err());
+
+		let missing_key = test_config().replace("ldk_storage_dir_path", "# ldk_storage_dir_path");
+		assert!(parse_config_file(&missing_key, HashMap::new()).is_err());
+	}
+}
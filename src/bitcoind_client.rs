@@ -1,6 +1,6 @@
 use crate::convert::{
-	BlockchainInfo, FeeResponse, FundedTx, ListUnspentResponse, MempoolMinFeeResponse, NewAddress,
-	RawTx, SignedTx,
+	BlockchainInfo, FeeResponse, FundedTx, GeneratedBlocks, ListUnspentResponse,
+	MempoolMinFeeResponse, MempoolTxids, NewAddress, RawTx, SignedTx, UnspentTxOut,
 };
 use crate::disk::FilesystemLogger;
 use base64::engine::general_purpose::STANDARD as Base64Engine;
@@ -9,7 +9,7 @@ use bitcoin::blockdata::transaction::Transaction;
 use bitcoin::consensus::encode;
 use bitcoin::constants::ChainHash;
 use bitcoin::hash_types::{BlockHash, Txid};
-use bitcoin::{Address, Network};
+use bitcoin::{Address, Network, OutPoint};
 use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
 use lightning::log_error;
 use lightning::routing::utxo::{UtxoLookup, UtxoResult};
@@ -278,6 +278,41 @@ impl BitcoindClient {
 			.unwrap()
 	}
 
+	/// Returns whether `outpoint` is in bitcoind's UTXO set, i.e. it exists and isn't spent. With
+	/// `include_mempool`, outputs spent by transactions in the mempool count as spent too.
+	pub async fn is_output_unspent(
+		&self, outpoint: &OutPoint, include_mempool: bool,
+	) -> std::io::Result<bool> {
+		let args = [
+			serde_json::json!(outpoint.txid.to_string()),
+			serde_json::json!(outpoint.vout),
+			serde_json::json!(include_mempool),
+		];
+		let tx_out =
+			self.bitcoind_rpc_client.call_method::<UnspentTxOut>("gettxout", &args).await?;
+		Ok(tx_out.0)
+	}
+
+	/// Returns whether `txid` is in bitcoind's mempool.
+	pub async fn is_in_mempool(&self, txid: &Txid) -> std::io::Result<bool> {
+		let txids =
+			self.bitcoind_rpc_client.call_method::<MempoolTxids>("getrawmempool", &vec![]).await?;
+		Ok(txids.0.contains(txid))
+	}
+
+	/// Mines `num_blocks` blocks paying their coinbase to `address`, which bitcoind only allows on
+	/// regtest.
+	pub async fn generate_to_address(
+		&self, num_blocks: u64, address: &Address,
+	) -> std::io::Result<Vec<BlockHash>> {
+		let args = [serde_json::json!(num_blocks), serde_json::json!(address.to_string())];
+		let blocks = self
+			.bitcoind_rpc_client
+			.call_method::<GeneratedBlocks>("generatetoaddress", &args)
+			.await?;
+		Ok(blocks.0)
+	}
+
 	pub async fn list_unspent(&self) -> ListUnspentResponse {
 		self.bitcoind_rpc_client
 			.call_method::<ListUnspentResponse>("listunspent", &vec![])
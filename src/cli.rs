@@ -1,46 +1,77 @@
-use crate::disk::{self, read_channel_peer_data, INBOUND_PAYMENTS_FNAME, OUTBOUND_PAYMENTS_FNAME};
+use crate::args;
+use crate::bitcoind_client::BitcoindClient;
+use crate::disk::{
+	self, read_channel_peer_data, INBOUND_PAYMENTS_FNAME, OUTBOUND_PAYMENTS_FNAME,
+	PAYMENT_POLICY_FNAME, PEER_ACCESS_LISTS_FNAME, USER_CONFIG_FNAME,
+};
 use crate::hex_utils;
+use crate::onion_messages::{
+	ReceivedOnionMessage, UserOnionMessageContents, UserOnionMessageHandler, MIN_CUSTOM_TLV_TYPE,
+};
+use crate::socks5;
+use crate::sweep;
+use crate::wallet::Wallet;
+use crate::yuv_client::{ProofDelivery, YuvClient};
 use crate::{
-	ChannelManager, HTLCStatus, MillisatAmount, NetworkGraph, OnionMessenger, PaymentInfo,
-	PaymentInfoStorage, PeerManager,
+	ChainMonitor, ChannelBackup, ChannelClosureStorage, ChannelManager, ChannelMonitorBackup,
+	FailedSweepStorage, ForwardInfo, ForwardInfoStorage, HTLCStatus, InboundChannelPolicy,
+	InterceptedHtlc, MillisatAmount, NetworkGraph, OnionMessenger, PaymentInfo, PaymentInfoStorage,
+	PaymentPolicy, PeerAccessLists, PeerManager, Router, SavedUserConfig,
+	UpdateBalanceRequestStorage,
 };
 use bitcoin::hashes::Hash;
 use bitcoin::network::constants::Network;
-use bitcoin::secp256k1::PublicKey;
-use bitcoin::PrivateKey;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::{PrivateKey, Txid};
 use crossterm::event::{read, Event, KeyCode, KeyModifiers};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode, ClearType};
 use crossterm::{cursor, terminal, ExecutableCommand};
 use eyre::bail;
-use lightning::ln::channelmanager::{PaymentId, RecipientOnionFields, Retry, UpdateBalance};
+use lightning::chain::chaininterface::{
+	BroadcasterInterface, ConfirmationTarget, FeeEstimator, YuvBroadcaster,
+};
+use lightning::ln::channelmanager::{
+	ChannelDetails, InterceptId, PaymentId, RecipientOnionFields, Retry, RetryableSendFailure,
+	UpdateBalance, MIN_CLTV_EXPIRY_DELTA,
+};
 use lightning::ln::msgs::SocketAddress;
 use lightning::ln::{ChannelId, PaymentHash, PaymentPreimage};
+use lightning::offers::offer::Offer;
+use lightning::offers::parse::Bolt12SemanticError;
 use lightning::onion_message::messenger::Destination;
-use lightning::onion_message::packet::OnionMessageContents;
 use lightning::routing::gossip::NodeId;
-use lightning::routing::router::{PaymentParameters, RouteParameters};
+use lightning::routing::router::{PaymentParameters, RouteParameters, Router as _};
 use lightning::sign::{EntropySource, KeysManager};
-use lightning::util::config::UserConfig;
+use lightning::util::config::{ChannelConfig, ChannelConfigUpdate, UserConfig};
+use lightning::util::logger::Level;
 use lightning::util::persist::KVStore;
-use lightning::util::ser::{Writeable, Writer};
+use lightning::util::ser::Writeable;
 use lightning_invoice::payment::{
 	payment_parameters_from_invoice, payment_parameters_from_zero_amount_invoice,
 };
-use lightning_invoice::{utils, Bolt11Invoice, Currency};
+use lightning_invoice::{utils, Bolt11Invoice, Bolt11InvoiceDescription, Currency};
 use lightning_persister::fs_store::FilesystemStore;
+use rand::{thread_rng, RngCore};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fmt::Debug;
+use std::future::Future;
 use std::io::{stdout, Write};
 use std::net::{SocketAddr, ToSocketAddrs};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::str::{FromStr, SplitWhitespace};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex as TokioMutex;
 use yuv_pixels::{Chroma, Luma, Pixel};
 
 pub(crate) struct LdkUserInfo {
 	pub(crate) bitcoind_rpc_username: String,
 	pub(crate) bitcoind_rpc_password: String,
+	/// The cookie file the bitcoind RPC username and password were read from, if any.
+	pub(crate) bitcoind_rpc_cookie: Option<PathBuf>,
 	pub(crate) bitcoind_rpc_port: u16,
 	pub(crate) private_key: PrivateKey,
 	pub(crate) bitcoind_rpc_host: String,
@@ -50,24 +81,45 @@ pub(crate) struct LdkUserInfo {
 	pub(crate) ldk_announced_node_name: [u8; 32],
 	pub(crate) network: Network,
 	pub(crate) yuv_rpc_url: Option<String>,
-}
-
-#[derive(Debug)]
-struct UserOnionMessageContents {
-	tlv_type: u64,
-	data: Vec<u8>,
-}
-
-impl OnionMessageContents for UserOnionMessageContents {
-	fn tlv_type(&self) -> u64 {
-		self.tlv_type
-	}
-}
-
-impl Writeable for UserOnionMessageContents {
-	fn write<W: Writer>(&self, w: &mut W) -> Result<(), std::io::Error> {
-		w.write_all(&self.data)
-	}
+	pub(crate) yuv_poll_interval: Duration,
+	pub(crate) yuv_rpc_timeout: Duration,
+	pub(crate) yuv_tx_cache_size: usize,
+	/// The maximum number of txids requested from the YUV node in a single call.
+	pub(crate) yuv_txids_chunk_size: usize,
+	/// The maximum number of chunks of txids requested from the YUV node at the same time.
+	pub(crate) yuv_max_concurrent_chunks: usize,
+	/// Whether confirmed YUV transactions are pushed by the YUV node over a WebSocket rather than
+	/// only polled for.
+	pub(crate) yuv_subscribe: bool,
+	pub(crate) sweep_conf_target: ConfirmationTarget,
+	/// The key swept outputs are sent to instead of the node's own wallet, e.g. one of a cold
+	/// wallet.
+	pub(crate) sweep_destination: Option<PublicKey>,
+	/// How many times the YUV node may reject sweeping a set of outputs before it's given up on.
+	pub(crate) max_sweep_rejections: u32,
+	/// How many confirmations the funding transaction of an inbound channel needs before the
+	/// channel can be used, if not LDK's default.
+	pub(crate) min_funding_confs: Option<u32>,
+	pub(crate) inbound_channel_policy: InboundChannelPolicy,
+	/// Whether inbound channels go through the `OpenChannelRequest` event, where the inbound
+	/// channel policy is applied, rather than being accepted by LDK.
+	pub(crate) manually_accept_inbound_channels: bool,
+	pub(crate) negotiate_anchors: bool,
+	pub(crate) htlc_minimum_msat: u64,
+	/// Whether HTLCs sent to intercept SCIDs are held for `forwardhtlc` or `failhtlc`.
+	pub(crate) intercept_htlcs: bool,
+	pub(crate) persist_interval: Duration,
+	pub(crate) announcement_interval: Duration,
+	pub(crate) announcement_warmup: Duration,
+	pub(crate) log_format: disk::LogFormat,
+	pub(crate) log_level: Level,
+	pub(crate) log_max_bytes: Option<u64>,
+	pub(crate) rpc_listen: Option<SocketAddr>,
+	pub(crate) metrics_listen: Option<SocketAddr>,
+	/// The SOCKS5 proxy, e.g. Tor, outbound peer connections are made through.
+	pub(crate) proxy: Option<SocketAddr>,
+	/// A backup written by `exportchannelbackup` to restore into an empty storage directory.
+	pub(crate) import_channel_backup: Option<PathBuf>,
 }
 
 pub(crate) fn read_input(
@@ -190,13 +242,35 @@ pub(crate) fn read_input(
 	Ok(result)
 }
 
+/// Asks the user to confirm with `y` or `yes`, anything else declining. The answer isn't kept in
+/// the command history.
+fn confirm(prompt: &str) -> bool {
+	match read_input(prompt, &mut Vec::new()) {
+		Ok(Some(answer)) => is_confirmation(&answer),
+		_ => false,
+	}
+}
+
+fn is_confirmation(answer: &str) -> bool {
+	matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
 pub(crate) fn poll_for_user_input(
 	peer_manager: Arc<PeerManager>, channel_manager: Arc<ChannelManager>,
 	keys_manager: Arc<KeysManager>, network_graph: Arc<NetworkGraph>,
 	onion_messenger: Arc<OnionMessenger>, inbound_payments: Arc<Mutex<PaymentInfoStorage>>,
 	outbound_payments: Arc<Mutex<PaymentInfoStorage>>, ldk_data_dir: String, network: Network,
 	logger: Arc<disk::FilesystemLogger>, fs_store: Arc<FilesystemStore>,
-	default_config: Arc<Mutex<UserConfig>>,
+	default_config: Arc<Mutex<UserConfig>>, wallet: Arc<TokioMutex<Wallet>>,
+	yuv_client: Option<Arc<YuvClient>>, bitcoind_client: Arc<BitcoindClient>,
+	update_balance_requests: Arc<Mutex<UpdateBalanceRequestStorage>>,
+	update_balance_max_delta_msat: Arc<Mutex<Option<u64>>>,
+	forwards: Arc<Mutex<ForwardInfoStorage>>, closures: Arc<Mutex<ChannelClosureStorage>>,
+	failed_sweeps: Arc<Mutex<FailedSweepStorage>>, peer_access_lists: Arc<Mutex<PeerAccessLists>>,
+	router: Arc<Router>, proxy: Option<SocketAddr>, node_alias: Arc<Mutex<[u8; 32]>>,
+	announced_addrs: Arc<Mutex<Vec<SocketAddress>>>, chain_monitor: Arc<ChainMonitor>,
+	payment_policy: Arc<Mutex<PaymentPolicy>>, onion_message_handler: Arc<UserOnionMessageHandler>,
+	intercepted_htlcs: Arc<Mutex<HashMap<InterceptId, InterceptedHtlc>>>,
 ) {
 	println!(
 		"\rLDK startup successful. Enter \"help\" to view available commands. Press Ctrl-D to quit."
@@ -204,7 +278,11 @@ pub(crate) fn poll_for_user_input(
 	println!("\rLDK logs are available at <your-supplied-ldk-data-dir-path>/.ldk/logs");
 	println!("\rLocal Node ID is {}.", channel_manager.get_our_node_id());
 
-	let mut commands_history = Vec::new();
+	let yuv_enabled = yuv_client.is_some();
+	let cli_history_path = format!("{}/{}", ldk_data_dir, disk::CLI_HISTORY_FNAME);
+	let mut commands_history = disk::read_cli_history(Path::new(&cli_history_path));
+	// The last transfer of the YUV holdings of each key imported with `importprivkey`.
+	let mut yuv_transfers = HashMap::new();
 
 	'outer: loop {
 		stdout().flush().unwrap();
@@ -217,6 +295,23 @@ pub(crate) fn poll_for_user_input(
 			continue;
 		}
 
+		let history_to_persist = commands_history
+			.iter()
+			.filter(|command| !command.is_empty() && !contains_private_key(command))
+			.map(|command| command.as_str())
+			.collect::<Vec<_>>();
+		if let Err(e) = disk::persist_cli_history(Path::new(&cli_history_path), &history_to_persist)
+		{
+			println!("\rERROR: failed to persist command history: {}", e);
+		}
+
+		// Rather than each command checking for a YUV node, the ones which need it are turned
+		// away here.
+		if !yuv_enabled && requires_yuv(&line) {
+			println!("\rERROR: YUV not configured, the node must be started with a YUV RPC URL to use YUV tokens");
+			continue;
+		}
+
 		let mut words = line.split_whitespace();
 		if let Some(word) = words.next() {
 			match word {
@@ -233,6 +328,11 @@ pub(crate) fn poll_for_user_input(
 								let mut default_config = default_config.lock().unwrap();
 								default_config.channel_handshake_config.our_htlc_minimum_msat =
 									min_inbound_htlc;
+								persist_user_config(
+									&fs_store,
+									&default_config,
+									*update_balance_max_delta_msat.lock().unwrap(),
+								);
 							}
 							"--max-inb-htlc-pct" => {
 								let max_inbound_htlc_percent =
@@ -245,6 +345,60 @@ pub(crate) fn poll_for_user_input(
 								default_config
 									.channel_handshake_config
 									.max_inbound_htlc_value_in_flight_percent_of_channel = max_inbound_htlc_percent;
+								persist_user_config(
+									&fs_store,
+									&default_config,
+									*update_balance_max_delta_msat.lock().unwrap(),
+								);
+							}
+							"--fee-base-msat" => {
+								let fee_base_msat = match parse_named_param(&mut words, word) {
+									Some(fee_base_msat) => fee_base_msat,
+									None => continue 'outer,
+								};
+
+								let mut default_config = default_config.lock().unwrap();
+								default_config.channel_config.forwarding_fee_base_msat =
+									fee_base_msat;
+								persist_user_config(
+									&fs_store,
+									&default_config,
+									*update_balance_max_delta_msat.lock().unwrap(),
+								);
+							}
+							"--fee-ppm" => {
+								let fee_ppm = match parse_named_param(&mut words, word) {
+									Some(fee_ppm) => fee_ppm,
+									None => continue 'outer,
+								};
+
+								let mut default_config = default_config.lock().unwrap();
+								default_config
+									.channel_config
+									.forwarding_fee_proportional_millionths = fee_ppm;
+								persist_user_config(
+									&fs_store,
+									&default_config,
+									*update_balance_max_delta_msat.lock().unwrap(),
+								);
+							}
+							"--cltv-delta" => {
+								let cltv_expiry_delta = match parse_named_param(&mut words, word) {
+									Some(cltv_expiry_delta) => cltv_expiry_delta,
+									None => continue 'outer,
+								};
+								if let Err(e) = validate_cltv_expiry_delta(cltv_expiry_delta) {
+									println!("\rERROR: {}", e);
+									continue 'outer;
+								}
+
+								let mut default_config = default_config.lock().unwrap();
+								default_config.channel_config.cltv_expiry_delta = cltv_expiry_delta;
+								persist_user_config(
+									&fs_store,
+									&default_config,
+									*update_balance_max_delta_msat.lock().unwrap(),
+								);
 							}
 							"--support-yuv" => {
 								let support_yuv = match parse_named_param(&mut words, word) {
@@ -254,6 +408,101 @@ pub(crate) fn poll_for_user_input(
 
 								let mut default_config = default_config.lock().unwrap();
 								default_config.support_yuv_payments = support_yuv;
+								persist_user_config(
+									&fs_store,
+									&default_config,
+									*update_balance_max_delta_msat.lock().unwrap(),
+								);
+							}
+							"--auto-update-balance-max-delta" => {
+								let max_delta_msat = match parse_named_param(&mut words, word) {
+									Some(max_delta_msat) => max_delta_msat,
+									None => continue 'outer,
+								};
+
+								let default_config = default_config.lock().unwrap();
+								let mut update_balance_max_delta_msat =
+									update_balance_max_delta_msat.lock().unwrap();
+								*update_balance_max_delta_msat = Some(max_delta_msat);
+								persist_user_config(
+									&fs_store,
+									&default_config,
+									*update_balance_max_delta_msat,
+								);
+							}
+							"--allow-peer" | "--deny-peer" | "--remove-peer" => {
+								let node_id =
+									match words.next().map(hex_utils::to_compressed_pubkey) {
+										Some(Some(node_id)) => node_id,
+										_ => {
+											println!("\rERROR: {word} requires a peer pubkey");
+											continue 'outer;
+										}
+									};
+
+								let mut peer_access_lists = peer_access_lists.lock().unwrap();
+								if word == "--allow-peer" {
+									peer_access_lists.allow(node_id);
+								} else if word == "--deny-peer" {
+									peer_access_lists.deny(node_id);
+								} else if !peer_access_lists.remove(&node_id) {
+									println!(
+										"\rERROR: peer {} is neither allowed nor denied",
+										node_id
+									);
+									continue 'outer;
+								}
+								if let Err(e) = fs_store.write(
+									"",
+									"",
+									PEER_ACCESS_LISTS_FNAME,
+									&peer_access_lists.encode(),
+								) {
+									println!("\rERROR: failed to persist peer access lists: {}", e);
+								}
+							}
+							_ => {
+								println!("\rERROR: unknown parameter: {word}");
+								continue 'outer;
+							}
+						}
+					}
+				}
+				"configpayment" => {
+					let mut policy = payment_policy.lock().unwrap().clone();
+					while let Some(word) = words.next() {
+						match word {
+							"--retry" => {
+								policy.retry_attempts = match parse_named_param(&mut words, word) {
+									Some(attempts) => Some(attempts),
+									None => continue 'outer,
+								};
+							}
+							"--retry-timeout-secs" => {
+								policy.retry_timeout_secs =
+									match parse_named_param(&mut words, word) {
+										Some(secs) => secs,
+										None => continue 'outer,
+									};
+								policy.retry_attempts = None;
+							}
+							"--max-fee-ppm" => {
+								policy.max_fee_ppm = match parse_fee_limit(&mut words, word) {
+									Some(max_fee_ppm) => max_fee_ppm,
+									None => continue 'outer,
+								};
+							}
+							"--max-fee-msat" => {
+								policy.max_fee_msat = match parse_fee_limit(&mut words, word) {
+									Some(max_fee_msat) => max_fee_msat,
+									None => continue 'outer,
+								};
+							}
+							"--probe" => {
+								policy.probe = match parse_named_param(&mut words, word) {
+									Some(probe) => probe,
+									None => continue 'outer,
+								};
 							}
 							_ => {
 								println!("\rERROR: unknown parameter: {word}");
@@ -261,20 +510,39 @@ pub(crate) fn poll_for_user_input(
 							}
 						}
 					}
+
+					if let Err(e) = fs_store.write("", "", PAYMENT_POLICY_FNAME, &policy.encode()) {
+						println!("\rERROR: failed to persist payment policy: {}", e);
+						continue;
+					}
+					print_payment_policy(&policy);
+					*payment_policy.lock().unwrap() = policy;
 				}
 				"openchannel" => {
 					let peer_pubkey = words.next();
 					let channel_value_sat = words.next();
 					if peer_pubkey.is_none() || channel_value_sat.is_none() {
-						println!("\rERROR: openchannel has 2 required arguments: `openchannel peer_pubkey channel_amt_satoshis [--pixel <luma>:<chroma>] [--public] [--with-anchors] [--min-inbound-htlc]`");
+						println!("\rERROR: openchannel has 2 required arguments: `openchannel peer_pubkey[@host:port] channel_amt_satoshis [--pixel <luma>:<chroma>] [--public] [--with-anchors] [--push-msat <n>] [--push-yuv <luma>]`");
 						continue;
 					}
 
-					let pubkey = match hex_utils::to_compressed_pubkey(peer_pubkey.unwrap()) {
-						Some(pubkey) => pubkey,
-						None => {
-							println!("\rError: invalid peer pubkey");
-							continue;
+					// With a full connection string the peer needn't be known yet.
+					let peer_pubkey = peer_pubkey.unwrap();
+					let (pubkey, peer_addr) = if peer_pubkey.contains('@') {
+						match parse_peer_info(peer_pubkey.to_string()) {
+							Ok((pubkey, peer_addr)) => (pubkey, Some(peer_addr)),
+							Err(e) => {
+								println!("\r{:?}", e.into_inner().unwrap());
+								continue;
+							}
+						}
+					} else {
+						match hex_utils::to_compressed_pubkey(peer_pubkey) {
+							Some(pubkey) => (pubkey, None),
+							None => {
+								println!("\rError: invalid peer pubkey");
+								continue;
+							}
 						}
 					};
 
@@ -286,6 +554,7 @@ pub(crate) fn poll_for_user_input(
 
 					let (mut announce_channel, mut with_anchors) = (false, false);
 					let mut yuv_pixel = None;
+					let (mut push_msat, mut push_yuv_luma) = (0, None);
 					while let Some(word) = words.next() {
 						match word {
 							"--pixel" => {
@@ -306,6 +575,18 @@ pub(crate) fn poll_for_user_input(
 							"--public=false" => announce_channel = false,
 							"--with-anchors" | "--with-anchors=true" => with_anchors = true,
 							"--with-anchors=false" => with_anchors = false,
+							"--push-msat" => {
+								push_msat = match parse_named_param(&mut words, word) {
+									Some(push_msat) => push_msat,
+									None => continue 'outer,
+								};
+							}
+							"--push-yuv" => {
+								push_yuv_luma = match parse_named_param(&mut words, word) {
+									Some(push_yuv_luma) => Some(push_yuv_luma),
+									None => continue 'outer,
+								};
+							}
 							_ => {
 								println!("\rERROR: unknown parameter: {word}");
 								continue 'outer;
@@ -322,9 +603,10 @@ pub(crate) fn poll_for_user_input(
 					let peer_data_path = Path::new(peer_data_path_str.as_str());
 					let peers_data = read_channel_peer_data(peer_data_path).unwrap();
 
-					let peer_addr = match peers_data.get(&pubkey) {
-						Some(peer_addr) => peer_addr,
-						None => {
+					let peer_addrs = match (&peer_addr, peers_data.get(&pubkey)) {
+						(Some(peer_addr), _) => vec![peer_addr.clone()],
+						(None, Some(peer_addrs)) => peer_addrs.clone(),
+						(None, None) => {
 							println!("[ERROR]: Uknown peer: {}", pubkey.to_string());
 							println!("List of known:");
 							list_peers(ldk_data_dir.clone());
@@ -333,47 +615,130 @@ pub(crate) fn poll_for_user_input(
 					};
 
 					if let Err(_) = tokio::runtime::Handle::current().block_on(
-						connect_peer_if_necessary(pubkey, *peer_addr, peer_manager.clone()),
+						connect_peer_if_necessary(pubkey, &peer_addrs, peer_manager.clone(), proxy),
 					) {
 						continue;
 					}
+					if let Some(peer_addr) = peer_addr {
+						if let Err(e) = disk::persist_channel_peer(
+							peer_data_path,
+							&pubkey,
+							&peer_addr.to_string(),
+						) {
+							println!("\rERROR: failed to persist peer data: {}", e);
+						}
+					}
+
+					if let Some(pixel) = &yuv_pixel {
+						if let Err(e) = tokio::runtime::Handle::current().block_on(
+							check_yuv_funding_balance(&wallet, &channel_manager, pixel, network),
+						) {
+							println!("\rERROR: failed to open channel: {}", e);
+							continue;
+						}
+					}
 
 					let _ = open_channel(
 						pubkey,
 						chan_amt_sat.unwrap(),
+						push_msat,
+						push_yuv_luma,
 						config,
 						channel_manager.clone(),
 						yuv_pixel,
 					);
 				}
+				"batchopenchannel" => {
+					let channels = match words.next().map(parse_batch_channels) {
+						Some(Ok(channels)) => channels,
+						Some(Err(e)) => {
+							println!("\rERROR: {}", e);
+							continue;
+						}
+						None => {
+							println!("\rERROR: batchopenchannel requires the channels to open: `batchopenchannel <pubkey@host:port,amt_satoshis;...>`");
+							continue;
+						}
+					};
+					let config = default_config.lock().unwrap().clone();
+					batch_open_channels(
+						channels,
+						config,
+						&peer_manager,
+						&channel_manager,
+						&ldk_data_dir,
+						proxy,
+					);
+				}
 				"sendpayment" => {
 					let invoice_str = words.next();
 					if invoice_str.is_none() {
 						println!(
-							"\rERROR: sendpayment requires an invoice: `sendpayment <invoice>`"
+							"\rERROR: sendpayment requires an invoice: `sendpayment <invoice> [amount] [--yuv-amount <n>] [--max-fee-msat <n>] [--retry <count> | --retry-timeout-secs <n>]`"
 						);
 						continue;
 					}
 
 					let mut user_provided_amt: Option<u64> = None;
-					if let Some(amt_msat_str) = words.next() {
-						match amt_msat_str.parse() {
-							Ok(amt) => user_provided_amt = Some(amt),
-							Err(e) => {
-								println!("ERROR: couldn't parse amount_msat: {}", e);
-								continue;
+					let mut user_provided_yuv_amt: Option<u128> = None;
+					let mut max_fee_msat: Option<u64> = None;
+					let mut retry = None;
+					while let Some(word) = words.next() {
+						match word {
+							"--retry" => {
+								retry = match parse_named_param(&mut words, word) {
+									Some(attempts) => Some(Retry::Attempts(attempts)),
+									None => continue 'outer,
+								};
 							}
-						};
+							"--retry-timeout-secs" => {
+								retry = match parse_named_param(&mut words, word) {
+									Some(secs) => Some(Retry::Timeout(Duration::from_secs(secs))),
+									None => continue 'outer,
+								};
+							}
+							"--yuv-amount" => {
+								user_provided_yuv_amt = match parse_named_param(&mut words, word) {
+									Some(yuv_amount) => Some(yuv_amount),
+									None => continue 'outer,
+								};
+							}
+							"--max-fee-msat" => {
+								max_fee_msat = match parse_named_param(&mut words, word) {
+									Some(max_fee_msat) => Some(max_fee_msat),
+									None => continue 'outer,
+								};
+							}
+							amt_str if user_provided_amt.is_none() => {
+								match parse_amount_msat(amt_str) {
+									Ok(amt) => user_provided_amt = Some(amt),
+									Err(e) => {
+										println!("\rERROR: couldn't parse amount: {}", e);
+										continue 'outer;
+									}
+								};
+							}
+							_ => {
+								println!("\rERROR: unknown parameter: {word}");
+								continue 'outer;
+							}
+						}
 					}
 
 					match Bolt11Invoice::from_str(invoice_str.unwrap()) {
-						Ok(invoice) => send_payment(
-							&channel_manager,
-							&invoice,
-							user_provided_amt,
-							&mut outbound_payments.lock().unwrap(),
-							Arc::clone(&fs_store),
-						),
+						Ok(invoice) => {
+							let _ = send_payment(
+								&channel_manager,
+								&invoice,
+								user_provided_amt,
+								user_provided_yuv_amt,
+								max_fee_msat,
+								retry,
+								&payment_policy.lock().unwrap(),
+								&mut outbound_payments.lock().unwrap(),
+								Arc::clone(&fs_store),
+							);
+						}
 						Err(e) => {
 							println!("\rERROR: invalid invoice: {:?}", e);
 						}
@@ -389,14 +754,14 @@ pub(crate) fn poll_for_user_input(
 							}
 						},
 						None => {
-							println!("\rERROR: keysend requires a destination pubkey: `keysend <dest_pubkey> <amt_msat>`");
+							println!("\rERROR: keysend requires a destination pubkey: `keysend <dest_pubkey> <amt_msat> [--pixel <luma>:<chroma>] [--retry <count> | --retry-timeout-secs <n>]`");
 							continue;
 						}
 					};
 					let amt_msat_str = match words.next() {
 						Some(amt) => amt,
 						None => {
-							println!("\rERROR: keysend requires an amount in millisatoshis: `keysend <dest_pubkey> <amt_msat>`");
+							println!("\rERROR: keysend requires an amount in millisatoshis: `keysend <dest_pubkey> <amt_msat> [--pixel <luma>:<chroma>] [--retry <count> | --retry-timeout-secs <n>]`");
 							continue;
 						}
 					};
@@ -407,39 +772,109 @@ pub(crate) fn poll_for_user_input(
 							continue;
 						}
 					};
+
+					let mut yuv_pixel = None;
+					let mut retry = None;
+					while let Some(word) = words.next() {
+						match word {
+							"--retry" => {
+								retry = match parse_named_param(&mut words, word) {
+									Some(attempts) => Some(Retry::Attempts(attempts)),
+									None => continue 'outer,
+								};
+							}
+							"--retry-timeout-secs" => {
+								retry = match parse_named_param(&mut words, word) {
+									Some(secs) => Some(Retry::Timeout(Duration::from_secs(secs))),
+									None => continue 'outer,
+								};
+							}
+							"--pixel" => {
+								let pixel_word = words.next().unwrap_or_default();
+
+								yuv_pixel = match parse_pixel_word(pixel_word) {
+									Ok(pixel) => Some(pixel),
+									Err(err) => {
+										println!(
+											"\rERROR: invalid `--pixel` param: {}",
+											err.to_string()
+										);
+										continue 'outer;
+									}
+								}
+							}
+							_ => {
+								println!("\rERROR: unknown parameter: {word}");
+								continue 'outer;
+							}
+						}
+					}
+
 					keysend(
 						&channel_manager,
 						dest_pubkey,
 						amt_msat,
+						yuv_pixel,
+						retry,
+						&payment_policy.lock().unwrap(),
 						&*keys_manager,
 						&mut outbound_payments.lock().unwrap(),
 						Arc::clone(&fs_store),
 					);
 				}
-				"getinvoice" => {
-					let amt_str = words.next();
-					if amt_str.is_none() {
-						println!("\rERROR: getinvoice requires an amount in millisatoshis");
-						continue;
-					}
-
-					let amt_msat: Result<u64, _> = amt_str.unwrap().parse();
-					if amt_msat.is_err() {
-						println!("\rERROR: getinvoice provided payment amount was not a number");
-						continue;
-					}
-
-					let expiry_secs_str = words.next();
-					if expiry_secs_str.is_none() {
-						println!("\rERROR: getinvoice requires an expiry in seconds");
-						continue;
-					}
+				"sendprobe" => {
+					let dest_pubkey = match words.next() {
+						Some(dest) => match hex_utils::to_compressed_pubkey(dest) {
+							Some(pk) => pk,
+							None => {
+								println!("\rERROR: couldn't parse destination pubkey");
+								continue;
+							}
+						},
+						None => {
+							println!("\rERROR: sendprobe requires a destination pubkey: `sendprobe <dest_pubkey> <amt_msat>`");
+							continue;
+						}
+					};
+					let amt_msat: u64 = match words.next().map(|amt| amt.parse()) {
+						Some(Ok(amt)) => amt,
+						Some(Err(e)) => {
+							println!("\rERROR: couldn't parse amount_msat: {}", e);
+							continue;
+						}
+						None => {
+							println!("\rERROR: sendprobe requires an amount in millisatoshis: `sendprobe <dest_pubkey> <amt_msat>`");
+							continue;
+						}
+					};
 
-					let expiry_secs: Result<u32, _> = expiry_secs_str.unwrap().parse();
-					if expiry_secs.is_err() {
-						println!("\rERROR: getinvoice provided expiry was not a number");
-						continue;
-					}
+					send_probe(&channel_manager, dest_pubkey, amt_msat);
+				}
+				"getrouteinfo" => {
+					let dest_pubkey = match words.next() {
+						Some(dest) => match hex_utils::to_compressed_pubkey(dest) {
+							Some(pk) => pk,
+							None => {
+								println!("\rERROR: couldn't parse destination pubkey");
+								continue;
+							}
+						},
+						None => {
+							println!("\rERROR: getrouteinfo requires a destination pubkey: `getrouteinfo <dest_pubkey> <amt_msat> [--pixel <luma>:<chroma>]`");
+							continue;
+						}
+					};
+					let amt_msat: u64 = match words.next().map(|amt| amt.parse()) {
+						Some(Ok(amt)) => amt,
+						Some(Err(e)) => {
+							println!("\rERROR: couldn't parse amount_msat: {}", e);
+							continue;
+						}
+						None => {
+							println!("\rERROR: getrouteinfo requires an amount in millisatoshis: `getrouteinfo <dest_pubkey> <amt_msat> [--pixel <luma>:<chroma>]`");
+							continue;
+						}
+					};
 
 					let mut yuv_pixel = None;
 					while let Some(word) = words.next() {
@@ -465,51 +900,176 @@ pub(crate) fn poll_for_user_input(
 						}
 					}
 
-					let mut inbound_payments = inbound_payments.lock().unwrap();
-					get_invoice(
-						amt_msat.unwrap(),
-						&mut inbound_payments,
-						&channel_manager,
-						Arc::clone(&keys_manager),
-						network,
-						expiry_secs.unwrap(),
-						yuv_pixel,
-						Arc::clone(&logger),
-					);
-					fs_store
-						.write("", "", INBOUND_PAYMENTS_FNAME, &inbound_payments.encode())
-						.unwrap();
+					get_route_info(&channel_manager, &router, dest_pubkey, amt_msat, yuv_pixel);
 				}
-				"connectpeer" => {
-					let peer_pubkey_and_ip_addr = words.next();
-					if peer_pubkey_and_ip_addr.is_none() {
-						println!("\rERROR: connectpeer requires peer connection info: `connectpeer pubkey@host:port`");
-						continue;
+				"createoffer" => {
+					let amt_msat = match words.next().map(parse_amount_msat) {
+						Some(Ok(amt_msat)) => amt_msat,
+						Some(Err(e)) => {
+							println!("\rERROR: createoffer provided invalid payment amount: {}", e);
+							continue;
+						}
+						None => {
+							println!("\rERROR: createoffer requires an amount, e.g. `1000000`, `1000sat`, or `0` for any amount");
+							continue;
+						}
+					};
+					match words.next() {
+						None => {}
+						// Unlike BOLT11 invoices, offers and the invoices requested for them have
+						// no field to carry a pixel in.
+						Some("--pixel") => {
+							println!("\rERROR: offers can't carry YUV pixels, use `getinvoice --pixel` for token payments");
+							continue;
+						}
+						Some(word) => {
+							println!("\rERROR: unknown parameter: {word}");
+							continue;
+						}
 					}
-					let (pubkey, peer_addr) =
-						match parse_peer_info(peer_pubkey_and_ip_addr.unwrap().to_string()) {
-							Ok(info) => info,
-							Err(e) => {
-								println!("\r{:?}", e.into_inner().unwrap());
-								continue;
+
+					match create_offer(amt_msat, &channel_manager) {
+						Ok(offer) => println!("\rSUCCESS: created offer: {}", offer),
+						Err(e) => println!("\rERROR: failed to create offer: {:?}", e),
+					}
+				}
+				"payoffer" => {
+					let offer =
+						match words.next().map(Offer::from_str) {
+							Some(Ok(offer)) => offer,
+							Some(Err(e)) => {
+								println!("\rERROR: invalid offer: {:?}", e);
+								continue;
+							}
+							None => {
+								println!("\rERROR: payoffer requires an offer: `payoffer <offer> [amount]`");
+								continue;
+							}
+						};
+					let amt_msat = match words.next().map(parse_amount_msat) {
+						Some(Ok(amt_msat)) => Some(amt_msat),
+						Some(Err(e)) => {
+							println!("\rERROR: payoffer provided invalid payment amount: {}", e);
+							continue;
+						}
+						None => None,
+					};
+
+					pay_offer(
+						&offer,
+						amt_msat,
+						&payment_policy.lock().unwrap(),
+						&channel_manager,
+						&*keys_manager,
+					);
+				}
+				"getinvoice" => {
+					let amt_str = words.next();
+					if amt_str.is_none() {
+						println!("\rERROR: getinvoice requires an amount, e.g. `1000000`, `1000sat`, or `0.00001btc`");
+						continue;
+					}
+
+					let amt_msat = match parse_amount_msat(amt_str.unwrap()) {
+						Ok(amt_msat) => amt_msat,
+						Err(e) => {
+							println!("\rERROR: getinvoice provided invalid payment amount: {}", e);
+							continue;
+						}
+					};
+
+					let expiry_secs_str = words.next();
+					if expiry_secs_str.is_none() {
+						println!("\rERROR: getinvoice requires an expiry in seconds");
+						continue;
+					}
+
+					let expiry_secs: Result<u32, _> = expiry_secs_str.unwrap().parse();
+					if expiry_secs.is_err() {
+						println!("\rERROR: getinvoice provided expiry was not a number");
+						continue;
+					}
+
+					let mut yuv_pixel = None;
+					while let Some(word) = words.next() {
+						match word {
+							"--pixel" => {
+								let pixel_word = words.next().unwrap_or_default();
+
+								yuv_pixel = match parse_pixel_word(pixel_word) {
+									Ok(pixel) => Some(pixel),
+									Err(err) => {
+										println!(
+											"\rERROR: invalid `--pixel` param: {}",
+											err.to_string()
+										);
+										continue 'outer;
+									}
+								}
+							}
+							_ => {
+								println!("\rERROR: unknown parameter: {word}");
+								continue 'outer;
+							}
+						}
+					}
+
+					let mut inbound_payments = inbound_payments.lock().unwrap();
+					let _ = get_invoice(
+						amt_msat,
+						&mut inbound_payments,
+						&channel_manager,
+						Arc::clone(&keys_manager),
+						network,
+						expiry_secs.unwrap(),
+						yuv_pixel,
+						Arc::clone(&logger),
+					);
+					if let Err(e) = disk::persist_payment_info(
+						&fs_store,
+						INBOUND_PAYMENTS_FNAME,
+						&inbound_payments,
+					) {
+						println!("\rERROR: failed to persist inbound payments: {}", e);
+					}
+				}
+				"connectpeer" => {
+					let peer_pubkey_and_ip_addr = words.next();
+					if peer_pubkey_and_ip_addr.is_none() {
+						println!("\rERROR: connectpeer requires peer connection info: `connectpeer pubkey@host:port`");
+						continue;
+					}
+					let (pubkey, peer_addr) =
+						match parse_peer_info(peer_pubkey_and_ip_addr.unwrap().to_string()) {
+							Ok(info) => info,
+							Err(e) => {
+								println!("\r{:?}", e.into_inner().unwrap());
+								continue;
 							}
 						};
 					if tokio::runtime::Handle::current()
 						.block_on(connect_peer_if_necessary(
 							pubkey,
-							peer_addr,
+							&[peer_addr.clone()],
 							peer_manager.clone(),
+							proxy,
 						))
-						.is_ok()
+						.is_err()
 					{
-						println!("\rSUCCESS: connected to peer {}", pubkey);
+						// Unreachable peers aren't persisted, as they would otherwise be retried
+						// by the reconnection loop forever.
+						continue;
 					}
+					println!("\rSUCCESS: connected to peer {}", pubkey);
 
 					let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir.clone());
-					let _ = disk::persist_channel_peer(
+					if let Err(e) = disk::persist_channel_peer(
 						Path::new(&peer_data_path),
-						peer_pubkey_and_ip_addr.unwrap(),
-					);
+						&pubkey,
+						&peer_addr.to_string(),
+					) {
+						println!("\rERROR: failed to persist peer data: {}", e);
+					}
 				}
 				"disconnectpeer" => {
 					let peer_pubkey = words.next();
@@ -537,15 +1097,263 @@ pub(crate) fn poll_for_user_input(
 						println!("\rSUCCESS: disconnected from peer {}", peer_pubkey);
 					}
 				}
-				"listchannels" => list_channels(&channel_manager, &network_graph),
-				"listpayments" => list_payments(
-					&inbound_payments.lock().unwrap(),
-					&outbound_payments.lock().unwrap(),
-				),
+				"disconnectallpeers" => {
+					let num_peers = peer_manager.list_peers().len();
+					peer_manager.disconnect_all_peers();
+					println!("\rSUCCESS: disconnected from {} peers, channel peers will be reconnected to shortly", num_peers);
+				}
+				"reconnectpeers" => {
+					match reconnect_channel_peers(
+						&channel_manager,
+						&peer_manager,
+						&ldk_data_dir,
+						proxy,
+					) {
+						Ok((connected, total)) => {
+							println!("\rReconnected to {} of {} channel peers.", connected, total)
+						}
+						Err(e) => println!("\rERROR: failed to read channel peer data: {}", e),
+					}
+				}
+				"listchannels" => match words.next() {
+					None => list_channels(&channel_manager, &network_graph),
+					Some("--json") => {
+						print_json(&list_channels_json(&channel_manager, &network_graph, network))
+					}
+					Some(word) => println!("\rERROR: unknown parameter: {word}"),
+				},
+				"getchannel" => {
+					let channel_id_str = words.next();
+					if channel_id_str.is_none() {
+						println!(
+							"\rERROR: getchannel requires a channel ID: `getchannel <channel_id>`"
+						);
+						continue;
+					}
+					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
+					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
+						println!("\rERROR: couldn't parse channel_id");
+						continue;
+					}
+					let mut channel_id = [0; 32];
+					channel_id.copy_from_slice(&channel_id_vec.unwrap());
+
+					get_channel(channel_id, &channel_manager, &network_graph);
+				}
+				"setchannelfee" => {
+					let channel_id_str = words.next();
+					if channel_id_str.is_none() {
+						println!("\rERROR: setchannelfee requires a channel ID: `setchannelfee <channel_id> --base-msat <n> --ppm <n> [--cltv-delta <n>]`");
+						continue;
+					}
+					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
+					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
+						println!("\rERROR: couldn't parse channel_id");
+						continue;
+					}
+					let mut channel_id = [0; 32];
+					channel_id.copy_from_slice(&channel_id_vec.unwrap());
+
+					let mut fee_base_msat: Option<u32> = None;
+					let mut fee_ppm: Option<u32> = None;
+					let mut cltv_expiry_delta: Option<u16> = None;
+					while let Some(word) = words.next() {
+						match word {
+							"--base-msat" => {
+								fee_base_msat = match parse_named_param(&mut words, word) {
+									Some(fee_base_msat) => Some(fee_base_msat),
+									None => continue 'outer,
+								};
+							}
+							"--ppm" => {
+								fee_ppm = match parse_named_param(&mut words, word) {
+									Some(fee_ppm) => Some(fee_ppm),
+									None => continue 'outer,
+								};
+							}
+							"--cltv-delta" => {
+								cltv_expiry_delta = match parse_named_param(&mut words, word) {
+									Some(cltv_expiry_delta) => Some(cltv_expiry_delta),
+									None => continue 'outer,
+								};
+							}
+							_ => {
+								println!("\rERROR: unknown parameter: {word}");
+								continue 'outer;
+							}
+						}
+					}
+					let (Some(fee_base_msat), Some(fee_ppm)) = (fee_base_msat, fee_ppm) else {
+						println!("\rERROR: setchannelfee requires both --base-msat and --ppm");
+						continue;
+					};
+					if let Some(Err(e)) = cltv_expiry_delta.map(validate_cltv_expiry_delta) {
+						println!("\rERROR: {}", e);
+						continue;
+					}
+
+					let update = ChannelConfigUpdate {
+						forwarding_fee_base_msat: Some(fee_base_msat),
+						forwarding_fee_proportional_millionths: Some(fee_ppm),
+						cltv_expiry_delta,
+						..Default::default()
+					};
+					set_channel_fee(channel_id, &channel_manager, &update);
+				}
+				"listinterceptedhtlcs" => {
+					list_intercepted_htlcs(&intercepted_htlcs.lock().unwrap())
+				}
+				"interceptscid" => {
+					if !channel_manager.get_current_default_configuration().accept_intercept_htlcs {
+						println!("\rERROR: HTLCs are only intercepted with --intercept-htlcs true");
+						continue;
+					}
+					println!(
+						"\rSUCCESS: HTLCs sent to SCID {} will be intercepted",
+						channel_manager.get_intercept_scid()
+					);
+				}
+				"forwardhtlc" => {
+					let intercept_id = match words.next().and_then(hex_utils::to_vec) {
+						Some(id) if id.len() == 32 => InterceptId(id.try_into().unwrap()),
+						_ => {
+							println!("\rERROR: forwardhtlc requires an intercept ID: `forwardhtlc <intercept_id> <channel_id>`");
+							continue;
+						}
+					};
+					let channel_id_vec = words.next().and_then(hex_utils::to_vec);
+					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
+						println!("\rERROR: forwardhtlc requires a channel ID: `forwardhtlc <intercept_id> <channel_id>`");
+						continue;
+					}
+					let mut channel_id = [0; 32];
+					channel_id.copy_from_slice(&channel_id_vec.unwrap());
+
+					forward_intercepted_htlc(
+						intercept_id,
+						channel_id,
+						&channel_manager,
+						&intercepted_htlcs,
+					);
+				}
+				"failhtlc" => {
+					let intercept_id = match words.next().and_then(hex_utils::to_vec) {
+						Some(id) if id.len() == 32 => InterceptId(id.try_into().unwrap()),
+						_ => {
+							println!("\rERROR: failhtlc requires an intercept ID: `failhtlc <intercept_id>`");
+							continue;
+						}
+					};
+
+					match channel_manager.fail_intercepted_htlc(intercept_id) {
+						Ok(()) => {
+							intercepted_htlcs.lock().unwrap().remove(&intercept_id);
+							println!("\rSUCCESS: failed intercepted HTLC back");
+						}
+						Err(e) => println!("\rERROR: failed to fail the intercepted HTLC: {:?}", e),
+					}
+				}
+				"updatechannelconfig" => {
+					let channel_id_str = words.next();
+					if channel_id_str.is_none() {
+						println!("\rERROR: updatechannelconfig requires a channel ID: `updatechannelconfig <channel_id>`");
+						continue;
+					}
+					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
+					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
+						println!("\rERROR: couldn't parse channel_id");
+						continue;
+					}
+					let mut channel_id = [0; 32];
+					channel_id.copy_from_slice(&channel_id_vec.unwrap());
+					match words.next() {
+						Some(word @ ("--min-inb-htlc" | "--max-inb-htlc-pct")) => {
+							println!("\rERROR: {word} is negotiated when a channel is opened, so it can't be updated on an open channel. Set it with `configchannel` for new channels instead");
+							continue;
+						}
+						Some(word) => {
+							println!("\rERROR: unknown parameter: {word}");
+							continue;
+						}
+						None => {}
+					}
+
+					let config = default_config.lock().unwrap().clone();
+					update_channel_config(channel_id, &channel_manager, &config);
+				}
+				"channelbalances" => channel_balances(&channel_manager, network),
+				"listpayments" => {
+					let inbound_payments = inbound_payments.lock().unwrap();
+					let outbound_payments = outbound_payments.lock().unwrap();
+					match words.next() {
+						None => list_payments(&inbound_payments, &outbound_payments, network),
+						Some("--json") => print_json(&list_payments_json(
+							&inbound_payments,
+							&outbound_payments,
+							network,
+						)),
+						Some(word) => println!("\rERROR: unknown parameter: {word}"),
+					}
+				}
+				"abandonpayment" => {
+					let payment_hash = match words.next().and_then(hex_utils::to_vec) {
+						Some(hash) if hash.len() == 32 => {
+							let mut payment_hash = [0; 32];
+							payment_hash.copy_from_slice(&hash);
+							PaymentHash(payment_hash)
+						}
+						_ => {
+							println!("\rERROR: abandonpayment requires a payment hash: `abandonpayment <payment_hash>`");
+							continue;
+						}
+					};
+					abandon_payment(
+						payment_hash,
+						&channel_manager,
+						&mut outbound_payments.lock().unwrap(),
+						&fs_store,
+					);
+				}
+				"waitchannel" => {
+					let channel_id_str = words.next();
+					if channel_id_str.is_none() {
+						println!("\rERROR: waitchannel requires a channel ID: `waitchannel <channel_id> [--timeout-secs <secs>]`");
+						continue;
+					}
+					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
+					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
+						println!("\rERROR: couldn't parse channel_id");
+						continue;
+					}
+					let mut channel_id = [0; 32];
+					channel_id.copy_from_slice(&channel_id_vec.unwrap());
+
+					let mut timeout_secs = DEFAULT_WAIT_CHANNEL_TIMEOUT_SECS;
+					while let Some(word) = words.next() {
+						match word {
+							"--timeout-secs" => {
+								timeout_secs = match parse_named_param(&mut words, word) {
+									Some(timeout_secs) => timeout_secs,
+									None => continue 'outer,
+								};
+							}
+							_ => {
+								println!("\rERROR: unknown parameter: {word}");
+								continue 'outer;
+							}
+						}
+					}
+
+					wait_channel(
+						ChannelId(channel_id),
+						Duration::from_secs(timeout_secs),
+						&channel_manager,
+					);
+				}
 				"closechannel" => {
 					let channel_id_str = words.next();
 					if channel_id_str.is_none() {
-						println!("\rERROR: closechannel requires a channel ID: `closechannel <channel_id> <peer_pubkey>`");
+						println!("\rERROR: closechannel requires a channel ID: `closechannel <channel_id> <peer_pubkey> [--feerate <sat_per_vb>]`");
 						continue;
 					}
 					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
@@ -558,7 +1366,7 @@ pub(crate) fn poll_for_user_input(
 
 					let peer_pubkey_str = words.next();
 					if peer_pubkey_str.is_none() {
-						println!("\rERROR: closechannel requires a peer pubkey: `closechannel <channel_id> <peer_pubkey>`");
+						println!("\rERROR: closechannel requires a peer pubkey: `closechannel <channel_id> <peer_pubkey> [--feerate <sat_per_vb>]`");
 						continue;
 					}
 					let peer_pubkey_vec = match hex_utils::to_vec(peer_pubkey_str.unwrap()) {
@@ -576,12 +1384,33 @@ pub(crate) fn poll_for_user_input(
 						}
 					};
 
-					close_channel(channel_id, peer_pubkey, channel_manager.clone());
+					let mut feerate_sat_per_vb: Option<u32> = None;
+					while let Some(word) = words.next() {
+						match word {
+							"--feerate" => {
+								feerate_sat_per_vb = match parse_named_param(&mut words, word) {
+									Some(feerate) => Some(feerate),
+									None => continue 'outer,
+								};
+							}
+							_ => {
+								println!("\rERROR: unknown parameter: {word}");
+								continue 'outer;
+							}
+						}
+					}
+
+					let _ = close_channel(
+						channel_id,
+						peer_pubkey,
+						feerate_sat_per_vb,
+						channel_manager.clone(),
+					);
 				}
 				"forceclosechannel" => {
 					let channel_id_str = words.next();
 					if channel_id_str.is_none() {
-						println!("\rERROR: forceclosechannel requires a channel ID: `forceclosechannel <channel_id> <peer_pubkey>`");
+						println!("\rERROR: forceclosechannel requires a channel ID: `forceclosechannel <channel_id> <peer_pubkey> [--yes]`");
 						continue;
 					}
 					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
@@ -594,7 +1423,7 @@ pub(crate) fn poll_for_user_input(
 
 					let peer_pubkey_str = words.next();
 					if peer_pubkey_str.is_none() {
-						println!("\rERROR: forceclosechannel requires a peer pubkey: `forceclosechannel <channel_id> <peer_pubkey>`");
+						println!("\rERROR: forceclosechannel requires a peer pubkey: `forceclosechannel <channel_id> <peer_pubkey> [--yes]`");
 						continue;
 					}
 					let peer_pubkey_vec = match hex_utils::to_vec(peer_pubkey_str.unwrap()) {
@@ -612,10 +1441,74 @@ pub(crate) fn poll_for_user_input(
 						}
 					};
 
-					force_close_channel(channel_id, peer_pubkey, channel_manager.clone());
-				}
-				"nodeinfo" => node_info(&channel_manager, &peer_manager),
-				"listpeers" => list_peers(ldk_data_dir.clone()),
+					let mut confirmed = false;
+					while let Some(word) = words.next() {
+						match word {
+							"--yes" => confirmed = true,
+							_ => {
+								println!("\rERROR: unknown parameter: {word}");
+								continue 'outer;
+							}
+						}
+					}
+					// The latest commitment transaction is broadcast right away, and our funds are
+					// then locked for the channel's CSV delay, so a typo is costly.
+					if !confirmed
+						&& !confirm(&format!(
+							"Force-close channel {}? (y/N) ",
+							ChannelId(channel_id)
+						)) {
+						println!("\rForce-close aborted");
+						continue;
+					}
+
+					force_close_channel(channel_id, peer_pubkey, channel_manager.clone());
+				}
+				"getinfo" => {
+					let support_yuv_payments = default_config.lock().unwrap().support_yuv_payments;
+					print_json(&get_info_json(
+						&channel_manager,
+						&peer_manager,
+						network,
+						support_yuv_payments,
+						yuv_enabled,
+					));
+				}
+				"nodeinfo" => match words.next() {
+					None => node_info(&channel_manager, &peer_manager),
+					Some("--json") => print_json(&node_info_json(&channel_manager, &peer_manager)),
+					Some(word) => println!("\rERROR: unknown parameter: {word}"),
+				},
+				"listpeers" => list_peers(ldk_data_dir.clone()),
+				"feereport" => fee_report(&forwards.lock().unwrap(), network),
+				"listclosures" => list_closures(&closures.lock().unwrap()),
+				"listfailedsweeps" => list_failed_sweeps(&failed_sweeps.lock().unwrap()),
+				"listpeeraccess" => list_peer_access(&peer_access_lists.lock().unwrap()),
+				"retryfailedsweeps" => {
+					let mut failed_sweeps = failed_sweeps.lock().unwrap();
+					match sweep::retry_failed_sweeps(&ldk_data_dir, &fs_store, &mut failed_sweeps) {
+						Ok(0) => println!("\rNo set aside outputs to sweep again"),
+						Ok(num_sets) => {
+							println!("\rSUCCESS: {} sets of outputs will be swept again", num_sets)
+						}
+						Err(e) => println!("\rERROR: failed to retry failed sweeps: {}", e),
+					}
+				}
+				"exportchannelbackup" => {
+					let Some(path) = words.next() else {
+						println!("\rERROR: exportchannelbackup requires a path: `exportchannelbackup <path>`");
+						continue;
+					};
+
+					let backup = channel_backup(&channel_manager, &chain_monitor);
+					match disk::write_channel_backup(Path::new(path), &backup) {
+						Ok(()) => {
+							println!("\rSUCCESS: exported the channel manager and {} channel monitors to {}", backup.channel_monitors.len(), path);
+							println!("\rWARNING: the backup is only safe to restore until any of its channels is next updated. Restoring stale channel state lets the counterparty claim ALL of the channel's funds with a penalty transaction.");
+						}
+						Err(e) => println!("\rERROR: failed to write the channel backup: {}", e),
+					}
+				}
 				"signmessage" => {
 					const MSG_STARTPOS: usize = "signmessage".len() + 1;
 					if line.trim().as_bytes().len() <= MSG_STARTPOS {
@@ -630,6 +1523,63 @@ pub(crate) fn poll_for_user_input(
 						)
 					);
 				}
+				"setalias" => {
+					const ALIAS_STARTPOS: usize = "setalias".len() + 1;
+					let alias = line.trim().get(ALIAS_STARTPOS..).unwrap_or_default().trim();
+					if alias.is_empty() {
+						println!("\rERROR: setalias requires a name: `setalias <name>`");
+						continue;
+					}
+					let Ok(alias) = args::parse_node_name(alias) else {
+						continue;
+					};
+
+					*node_alias.lock().unwrap() = alias;
+					println!("\rSUCCESS: alias updated, it will be announced with the next node announcement");
+				}
+				"addannouncedaddress" | "removeannouncedaddress" => {
+					let addr = match words.next().map(SocketAddress::from_str) {
+						Some(Ok(addr)) => addr,
+						Some(Err(_)) => {
+							println!("\rERROR: invalid address, expected <host:port>");
+							continue;
+						}
+						None => {
+							println!(
+								"\rERROR: {} requires an address: `{} <host:port>`",
+								word, word
+							);
+							continue;
+						}
+					};
+
+					let mut announced_addrs = announced_addrs.lock().unwrap();
+					let res = if word == "addannouncedaddress" {
+						add_announced_address(&mut announced_addrs, addr)
+					} else {
+						remove_announced_address(&mut announced_addrs, &addr)
+					};
+					match res {
+						Ok(()) => println!("\rSUCCESS: announced addresses updated, they will be announced with the next node announcement"),
+						Err(e) => println!("\rERROR: {}", e),
+					}
+				}
+				"verifymessage" => {
+					const ARGS_STARTPOS: usize = "verifymessage".len() + 1;
+					let args = line.trim().get(ARGS_STARTPOS..).unwrap_or_default();
+					let Some((message, signature)) = args.trim().rsplit_once(' ') else {
+						println!("\rERROR: verifymessage requires a message and a signature: `verifymessage <message> <signature>`");
+						continue;
+					};
+
+					match recover_signer(message.trim(), signature) {
+						Ok(node_id) => println!("\r{{\n\r\t node_id: {}\n\r}}", node_id),
+						Err(e) => println!("\rERROR: {}", e),
+					}
+				}
+				"listonionmessages" => {
+					list_onion_messages(&onion_message_handler.received_messages())
+				}
 				"sendonionmessage" => {
 					let path_pks_str = words.next();
 					if path_pks_str.is_none() {
@@ -663,16 +1613,34 @@ pub(crate) fn poll_for_user_input(
 						continue;
 					}
 					let tlv_type = match words.next().map(|ty_str| ty_str.parse()) {
-						Some(Ok(ty)) if ty >= 64 => ty,
+						Some(Ok(ty)) if ty >= MIN_CUSTOM_TLV_TYPE => ty,
 						_ => {
 							println!("\rNeed an integral message type above 64");
 							continue;
 						}
 					};
-					let data = match words.next().map(|s| hex_utils::to_vec(s)) {
-						Some(Some(data)) => data,
-						_ => {
-							println!("\rNeed a hex data string");
+					// Large payloads are easier to pass in a file than typed out as hex.
+					let data = match words.next() {
+						Some("--data-file") => match words.next().map(std::fs::read) {
+							Some(Ok(data)) => data,
+							Some(Err(e)) => {
+								println!("\rERROR: couldn't read the data file: {}", e);
+								continue;
+							}
+							None => {
+								println!("\rERROR: --data-file requires a path");
+								continue;
+							}
+						},
+						Some(hex_str) => match hex_utils::to_vec(hex_str) {
+							Some(data) => data,
+							None => {
+								println!("\rNeed a hex data string or `--data-file <path>`");
+								continue;
+							}
+						},
+						None => {
+							println!("\rNeed a hex data string or `--data-file <path>`");
 							continue;
 						}
 					};
@@ -731,7 +1699,7 @@ pub(crate) fn poll_for_user_input(
 						new_yuv_luma = Some(luma);
 					}
 
-					update_balance(
+					let _ = update_balance(
 						channel_id,
 						peer_pubkey,
 						new_balance_msat,
@@ -739,9 +1707,135 @@ pub(crate) fn poll_for_user_input(
 						channel_manager.clone(),
 					);
 				}
+				"respondupdatebalance" => {
+					let channel_id_str = words.next();
+					if channel_id_str.is_none() {
+						println!("\rERROR: respondupdatebalance requires a channel ID: `respondupdatebalance <channel_id> <accept|reject>`");
+						continue;
+					}
+					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
+					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
+						println!("\rERROR: couldn't parse channel_id");
+						continue;
+					}
+					let mut channel_id = [0; 32];
+					channel_id.copy_from_slice(&channel_id_vec.unwrap());
+
+					let accept = match words.next() {
+						Some("accept") => true,
+						Some("reject") => false,
+						_ => {
+							println!("\rERROR: respondupdatebalance requires a response: `respondupdatebalance <channel_id> <accept|reject>`");
+							continue;
+						}
+					};
+
+					respond_update_balance(
+						ChannelId(channel_id),
+						accept,
+						&update_balance_requests,
+						channel_manager.clone(),
+						&fs_store,
+					);
+				}
 				"listnodes" => {
 					println!("\r{}", &network_graph)
 				}
+				"getnewaddress" => get_new_address(&wallet),
+				"onchainbalance" => onchain_balance(&wallet),
+				"estimatefee" => estimate_fee(&bitcoind_client),
+				"rescan" => rescan(&wallet),
+				"fundwallet" => {
+					let amt_sat = match words.next().map(u64::from_str) {
+						Some(Ok(amt_sat)) if amt_sat > 0 => amt_sat,
+						_ => {
+							println!("\rERROR: fundwallet requires a positive amount in satoshis: `fundwallet <amt_sat>`");
+							continue;
+						}
+					};
+					if network != Network::Regtest {
+						println!("\rERROR: fundwallet is only available on regtest");
+						continue;
+					}
+
+					fund_wallet(amt_sat, &wallet, &bitcoind_client);
+				}
+				"sync" => sync_wallet(&wallet, &channel_manager),
+				"importprivkey" => {
+					let private_key = match words.next().map(PrivateKey::from_str) {
+						Some(Ok(private_key)) => private_key,
+						Some(Err(e)) => {
+							println!("\rERROR: invalid private key: {}", e);
+							continue;
+						}
+						None => {
+							println!("\rERROR: importprivkey requires a WIF private key: `importprivkey <private_key>`");
+							continue;
+						}
+					};
+
+					import_private_key(
+						&wallet,
+						yuv_client.as_deref(),
+						&bitcoind_client,
+						private_key,
+						&mut yuv_transfers,
+					);
+				}
+				"yuvbalance" => yuv_balance(&wallet, network),
+				"yuvstats" => {
+					if let Some(yuv_client) = yuv_client.as_ref() {
+						yuv_stats(yuv_client);
+					}
+				}
+				"listyuvutxos" => list_yuv_utxos(&wallet, network),
+				"yuvtransfer" => {
+					let Some(yuv_client) = yuv_client.as_ref() else {
+						continue;
+					};
+
+					let recipient = match words.next() {
+						Some(recipient) => match hex_utils::to_compressed_pubkey(recipient) {
+							Some(pubkey) => pubkey,
+							None => {
+								println!("\rERROR: couldn't parse recipient pubkey");
+								continue;
+							}
+						},
+						None => {
+							println!("\rERROR: yuvtransfer requires a recipient pubkey: `yuvtransfer <recipient_pubkey> <chroma> <amount>`");
+							continue;
+						}
+					};
+
+					let chroma = match words.next() {
+						Some(chroma) => match Chroma::from_address(chroma) {
+							Ok(chroma) => chroma,
+							Err(e) => {
+								println!("\rERROR: invalid Chroma(P2TR): {}", e);
+								continue;
+							}
+						},
+						None => {
+							println!("\rERROR: yuvtransfer requires a chroma: `yuvtransfer <recipient_pubkey> <chroma> <amount>`");
+							continue;
+						}
+					};
+
+					let amount: u128 = match words.next().map(|amount| amount.parse()) {
+						Some(Ok(amount)) => amount,
+						Some(Err(e)) => {
+							println!("\rERROR: couldn't parse amount: {}", e);
+							continue;
+						}
+						None => {
+							println!("\rERROR: yuvtransfer requires an amount: `yuvtransfer <recipient_pubkey> <chroma> <amount>`");
+							continue;
+						}
+					};
+
+					yuv_transfer(&wallet, yuv_client, &bitcoind_client, recipient, chroma, amount);
+				}
 				"quit" | "exit" => break,
 				_ => println!("\rUnknown command. See \"help\" for available commands."),
 			}
@@ -846,6 +1940,47 @@ pub fn parse_pixel_word(word: &str) -> eyre::Result<Pixel> {
 	bail!("Pixel must be in the form: <luma>:<chroma>")
 }
 
+/// Parses an amount given either as a bare number of millisatoshis or with an explicit `msat`,
+/// `sat`, or `btc` unit, e.g. `1000sat` or `0.00001btc`, into millisatoshis.
+pub(crate) fn parse_amount_msat(amount: &str) -> Result<u64, String> {
+	let amount = amount.to_lowercase();
+	// The number of decimal places each unit has in millisatoshis. `msat` has to be checked
+	// before `sat`, as the latter is a suffix of the former.
+	let (number, decimals) = if let Some(number) = amount.strip_suffix("msat") {
+		(number, 0)
+	} else if let Some(number) = amount.strip_suffix("sat") {
+		(number, 3)
+	} else if let Some(number) = amount.strip_suffix("btc") {
+		(number, 11)
+	} else {
+		(amount.as_str(), 0)
+	};
+
+	let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+	let is_digits = |digits: &str| digits.chars().all(|c| c.is_ascii_digit());
+	if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+		return Err(format!("expected a number of msat, sat, or btc, got `{}`", amount));
+	}
+	if fraction.len() > decimals {
+		return Err(format!("`{}` is more precise than a millisatoshi", amount));
+	}
+
+	let scale = 10u64.pow(decimals as u32);
+	let fraction_scale = 10u64.pow((decimals - fraction.len()) as u32);
+	let whole = whole.parse::<u64>().ok();
+	let fraction = if fraction.is_empty() { Some(0) } else { fraction.parse::<u64>().ok() };
+	whole
+		.and_then(|whole| whole.checked_mul(scale))
+		.zip(fraction.map(|fraction| fraction * fraction_scale))
+		.and_then(|(whole, fraction)| whole.checked_add(fraction))
+		.ok_or_else(|| format!("`{}` is too large", amount))
+}
+
+/// Commands which contain a WIF-encoded private key are never written to the history file.
+fn contains_private_key(command: &str) -> bool {
+	command.split_whitespace().any(|word| PrivateKey::from_str(word).is_ok())
+}
+
 fn pubkey_from_input(words: &mut SplitWhitespace<'_>) -> Option<PublicKey> {
 	let peer_pubkey_str = words.next();
 	if peer_pubkey_str.is_none() {
@@ -881,34 +2016,138 @@ fn help() {
 	println!("\r\t  help\tShows a list of commands.");
 	println!("\r\t  quit\tClose the application.");
 	println!("\r\n\t  Channels:");
-	println!("\r\t      openchannel peer_pubkey channel_amt_satoshis [--pixel <luma>:<chroma>][--public][--with-anchors]");
-	println!("\r\t      closechannel <channel_id> <peer_pubkey>");
-	println!("\r\t      forceclosechannel <channel_id> <peer_pubkey>");
-	println!("\r\t      listchannels");
+	println!("\r\t      openchannel peer_pubkey[@host:port] channel_amt_satoshis [--pixel <luma>:<chroma>][--public][--with-anchors][--push-msat <n>][--push-yuv <luma>]");
+	println!("\r\t      batchopenchannel <pubkey@host:port,amt_satoshis;...>");
+	println!("\r\t      waitchannel <channel_id> [--timeout-secs <secs>]");
+	println!("\r\t      closechannel <channel_id> <peer_pubkey> [--feerate <sat_per_vb>]");
+	println!("\r\t      forceclosechannel <channel_id> <peer_pubkey> [--yes]");
+	println!("\r\t      listclosures");
+	println!("\r\t      exportchannelbackup <path>");
+	println!("\r\t      listchannels [--json]");
+	println!("\r\t      getchannel <channel_id>");
+	println!("\r\t      setchannelfee <channel_id> --base-msat <n> --ppm <n> [--cltv-delta <n>]");
+	println!("\r\t      updatechannelconfig <channel_id>");
+	println!("\r\t      interceptscid");
+	println!("\r\t      forwardhtlc <intercept_id> <channel_id>");
+	println!("\r\t      failhtlc <intercept_id>");
+	println!("\r\t      listinterceptedhtlcs");
+	println!("\r\t      channelbalances");
 	println!("\r\t      configchannel");
-	println!("\r\t          [--min-inb-htlc <min_inbound_htlc_msat>]");
-	println!("\r\t          [--max-inb-htlc-pct <max_inbound_htlc_msat_percent>]");
+	println!("\r\t          [--min-inb-htlc <min_inbound_htlc_msat>] (new channels only)");
+	println!(
+		"\r\t          [--max-inb-htlc-pct <max_inbound_htlc_msat_percent>] (new channels only)"
+	);
+	println!("\r\t          [--fee-base-msat <msat>]");
+	println!("\r\t          [--fee-ppm <proportional_millionths>]");
+	println!("\r\t          [--cltv-delta <blocks>]");
 	println!("\r\t          [--support-yuv <true|false>]");
+	println!(
+		"\r\t          [--auto-update-balance-max-delta <msat>] (luma-preserving updates only)"
+	);
+	println!("\r\t          [--allow-peer <peer_pubkey>]");
+	println!("\r\t          [--deny-peer <peer_pubkey>]");
+	println!("\r\t          [--remove-peer <peer_pubkey>]");
+	println!("\r\t      listpeeraccess");
 	println!("\r\n\t  Peers:");
 	println!("\r\t      connectpeer pubkey@host:port");
 	println!("\r\t      disconnectpeer <peer_pubkey>");
 	println!("\r\t      listpeers");
+	println!("\r\t      disconnectallpeers");
+	println!("\r\t      reconnectpeers");
 	println!("\r\n\t  Payments:");
-	println!("\r\t      keysend <dest_pubkey> <amt_msats>");
-	println!("\r\t      listpayments");
+	println!("\r\t      keysend <dest_pubkey> <amt_msats> [--pixel <luma>:<chroma>] [--retry <count> | --retry-timeout-secs <n>]");
+	println!("\r\t      listpayments [--json]");
+	println!("\r\t      abandonpayment <payment_hash>");
+	println!("\r\t      sendprobe <dest_pubkey> <amt_msat>");
+	println!("\r\t      getrouteinfo <dest_pubkey> <amt_msat> [--pixel <luma>:<chroma>]");
+	println!("\r\t      feereport");
+	println!("\r\t      configpayment");
+	println!("\r\t          [--retry <count> | --retry-timeout-secs <n>]");
+	println!("\r\t          [--max-fee-ppm <ppm|none>]");
+	println!("\r\t          [--max-fee-msat <msat|none>]");
+	println!("\r\t          [--probe <true|false>]");
 	println!("\r\n\t  Invoices:");
-	println!("\r\t      getinvoice <amt_msats> <expiry_secs> [--pixel <luma>:<chroma>]");
-	println!("\r\t      sendpayment <invoice>");
+	println!("\r\t      getinvoice <amount> <expiry_secs> [--pixel <luma>:<chroma>]");
+	println!("\r\t      sendpayment <invoice> [amount] [--yuv-amount <n>] [--max-fee-msat <n>] [--retry <count> | --retry-timeout-secs <n>]");
+	println!("\r\t      createoffer <amount>");
+	println!("\r\t      payoffer <offer> [amount]");
 	println!("\r\n\t  UpdateBalance:");
 	println!(
 		"\r\t      updatebalance <channel_id> <peer_pubkey> [new_balance_msat] [new_yuv_luma]"
 	);
+	println!("\r\t      respondupdatebalance <channel_id> <accept|reject>");
+	println!("\r\n\t  On-chain:");
+	println!("\r\t      getnewaddress");
+	println!("\r\t      onchainbalance");
+	println!("\r\t      estimatefee");
+	println!("\r\t      sync");
+	println!("\r\t      rescan");
+	println!("\r\t      fundwallet <amt_sat> (regtest only)");
+	println!("\r\t      importprivkey <private_key>");
+	println!("\r\t      listfailedsweeps");
+	println!("\r\t      retryfailedsweeps");
+	println!("\r\n\t  YUV:");
+	println!("\r\t      yuvbalance");
+	println!("\r\t      listyuvutxos");
+	println!("\r\t      yuvstats");
+	println!("\r\t      yuvtransfer <recipient_pubkey> <chroma> <amount>");
 	println!("\r\n\t  Other:");
 	println!("\r\t      signmessage <message>");
+	println!("\r\t      verifymessage <message> <signature>");
 	println!(
-		"\r\t      sendonionmessage <node_id_1,node_id_2,..,destination_node_id> <type> <hex_bytes>"
+		"\r\t      sendonionmessage <node_id_1,node_id_2,..,destination_node_id> <type> <hex_bytes | --data-file <path>>"
 	);
-	println!("\r\t      nodeinfo");
+	println!("\r\t      listonionmessages");
+	println!("\r\t      nodeinfo [--json]");
+	println!("\r\t      getinfo");
+	println!("\r\t      setalias <name>");
+	println!("\r\t      addannouncedaddress <host:port>");
+	println!("\r\t      removeannouncedaddress <host:port>");
+}
+
+/// Snapshots the channel manager and every channel monitor, the latter encoded the way the monitor
+/// persister stores them, so that they can be restored with `--import-channel-backup`.
+fn channel_backup(channel_manager: &ChannelManager, chain_monitor: &ChainMonitor) -> ChannelBackup {
+	// The channel manager is encoded first, as it may only ever be restored alongside monitors
+	// which are at least as recent.
+	let channel_manager = channel_manager.encode();
+	let channel_monitors = chain_monitor
+		.list_monitors()
+		.into_iter()
+		.filter_map(|(funding_txo, _)| {
+			let monitor = chain_monitor.get_monitor(funding_txo).ok()?;
+			Some(ChannelMonitorBackup {
+				key: format!("{}_{}", funding_txo.txid, funding_txo.index),
+				monitor: monitor.encode(),
+			})
+		})
+		.collect();
+	ChannelBackup { channel_manager, channel_monitors }
+}
+
+/// The confirmation targets the fee estimator tracks, by the name they're printed with.
+const CONFIRMATION_TARGETS: [(ConfirmationTarget, &str); 6] = [
+	(ConfirmationTarget::OnChainSweep, "on_chain_sweep"),
+	(ConfirmationTarget::MinAllowedAnchorChannelRemoteFee, "min_allowed_anchor_channel_remote_fee"),
+	(
+		ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
+		"min_allowed_non_anchor_channel_remote_fee",
+	),
+	(ConfirmationTarget::AnchorChannelFee, "anchor_channel_fee"),
+	(ConfirmationTarget::NonAnchorChannelFee, "non_anchor_channel_fee"),
+	(ConfirmationTarget::ChannelCloseMinimum, "channel_close_minimum"),
+];
+
+/// Prints the current feerate estimate of each confirmation target in sat/vB, which is what
+/// `closechannel --feerate` takes.
+fn estimate_fee(fee_estimator: &BitcoindClient) {
+	println!("\r{{");
+	for (target, name) in CONFIRMATION_TARGETS {
+		let sat_per_kw = fee_estimator.get_est_sat_per_1000_weight(target);
+		// A vbyte is four weight units, so a sat/vB feerate is 250 sat per 1000 weight units.
+		println!("\r\t {}: {:.3} sat/vB ({} sat/kw),", name, sat_per_kw as f64 / 250.0, sat_per_kw);
+	}
+	println!("\r}}");
 }
 
 fn node_info(channel_manager: &Arc<ChannelManager>, peer_manager: &Arc<PeerManager>) {
@@ -923,285 +2162,1543 @@ fn node_info(channel_manager: &Arc<ChannelManager>, peer_manager: &Arc<PeerManag
 	println!("\r}}");
 }
 
-fn list_peers(ldk_data_dir: String) {
-	let peer_data_path_str = format!("{}/channel_peer_data", ldk_data_dir);
-	let peer_data_path = Path::new(peer_data_path_str.as_str());
+pub(crate) fn node_info_json(
+	channel_manager: &Arc<ChannelManager>, peer_manager: &Arc<PeerManager>,
+) -> serde_json::Value {
+	let chans = channel_manager.list_channels();
+	json!({
+		"node_pubkey": channel_manager.get_our_node_id().to_string(),
+		"num_channels": chans.len(),
+		"num_usable_channels": chans.iter().filter(|c| c.is_usable).count(),
+		"local_balance_msat": chans.iter().map(|c| c.balance_msat).sum::<u64>(),
+		"num_peers": peer_manager.list_peers().len(),
+	})
+}
 
-	let peer_node_ids = read_channel_peer_data(peer_data_path).unwrap();
+/// Describes the node and the environment it runs in, for integrations to check what they're
+/// talking to.
+pub(crate) fn get_info_json(
+	channel_manager: &ChannelManager, peer_manager: &PeerManager, network: Network,
+	support_yuv_payments: bool, yuv_enabled: bool,
+) -> serde_json::Value {
+	let best_block = channel_manager.current_best_block();
+	json!({
+		"node_pubkey": channel_manager.get_our_node_id().to_string(),
+		"version": env!("CARGO_PKG_VERSION"),
+		"network": network.to_string(),
+		"block_height": best_block.height,
+		"block_hash": best_block.block_hash.to_string(),
+		"num_channels": channel_manager.list_channels().len(),
+		"num_peers": peer_manager.list_peers().len(),
+		"support_yuv_payments": support_yuv_payments,
+		"yuv_node_configured": yuv_enabled,
+	})
+}
 
-	if peer_node_ids.is_empty() {
-		return;
-	}
+/// The windows routing income is reported over, as their name and length in seconds.
+const FEE_REPORT_WINDOWS: [(&str, u64); 3] =
+	[("day", 24 * 60 * 60), ("week", 7 * 24 * 60 * 60), ("month", 30 * 24 * 60 * 60)];
 
-	println!("\r{{");
-	for (pubkey, addr) in peer_node_ids {
-		println!("\r\t pubkey: {}@{}", pubkey, addr);
-	}
-	println!("\r}}");
+struct FeeReportWindow {
+	name: &'static str,
+	num_forwards: usize,
+	fee_earned_msat: u64,
+	/// The forwarded YUV amounts, keyed by chroma address.
+	yuv_volume: BTreeMap<String, u128>,
 }
 
-fn list_channels(channel_manager: &Arc<ChannelManager>, network_graph: &Arc<NetworkGraph>) {
-	let list_channels = channel_manager.list_channels();
-
-	if list_channels.is_empty() {
-		return;
-	}
+fn fee_report_windows(
+	forwards: &[ForwardInfo], now_secs: u64, network: Network,
+) -> Vec<FeeReportWindow> {
+	FEE_REPORT_WINDOWS
+		.iter()
+		.map(|&(name, length_secs)| {
+			let window_forwards = forwards
+				.iter()
+				.filter(|forward| forward.timestamp_secs + length_secs > now_secs)
+				.collect::<Vec<_>>();
 
-	print!("\r[");
-	for chan_info in channel_manager.list_channels() {
-		println!("\r");
-		println!("\r\t{{");
-		println!("\r\t\tchannel_id: {},", chan_info.channel_id);
-		if let Some(funding_txo) = chan_info.funding_txo {
-			println!("\r\t\tfunding_txid: {},", funding_txo.txid);
-		}
+			let mut yuv_volume = BTreeMap::new();
+			for pixel in window_forwards.iter().filter_map(|forward| forward.yuv_pixel) {
+				*yuv_volume.entry(pixel.chroma.to_address(network).to_string()).or_insert(0) +=
+					pixel.luma.amount;
+			}
 
-		println!(
-			"\r\t\tpeer_pubkey: {},",
-			hex_utils::hex_str(&chan_info.counterparty.node_id.serialize())
-		);
-		if let Some(node_info) = network_graph
-			.read_only()
-			.nodes()
-			.get(&NodeId::from_pubkey(&chan_info.counterparty.node_id))
-		{
-			if let Some(announcement) = &node_info.announcement_info {
-				println!("\r\t\tpeer_alias: {}", announcement.alias);
+			FeeReportWindow {
+				name,
+				num_forwards: window_forwards.len(),
+				fee_earned_msat: window_forwards
+					.iter()
+					.filter_map(|forward| forward.fee_earned_msat)
+					.sum(),
+				yuv_volume,
 			}
-		}
+		})
+		.collect()
+}
 
-		if let Some(id) = chan_info.short_channel_id {
-			println!("\r\t\tshort_channel_id: {},", id);
-		}
-		println!("\r\t\thtlc_limits: {{");
-		println!("\r\t\t\tinbound: {{");
-		println!("\r\t\t\t\tminimum_msat: {},", chan_info.inbound_htlc_minimum_msat.unwrap());
-		println!("\r\t\t\t\tmaximum_msat: {},", chan_info.inbound_htlc_maximum_msat.unwrap());
-		println!("\r\t\t\t}},");
-		println!("\r\t\t\toutbound: {{");
-		println!(
-			"\r\t\t\t\tminimum_msat_configured: {},",
-			chan_info.counterparty.outbound_htlc_minimum_msat.unwrap(),
-		);
-		println!(
-			"\r\t\t\t\tminimum_msat_сonsidering_dust: {},",
-			chan_info.next_outbound_htlc_minimum_msat,
-		);
+fn fee_report(forwards: &ForwardInfoStorage, network: Network) {
+	if forwards.forwards.is_empty() {
+		println!("\rNo forwarded payments recorded yet");
+		return;
+	}
+
+	let now_secs = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+	println!("\r{{");
+	for window in fee_report_windows(&forwards.forwards, now_secs, network) {
 		println!(
-			"\r\t\t\t\tmaximum_msat: {},",
-			chan_info.counterparty.outbound_htlc_maximum_msat.unwrap()
+			"\r\t {}: {} forwards, {} msat earned",
+			window.name, window.num_forwards, window.fee_earned_msat
 		);
-		println!("\r\t\t\t}},");
-		println!("\r\t\t}},");
-		println!("\r\t\tis_channel_ready: {},", chan_info.is_channel_ready);
-		println!("\r\t\tchannel_value_satoshis: {},", chan_info.channel_value_satoshis);
-		println!("\r\t\toutbound_capacity_msat: {},", chan_info.outbound_capacity_msat);
-		if chan_info.is_usable {
-			println!(
-				"\r\t\tavailable_balance_for_send_msat: {},",
-				chan_info.outbound_capacity_msat
-			);
-			println!("\r\t\tavailable_balance_for_recv_msat: {},", chan_info.inbound_capacity_msat);
-			println!(
-				"\r\t\tholder_reserved_satoshis: {},",
-				chan_info.unspendable_punishment_reserve.unwrap_or(0)
-			);
-			println!(
-				"\r\t\tcounterparty_reserved_satoshis: {},",
-				chan_info.counterparty.unspendable_punishment_reserve
-			);
-		}
-		println!("\r\t\tchannel_can_send_payments: {},", chan_info.is_usable);
-		println!("\r\t\tpublic: {},", chan_info.is_public);
-		if let (Some(holder_pixel), Some(counterparty_pixel)) =
-			(chan_info.yuv_holder_pixel, chan_info.yuv_counterparty_pixel)
-		{
-			println!(
-				"\r\t\tyuv_chroma: {},",
-				holder_pixel.chroma.to_address(Network::Regtest).to_string()
-			);
-			println!("\r\t\tholder_yuv_amount: {},", holder_pixel.luma.amount);
-			println!("\r\t\tcounterparty_yuv_amount: {},", counterparty_pixel.luma.amount);
-		}
-		if let Some(pending_update_balances) = chan_info.clone().pending_update_balance {
-			println!("\r\t\tupdate_balance: {{");
-			println!(
-				"\r\t\t\tholder_ready_to_update_balance: {}",
-				chan_info
-					.clone()
-					.update_balance_amounts
-					.map_or(0, |update_balances| update_balances.holders_msat)
-			);
-			println!(
-				"\r\t\t\tcounterparty_ready_to_update_balance: {}",
-				chan_info
-					.clone()
-					.update_balance_amounts
-					.map_or(0, |update_balances| update_balances.counterpartys_msat)
-			);
-			if let Some(inbound) = pending_update_balances.inbound_request {
-				println!("\r\t\t\tinbound: {{");
-				println!("\r\t\t\t\tnew_balance_msat: {},", inbound.inner().new_balance_msat);
-				println!(
-					"\r\t\t\t\tnew_yuv_pixel_luma: {},",
-					inbound.inner().new_yuv_pixel_luma.map_or(0, |luma| luma.amount)
-				);
-				println!("\r\t\t\t}},");
-			}
-			if let Some(outbound) = pending_update_balances.outbound_request {
-				println!("\r\t\t\toutbound: {{");
-				println!("\r\t\t\t\tnew_balance_msat: {},", outbound.inner().new_balance_msat);
-				println!(
-					"\r\t\t\t\tnew_yuv_pixel_luma: {},",
-					outbound.inner().new_yuv_pixel_luma.map_or(0, |luma| luma.amount)
-				);
-				println!("\r\t\t\t}}");
-			}
-			println!("\r\t\t}}");
+		for (chroma, amount) in window.yuv_volume {
+			println!("\r\t\t YUV {}: {}", chroma, amount);
 		}
-		println!("\r\t}},");
 	}
-	println!("\r\n]");
+	println!("\r}}");
 }
 
-fn list_payments(inbound_payments: &PaymentInfoStorage, outbound_payments: &PaymentInfoStorage) {
+fn list_closures(closures: &ChannelClosureStorage) {
 	print!("\r[");
-
-	let inbound_payments = &inbound_payments.payments;
-	if !inbound_payments.is_empty() {
+	for closure in &closures.closures {
 		println!();
-		for (payment_hash, payment_info) in inbound_payments {
-			println!("\r\t{{");
-			println!("\r\t\tamount_millisatoshis: {},", payment_info.amt_msat);
-			println!("\r\t\tpayment_hash: {},", payment_hash);
-			println!("\r\t\thtlc_direction: inbound,");
-			println!(
-				"\r\t\thtlc_status: {},",
-				match payment_info.status {
-					HTLCStatus::Pending => "pending",
-					HTLCStatus::Succeeded => "succeeded",
-					HTLCStatus::Failed => "failed",
-				}
-			);
-
-			print!("\r\t}},\n\r");
+		println!("\r\t{{");
+		println!("\r\t\tchannel_id: {},", closure.channel_id);
+		if let Some(counterparty_node_id) = closure.counterparty_node_id {
+			println!("\r\t\tcounterparty_pubkey: {},", counterparty_node_id);
 		}
+		if let Some(channel_capacity_sats) = closure.channel_capacity_sats {
+			println!("\r\t\tchannel_value_satoshis: {},", channel_capacity_sats);
+		}
+		println!("\r\t\tclosed_at: {},", closure.timestamp_secs);
+		println!("\r\t\treason: {},", closure.reason);
+		print!("\r\t}},");
 	}
+	println!("\n\r]");
+}
 
-	let outbound_payments = &outbound_payments.payments;
-	for (payment_hash, payment_info) in outbound_payments {
+fn list_onion_messages(messages: &[ReceivedOnionMessage]) {
+	print!("\r[");
+	for msg in messages {
 		println!();
 		println!("\r\t{{");
-		println!("\r\t\tamount_millisatoshis: {},", payment_info.amt_msat);
-		println!("\r\t\tpayment_hash: {},", payment_hash);
-		println!("\r\t\thtlc_direction: outbound,");
-		println!(
-			"\r\thtlc_status: {},",
-			match payment_info.status {
-				HTLCStatus::Pending => "pending",
-				HTLCStatus::Succeeded => "succeeded",
-				HTLCStatus::Failed => "failed",
-			}
-		);
+		println!("\r\t\treceived_at: {},", msg.timestamp_secs);
+		println!("\r\t\ttlv_type: {},", msg.tlv_type);
+		println!("\r\t\tdata: {},", hex_utils::hex_str(&msg.data));
+		print!("\r\t}},");
+	}
+	println!("\n\r]");
+}
 
-		println!("\r\t}},\n\r");
+/// Parses the value of a fee limit parameter, where `none` lifts the limit.
+fn parse_fee_limit<F: FromStr>(words: &mut SplitWhitespace, param_name: &str) -> Option<Option<F>> {
+	if words.clone().next() == Some("none") {
+		words.next();
+		return Some(None);
+	}
+	parse_named_param(words, param_name).map(Some)
+}
+
+fn print_payment_policy(policy: &PaymentPolicy) {
+	let limit = |limit: Option<String>| limit.unwrap_or_else(|| "none".to_string());
+	println!("\r{{");
+	match policy.retry_attempts {
+		Some(attempts) => println!("\r\tretry_attempts: {},", attempts),
+		None => println!("\r\tretry_timeout_secs: {},", policy.retry_timeout_secs),
+	}
+	println!("\r\tmax_fee_ppm: {},", limit(policy.max_fee_ppm.map(|ppm| ppm.to_string())));
+	println!("\r\tmax_fee_msat: {},", limit(policy.max_fee_msat.map(|msat| msat.to_string())));
+	println!("\r\tprobe: {},", policy.probe);
+	println!("\r}}");
+}
+
+fn persist_user_config(
+	fs_store: &FilesystemStore, config: &UserConfig, update_balance_max_delta_msat: Option<u64>,
+) {
+	let saved_config = SavedUserConfig::from_config(config, update_balance_max_delta_msat);
+	if let Err(e) = fs_store.write("", "", USER_CONFIG_FNAME, &saved_config.encode()) {
+		println!("\rERROR: failed to persist channel config: {}", e);
+	}
+}
+
+fn add_announced_address(
+	addrs: &mut Vec<SocketAddress>, addr: SocketAddress,
+) -> Result<(), String> {
+	if addrs.contains(&addr) {
+		return Err(format!("{} is already announced", addr));
+	}
+	addrs.push(addr);
+	Ok(())
+}
+
+fn remove_announced_address(
+	addrs: &mut Vec<SocketAddress>, addr: &SocketAddress,
+) -> Result<(), String> {
+	let len = addrs.len();
+	addrs.retain(|announced| announced != addr);
+	if addrs.len() == len {
+		return Err(format!("{} isn't announced", addr));
+	}
+	Ok(())
+}
+
+/// The length of the zbase32 encoding of a recoverable signature made by `signmessage`.
+const SIGNATURE_LEN: usize = 104;
+
+/// Recovers the node id which signed `message` with `signature`, as made by `signmessage`.
+fn recover_signer(message: &str, signature: &str) -> Result<PublicKey, String> {
+	// LDK indexes into the decoded signature, so anything but a full one is refused upfront.
+	if signature.len() != SIGNATURE_LEN {
+		return Err(format!("malformed signature: expected {} characters", SIGNATURE_LEN));
+	}
+	lightning::util::message_signing::recover_pk(message.as_bytes(), signature)
+		.map_err(|e| format!("malformed signature: {}", e))
+}
+
+fn list_failed_sweeps(failed_sweeps: &FailedSweepStorage) {
+	print!("\r[");
+	for failed_sweep in &failed_sweeps.sweeps {
+		println!();
+		println!("\r\t{{");
+		println!("\r\t\toutputs: {},", failed_sweep.outputs_key);
+		println!("\r\t\ttxid: {},", failed_sweep.txid);
+		println!("\r\t\treason: {},", failed_sweep.reason);
+		println!("\r\t\tproofs: {},", failed_sweep.proofs);
+		println!("\r\t\tfailed_at: {},", failed_sweep.timestamp_secs);
+		println!("\r\t\tabandoned: {},", failed_sweep.abandoned);
+		print!("\r\t}},");
+	}
+	println!("\n\r]");
+}
+
+fn list_peer_access(peer_access_lists: &PeerAccessLists) {
+	println!("\r{{");
+	for node_id in &peer_access_lists.allowed {
+		println!("\r\t allowed: {}", node_id);
+	}
+	for node_id in &peer_access_lists.denied {
+		println!("\r\t denied: {}", node_id);
+	}
+	println!("\r}}");
+}
+
+fn list_peers(ldk_data_dir: String) {
+	let peer_data_path_str = format!("{}/channel_peer_data", ldk_data_dir);
+	let peer_data_path = Path::new(peer_data_path_str.as_str());
+
+	let peer_node_ids = read_channel_peer_data(peer_data_path).unwrap();
+
+	if peer_node_ids.is_empty() {
+		return;
+	}
+
+	println!("\r{{");
+	for (pubkey, addrs) in peer_node_ids {
+		let addrs = addrs.iter().map(|addr| addr.to_string()).collect::<Vec<_>>();
+		println!("\r\t pubkey: {}@{}", pubkey, addrs.join(","));
+	}
+	println!("\r}}");
+}
+
+fn get_new_address(wallet: &Arc<TokioMutex<Wallet>>) {
+	let wallet = tokio::runtime::Handle::current().block_on(wallet.lock());
+	match wallet.get_new_address() {
+		Ok(address) => println!("\rSUCCESS: new address: {}", address),
+		Err(e) => println!("\rERROR: failed to get new address: {:?}", e),
+	}
+}
+
+fn rescan(wallet: &Arc<TokioMutex<Wallet>>) {
+	println!("\rRescanning the wallet, this may take a while...");
+	let res = tokio::runtime::Handle::current()
+		.block_on(async { wallet.lock().await.full_rescan().await });
+
+	match res {
+		Ok(()) => println!("\rSUCCESS: wallet rescanned"),
+		Err(e) => println!("\rERROR: failed to rescan wallet: {:?}", e),
+	}
+}
+
+/// Syncs the wallet and compares the height it got to with the chain tip the node is at, which
+/// tells whether balances may be stale.
+fn sync_wallet(wallet: &Arc<TokioMutex<Wallet>>, channel_manager: &ChannelManager) {
+	let res = tokio::runtime::Handle::current().block_on(async {
+		let wallet = wallet.lock().await;
+		wallet.sync().await?;
+		wallet.sync_status()
+	});
+
+	let tip_height = channel_manager.current_best_block().height;
+	match res {
+		Ok(Some(synced_height)) => println!(
+			"\rSUCCESS: wallet synced to height {}, the chain tip is at height {} ({} blocks behind)",
+			synced_height,
+			tip_height,
+			tip_height.saturating_sub(synced_height)
+		),
+		Ok(None) => println!(
+			"\rERROR: wallet synced but reports no sync height, the chain tip is at height {}",
+			tip_height
+		),
+		Err(e) => println!("\rERROR: failed to sync wallet: {:?}", e),
+	}
+}
+
+/// How many blocks a coinbase output must be buried under before it can be spent.
+const COINBASE_MATURITY: u64 = 100;
+
+/// Funds the wallet on regtest by mining blocks paying it at least `amt_sat`, then enough blocks
+/// on top to the bitcoind wallet for their coinbase outputs to be spendable.
+fn fund_wallet(amt_sat: u64, wallet: &Arc<TokioMutex<Wallet>>, bitcoind_client: &BitcoindClient) {
+	let handle = tokio::runtime::Handle::current();
+
+	let address = match handle.block_on(async { wallet.lock().await.get_new_address() }) {
+		Ok(address) => address,
+		Err(e) => {
+			println!("\rERROR: failed to get a wallet address: {:?}", e);
+			return;
+		}
+	};
+	let tip_height = handle.block_on(bitcoind_client.get_blockchain_info()).latest_height as u64;
+	let num_blocks = match blocks_to_fund(amt_sat, tip_height) {
+		Some(num_blocks) => num_blocks,
+		None => {
+			println!("\rERROR: the block subsidy is too low to mine {} sat", amt_sat);
+			return;
+		}
+	};
+
+	let res = handle.block_on(async {
+		bitcoind_client.generate_to_address(num_blocks, &address).await?;
+		let maturity_address = bitcoind_client.get_new_address().await;
+		bitcoind_client.generate_to_address(COINBASE_MATURITY, &maturity_address).await
+	});
+	match res {
+		Ok(_) => println!(
+			"\rSUCCESS: mined {} block(s) paying {} and {} more to mature them",
+			num_blocks, address, COINBASE_MATURITY
+		),
+		Err(e) => println!("\rERROR: failed to mine blocks: {}", e),
+	}
+}
+
+/// The block subsidy on regtest, which halves every 150 blocks rather than every 210,000.
+fn regtest_block_subsidy_sat(height: u64) -> u64 {
+	match height / 150 {
+		halvings if halvings >= 64 => 0,
+		halvings => (50 * 100_000_000) >> halvings,
+	}
+}
+
+/// Returns how many blocks on top of `tip_height` pay at least `amt_sat` in subsidies, or `None`
+/// if the subsidy runs out first.
+fn blocks_to_fund(amt_sat: u64, tip_height: u64) -> Option<u64> {
+	let (mut height, mut funded_sat) = (tip_height + 1, 0);
+	while funded_sat < amt_sat {
+		let subsidy = regtest_block_subsidy_sat(height);
+		if subsidy == 0 {
+			return None;
+		}
+		funded_sat += subsidy;
+		height += 1;
+	}
+	Some(height - tip_height - 1)
+}
+
+/// Commands which only work with a YUV node.
+const YUV_COMMANDS: [&str; 4] = ["yuvbalance", "yuvstats", "listyuvutxos", "yuvtransfer"];
+
+/// Parameters which make a command deal in YUV tokens, so that it needs a YUV node too.
+const YUV_PARAMS: [&str; 3] = ["--pixel", "--push-yuv", "--yuv-amount"];
+
+/// Returns whether the command line can't be run without a YUV node.
+fn requires_yuv(line: &str) -> bool {
+	let words = line.split_whitespace().collect::<Vec<_>>();
+	let Some((command, params)) = words.split_first() else {
+		return false;
+	};
+	YUV_COMMANDS.contains(command)
+		|| params.iter().any(|param| YUV_PARAMS.contains(param))
+		|| params.windows(2).any(|pair| pair == ["--support-yuv", "true"])
+}
+
+fn import_private_key(
+	wallet: &Arc<TokioMutex<Wallet>>, yuv_client: Option<&YuvClient>,
+	bitcoind_client: &BitcoindClient, private_key: PrivateKey,
+	yuv_transfers: &mut HashMap<PublicKey, Txid>,
+) {
+	let handle = tokio::runtime::Handle::current();
+	let pubkey = private_key.public_key(&Secp256k1::new()).inner;
+
+	println!("\rImporting the key and rescanning, this may take a while...");
+	let imported = handle.block_on(async { wallet.lock().await.import_private_key(private_key) });
+	match imported {
+		Ok(true) => {}
+		Ok(false) => println!("\rThe key was already imported"),
+		Err(e) => {
+			println!("\rERROR: failed to import private key: {:?}", e);
+			return;
+		}
+	}
+
+	match handle.block_on(async { wallet.lock().await.imported_key_balance(&pubkey) }) {
+		Ok(balance) => println!(
+			"\rSUCCESS: imported the key, it holds {} sat, which is spent with the wallet's bitcoin",
+			balance.get_total()
+		),
+		Err(e) => {
+			println!("\rERROR: failed to get imported key balance: {:?}", e);
+			return;
+		}
+	}
+
+	// YUV holdings are only found when syncing with a YUV node.
+	let Some(yuv_client) = yuv_client else {
+		return;
+	};
+
+	// The YUV wallet of the key is synced from the chain only, so until an earlier transfer
+	// confirms it would find the same holdings and transfer them again.
+	if let Some(txid) = yuv_transfers.get(&pubkey) {
+		match handle.block_on(bitcoind_client.is_in_mempool(txid)) {
+			Ok(false) => {}
+			Ok(true) => {
+				println!("\rERROR: the earlier transfer {} of the key's YUV holdings hasn't confirmed yet, run importprivkey again once it has", txid);
+				return;
+			}
+			Err(e) => {
+				println!(
+					"\rERROR: failed to look for the earlier transfer {} in the mempool: {}",
+					txid, e
+				);
+				return;
+			}
+		}
+	}
+
+	let transfer =
+		handle.block_on(async { wallet.lock().await.transfer_imported_yuv(private_key).await });
+	let yuv_tx = match transfer {
+		Ok(Some(yuv_tx)) => yuv_tx,
+		Ok(None) => return,
+		Err(e) => {
+			println!("\rERROR: failed to transfer the key's YUV holdings: {:?}", e);
+			return;
+		}
+	};
+
+	match handle.block_on(yuv_client.emulate_yuv_transaction(yuv_tx.clone())) {
+		Ok(None) => {}
+		Ok(Some(reason)) => {
+			println!("\rERROR: YUV transfer was rejected by the YUV node: {}", reason);
+			return;
+		}
+		Err(e) => {
+			println!("\rERROR: failed to check YUV transfer with the YUV node: {}", e);
+			return;
+		}
+	}
+
+	yuv_client.broadcast_transactions_proofs(yuv_tx.clone());
+	bitcoind_client.broadcast_transactions(&[&yuv_tx.bitcoin_tx]);
+	yuv_transfers.insert(pubkey, yuv_tx.bitcoin_tx.txid());
+
+	println!(
+		"\rSUCCESS: broadcasted transfer {} of the key's YUV holdings, run importprivkey again once it confirms to transfer any other chroma",
+		yuv_tx.bitcoin_tx.txid()
+	);
+}
+
+fn onchain_balance(wallet: &Arc<TokioMutex<Wallet>>) {
+	let balance = tokio::runtime::Handle::current()
+		.block_on(async { wallet.lock().await.get_onchain_balance().await });
+
+	match balance {
+		Ok(balance) => {
+			println!("\r{{");
+			println!("\r\t confirmed_sat: {}", balance.confirmed);
+			println!("\r\t trusted_pending_sat: {}", balance.trusted_pending);
+			println!("\r\t untrusted_pending_sat: {}", balance.untrusted_pending);
+			println!("\r\t immature_sat: {}", balance.immature);
+			println!("\r}}");
+		}
+		Err(e) => println!("\rERROR: failed to get on-chain balance: {:?}", e),
+	}
+}
+
+fn yuv_balance(wallet: &Arc<TokioMutex<Wallet>>, network: Network) {
+	let balances = tokio::runtime::Handle::current().block_on(yuv_balances(wallet, network));
+
+	let balances = match balances {
+		Ok(balances) => balances,
+		Err(e) => {
+			println!("\rERROR: failed to get YUV balances: {:?}", e);
+			return;
+		}
+	};
+
+	if balances.is_empty() {
+		println!("\rNo YUV tokens found in the wallet");
+		return;
+	}
+
+	println!("\r{{");
+	for (chroma, amount) in balances {
+		println!("\r\t {}: {}", chroma, amount);
+	}
+	println!("\r}}");
+}
+
+fn yuv_stats(yuv_client: &YuvClient) {
+	println!("\r{{");
+	for (method, stats) in yuv_client.stats().methods() {
+		println!(
+			"\r\t {}: {{ successes: {}, failures: {} }}",
+			method, stats.successes, stats.failures
+		);
+	}
+	let undelivered_proofs = yuv_client.undelivered_proofs();
+	if !undelivered_proofs.is_empty() {
+		println!("\r\t undelivered_proofs: [");
+		for (txid, delivery) in undelivered_proofs {
+			match delivery {
+				ProofDelivery::Pending { attempts } => {
+					println!(
+						"\r\t\t {{ txid: {}, status: pending, attempts: {} }},",
+						txid, attempts
+					)
+				}
+				ProofDelivery::Rejected => {
+					println!("\r\t\t {{ txid: {}, status: rejected }},", txid)
+				}
+				ProofDelivery::Failed => {
+					println!("\r\t\t {{ txid: {}, status: failed }},", txid)
+				}
+			}
+		}
+		println!("\r\t ]");
+	}
+	println!("\r}}");
+}
+
+fn list_yuv_utxos(wallet: &Arc<TokioMutex<Wallet>>, network: Network) {
+	let handle = tokio::runtime::Handle::current();
+	let utxos = match handle.block_on(async { wallet.lock().await.list_yuv_utxos().await }) {
+		Ok(utxos) => utxos,
+		Err(e) => {
+			println!("\rERROR: failed to list YUV UTXOs: {:?}", e);
+			return;
+		}
+	};
+
+	if utxos.is_empty() {
+		println!("\rNo YUV UTXOs found in the wallet");
+		return;
+	}
+
+	println!("\r[");
+	for (outpoint, pixel) in utxos {
+		println!("\r\t{{");
+		println!("\r\t\t outpoint: {}:{},", outpoint.txid, outpoint.vout);
+		println!("\r\t\t chroma: {},", pixel.chroma.to_address(network));
+		println!("\r\t\t luma: {},", pixel.luma.amount);
+		println!("\r\t}},");
+	}
+	println!("\r]");
+}
+
+/// Returns the wallet's YUV balances keyed by chroma address, sorted by it.
+pub(crate) async fn yuv_balances(
+	wallet: &Arc<TokioMutex<Wallet>>, network: Network,
+) -> eyre::Result<Vec<(String, u128)>> {
+	let balances = wallet.lock().await.get_yuv_balances().await?;
+
+	let mut balances = balances
+		.into_iter()
+		.map(|(chroma, amount)| (chroma.to_address(network).to_string(), amount))
+		.collect::<Vec<_>>();
+	balances.sort();
+
+	Ok(balances)
+}
+
+fn yuv_transfer(
+	wallet: &Arc<TokioMutex<Wallet>>, yuv_client: &YuvClient, bitcoind_client: &BitcoindClient,
+	recipient: PublicKey, chroma: Chroma, amount: u128,
+) {
+	let handle = tokio::runtime::Handle::current();
+
+	let yuv_tx = match handle
+		.block_on(async { wallet.lock().await.new_yuv_transfer(recipient, chroma, amount).await })
+	{
+		Ok(yuv_tx) => yuv_tx,
+		Err(e) => {
+			println!("\rERROR: failed to create YUV transfer: {:?}", e);
+			return;
+		}
+	};
+
+	match handle.block_on(yuv_client.emulate_yuv_transaction(yuv_tx.clone())) {
+		Ok(None) => {}
+		Ok(Some(reason)) => {
+			println!("\rERROR: YUV transfer was rejected by the YUV node: {}", reason);
+			return;
+		}
+		Err(e) => {
+			println!("\rERROR: failed to check YUV transfer with the YUV node: {}", e);
+			return;
+		}
+	}
+
+	yuv_client.broadcast_transactions_proofs(yuv_tx.clone());
+	bitcoind_client.broadcast_transactions(&[&yuv_tx.bitcoin_tx]);
+
+	println!("\rSUCCESS: broadcasted YUV transfer {}", yuv_tx.bitcoin_tx.txid());
+}
+
+/// Prints how the bitcoin and YUV tokens of each usable channel are split between us and the
+/// counterparty.
+fn channel_balances(channel_manager: &Arc<ChannelManager>, network: Network) {
+	let channels = channel_manager.list_usable_channels();
+	if channels.is_empty() {
+		println!("\rNo usable channels");
+		return;
+	}
+
+	println!("\r[");
+	for chan_info in channels {
+		// Anything not ours is the counterparty's, including what's still in flight.
+		let remote_msat =
+			(chan_info.channel_value_satoshis * 1000).saturating_sub(chan_info.balance_msat);
+		println!("\r\t{{");
+		println!("\r\t\tchannel_id: {},", chan_info.channel_id);
+		println!("\r\t\tpeer_pubkey: {},", chan_info.counterparty.node_id);
+		println!("\r\t\tlocal_msat: {},", chan_info.balance_msat);
+		println!("\r\t\tremote_msat: {},", remote_msat);
+		if let (Some(holder_pixel), Some(counterparty_pixel)) =
+			(chan_info.yuv_holder_pixel, chan_info.yuv_counterparty_pixel)
+		{
+			println!("\r\t\tyuv_chroma: {},", holder_pixel.chroma.to_address(network));
+			println!("\r\t\tlocal_yuv_amount: {},", holder_pixel.luma.amount);
+			println!("\r\t\tremote_yuv_amount: {},", counterparty_pixel.luma.amount);
+		}
+		println!("\r\t}},");
+	}
+	println!("\r]");
+}
+
+fn list_channels(channel_manager: &Arc<ChannelManager>, network_graph: &Arc<NetworkGraph>) {
+	let list_channels = channel_manager.list_channels();
+
+	if list_channels.is_empty() {
+		return;
+	}
+
+	let pending_yuv_txids = channel_manager.get_pending_yuv_txs();
+	print!("\r[");
+	for chan_info in channel_manager.list_channels() {
+		println!("\r");
+		print_channel(&chan_info, network_graph, &pending_yuv_txids);
+	}
+	println!("\r\n]");
+}
+
+/// Prints the details of a single channel, as listed by `listchannels`.
+fn print_channel(
+	chan_info: &ChannelDetails, network_graph: &NetworkGraph, pending_yuv_txids: &[Txid],
+) {
+	println!("\r\t{{");
+	println!("\r\t\tchannel_id: {},", chan_info.channel_id);
+	if let Some(funding_txo) = chan_info.funding_txo {
+		println!("\r\t\tfunding_txid: {},", funding_txo.txid);
+	}
+
+	println!(
+		"\r\t\tpeer_pubkey: {},",
+		hex_utils::hex_str(&chan_info.counterparty.node_id.serialize())
+	);
+	if let Some(node_info) =
+		network_graph.read_only().nodes().get(&NodeId::from_pubkey(&chan_info.counterparty.node_id))
+	{
+		if let Some(announcement) = &node_info.announcement_info {
+			println!("\r\t\tpeer_alias: {}", announcement.alias);
+		}
+	}
+
+	if let Some(id) = chan_info.short_channel_id {
+		println!("\r\t\tshort_channel_id: {},", id);
+	}
+	// The aliases are what routing hints of private channels use before and after the
+	// funding transaction confirms.
+	if let Some(alias) = chan_info.inbound_scid_alias {
+		println!("\r\t\tinbound_scid_alias: {},", alias);
+	}
+	if let Some(alias) = chan_info.outbound_scid_alias {
+		println!("\r\t\toutbound_scid_alias: {},", alias);
+	}
+	// LDK counts these from the funding transaction's height up to the current best block.
+	if let Some(confirmations) = chan_info.confirmations {
+		println!("\r\t\tfunding_confirmations: {},", confirmations);
+	}
+	if let Some(confirmations_required) = chan_info.confirmations_required {
+		println!("\r\t\tfunding_confirmations_required: {},", confirmations_required);
+	}
+	println!("\r\t\thtlc_limits: {{");
+	println!("\r\t\t\tinbound: {{");
+	println!("\r\t\t\t\tminimum_msat: {},", chan_info.inbound_htlc_minimum_msat.unwrap());
+	println!("\r\t\t\t\tmaximum_msat: {},", chan_info.inbound_htlc_maximum_msat.unwrap());
+	println!("\r\t\t\t}},");
+	println!("\r\t\t\toutbound: {{");
+	println!(
+		"\r\t\t\t\tminimum_msat_configured: {},",
+		chan_info.counterparty.outbound_htlc_minimum_msat.unwrap(),
+	);
+	println!(
+		"\r\t\t\t\tminimum_msat_сonsidering_dust: {},",
+		chan_info.next_outbound_htlc_minimum_msat,
+	);
+	println!(
+		"\r\t\t\t\tmaximum_msat: {},",
+		chan_info.counterparty.outbound_htlc_maximum_msat.unwrap()
+	);
+	println!("\r\t\t\t}},");
+	println!("\r\t\t}},");
+	println!("\r\t\tis_channel_ready: {},", chan_info.is_channel_ready);
+	println!("\r\t\tchannel_value_satoshis: {},", chan_info.channel_value_satoshis);
+	println!("\r\t\toutbound_capacity_msat: {},", chan_info.outbound_capacity_msat);
+	if chan_info.is_usable {
+		println!("\r\t\tavailable_balance_for_send_msat: {},", chan_info.outbound_capacity_msat);
+		println!("\r\t\tavailable_balance_for_recv_msat: {},", chan_info.inbound_capacity_msat);
+		println!(
+			"\r\t\tholder_reserved_satoshis: {},",
+			chan_info.unspendable_punishment_reserve.unwrap_or(0)
+		);
+		println!(
+			"\r\t\tcounterparty_reserved_satoshis: {},",
+			chan_info.counterparty.unspendable_punishment_reserve
+		);
+	}
+	println!("\r\t\tchannel_can_send_payments: {},", chan_info.is_usable);
+	println!("\r\t\tpublic: {},", chan_info.is_public);
+	if let (Some(holder_pixel), Some(counterparty_pixel)) =
+		(chan_info.yuv_holder_pixel, chan_info.yuv_counterparty_pixel)
+	{
+		println!(
+			"\r\t\tyuv_chroma: {},",
+			holder_pixel.chroma.to_address(Network::Regtest).to_string()
+		);
+		println!("\r\t\tholder_yuv_amount: {},", holder_pixel.luma.amount);
+		println!("\r\t\tcounterparty_yuv_amount: {},", counterparty_pixel.luma.amount);
+		println!(
+			"\r\t\tyuv_funding_status: {},",
+			yuv_funding_status(
+				chan_info.funding_txo.map(|funding_txo| funding_txo.txid),
+				chan_info.is_channel_ready,
+				pending_yuv_txids
+			)
+		);
+	}
+	if let Some(pending_update_balances) = chan_info.clone().pending_update_balance {
+		println!("\r\t\tupdate_balance: {{");
+		println!(
+			"\r\t\t\tholder_ready_to_update_balance: {}",
+			chan_info
+				.clone()
+				.update_balance_amounts
+				.map_or(0, |update_balances| update_balances.holders_msat)
+		);
+		println!(
+			"\r\t\t\tcounterparty_ready_to_update_balance: {}",
+			chan_info
+				.clone()
+				.update_balance_amounts
+				.map_or(0, |update_balances| update_balances.counterpartys_msat)
+		);
+		if let Some(inbound) = pending_update_balances.inbound_request {
+			println!("\r\t\t\tinbound: {{");
+			println!("\r\t\t\t\tnew_balance_msat: {},", inbound.inner().new_balance_msat);
+			println!(
+				"\r\t\t\t\tnew_yuv_pixel_luma: {},",
+				inbound.inner().new_yuv_pixel_luma.map_or(0, |luma| luma.amount)
+			);
+			println!("\r\t\t\t}},");
+		}
+		if let Some(outbound) = pending_update_balances.outbound_request {
+			println!("\r\t\t\toutbound: {{");
+			println!("\r\t\t\t\tnew_balance_msat: {},", outbound.inner().new_balance_msat);
+			println!(
+				"\r\t\t\t\tnew_yuv_pixel_luma: {},",
+				outbound.inner().new_yuv_pixel_luma.map_or(0, |luma| luma.amount)
+			);
+			println!("\r\t\t\t}}");
+		}
+		println!("\r\t\t}}");
+	}
+	println!("\r\t}},");
+}
+
+/// Describes how far the funding of a YUV channel got. Its funding transaction may confirm before
+/// the YUV node has confirmed the proof of the tokens it moves, and the channel isn't usable until
+/// then.
+fn yuv_funding_status(
+	funding_txid: Option<Txid>, is_channel_ready: bool, pending_yuv_txids: &[Txid],
+) -> &'static str {
+	match funding_txid {
+		None => "awaiting funding transaction",
+		Some(txid) if pending_yuv_txids.contains(&txid) => "awaiting YUV proof confirmation",
+		Some(_) if !is_channel_ready => "awaiting funding confirmation",
+		Some(_) => "confirmed",
+	}
+}
+
+/// Prints the details of the channel with the given id, as `listchannels` does.
+fn get_channel(
+	channel_id: [u8; 32], channel_manager: &ChannelManager, network_graph: &NetworkGraph,
+) {
+	match channel_manager.list_channels().into_iter().find(|chan| chan.channel_id.0 == channel_id) {
+		Some(chan_info) => {
+			print_channel(&chan_info, network_graph, &channel_manager.get_pending_yuv_txs())
+		}
+		None => println!("\rERROR: channel {} not found", ChannelId(channel_id)),
+	}
+}
+
+/// Applies the forwarding parameters set with `configchannel` to a channel which is already open.
+/// The `--min-inb-htlc` and `--max-inb-htlc-pct` limits are negotiated when a channel is opened, so
+/// they only apply to channels opened after `configchannel`, and `updatechannelconfig` rejects them.
+fn update_channel_config(
+	channel_id: [u8; 32], channel_manager: &ChannelManager, config: &UserConfig,
+) {
+	let chan_info = match channel_manager
+		.list_channels()
+		.into_iter()
+		.find(|chan| chan.channel_id.0 == channel_id)
+	{
+		Some(chan_info) => chan_info,
+		None => {
+			println!("\rERROR: channel {} not found", ChannelId(channel_id));
+			return;
+		}
+	};
+
+	let update = forwarding_config_update(&config.channel_config);
+	if let Err(e) = channel_manager.update_partial_channel_config(
+		&chan_info.counterparty.node_id,
+		&[chan_info.channel_id],
+		&update,
+	) {
+		println!("\rERROR: failed to update the channel config: {:?}", e);
+		return;
+	}
+	println!(
+		"\rSUCCESS: channel {} now forwards with a base fee of {} msat, {} ppm and a CLTV delta of {}",
+		chan_info.channel_id,
+		config.channel_config.forwarding_fee_base_msat,
+		config.channel_config.forwarding_fee_proportional_millionths,
+		config.channel_config.cltv_expiry_delta
+	);
+}
+
+fn list_intercepted_htlcs(intercepted_htlcs: &HashMap<InterceptId, InterceptedHtlc>) {
+	let mut intercepted_htlcs = intercepted_htlcs.iter().collect::<Vec<_>>();
+	intercepted_htlcs.sort_by_key(|(_, htlc)| htlc.intercepted_at_secs);
+	print!("\r[");
+	for (intercept_id, htlc) in intercepted_htlcs {
+		println!();
+		println!("\r\t{{");
+		println!("\r\t\tintercept_id: {},", hex_utils::hex_str(&intercept_id.0));
+		println!("\r\t\tpayment_hash: {},", htlc.payment_hash);
+		println!("\r\t\trequested_scid: {},", htlc.requested_next_hop_scid);
+		println!("\r\t\tinbound_amount_msat: {},", htlc.inbound_amount_msat);
+		println!("\r\t\texpected_outbound_amount_msat: {},", htlc.expected_outbound_amount_msat);
+		println!("\r\t\tintercepted_at: {},", htlc.intercepted_at_secs);
+		print!("\r\t}},");
+	}
+	println!("\n\r]");
+}
+
+/// Forwards an intercepted HTLC over `channel_id` for the amount the sender expected to reach the
+/// next hop, e.g. once a channel has been opened to it just in time.
+fn forward_intercepted_htlc(
+	intercept_id: InterceptId, channel_id: [u8; 32], channel_manager: &ChannelManager,
+	intercepted_htlcs: &Mutex<HashMap<InterceptId, InterceptedHtlc>>,
+) {
+	let mut intercepted_htlcs = intercepted_htlcs.lock().unwrap();
+	let htlc = match intercepted_htlcs.get(&intercept_id) {
+		Some(htlc) => htlc,
+		None => {
+			println!("\rERROR: no intercepted HTLC {}", hex_utils::hex_str(&intercept_id.0));
+			return;
+		}
+	};
+	let chan_info = match channel_manager
+		.list_channels()
+		.into_iter()
+		.find(|chan| chan.channel_id.0 == channel_id)
+	{
+		Some(chan_info) => chan_info,
+		None => {
+			println!("\rERROR: channel {} not found", ChannelId(channel_id));
+			return;
+		}
+	};
+	if !chan_info.is_usable {
+		println!("\rERROR: channel {} isn't usable yet", chan_info.channel_id);
+		return;
+	}
+
+	match channel_manager.forward_intercepted_htlc(
+		intercept_id,
+		&chan_info.channel_id,
+		chan_info.counterparty.node_id,
+		htlc.expected_outbound_amount_msat,
+	) {
+		Ok(()) => {
+			println!(
+				"\rSUCCESS: forwarding {} msat of payment {} over channel {}",
+				htlc.expected_outbound_amount_msat, htlc.payment_hash, chan_info.channel_id
+			);
+			intercepted_htlcs.remove(&intercept_id);
+		}
+		Err(e) => println!("\rERROR: failed to forward the intercepted HTLC: {:?}", e),
+	}
+}
+
+/// Sets the forwarding fees of a single channel, leaving the defaults of other channels alone.
+fn set_channel_fee(
+	channel_id: [u8; 32], channel_manager: &ChannelManager, update: &ChannelConfigUpdate,
+) {
+	let chan_info = match channel_manager
+		.list_channels()
+		.into_iter()
+		.find(|chan| chan.channel_id.0 == channel_id)
+	{
+		Some(chan_info) => chan_info,
+		None => {
+			println!("\rERROR: channel {} not found", ChannelId(channel_id));
+			return;
+		}
+	};
+
+	if let Err(e) = channel_manager.update_partial_channel_config(
+		&chan_info.counterparty.node_id,
+		&[chan_info.channel_id],
+		update,
+	) {
+		println!("\rERROR: failed to set the channel fee: {:?}", e);
+		return;
+	}
+	println!("\rSUCCESS: updated the forwarding fees of channel {}", chan_info.channel_id);
+	if !chan_info.is_public {
+		println!("\rNOTE: the channel isn't announced, so its fees are only seen through invoice route hints");
+	}
+}
+
+/// Checks a CLTV expiry delta leaves us enough blocks to claim an HTLC on-chain, as LDK
+/// refuses to forward with a smaller one.
+fn validate_cltv_expiry_delta(cltv_expiry_delta: u16) -> Result<(), String> {
+	if cltv_expiry_delta < MIN_CLTV_EXPIRY_DELTA {
+		return Err(format!(
+			"the CLTV delta must be at least {} blocks, got {}",
+			MIN_CLTV_EXPIRY_DELTA, cltv_expiry_delta
+		));
+	}
+	Ok(())
+}
+
+/// Returns the update setting a channel's forwarding parameters to those of `channel_config`,
+/// leaving the rest of its config alone.
+fn forwarding_config_update(channel_config: &ChannelConfig) -> ChannelConfigUpdate {
+	ChannelConfigUpdate {
+		forwarding_fee_base_msat: Some(channel_config.forwarding_fee_base_msat),
+		forwarding_fee_proportional_millionths: Some(
+			channel_config.forwarding_fee_proportional_millionths,
+		),
+		cltv_expiry_delta: Some(channel_config.cltv_expiry_delta),
+		..Default::default()
+	}
+}
+
+/// Returns the description of the invoice, unless it only commits to the hash of one.
+fn invoice_description(invoice: &Bolt11Invoice) -> Option<String> {
+	match invoice.description() {
+		Bolt11InvoiceDescription::Direct(description) => Some(description.to_string()),
+		Bolt11InvoiceDescription::Hash(_) => None,
+	}
+}
+
+fn list_payments(
+	inbound_payments: &PaymentInfoStorage, outbound_payments: &PaymentInfoStorage, network: Network,
+) {
+	print!("\r[");
+
+	let inbound_payments = &inbound_payments.payments;
+	if !inbound_payments.is_empty() {
+		println!();
+		for (payment_hash, payment_info) in inbound_payments {
+			println!("\r\t{{");
+			println!("\r\t\tamount_millisatoshis: {},", payment_info.amt_msat);
+			println!("\r\t\tpayment_hash: {},", payment_hash);
+			println!("\r\t\thtlc_direction: inbound,");
+			println!(
+				"\r\t\thtlc_status: {},",
+				match payment_info.status {
+					HTLCStatus::Pending => "pending",
+					HTLCStatus::Succeeded => "succeeded",
+					HTLCStatus::Failed => "failed",
+				}
+			);
+			if let Some(pixel) = payment_info.yuv_pixel {
+				println!("\r\t\tyuv_chroma: {},", pixel.chroma.to_address(network));
+				println!("\r\t\tyuv_amount: {},", pixel.luma.amount);
+			}
+			if let Some(description) = &payment_info.description {
+				println!("\r\t\tdescription: {},", description);
+			}
+
+			print!("\r\t}},\n\r");
+		}
+	}
+
+	let outbound_payments = &outbound_payments.payments;
+	for (payment_hash, payment_info) in outbound_payments {
+		println!();
+		println!("\r\t{{");
+		println!("\r\t\tamount_millisatoshis: {},", payment_info.amt_msat);
+		println!("\r\t\tpayment_hash: {},", payment_hash);
+		println!("\r\t\thtlc_direction: outbound,");
+		println!(
+			"\r\thtlc_status: {},",
+			match payment_info.status {
+				HTLCStatus::Pending => "pending",
+				HTLCStatus::Succeeded => "succeeded",
+				HTLCStatus::Failed => "failed",
+			}
+		);
+		if let Some(pixel) = payment_info.yuv_pixel {
+			println!("\r\t\tyuv_chroma: {},", pixel.chroma.to_address(network));
+			println!("\r\t\tyuv_amount: {},", pixel.luma.amount);
+		}
+		if let Some(description) = &payment_info.description {
+			println!("\r\t\tdescription: {},", description);
+		}
+
+		println!("\r\t}},\n\r");
+	}
+	println!("]");
+}
+
+pub(crate) fn list_channels_json(
+	channel_manager: &Arc<ChannelManager>, network_graph: &Arc<NetworkGraph>, network: Network,
+) -> serde_json::Value {
+	let read_only_graph = network_graph.read_only();
+	let pending_yuv_txids = channel_manager.get_pending_yuv_txs();
+	let channels = channel_manager
+		.list_channels()
+		.into_iter()
+		.map(|chan_info| {
+			let peer_alias = read_only_graph
+				.nodes()
+				.get(&NodeId::from_pubkey(&chan_info.counterparty.node_id))
+				.and_then(|node_info| node_info.announcement_info.as_ref())
+				.map(|announcement| announcement.alias.to_string());
+
+			let pending_update_balance = chan_info.pending_update_balance.as_ref().map(|pending| {
+				let request_json = |new_balance_msat: u64, new_yuv_pixel_luma: Option<Luma>| {
+					json!({
+						"new_balance_msat": new_balance_msat,
+						"new_yuv_pixel_luma": new_yuv_pixel_luma.map(|luma| luma.amount.to_string()),
+					})
+				};
+
+				json!({
+					"holder_ready_to_update_balance": chan_info
+						.update_balance_amounts
+						.as_ref()
+						.map_or(0, |amounts| amounts.holders_msat),
+					"counterparty_ready_to_update_balance": chan_info
+						.update_balance_amounts
+						.as_ref()
+						.map_or(0, |amounts| amounts.counterpartys_msat),
+					"inbound": pending.inbound_request.as_ref().map(|inbound| request_json(
+						inbound.inner().new_balance_msat,
+						inbound.inner().new_yuv_pixel_luma,
+					)),
+					"outbound": pending.outbound_request.as_ref().map(|outbound| request_json(
+						outbound.inner().new_balance_msat,
+						outbound.inner().new_yuv_pixel_luma,
+					)),
+				})
+			});
+
+			json!({
+				"channel_id": chan_info.channel_id.to_string(),
+				"funding_txid": chan_info.funding_txo.map(|funding_txo| funding_txo.txid.to_string()),
+				"peer_pubkey": hex_utils::hex_str(&chan_info.counterparty.node_id.serialize()),
+				"peer_alias": peer_alias,
+				"short_channel_id": chan_info.short_channel_id,
+				"inbound_scid_alias": chan_info.inbound_scid_alias,
+				"outbound_scid_alias": chan_info.outbound_scid_alias,
+				"funding_confirmations": chan_info.confirmations,
+				"funding_confirmations_required": chan_info.confirmations_required,
+				"htlc_limits": {
+					"inbound": {
+						"minimum_msat": chan_info.inbound_htlc_minimum_msat,
+						"maximum_msat": chan_info.inbound_htlc_maximum_msat,
+					},
+					"outbound": {
+						"minimum_msat_configured": chan_info.counterparty.outbound_htlc_minimum_msat,
+						"minimum_msat_considering_dust": chan_info.next_outbound_htlc_minimum_msat,
+						"maximum_msat": chan_info.counterparty.outbound_htlc_maximum_msat,
+					},
+				},
+				"is_channel_ready": chan_info.is_channel_ready,
+				"channel_value_satoshis": chan_info.channel_value_satoshis,
+				"outbound_capacity_msat": chan_info.outbound_capacity_msat,
+				"inbound_capacity_msat": chan_info.inbound_capacity_msat,
+				"holder_reserved_satoshis": chan_info.unspendable_punishment_reserve,
+				"counterparty_reserved_satoshis": chan_info.counterparty.unspendable_punishment_reserve,
+				"channel_can_send_payments": chan_info.is_usable,
+				"public": chan_info.is_public,
+				"yuv_holder_pixel": chan_info.yuv_holder_pixel.map(|pixel| pixel_json(&pixel, network)),
+				"yuv_counterparty_pixel": chan_info
+					.yuv_counterparty_pixel
+					.map(|pixel| pixel_json(&pixel, network)),
+				"yuv_funding_status": chan_info.yuv_holder_pixel.map(|_| {
+					yuv_funding_status(
+						chan_info.funding_txo.map(|funding_txo| funding_txo.txid),
+						chan_info.is_channel_ready,
+						&pending_yuv_txids,
+					)
+				}),
+				"pending_update_balance": pending_update_balance,
+			})
+		})
+		.collect::<Vec<_>>();
+
+	json!(channels)
+}
+
+fn list_payments_json(
+	inbound_payments: &PaymentInfoStorage, outbound_payments: &PaymentInfoStorage, network: Network,
+) -> serde_json::Value {
+	let payment_json = |payment_hash: &PaymentHash, payment_info: &PaymentInfo, direction: &str| {
+		json!({
+			"amount_millisatoshis": payment_info.amt_msat.0,
+			"payment_hash": payment_hash.to_string(),
+			"htlc_direction": direction,
+			"htlc_status": match payment_info.status {
+				HTLCStatus::Pending => "pending",
+				HTLCStatus::Succeeded => "succeeded",
+				HTLCStatus::Failed => "failed",
+			},
+			"yuv_pixel": payment_info.yuv_pixel.map(|pixel| pixel_json(&pixel, network)),
+			"description": payment_info.description,
+		})
+	};
+
+	let payments = inbound_payments
+		.payments
+		.iter()
+		.map(|(payment_hash, payment_info)| payment_json(payment_hash, payment_info, "inbound"))
+		.chain(outbound_payments.payments.iter().map(|(payment_hash, payment_info)| {
+			payment_json(payment_hash, payment_info, "outbound")
+		}))
+		.collect::<Vec<_>>();
+
+	json!(payments)
+}
+
+/// Luma amounts are `u128`s, which don't fit into a JSON number, so they are encoded as strings.
+pub(crate) fn pixel_json(pixel: &Pixel, network: Network) -> serde_json::Value {
+	json!({
+		"luma": pixel.luma.amount.to_string(),
+		"chroma": pixel.chroma.to_address(network).to_string(),
+	})
+}
+
+fn print_json(value: &serde_json::Value) {
+	match serde_json::to_string_pretty(value) {
+		Ok(json) => println!("\r{}", json),
+		Err(e) => println!("\rERROR: failed to serialize output: {}", e),
 	}
-	println!("]");
 }
 
 pub(crate) async fn connect_peer_if_necessary(
-	pubkey: PublicKey, peer_addr: SocketAddr, peer_manager: Arc<PeerManager>,
+	pubkey: PublicKey, peer_addrs: &[SocketAddress], peer_manager: Arc<PeerManager>,
+	proxy: Option<SocketAddr>,
 ) -> Result<(), ()> {
 	for peer in peer_manager.list_peers() {
 		if peer.counterparty_node_id == pubkey {
 			return Ok(());
 		}
 	}
-	let res = do_connect_peer(pubkey, peer_addr, peer_manager).await;
-	if res.is_err() {
-		println!("\rERROR: failed to connect to peer");
+	let res = do_connect_peer_addrs(pubkey, peer_addrs, peer_manager, proxy).await;
+	if res.is_err() {
+		println!("\rERROR: failed to connect to peer");
+	}
+	res
+}
+
+/// Tries each of the peer's addresses in order, until one of them connects.
+pub(crate) async fn do_connect_peer_addrs(
+	pubkey: PublicKey, peer_addrs: &[SocketAddress], peer_manager: Arc<PeerManager>,
+	proxy: Option<SocketAddr>,
+) -> Result<(), ()> {
+	for peer_addr in peer_addrs {
+		if do_connect_peer(pubkey, peer_addr, Arc::clone(&peer_manager), proxy).await.is_ok() {
+			return Ok(());
+		}
+	}
+	Err(())
+}
+
+/// Connects to the peer directly or, if a `proxy` is given, through it. Onion addresses can only
+/// be reached through a proxy.
+pub(crate) async fn do_connect_peer(
+	pubkey: PublicKey, peer_addr: &SocketAddress, peer_manager: Arc<PeerManager>,
+	proxy: Option<SocketAddr>,
+) -> Result<(), ()> {
+	let mut connection_closed_future: Pin<Box<dyn Future<Output = ()> + Send>> = match proxy {
+		Some(proxy) => {
+			let stream = match tokio::time::timeout(
+				Duration::from_secs(10),
+				socks5::connect(proxy, peer_addr),
+			)
+			.await
+			{
+				Ok(Ok(stream)) => stream.into_std().map_err(|_| ())?,
+				Ok(Err(e)) => {
+					println!(
+						"\rERROR: failed to connect to {} through the proxy: {}",
+						peer_addr, e
+					);
+					return Err(());
+				}
+				Err(_) => return Err(()),
+			};
+			Box::pin(lightning_net_tokio::setup_outbound(Arc::clone(&peer_manager), pubkey, stream))
+		}
+		None => {
+			let socket_addr = match peer_addr.to_socket_addrs().map(|mut addrs| addrs.next()) {
+				Ok(Some(socket_addr)) => socket_addr,
+				_ => return Err(()),
+			};
+			match lightning_net_tokio::connect_outbound(
+				Arc::clone(&peer_manager),
+				pubkey,
+				socket_addr,
+			)
+			.await
+			{
+				Some(connection_closed_future) => Box::pin(connection_closed_future),
+				None => return Err(()),
+			}
+		}
+	};
+	loop {
+		tokio::select! {
+			_ = &mut connection_closed_future => return Err(()),
+			_ = tokio::time::sleep(Duration::from_millis(10)) => {},
+		}
+		if peer_manager
+			.list_peers()
+			.iter()
+			.find(|details| details.counterparty_node_id == pubkey)
+			.is_some()
+		{
+			return Ok(());
+		}
+	}
+}
+
+/// Connects to every channel peer we aren't connected to, like the reconnection task does but
+/// without waiting out its backoff, returning how many of them were connected to out of how many
+/// were tried.
+fn reconnect_channel_peers(
+	channel_manager: &Arc<ChannelManager>, peer_manager: &Arc<PeerManager>, ldk_data_dir: &str,
+	proxy: Option<SocketAddr>,
+) -> Result<(usize, usize), std::io::Error> {
+	let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir);
+	let peer_data = read_channel_peer_data(Path::new(&peer_data_path))?;
+	let connected_peers = peer_manager.list_peers();
+
+	let mut node_ids = Vec::new();
+	for chan_info in channel_manager.list_channels() {
+		let node_id = chan_info.counterparty.node_id;
+		if !node_ids.contains(&node_id)
+			&& !connected_peers.iter().any(|peer| peer.counterparty_node_id == node_id)
+		{
+			node_ids.push(node_id);
+		}
+	}
+
+	let mut connected = 0;
+	let mut total = 0;
+	for node_id in node_ids {
+		let Some(peer_addrs) = peer_data.get(&node_id) else {
+			continue;
+		};
+		total += 1;
+		if tokio::runtime::Handle::current()
+			.block_on(do_connect_peer_addrs(node_id, peer_addrs, Arc::clone(peer_manager), proxy))
+			.is_ok()
+		{
+			connected += 1;
+		} else {
+			println!("\rERROR: failed to reconnect to peer {}", node_id);
+		}
+	}
+	Ok((connected, total))
+}
+
+fn do_disconnect_peer(
+	pubkey: PublicKey, peer_manager: Arc<PeerManager>, channel_manager: Arc<ChannelManager>,
+) -> Result<(), ()> {
+	//check for open channels with peer
+	for channel in channel_manager.list_channels() {
+		if channel.counterparty.node_id == pubkey {
+			println!(
+				"\rError: Node has an active channel with this peer, close any channels first"
+			);
+			return Err(());
+		}
+	}
+
+	//check the pubkey matches a valid connected peer
+	let peers = peer_manager.list_peers();
+	if !peers.iter().any(|peer| pubkey == peer.counterparty_node_id) {
+		println!("\rError: Could not find peer {}", pubkey);
+		return Err(());
+	}
+
+	peer_manager.disconnect_by_node_id(pubkey);
+	Ok(())
+}
+
+/// How long `waitchannel` waits for a channel to become usable unless told otherwise.
+const DEFAULT_WAIT_CHANNEL_TIMEOUT_SECS: u64 = 600;
+
+/// Blocks until the channel is usable, printing whether it became so before the timeout.
+///
+/// The channel ID changes from the temporary one once the funding transaction is created, so the
+/// channel is followed by its counterparty and the user channel ID it was opened with instead.
+fn wait_channel(channel_id: ChannelId, timeout: Duration, channel_manager: &ChannelManager) {
+	let Some((counterparty_node_id, user_channel_id)) = channel_manager
+		.list_channels()
+		.into_iter()
+		.find(|chan| chan.channel_id == channel_id)
+		.map(|chan| (chan.counterparty.node_id, chan.user_channel_id))
+	else {
+		println!("\rERROR: channel {} not found", channel_id);
+		return;
+	};
+
+	let start = Instant::now();
+	loop {
+		match channel_manager.list_channels().into_iter().find(|chan| {
+			chan.counterparty.node_id == counterparty_node_id
+				&& chan.user_channel_id == user_channel_id
+		}) {
+			Some(chan) if chan.is_usable => {
+				println!("\rSUCCESS: channel {} is ready", chan.channel_id);
+				return;
+			}
+			Some(_) => {}
+			None => {
+				println!("\rERROR: channel {} not found, it may have been closed", channel_id);
+				return;
+			}
+		}
+		if start.elapsed() >= timeout {
+			println!(
+				"\rERROR: channel {} wasn't ready after {} seconds",
+				channel_id,
+				timeout.as_secs()
+			);
+			return;
+		}
+		std::thread::sleep(Duration::from_millis(500));
+	}
+}
+
+/// Parses the `pubkey@host:port,amt_satoshis` entries, separated by `;`, of `batchopenchannel`.
+fn parse_batch_channels(batch: &str) -> Result<Vec<(PublicKey, SocketAddress, u64)>, String> {
+	let mut channels = Vec::new();
+	for entry in batch.split(';').filter(|entry| !entry.is_empty()) {
+		let (peer_info, amt_sat) = entry.rsplit_once(',').ok_or_else(|| {
+			format!(
+				"invalid channel `{}`, should be formatted as `pubkey@host:port,amt_satoshis`",
+				entry
+			)
+		})?;
+		let (pubkey, peer_addr) = parse_peer_info(peer_info.to_string())
+			.map_err(|e| format!("invalid channel `{}`: {}", entry, e))?;
+		let amt_sat = amt_sat
+			.parse()
+			.map_err(|_| format!("invalid channel `{}`: amount must be a number", entry))?;
+		channels.push((pubkey, peer_addr, amt_sat));
+	}
+	if channels.is_empty() {
+		return Err("no channels to open were given".to_string());
+	}
+	Ok(channels)
+}
+
+/// Connects to each peer and opens a channel to it, carrying on with the rest of the channels if
+/// one of them fails.
+fn batch_open_channels(
+	channels: Vec<(PublicKey, SocketAddress, u64)>, config: UserConfig,
+	peer_manager: &Arc<PeerManager>, channel_manager: &Arc<ChannelManager>, ldk_data_dir: &str,
+	proxy: Option<SocketAddr>,
+) {
+	let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir);
+	let total = channels.len();
+	let mut failed = Vec::new();
+	for (pubkey, peer_addr, amt_sat) in channels {
+		if tokio::runtime::Handle::current()
+			.block_on(connect_peer_if_necessary(
+				pubkey,
+				&[peer_addr.clone()],
+				Arc::clone(peer_manager),
+				proxy,
+			))
+			.is_err()
+		{
+			failed.push(pubkey);
+			continue;
+		}
+		if let Err(e) =
+			disk::persist_channel_peer(Path::new(&peer_data_path), &pubkey, &peer_addr.to_string())
+		{
+			println!("\rERROR: failed to persist peer data: {}", e);
+		}
+		if open_channel(pubkey, amt_sat, 0, None, config.clone(), Arc::clone(channel_manager), None)
+			.is_err()
+		{
+			failed.push(pubkey);
+		}
+	}
+
+	println!("\rInitiated {} of {} channels.", total - failed.len(), total);
+	for pubkey in failed {
+		println!("\r\tfailed: {}", pubkey);
 	}
-	res
 }
 
-pub(crate) async fn do_connect_peer(
-	pubkey: PublicKey, peer_addr: SocketAddr, peer_manager: Arc<PeerManager>,
-) -> Result<(), ()> {
-	match lightning_net_tokio::connect_outbound(Arc::clone(&peer_manager), pubkey, peer_addr).await
-	{
-		Some(connection_closed_future) => {
-			let mut connection_closed_future = Box::pin(connection_closed_future);
-			loop {
-				tokio::select! {
-					_ = &mut connection_closed_future => return Err(()),
-					_ = tokio::time::sleep(Duration::from_millis(10)) => {},
-				}
-				if peer_manager
-					.list_peers()
-					.iter()
-					.find(|details| details.counterparty_node_id == pubkey)
-					.is_some()
-				{
-					return Ok(());
-				}
-			}
-		}
-		None => Err(()),
+/// Checks that the amounts pushed to the counterparty on open fit within the channel, i.e. that
+/// `push_msat` doesn't exceed its value and `push_yuv_luma` doesn't exceed its pixel's luma.
+fn validate_push_amounts(
+	channel_amt_sat: u64, push_msat: u64, yuv_pixel: Option<&Pixel>, push_yuv_luma: Option<u128>,
+) -> Result<(), String> {
+	if push_msat > channel_amt_sat.saturating_mul(1000) {
+		return Err(format!(
+			"push amount of {} msat exceeds the channel value of {} sat",
+			push_msat, channel_amt_sat
+		));
+	}
+	match (push_yuv_luma, yuv_pixel) {
+		(Some(_), None) => Err("pushing YUV tokens requires a channel pixel".to_string()),
+		(Some(push_yuv_luma), Some(pixel)) if push_yuv_luma > pixel.luma.amount => Err(format!(
+			"pushed luma of {} exceeds the channel luma of {}",
+			push_yuv_luma, pixel.luma.amount
+		)),
+		_ => Ok(()),
 	}
 }
 
-fn do_disconnect_peer(
-	pubkey: PublicKey, peer_manager: Arc<PeerManager>, channel_manager: Arc<ChannelManager>,
-) -> Result<(), ()> {
-	//check for open channels with peer
-	for channel in channel_manager.list_channels() {
-		if channel.counterparty.node_id == pubkey {
-			println!(
-				"\rError: Node has an active channel with this peer, close any channels first"
-			);
-			return Err(());
-		}
-	}
+/// Checks that the wallet holds enough tokens of the pixel's chroma to fund a channel with it,
+/// so that the open doesn't fail once the funding transaction is built and force-close the channel.
+///
+/// Tokens already spent by pending funding transactions are left out, as is the luma of outbound
+/// channels still waiting for theirs to be built.
+pub(crate) async fn check_yuv_funding_balance(
+	wallet: &Arc<TokioMutex<Wallet>>, channel_manager: &ChannelManager, pixel: &Pixel,
+	network: Network,
+) -> Result<(), String> {
+	let available = wallet
+		.lock()
+		.await
+		.get_unreserved_yuv_balance(&pixel.chroma)
+		.await
+		.map_err(|e| format!("failed to get YUV balance: {:?}", e))?;
+	let committed = channel_manager
+		.list_channels()
+		.into_iter()
+		.filter(|chan| chan.is_outbound && chan.funding_txo.is_none())
+		.filter_map(|chan| {
+			let holder_pixel = chan.yuv_holder_pixel.filter(|p| p.chroma == pixel.chroma)?;
+			let counterparty_luma = chan.yuv_counterparty_pixel.map_or(0, |p| p.luma.amount);
+			Some(holder_pixel.luma.amount + counterparty_luma)
+		})
+		.sum();
 
-	//check the pubkey matches a valid connected peer
-	let peers = peer_manager.list_peers();
-	if !peers.iter().any(|peer| pubkey == peer.counterparty_node_id) {
-		println!("\rError: Could not find peer {}", pubkey);
-		return Err(());
-	}
+	validate_yuv_funding(pixel.luma.amount, available, committed)
+		.map_err(|e| format!("{} of chroma {}", e, pixel.chroma.to_address(network)))
+}
 
-	peer_manager.disconnect_by_node_id(pubkey);
+/// Checks that `luma` fits in the `available` balance once `committed` is set aside for pending
+/// channel opens.
+fn validate_yuv_funding(luma: u128, available: u128, committed: u128) -> Result<(), String> {
+	let spendable = available.saturating_sub(committed);
+	if luma > spendable {
+		return Err(format!(
+			"insufficient YUV balance to fund the channel: requested luma {}, but only {} is available ({} held, {} committed to pending channel opens)",
+			luma, spendable, available, committed
+		));
+	}
 	Ok(())
 }
 
-fn open_channel(
-	peer_pubkey: PublicKey, channel_amt_sat: u64, config: UserConfig,
-	channel_manager: Arc<ChannelManager>, yuv_pixel: Option<Pixel>,
-) -> Result<(), ()> {
+pub(crate) fn open_channel(
+	peer_pubkey: PublicKey, channel_amt_sat: u64, push_msat: u64, push_yuv_luma: Option<u128>,
+	config: UserConfig, channel_manager: Arc<ChannelManager>, yuv_pixel: Option<Pixel>,
+) -> Result<ChannelId, String> {
+	if let Err(e) =
+		validate_push_amounts(channel_amt_sat, push_msat, yuv_pixel.as_ref(), push_yuv_luma)
+	{
+		let err = format!("failed to open channel: {}", e);
+		println!("\rERROR: {}", err);
+		return Err(err);
+	}
+	// A unique user channel ID lets `waitchannel` follow the channel once its ID changes.
+	let mut random_bytes = [0u8; 16];
+	thread_rng().fill_bytes(&mut random_bytes);
+	let user_channel_id = u128::from_be_bytes(random_bytes);
 	match channel_manager.create_channel(
 		peer_pubkey,
 		channel_amt_sat,
-		0,
-		0,
+		push_msat,
+		user_channel_id,
 		yuv_pixel,
-		None,
+		push_yuv_luma.map(Luma::from),
 		Some(config),
 	) {
-		Ok(_) => {
+		Ok(channel_id) => {
 			println!("\rEVENT: initiated channel with peer {}. ", peer_pubkey);
-			Ok(())
+			Ok(channel_id)
 		}
 		Err(e) => {
-			println!("\rERROR: failed to open channel: {:?}", e);
-			Err(())
+			let err = format!("failed to open channel: {:?}", e);
+			println!("\rERROR: {}", err);
+			Err(err)
 		}
 	}
 }
 
-fn send_payment(
+/// Sends a payment for the invoice. The retry strategy and fee limit default to the payment
+/// policy's, unless given.
+pub(crate) fn send_payment(
 	channel_manager: &ChannelManager, invoice: &Bolt11Invoice, required_amount_msat: Option<u64>,
-	outbound_payments: &mut PaymentInfoStorage, fs_store: Arc<FilesystemStore>,
-) {
+	required_yuv_amount: Option<u128>, max_fee_msat: Option<u64>, retry: Option<Retry>,
+	policy: &PaymentPolicy, outbound_payments: &mut PaymentInfoStorage,
+	fs_store: Arc<FilesystemStore>,
+) -> Result<PaymentHash, String> {
+	let fail = |err: String| {
+		println!("{}", err);
+		print!("> ");
+		Err(err)
+	};
+
 	let payment_id = PaymentId((*invoice.payment_hash()).to_byte_array());
 	let payment_secret = Some(*invoice.payment_secret());
 	let zero_amt_invoice =
@@ -1211,19 +3708,15 @@ fn send_payment(
 		if let Some(amt_msat) = required_amount_msat {
 			payment_parameters_from_zero_amount_invoice(invoice, amt_msat)
 		} else {
-			println!("Need an amount for the given 0-value invoice");
-			print!("> ");
-			return;
+			return fail("Need an amount for the given 0-value invoice".to_string());
 		}
 	} else {
 		if required_amount_msat.is_some() && invoice.amount_milli_satoshis() != required_amount_msat
 		{
-			println!(
+			return fail(format!(
 				"Amount didn't match invoice value of {}msat",
 				invoice.amount_milli_satoshis().unwrap_or(0)
-			);
-			print!("> ");
-			return;
+			));
 		}
 		payment_parameters_from_invoice(invoice)
 	};
@@ -1231,14 +3724,31 @@ fn send_payment(
 	let (payment_hash, recipient_onion, mut route_params) = match pay_params_opt {
 		Ok(res) => res,
 		Err(e) => {
-			println!("Failed to parse invoice: {:?}", e);
-			print!("> ");
-			return;
+			return fail(format!("Failed to parse invoice: {:?}", e));
 		}
 	};
 
-	// FIXME: remove it after tests are done.
-	route_params.max_total_routing_fee_msat = None;
+	let max_fee_msat =
+		max_fee_msat.or_else(|| policy.fee_limit_msat(route_params.final_value_msat));
+	route_params.max_total_routing_fee_msat = max_fee_msat;
+
+	let yuv_pixel = match (invoice.yuv_pixel(), required_yuv_amount) {
+		(None, Some(_)) => {
+			return fail("--yuv-amount can only be used with YUV invoices".to_string());
+		}
+		(Some(pixel), Some(yuv_amount)) => {
+			if pixel.luma.amount != 0 && pixel.luma.amount != yuv_amount {
+				return fail(format!(
+					"YUV amount didn't match invoice value of {}",
+					pixel.luma.amount
+				));
+			}
+			let pixel = Pixel::new(Luma::from(yuv_amount), pixel.chroma);
+			route_params.yuv_pixel = Some(pixel);
+			Some(pixel)
+		}
+		(pixel_opt, None) => pixel_opt,
+	};
 
 	outbound_payments.payments.insert(
 		payment_hash,
@@ -1247,45 +3757,200 @@ fn send_payment(
 			secret: payment_secret,
 			status: HTLCStatus::Pending,
 			amt_msat: MillisatAmount(invoice.amount_milli_satoshis()),
-			yuv_pixel: invoice.yuv_pixel(),
+			yuv_pixel,
+			max_fee_msat,
+			description: invoice_description(invoice),
 		},
 	);
-	fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode()).unwrap();
+	if let Err(e) =
+		disk::persist_payment_info(&fs_store, OUTBOUND_PAYMENTS_FNAME, &outbound_payments)
+	{
+		outbound_payments.payments.remove(&payment_hash);
+		return fail(format!("failed to persist outbound payments: {}", e));
+	}
+	if policy.probe {
+		probe_payment_routes(channel_manager, &route_params);
+	}
 	match channel_manager.send_payment(
 		payment_hash,
 		recipient_onion,
 		payment_id,
 		route_params,
-		Retry::Timeout(Duration::from_secs(10)),
+		retry.unwrap_or_else(|| policy.retry()),
 	) {
 		Ok(_payment_id) => {
 			let payee_pubkey = invoice.recover_payee_pub_key();
 			let amt_msat = invoice.amount_milli_satoshis().unwrap();
-			let pixel_opt = invoice.yuv_pixel();
 			println!("\rEVENT: initiated sending {} msats to {}", amt_msat, payee_pubkey);
-			if let Some(pixel) = pixel_opt {
+			if let Some(pixel) = yuv_pixel {
 				println!("... with yuv amount {} chroma {}", pixel.luma.amount, pixel.chroma);
 			}
+			Ok(payment_hash)
 		}
 		Err(e) => {
-			println!("\rERROR: failed to send payment: {:?}", e);
+			let err = match (e, max_fee_msat) {
+				(RetryableSendFailure::RouteNotFound, Some(max_fee_msat)) => format!(
+					"failed to send payment: no route found within the fee limit of {} msat",
+					max_fee_msat
+				),
+				(e, _) => format!("failed to send payment: {:?}", e),
+			};
+			println!("\rERROR: {}", err);
 			outbound_payments.payments.get_mut(&payment_hash).unwrap().status = HTLCStatus::Failed;
-			fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode()).unwrap();
+			if let Err(e) =
+				disk::persist_payment_info(&fs_store, OUTBOUND_PAYMENTS_FNAME, &outbound_payments)
+			{
+				println!("\rERROR: failed to persist outbound payments: {}", e);
+			}
+			Err(err)
+		}
+	}
+}
+
+/// Stops retrying a pending outbound payment and marks it as failed, so that it no longer shows
+/// up as pending. Any HTLCs already in flight may still resolve.
+fn abandon_payment(
+	payment_hash: PaymentHash, channel_manager: &ChannelManager,
+	outbound_payments: &mut PaymentInfoStorage, fs_store: &FilesystemStore,
+) {
+	let payment = match outbound_payments.payments.get_mut(&payment_hash) {
+		Some(payment) => payment,
+		None => {
+			println!("\rERROR: unknown outbound payment {}", payment_hash);
+			return;
+		}
+	};
+	if !matches!(payment.status, HTLCStatus::Pending) {
+		println!("\rERROR: payment {} is not pending", payment_hash);
+		return;
+	}
+
+	// Outbound payments are always sent with their payment hash as the payment id.
+	channel_manager.abandon_payment(PaymentId(payment_hash.0));
+	payment.status = HTLCStatus::Failed;
+	if let Err(e) =
+		disk::persist_payment_info(&fs_store, OUTBOUND_PAYMENTS_FNAME, &outbound_payments)
+	{
+		println!("\rERROR: failed to persist outbound payments: {}", e);
+		return;
+	}
+	println!("\rSUCCESS: abandoned payment {}", payment_hash);
+}
+
+/// Probes the routes of a payment right before it's sent, so that the scorer learns about their
+/// liquidity. The payment is sent whether or not the probes could be.
+fn probe_payment_routes(channel_manager: &ChannelManager, route_params: &RouteParameters) {
+	if let Err(e) = channel_manager.send_preflight_probes(route_params.clone(), None) {
+		println!("\rWARNING: failed to probe the payment routes: {:?}", e);
+	}
+}
+
+/// Probes the routes to `dest_pubkey` for `amt_msat`, without paying anything. Whether each probe
+/// got through is reported once it resolves, by the `ProbeSuccessful` and `ProbeFailed` events.
+fn send_probe(channel_manager: &ChannelManager, dest_pubkey: PublicKey, amt_msat: u64) {
+	match channel_manager.send_spontaneous_preflight_probes(dest_pubkey, amt_msat, 40, None) {
+		Ok(probes) => {
+			println!("\rSUCCESS: sent {} probe(s) to {}", probes.len(), dest_pubkey);
+			for (payment_hash, _) in probes {
+				println!("\r\t probe payment_hash: {}", payment_hash);
+			}
+		}
+		Err(e) => println!("\rERROR: failed to send probes: {:?}", e),
+	}
+}
+
+/// Prints the route a payment of `amt_msat` to `dest_pubkey` would currently take, with the same
+/// parameters as `keysend`, without sending anything.
+fn get_route_info(
+	channel_manager: &ChannelManager, router: &Router, dest_pubkey: PublicKey, amt_msat: u64,
+	yuv_pixel: Option<Pixel>,
+) {
+	let mut route_params = RouteParameters::from_payment_params_and_value(
+		PaymentParameters::for_keysend(dest_pubkey, 40, false),
+		amt_msat,
+	);
+	route_params.yuv_pixel = yuv_pixel;
+
+	let first_hops = channel_manager.list_usable_channels();
+	let route = match router.find_route(
+		&channel_manager.get_our_node_id(),
+		&route_params,
+		Some(&first_hops.iter().collect::<Vec<_>>()),
+		channel_manager.compute_inflight_htlcs(),
+	) {
+		Ok(route) => route,
+		Err(e) => {
+			println!("\rERROR: failed to find a route: {}", e.err);
+			return;
 		}
 	};
+
+	println!("\r{{");
+	println!("\r\t total_fees_msat: {},", route.get_total_fees());
+	for path in &route.paths {
+		println!("\r\t path: {{");
+		println!("\r\t\t amount_msat: {},", path.final_value_msat());
+		println!("\r\t\t fees_msat: {},", path.fee_msat());
+		println!(
+			"\r\t\t total_cltv_expiry_delta: {},",
+			path.hops.iter().map(|hop| hop.cltv_expiry_delta).sum::<u32>()
+		);
+		if let Some(pixel) = yuv_pixel {
+			// Of the channels along the path, only the one the payment leaves through can be
+			// checked for holding enough of the token.
+			let first_hop_holds_yuv = path.hops.first().map_or(false, |hop| {
+				first_hops.iter().any(|chan| {
+					chan.short_channel_id == Some(hop.short_channel_id)
+						&& chan.yuv_holder_pixel.map_or(false, |holder_pixel| {
+							holder_pixel.chroma == pixel.chroma
+								&& holder_pixel.luma.amount >= pixel.luma.amount
+						})
+				})
+			});
+			println!("\r\t\t first_hop_holds_yuv: {},", first_hop_holds_yuv);
+		}
+		for hop in &path.hops {
+			println!(
+				"\r\t\t hop: {} over channel {}, fee {} msat, cltv_expiry_delta {}",
+				hop.pubkey, hop.short_channel_id, hop.fee_msat, hop.cltv_expiry_delta
+			);
+		}
+		println!("\r\t }},");
+	}
+	println!("\r}}");
 }
 
 fn keysend<E: EntropySource>(
-	channel_manager: &ChannelManager, payee_pubkey: PublicKey, amt_msat: u64, entropy_source: &E,
+	channel_manager: &ChannelManager, payee_pubkey: PublicKey, amt_msat: u64,
+	yuv_pixel: Option<Pixel>, retry: Option<Retry>, policy: &PaymentPolicy, entropy_source: &E,
 	outbound_payments: &mut PaymentInfoStorage, fs_store: Arc<FilesystemStore>,
 ) {
+	// Tokens can only leave through a channel holding them, so don't even try to find a route
+	// otherwise.
+	if let Some(pixel) = yuv_pixel {
+		let has_yuv_channel = channel_manager.list_usable_channels().iter().any(|chan| {
+			chan.yuv_holder_pixel.map_or(false, |holder_pixel| {
+				holder_pixel.chroma == pixel.chroma && holder_pixel.luma.amount >= pixel.luma.amount
+			})
+		});
+		if !has_yuv_channel {
+			println!(
+				"\rERROR: failed to send payment: no usable channel holding {} of chroma {}",
+				pixel.luma.amount, pixel.chroma
+			);
+			return;
+		}
+	}
+
 	let payment_preimage = PaymentPreimage(entropy_source.get_secure_random_bytes());
 	let payment_hash = PaymentHash::from(payment_preimage);
 
-	let route_params = RouteParameters::from_payment_params_and_value(
+	let mut route_params = RouteParameters::from_payment_params_and_value(
 		PaymentParameters::for_keysend(payee_pubkey, 40, false),
 		amt_msat,
 	);
+	route_params.yuv_pixel = yuv_pixel;
+	route_params.max_total_routing_fee_msat = policy.fee_limit_msat(amt_msat);
 	outbound_payments.payments.insert(
 		payment_hash,
 		PaymentInfo {
@@ -1293,33 +3958,92 @@ fn keysend<E: EntropySource>(
 			secret: None,
 			status: HTLCStatus::Pending,
 			amt_msat: MillisatAmount(Some(amt_msat)),
-			yuv_pixel: None,
+			yuv_pixel,
+			max_fee_msat: route_params.max_total_routing_fee_msat,
+			description: None,
 		},
 	);
-	fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode()).unwrap();
+	if let Err(e) =
+		disk::persist_payment_info(&fs_store, OUTBOUND_PAYMENTS_FNAME, &outbound_payments)
+	{
+		outbound_payments.payments.remove(&payment_hash);
+		println!("\rERROR: failed to persist outbound payments: {}", e);
+		return;
+	}
+	if policy.probe {
+		probe_payment_routes(channel_manager, &route_params);
+	}
 	match channel_manager.send_spontaneous_payment_with_retry(
 		Some(payment_preimage),
 		RecipientOnionFields::spontaneous_empty(),
 		PaymentId(payment_hash.0),
 		route_params,
-		Retry::Timeout(Duration::from_secs(10)),
+		retry.unwrap_or_else(|| policy.retry()),
 	) {
 		Ok(_payment_hash) => {
 			println!("\rEVENT: initiated sending {} msats to {}", amt_msat, payee_pubkey);
+			if let Some(pixel) = yuv_pixel {
+				println!("... with yuv amount {} chroma {}", pixel.luma.amount, pixel.chroma);
+			}
 		}
 		Err(e) => {
 			println!("\rERROR: failed to send payment: {:?}", e);
 			outbound_payments.payments.get_mut(&payment_hash).unwrap().status = HTLCStatus::Failed;
-			fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode()).unwrap();
+			if let Err(e) =
+				disk::persist_payment_info(&fs_store, OUTBOUND_PAYMENTS_FNAME, &outbound_payments)
+			{
+				println!("\rERROR: failed to persist outbound payments: {}", e);
+			}
 		}
 	};
 }
 
-fn get_invoice(
+/// The description of the invoices and offers the node generates.
+const INVOICE_DESCRIPTION: &str = "ldk-tutorial-node";
+
+/// Creates a reusable offer for `amt_msat`, or for any amount if it's 0, which is paid to us over
+/// blinded paths. Each payer requests its own invoice for it through an onion message.
+pub(crate) fn create_offer(
+	amt_msat: u64, channel_manager: &ChannelManager,
+) -> Result<Offer, Bolt12SemanticError> {
+	let builder = channel_manager.create_offer_builder(INVOICE_DESCRIPTION.to_string())?;
+	let builder = if amt_msat > 0 { builder.amount_msats(amt_msat) } else { builder };
+	builder.build()
+}
+
+/// Requests an invoice for the offer, paying `amt_msat` or the offer's amount if not given. The
+/// payment has no payment hash until the invoice arrives, so it's only recorded once sent.
+fn pay_offer<E: EntropySource>(
+	offer: &Offer, amt_msat: Option<u64>, policy: &PaymentPolicy, channel_manager: &ChannelManager,
+	entropy_source: &E,
+) {
+	let payment_id = PaymentId(entropy_source.get_secure_random_bytes());
+	// Without an amount given, it's only known once the invoice arrives, so only the absolute fee
+	// limit applies.
+	let max_fee_msat =
+		amt_msat.map_or(policy.max_fee_msat, |amt_msat| policy.fee_limit_msat(amt_msat));
+	match channel_manager.pay_for_offer(
+		offer,
+		None,
+		amt_msat,
+		None,
+		payment_id,
+		policy.retry(),
+		max_fee_msat,
+	) {
+		Ok(()) => println!(
+			"\rSUCCESS: requested an invoice for the offer, the payment {} is sent once it arrives",
+			hex_utils::hex_str(&payment_id.0)
+		),
+		Err(e) => println!("\rERROR: failed to pay offer: {:?}", e),
+	}
+}
+
+pub(crate) fn get_invoice(
 	amt_msat: u64, inbound_payments: &mut PaymentInfoStorage, channel_manager: &ChannelManager,
 	keys_manager: Arc<KeysManager>, network: Network, expiry_secs: u32, yuv_pixel: Option<Pixel>,
 	logger: Arc<disk::FilesystemLogger>,
-) {
+) -> Result<Bolt11Invoice, String> {
 	let currency = match network {
 		Network::Bitcoin => Currency::Bitcoin,
 		Network::Testnet => Currency::BitcoinTestnet,
@@ -1327,7 +4051,7 @@ fn get_invoice(
 		Network::Signet => Currency::Signet,
 		_ => {
 			println!("\rERROR: unsupported network");
-			return;
+			return Err("unsupported network".to_string());
 		}
 	};
 
@@ -1342,7 +4066,7 @@ fn get_invoice(
 			logger,
 			currency,
 			Some(amt_msat),
-			"ldk-tutorial-node".to_string(),
+			INVOICE_DESCRIPTION.to_string(),
 			duration,
 			expiry_secs,
 			None,
@@ -1355,7 +4079,7 @@ fn get_invoice(
 			logger,
 			currency,
 			Some(amt_msat),
-			"ldk-tutorial-node".to_string(),
+			INVOICE_DESCRIPTION.to_string(),
 			duration,
 			expiry_secs,
 			None,
@@ -1368,8 +4092,9 @@ fn get_invoice(
 			inv
 		}
 		Err(e) => {
-			println!("\rERROR: failed to create invoice: {:?}", e);
-			return;
+			let err = format!("failed to create invoice: {:?}", e);
+			println!("\rERROR: {}", err);
+			return Err(err);
 		}
 	};
 
@@ -1382,16 +4107,36 @@ fn get_invoice(
 			status: HTLCStatus::Pending,
 			amt_msat: MillisatAmount(Some(amt_msat)),
 			yuv_pixel,
+			max_fee_msat: None,
+			description: Some(INVOICE_DESCRIPTION.to_string()),
 		},
 	);
+	Ok(invoice)
 }
 
-fn close_channel(
-	channel_id: [u8; 32], counterparty_node_id: PublicKey, channel_manager: Arc<ChannelManager>,
-) {
-	match channel_manager.close_channel(&ChannelId(channel_id), &counterparty_node_id) {
-		Ok(()) => println!("\rEVENT: initiating channel close"),
-		Err(e) => println!("\rERROR: failed to close channel: {:?}", e),
+/// Cooperatively closes the channel, at `feerate_sat_per_vb` if given or at the channel manager's
+/// default feerate otherwise.
+pub(crate) fn close_channel(
+	channel_id: [u8; 32], counterparty_node_id: PublicKey, feerate_sat_per_vb: Option<u32>,
+	channel_manager: Arc<ChannelManager>,
+) -> Result<(), String> {
+	// A vbyte is four weight units, so a sat/vB feerate is 250 sat per 1000 weight units.
+	let target_feerate_sat_per_kw = feerate_sat_per_vb.map(|feerate| feerate.saturating_mul(250));
+	match channel_manager.close_channel_with_feerate_and_script(
+		&ChannelId(channel_id),
+		&counterparty_node_id,
+		target_feerate_sat_per_kw,
+		None,
+	) {
+		Ok(()) => {
+			println!("\rEVENT: initiating channel close");
+			Ok(())
+		}
+		Err(e) => {
+			let err = format!("failed to close channel: {:?}", e);
+			println!("\rERROR: {}", err);
+			Err(err)
+		}
 	}
 }
 
@@ -1409,7 +4154,7 @@ fn force_close_channel(
 fn update_balance(
 	channel_id: [u8; 32], counterparty_node_id: PublicKey, new_balance_msat: Option<u64>,
 	new_yuv_luma: Option<Luma>, channel_manager: Arc<ChannelManager>,
-) {
+) -> Result<(), ()> {
 	match channel_manager.update_balance(UpdateBalance {
 		channel_id: ChannelId(channel_id),
 		node_id: counterparty_node_id,
@@ -1421,14 +4166,91 @@ fn update_balance(
 			if is_applying {
 				println!("\rEVENT: exchanging commitments with updated balances");
 			}
+			Ok(())
+		}
+		Err(e) => {
+			println!("\rERROR: failed to send update-balance request: {:?}", e);
+			Err(())
+		}
+	}
+}
+
+/// Accepting a request proposes the complementary balances on our side, so that both sides agree
+/// on the same split of the channel, while rejecting it sends a request without any new balances,
+/// which revokes the pending one.
+pub(crate) fn respond_update_balance(
+	channel_id: ChannelId, accept: bool,
+	update_balance_requests: &Mutex<UpdateBalanceRequestStorage>,
+	channel_manager: Arc<ChannelManager>, fs_store: &FilesystemStore,
+) {
+	let mut update_balance_requests = update_balance_requests.lock().unwrap();
+	let Some(request) = update_balance_requests.requests.get(&channel_id) else {
+		println!("\rERROR: no pending update-balance request for channel {}", channel_id);
+		return;
+	};
+
+	let channels = channel_manager.list_channels();
+	let Some(channel) = channels.iter().find(|c| c.channel_id == channel_id) else {
+		println!("\rERROR: couldn't find channel {}", channel_id);
+		return;
+	};
+
+	let (new_balance_msat, new_yuv_luma) = if accept {
+		let Some(amounts) = channel.update_balance_amounts.as_ref() else {
+			println!("\rERROR: channel {} has no balances to update", channel_id);
+			return;
+		};
+		let total_msat = amounts.holders_msat + amounts.counterpartys_msat;
+		let Some(new_balance_msat) = total_msat.checked_sub(request.counterparty_msat) else {
+			println!("\rERROR: requested balance exceeds the channel balances");
+			return;
+		};
+
+		let new_yuv_luma = match (
+			request.counterparty_yuv_luma,
+			channel.yuv_holder_pixel,
+			channel.yuv_counterparty_pixel,
+		) {
+			(Some(luma), Some(holder_pixel), Some(counterparty_pixel)) => {
+				let total_luma = holder_pixel.luma.amount + counterparty_pixel.luma.amount;
+				match total_luma.checked_sub(luma.amount) {
+					Some(amount) => Some(Luma::from(amount)),
+					None => {
+						println!("\rERROR: requested YUV luma exceeds the channel luma");
+						return;
+					}
+				}
+			}
+			_ => None,
+		};
+
+		(Some(new_balance_msat), new_yuv_luma)
+	} else {
+		(None, None)
+	};
+
+	let counterparty_node_id = channel.counterparty.node_id;
+	if update_balance(
+		channel_id.0,
+		counterparty_node_id,
+		new_balance_msat,
+		new_yuv_luma,
+		channel_manager,
+	)
+	.is_ok()
+	{
+		update_balance_requests.requests.remove(&channel_id);
+		if let Err(e) = disk::persist_update_balance_requests(fs_store, &update_balance_requests) {
+			println!("\rERROR: failed to persist update-balance requests: {}", e);
 		}
-		Err(e) => println!("\rERROR: failed to send update-balance request: {:?}", e),
 	}
 }
 
+/// Parses `pubkey@host:port` peer info, where the host may also be an onion address. Hostnames are
+/// resolved when connecting rather than here, so that they can be left to a proxy.
 pub(crate) fn parse_peer_info(
 	peer_pubkey_and_ip_addr: String,
-) -> Result<(PublicKey, SocketAddr), std::io::Error> {
+) -> Result<(PublicKey, SocketAddress), std::io::Error> {
 	let mut pubkey_and_addr = peer_pubkey_and_ip_addr.split("@");
 	let pubkey = pubkey_and_addr.next();
 	let peer_addr_str = pubkey_and_addr.next();
@@ -1439,8 +4261,8 @@ pub(crate) fn parse_peer_info(
 		));
 	}
 
-	let peer_addr = peer_addr_str.unwrap().to_socket_addrs().map(|mut r| r.next());
-	if peer_addr.is_err() || peer_addr.as_ref().unwrap().is_none() {
+	let peer_addr = SocketAddress::from_str(peer_addr_str.unwrap());
+	if peer_addr.is_err() {
 		return Err(std::io::Error::new(
 			std::io::ErrorKind::Other,
 			"ERROR: couldn't parse pubkey@host:port into a socket address",
@@ -1455,5 +4277,296 @@ pub(crate) fn parse_peer_info(
 		));
 	}
 
-	Ok((pubkey.unwrap(), peer_addr.unwrap().unwrap()))
+	Ok((pubkey.unwrap(), peer_addr.unwrap()))
+}
+
+#[cfg(test)]
+mod channel_config_update_tests {
+	use super::*;
+
+	#[test]
+	fn test_forwarding_config_update() {
+		let mut channel_config = ChannelConfig::default();
+		channel_config.forwarding_fee_base_msat = 500;
+		channel_config.forwarding_fee_proportional_millionths = 100;
+		channel_config.cltv_expiry_delta = 144;
+
+		let update = forwarding_config_update(&channel_config);
+		assert_eq!(update.forwarding_fee_base_msat, Some(500));
+		assert_eq!(update.forwarding_fee_proportional_millionths, Some(100));
+		assert_eq!(update.cltv_expiry_delta, Some(144));
+		assert!(update.max_dust_htlc_exposure_msat.is_none());
+		assert!(update.force_close_avoidance_max_fee_satoshis.is_none());
+	}
+
+	#[test]
+	fn test_validate_cltv_expiry_delta() {
+		assert!(validate_cltv_expiry_delta(MIN_CLTV_EXPIRY_DELTA).is_ok());
+		assert!(validate_cltv_expiry_delta(144).is_ok());
+		assert!(validate_cltv_expiry_delta(MIN_CLTV_EXPIRY_DELTA - 1).is_err());
+	}
+}
+
+#[cfg(test)]
+mod yuv_funding_status_tests {
+	use super::*;
+
+	#[test]
+	fn test_yuv_funding_status() {
+		let txid = Txid::from_byte_array([1; 32]);
+		let other_txid = Txid::from_byte_array([2; 32]);
+
+		assert_eq!(yuv_funding_status(None, false, &[]), "awaiting funding transaction");
+		assert_eq!(
+			yuv_funding_status(Some(txid), false, &[other_txid, txid]),
+			"awaiting YUV proof confirmation"
+		);
+		assert_eq!(
+			yuv_funding_status(Some(txid), false, &[other_txid]),
+			"awaiting funding confirmation"
+		);
+		assert_eq!(yuv_funding_status(Some(txid), true, &[]), "confirmed");
+	}
+}
+
+#[cfg(test)]
+mod fund_wallet_tests {
+	use super::*;
+
+	#[test]
+	fn test_regtest_block_subsidy() {
+		assert_eq!(regtest_block_subsidy_sat(0), 5_000_000_000);
+		assert_eq!(regtest_block_subsidy_sat(149), 5_000_000_000);
+		assert_eq!(regtest_block_subsidy_sat(150), 2_500_000_000);
+		assert_eq!(regtest_block_subsidy_sat(64 * 150), 0);
+	}
+
+	#[test]
+	fn test_blocks_to_fund() {
+		assert_eq!(blocks_to_fund(1, 0), Some(1));
+		assert_eq!(blocks_to_fund(5_000_000_000, 0), Some(1));
+		assert_eq!(blocks_to_fund(5_000_000_001, 0), Some(2));
+		// The subsidy halves from height 150 on.
+		assert_eq!(blocks_to_fund(7_500_000_000, 148), Some(2));
+		assert_eq!(blocks_to_fund(5_000_000_000, 149), Some(2));
+		assert_eq!(blocks_to_fund(1, 64 * 150), None);
+	}
+}
+
+#[cfg(test)]
+mod confirmation_tests {
+	use super::*;
+
+	#[test]
+	fn test_is_confirmation() {
+		assert!(is_confirmation("y"));
+		assert!(is_confirmation("Yes "));
+		assert!(!is_confirmation(""));
+		assert!(!is_confirmation("n"));
+		assert!(!is_confirmation("yep"));
+	}
+}
+
+#[cfg(test)]
+mod requires_yuv_tests {
+	use super::*;
+
+	#[test]
+	fn test_yuv_commands_require_yuv() {
+		assert!(requires_yuv("yuvbalance"));
+		assert!(requires_yuv("yuvtransfer 02aa bcrt1p 100"));
+		assert!(!requires_yuv("listchannels --json"));
+		assert!(!requires_yuv(""));
+	}
+
+	#[test]
+	fn test_yuv_params_require_yuv() {
+		assert!(requires_yuv("openchannel 02aa 200000 --pixel 6000:bcrt1p"));
+		assert!(requires_yuv("openchannel 02aa 200000 --push-yuv 10"));
+		assert!(requires_yuv("sendpayment lnbcrt1 --yuv-amount 10"));
+		assert!(requires_yuv("configchannel --support-yuv true"));
+		assert!(!requires_yuv("configchannel --support-yuv false"));
+		assert!(!requires_yuv("openchannel 02aa 200000 --public"));
+	}
+}
+
+#[cfg(test)]
+mod fee_report_tests {
+	use super::*;
+
+	const DAY_SECS: u64 = 24 * 60 * 60;
+
+	fn forward(timestamp_secs: u64, fee_earned_msat: Option<u64>) -> ForwardInfo {
+		ForwardInfo {
+			timestamp_secs,
+			fee_earned_msat,
+			amount_forwarded_msat: Some(100_000),
+			yuv_pixel: None,
+		}
+	}
+
+	#[test]
+	fn test_fee_report_windows() {
+		let now_secs = 100 * DAY_SECS;
+		let forwards = vec![
+			forward(now_secs - 60, Some(1_000)),
+			forward(now_secs - 2 * DAY_SECS, Some(2_000)),
+			forward(now_secs - 2 * DAY_SECS, None),
+			forward(now_secs - 10 * DAY_SECS, Some(4_000)),
+			forward(now_secs - 40 * DAY_SECS, Some(8_000)),
+		];
+
+		let windows = fee_report_windows(&forwards, now_secs, Network::Regtest);
+		let summary = windows
+			.iter()
+			.map(|window| (window.name, window.num_forwards, window.fee_earned_msat))
+			.collect::<Vec<_>>();
+		assert_eq!(summary, vec![("day", 1, 1_000), ("week", 3, 3_000), ("month", 4, 7_000)]);
+		assert!(windows.iter().all(|window| window.yuv_volume.is_empty()));
+	}
+}
+
+#[cfg(test)]
+mod announced_address_tests {
+	use super::*;
+
+	#[test]
+	fn test_add_and_remove_announced_address() {
+		let addr = SocketAddress::from_str("127.0.0.1:9735").unwrap();
+		let mut addrs = Vec::new();
+
+		add_announced_address(&mut addrs, addr.clone()).unwrap();
+		assert_eq!(addrs, vec![addr.clone()]);
+		assert!(add_announced_address(&mut addrs, addr.clone()).is_err());
+		assert_eq!(addrs.len(), 1);
+
+		remove_announced_address(&mut addrs, &addr).unwrap();
+		assert!(addrs.is_empty());
+		assert!(remove_announced_address(&mut addrs, &addr).is_err());
+	}
+}
+
+#[cfg(test)]
+mod verify_message_tests {
+	use super::*;
+	use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+	#[test]
+	fn test_recover_signer_of_signed_message() {
+		let secret_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_id = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+		let signature = lightning::util::message_signing::sign(b"hello world", &secret_key);
+
+		assert_eq!(recover_signer("hello world", &signature), Ok(node_id));
+		assert_ne!(recover_signer("goodbye world", &signature), Ok(node_id));
+	}
+
+	#[test]
+	fn test_recover_signer_rejects_malformed_signature() {
+		assert!(recover_signer("hello world", "").is_err());
+		assert!(recover_signer("hello world", "not-a-signature").is_err());
+		assert!(recover_signer("hello world", &"!".repeat(SIGNATURE_LEN)).is_err());
+	}
+}
+
+#[cfg(test)]
+mod amount_tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_amount_msat_units() {
+		assert_eq!(parse_amount_msat("1500"), Ok(1500));
+		assert_eq!(parse_amount_msat("1500msat"), Ok(1500));
+		assert_eq!(parse_amount_msat("10000sat"), Ok(10_000_000));
+		assert_eq!(parse_amount_msat("1.5sat"), Ok(1500));
+		assert_eq!(parse_amount_msat("0.0001btc"), Ok(10_000_000));
+		assert_eq!(parse_amount_msat("1BTC"), Ok(100_000_000_000));
+		assert_eq!(parse_amount_msat("0.00000000001btc"), Ok(1));
+	}
+
+	#[test]
+	fn test_parse_amount_msat_rejects_ambiguous_input() {
+		assert!(parse_amount_msat("").is_err());
+		assert!(parse_amount_msat("sat").is_err());
+		assert!(parse_amount_msat(".5sat").is_err());
+		assert!(parse_amount_msat("1.5").is_err());
+		assert!(parse_amount_msat("1.5msat").is_err());
+		assert!(parse_amount_msat("1.0001sat").is_err());
+		assert!(parse_amount_msat("-1sat").is_err());
+		assert!(parse_amount_msat("1 sat").is_err());
+		assert!(parse_amount_msat("10k").is_err());
+		assert!(parse_amount_msat("1000000000btc").is_err());
+	}
+}
+
+#[cfg(test)]
+mod push_amount_tests {
+	use super::*;
+
+	fn pixel(luma: u128) -> Pixel {
+		let chroma = Chroma::from_address(
+			"bcrt1p4v5dxtlzrrfuk57nxr3d6gwmtved47ulc55kcsk30h93e43ma2eqvrek30",
+		)
+		.unwrap();
+		Pixel::new(Luma::from(luma), chroma)
+	}
+
+	#[test]
+	fn test_validate_push_msat() {
+		assert!(validate_push_amounts(100_000, 0, None, None).is_ok());
+		assert!(validate_push_amounts(100_000, 100_000_000, None, None).is_ok());
+		assert!(validate_push_amounts(100_000, 100_000_001, None, None).is_err());
+	}
+
+	#[test]
+	fn test_validate_push_yuv() {
+		let pixel = pixel(6000);
+		assert!(validate_push_amounts(100_000, 0, Some(&pixel), Some(6000)).is_ok());
+		assert!(validate_push_amounts(100_000, 0, Some(&pixel), Some(6001)).is_err());
+		// Tokens can only be pushed in a YUV channel.
+		assert!(validate_push_amounts(100_000, 0, None, Some(1)).is_err());
+	}
+}
+
+#[cfg(test)]
+mod yuv_funding_tests {
+	use super::*;
+
+	#[test]
+	fn test_validate_yuv_funding() {
+		assert!(validate_yuv_funding(100, 100, 0).is_ok());
+		assert!(validate_yuv_funding(60, 100, 40).is_ok());
+		assert!(validate_yuv_funding(101, 100, 0).is_err());
+		assert!(validate_yuv_funding(61, 100, 40).is_err());
+		assert!(validate_yuv_funding(1, 100, 150).is_err());
+	}
+}
+
+#[cfg(test)]
+mod batch_open_channel_tests {
+	use super::*;
+
+	const PUBKEY: &str = "02f338eea03393497c2bd2bf0780398d0e4f23ac1e99d1c899f1f7553fe01b54dc";
+	const OTHER_PUBKEY: &str = "033ca6472b079e7c377d94d5b776225a7288e23685ce158b005c25b837c036b995";
+
+	#[test]
+	fn test_parse_batch_channels() {
+		let batch =
+			format!("{}@127.0.0.1:9735,100000;{}@127.0.0.1:9736,200000;", PUBKEY, OTHER_PUBKEY);
+		let channels = parse_batch_channels(&batch).unwrap();
+		assert_eq!(channels.len(), 2);
+		assert_eq!(channels[0].0, PublicKey::from_str(PUBKEY).unwrap());
+		assert_eq!(channels[0].1, SocketAddress::from_str("127.0.0.1:9735").unwrap());
+		assert_eq!(channels[0].2, 100_000);
+		assert_eq!(channels[1].0, PublicKey::from_str(OTHER_PUBKEY).unwrap());
+		assert_eq!(channels[1].2, 200_000);
+	}
+
+	#[test]
+	fn test_parse_batch_channels_fail() {
+		assert!(parse_batch_channels("").is_err());
+		assert!(parse_batch_channels(&format!("{}@127.0.0.1:9735", PUBKEY)).is_err());
+		assert!(parse_batch_channels(&format!("{}@127.0.0.1:9735,lots", PUBKEY)).is_err());
+		assert!(parse_batch_channels("not-a-pubkey@127.0.0.1:9735,100000").is_err());
+	}
 }
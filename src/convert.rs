@@ -100,6 +100,32 @@ impl TryInto<MempoolMinFeeResponse> for JsonResponse {
 	}
 }
 
+/// Whether `gettxout` found the output, which it only does for unspent ones.
+pub struct UnspentTxOut(pub bool);
+
+impl TryInto<UnspentTxOut> for JsonResponse {
+	type Error = std::io::Error;
+	fn try_into(self) -> std::io::Result<UnspentTxOut> {
+		Ok(UnspentTxOut(!self.0.is_null()))
+	}
+}
+
+pub struct MempoolTxids(pub Vec<Txid>);
+
+impl TryInto<MempoolTxids> for JsonResponse {
+	type Error = std::io::Error;
+	fn try_into(self) -> std::io::Result<MempoolTxids> {
+		let txids = self
+			.0
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|txid| Txid::from_str(txid.as_str().unwrap()).unwrap())
+			.collect();
+		Ok(MempoolTxids(txids))
+	}
+}
+
 pub struct BlockchainInfo {
 	pub latest_height: usize,
 	pub latest_blockhash: BlockHash,
@@ -151,3 +177,20 @@ impl TryInto<ListUnspentResponse> for JsonResponse {
 		Ok(ListUnspentResponse(utxos))
 	}
 }
+
+/// The hashes of the blocks mined by `generatetoaddress`.
+pub struct GeneratedBlocks(pub Vec<BlockHash>);
+
+impl TryInto<GeneratedBlocks> for JsonResponse {
+	type Error = std::io::Error;
+	fn try_into(self) -> std::io::Result<GeneratedBlocks> {
+		let hashes = self
+			.0
+			.as_array()
+			.unwrap()
+			.iter()
+			.map(|hash| BlockHash::from_str(hash.as_str().unwrap()).unwrap())
+			.collect();
+		Ok(GeneratedBlocks(hashes))
+	}
+}
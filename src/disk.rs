@@ -1,46 +1,102 @@
-use crate::{cli, NetworkGraph, PaymentInfoStorage};
+use crate::yuv_client::ProofDelivery;
+use crate::{
+	cli, ChannelBackup, ChannelClosureStorage, FailedSweepStorage, ForwardInfoStorage,
+	NetworkGraph, PaymentInfoStorage, PaymentPolicy, PeerAccessLists, SavedUserConfig,
+	UpdateBalanceRequestStorage,
+};
 use bitcoin::secp256k1::PublicKey;
-use bitcoin::Network;
+use bitcoin::{BlockHash, Network, Txid};
 use chrono::Utc;
+use lightning::chain::channelmonitor::ChannelMonitor;
+use lightning::ln::msgs::SocketAddress;
+use lightning::log_error;
 use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringDecayParameters};
-use lightning::util::logger::{Logger, Record};
-use lightning::util::ser::{Readable, ReadableArgs, Writer};
+use lightning::sign::{InMemorySigner, KeysManager};
+use lightning::util::logger::{Level, Logger, Record};
+use lightning::util::persist::{self, KVStore};
+use lightning::util::ser::{Readable, ReadableArgs, Writeable, Writer};
+use lightning_persister::fs_store::FilesystemStore;
 use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::net::SocketAddr;
-use std::path::Path;
-use std::sync::Arc;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use yuv_types::YuvTransaction;
 
 pub(crate) const INBOUND_PAYMENTS_FNAME: &str = "inbound_payments";
 pub(crate) const OUTBOUND_PAYMENTS_FNAME: &str = "outbound_payments";
+pub(crate) const CLI_HISTORY_FNAME: &str = "cli_history";
+pub(crate) const RPC_COOKIE_FNAME: &str = ".rpc_cookie";
+pub(crate) const FORWARDS_FNAME: &str = "forwards";
+pub(crate) const CHANNEL_CLOSURES_FNAME: &str = "channel_closures";
+pub(crate) const FAILED_SWEEPS_FNAME: &str = "failed_sweeps";
+pub(crate) const PEER_ACCESS_LISTS_FNAME: &str = "peer_access_lists";
+pub(crate) const USER_CONFIG_FNAME: &str = "user_config";
+pub(crate) const PAYMENT_POLICY_FNAME: &str = "payment_policy";
+pub(crate) const UPDATE_BALANCE_REQUESTS_FNAME: &str = "update_balance_requests";
+pub(crate) const YUV_PROOF_DELIVERIES_FNAME: &str = "yuv_proof_deliveries";
+
+/// How many rotated log files are kept next to the active one.
+const MAX_ROTATED_LOG_FILES: usize = 5;
+
+/// How each log line is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LogFormat {
+	/// Human-readable lines, e.g. `2024-01-01 00:00:00.000 INFO  [module:1] message`.
+	Text,
+	/// A JSON object per line, for shipping the logs to an aggregator.
+	Json,
+}
 
 pub(crate) struct FilesystemLogger {
 	data_dir: String,
+	format: LogFormat,
+	/// Records below this level are dropped.
+	min_level: Level,
+	/// Once the log file would grow past this size, it's rotated out for a fresh one.
+	max_bytes: Option<u64>,
+	/// Serializes writes, so that concurrent records don't both rotate the log file.
+	write_lock: Mutex<()>,
 }
 impl FilesystemLogger {
-	pub(crate) fn new(data_dir: String) -> Self {
+	pub(crate) fn new(
+		data_dir: String, format: LogFormat, min_level: Level, max_bytes: Option<u64>,
+	) -> Self {
 		let logs_path = format!("{}/logs", data_dir);
 		fs::create_dir_all(logs_path.clone()).unwrap();
-		Self { data_dir: logs_path }
+		Self { data_dir: logs_path, format, min_level, max_bytes, write_lock: Mutex::new(()) }
 	}
 }
 impl Logger for FilesystemLogger {
 	fn log(&self, record: Record) {
-		let raw_log = record.args.to_string();
-		let log = format!(
-			"{} {:<5} [{}:{}] {}\n",
+		if record.level < self.min_level {
+			return;
+		}
+		let log = format_log(
+			self.format,
 			// Note that a "real" lightning node almost certainly does *not* want subsecond
 			// precision for message-receipt information as it makes log entries a target for
 			// deanonymization attacks. For testing, however, its quite useful.
-			Utc::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-			record.level.to_string(),
+			&Utc::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+			record.level,
 			record.module_path,
 			record.line,
-			raw_log
+			&record.args.to_string(),
 		);
 		let logs_file_path = format!("{}/logs.txt", self.data_dir.clone());
+		let _write_guard = self.write_lock.lock().unwrap();
+		if let Some(max_bytes) = self.max_bytes {
+			let len = fs::metadata(&logs_file_path).map_or(0, |metadata| metadata.len());
+			if len > 0 && len + log.len() as u64 > max_bytes {
+				// Losing the log rotation is better than losing the record, which then still goes to
+				// the current file.
+				if let Err(e) = rotate_log_files(Path::new(&logs_file_path), MAX_ROTATED_LOG_FILES)
+				{
+					eprintln!("ERROR: failed to rotate log file {}: {}", logs_file_path, e);
+				}
+			}
+		}
 		fs::OpenOptions::new()
 			.create(true)
 			.append(true)
@@ -50,31 +106,138 @@ impl Logger for FilesystemLogger {
 			.unwrap();
 	}
 }
-pub(crate) fn persist_channel_peer(path: &Path, peer_info: &str) -> std::io::Result<()> {
-	let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
-	file.write_all(format!("{}\n", peer_info).as_bytes())
+/// Renames `logs.txt` to `logs.txt.1`, shifting the previously rotated files up by one index and
+/// dropping the oldest one, so that at most `max_files` of them are kept.
+fn rotate_log_files(logs_file_path: &Path, max_files: usize) -> std::io::Result<()> {
+	let rotated_path = |index: usize| {
+		let mut path = logs_file_path.as_os_str().to_owned();
+		path.push(format!(".{}", index));
+		std::path::PathBuf::from(path)
+	};
+
+	let oldest_path = rotated_path(max_files);
+	if oldest_path.exists() {
+		fs::remove_file(oldest_path)?;
+	}
+	for index in (1..max_files).rev() {
+		let path = rotated_path(index);
+		if path.exists() {
+			fs::rename(path, rotated_path(index + 1))?;
+		}
+	}
+	fs::rename(logs_file_path, rotated_path(1))
+}
+fn format_log(
+	format: LogFormat, timestamp: &str, level: Level, module_path: &str, line: u32, message: &str,
+) -> String {
+	match format {
+		LogFormat::Text => {
+			format!(
+				"{} {:<5} [{}:{}] {}\n",
+				timestamp,
+				level.to_string(),
+				module_path,
+				line,
+				message
+			)
+		}
+		LogFormat::Json => {
+			let log = serde_json::json!({
+				"timestamp": timestamp,
+				"level": level.to_string(),
+				"module": module_path,
+				"line": line,
+				"message": message,
+			});
+			format!("{}\n", log)
+		}
+	}
 }
 
+/// Stores the `host:port` address of the peer with the given pubkey. Each peer is stored on a
+/// single `pubkey@host:port[,host:port...]` line, with the addresses in the order they should be
+/// tried, so `peer_addr` is put first, ahead of any other addresses previously stored for the peer.
+pub(crate) fn persist_channel_peer(
+	path: &Path, pubkey: &PublicKey, peer_addr: &str,
+) -> std::io::Result<()> {
+	let pubkey_prefix = format!("{}@", pubkey);
+	let mut peer_addrs = vec![peer_addr.to_string()];
+	let mut peer_data = String::new();
+	if path.exists() {
+		for line in BufReader::new(File::open(path)?).lines() {
+			let line = line?;
+			match line.strip_prefix(&pubkey_prefix) {
+				Some(addrs) => {
+					for addr in addrs.split(',') {
+						if !peer_addrs.iter().any(|known| known == addr) {
+							peer_addrs.push(addr.to_string());
+						}
+					}
+				}
+				None => {
+					peer_data.push_str(&line);
+					peer_data.push('\n');
+				}
+			}
+		}
+	}
+	peer_data.push_str(&format!("{}{}\n", pubkey_prefix, peer_addrs.join(",")));
+
+	// Write the whole file anew and swap it in, so that a crash can't lose the other peers.
+	let mut tmp_path = path.as_os_str().to_owned();
+	tmp_path.push(".tmp");
+	fs::write(&tmp_path, peer_data)?;
+	fs::rename(&tmp_path, path)
+}
+
+/// Reads the addresses of each peer, in the order they should be tried. Files written before
+/// peers could have several addresses, with one `pubkey@host:port` line per address, are read
+/// the same way.
 pub(crate) fn read_channel_peer_data(
 	path: &Path,
-) -> Result<HashMap<PublicKey, SocketAddr>, std::io::Error> {
-	let mut peer_data = HashMap::new();
+) -> Result<HashMap<PublicKey, Vec<SocketAddress>>, std::io::Error> {
+	let mut peer_data: HashMap<PublicKey, Vec<SocketAddress>> = HashMap::new();
 	if !Path::new(&path).exists() {
 		return Ok(HashMap::new());
 	}
 	let file = File::open(path)?;
 	let reader = BufReader::new(file);
 	for line in reader.lines() {
-		match cli::parse_peer_info(line.unwrap()) {
-			Ok((pubkey, socket_addr)) => {
-				peer_data.insert(pubkey, socket_addr);
+		let line = line?;
+		let (pubkey_str, addrs) = line.split_once('@').unwrap_or((&line, ""));
+		for addr in addrs.split(',') {
+			let (pubkey, socket_addr) = cli::parse_peer_info(format!("{}@{}", pubkey_str, addr))?;
+			let peer_addrs = peer_data.entry(pubkey).or_default();
+			if !peer_addrs.contains(&socket_addr) {
+				peer_addrs.push(socket_addr);
 			}
-			Err(e) => return Err(e),
 		}
 	}
 	Ok(peer_data)
 }
 
+/// Writes the CLI history, which is kept newest-first in memory, to disk oldest-first.
+pub(crate) fn persist_cli_history(path: &Path, history: &[&str]) -> std::io::Result<()> {
+	let mut file = File::create(path)?;
+	for command in history.iter().rev() {
+		file.write_all(format!("{}\n", command).as_bytes())?;
+	}
+	Ok(())
+}
+
+pub(crate) fn read_cli_history(path: &Path) -> Vec<String> {
+	if let Ok(file) = File::open(path) {
+		let mut history = BufReader::new(file)
+			.lines()
+			.filter_map(|line| line.ok())
+			.filter(|line| !line.is_empty())
+			.collect::<Vec<_>>();
+		history.reverse();
+		return history;
+	}
+	Vec::new()
+}
+
 pub(crate) fn read_network(
 	path: &Path, network: Network, logger: Arc<FilesystemLogger>,
 ) -> NetworkGraph {
@@ -86,6 +249,33 @@ pub(crate) fn read_network(
 	NetworkGraph::new(network, logger)
 }
 
+/// Writes `data` to a `.tmp` file next to `path`, syncs it and then renames it over `path`, so that
+/// a crash mid-write leaves the previous contents of `path` in place rather than a torn file.
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+	let mut tmp_path = path.as_os_str().to_owned();
+	tmp_path.push(".tmp");
+	let tmp_path = PathBuf::from(tmp_path);
+	{
+		let mut tmp_file = File::create(&tmp_path)?;
+		tmp_file.write_all(data)?;
+		tmp_file.sync_all()?;
+	}
+	fs::rename(&tmp_path, path)?;
+	// The rename only survives a crash once the directory it happened in is synced too.
+	match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+		_ => Ok(()),
+	}
+}
+
+/// Persists the payments to the file `key` in the data dir of `fs_store`, see
+/// [`write_atomically`].
+pub(crate) fn persist_payment_info(
+	fs_store: &FilesystemStore, key: &str, payments: &PaymentInfoStorage,
+) -> std::io::Result<()> {
+	write_atomically(&fs_store.get_data_dir().join(key), &payments.encode())
+}
+
 pub(crate) fn read_payment_info(path: &Path) -> PaymentInfoStorage {
 	if let Ok(file) = File::open(path) {
 		if let Ok(info) = PaymentInfoStorage::read(&mut BufReader::new(file)) {
@@ -95,6 +285,219 @@ pub(crate) fn read_payment_info(path: &Path) -> PaymentInfoStorage {
 	PaymentInfoStorage { payments: HashMap::new() }
 }
 
+pub(crate) fn read_forwards(path: &Path) -> ForwardInfoStorage {
+	if let Ok(file) = File::open(path) {
+		if let Ok(forwards) = ForwardInfoStorage::read(&mut BufReader::new(file)) {
+			return forwards;
+		}
+	}
+	ForwardInfoStorage { forwards: Vec::new(), expired_forwards: 0, expired_forwarded_msat: 0 }
+}
+
+pub(crate) fn persist_channel_closures(
+	fs_store: &FilesystemStore, closures: &ChannelClosureStorage,
+) -> Result<(), std::io::Error> {
+	fs_store.write("", "", CHANNEL_CLOSURES_FNAME, &closures.encode())
+}
+
+pub(crate) fn read_channel_closures(path: &Path) -> ChannelClosureStorage {
+	if let Ok(file) = File::open(path) {
+		if let Ok(closures) = ChannelClosureStorage::read(&mut BufReader::new(file)) {
+			return closures;
+		}
+	}
+	ChannelClosureStorage { closures: Vec::new() }
+}
+
+pub(crate) fn read_failed_sweeps(path: &Path) -> FailedSweepStorage {
+	if let Ok(file) = File::open(path) {
+		if let Ok(failed_sweeps) = FailedSweepStorage::read(&mut BufReader::new(file)) {
+			return failed_sweeps;
+		}
+	}
+	FailedSweepStorage { sweeps: Vec::new() }
+}
+
+pub(crate) fn read_user_config(path: &Path) -> Option<SavedUserConfig> {
+	let file = File::open(path).ok()?;
+	SavedUserConfig::read(&mut BufReader::new(file)).ok()
+}
+
+pub(crate) fn persist_update_balance_requests(
+	fs_store: &FilesystemStore, requests: &UpdateBalanceRequestStorage,
+) -> Result<(), std::io::Error> {
+	fs_store.write("", "", UPDATE_BALANCE_REQUESTS_FNAME, &requests.encode())
+}
+
+pub(crate) fn read_update_balance_requests(path: &Path) -> UpdateBalanceRequestStorage {
+	if let Ok(file) = File::open(path) {
+		if let Ok(requests) = UpdateBalanceRequestStorage::read(&mut BufReader::new(file)) {
+			return requests;
+		}
+	}
+	UpdateBalanceRequestStorage {
+		requests: HashMap::new(),
+		legacy_auto_accept_max_delta_msat: None,
+	}
+}
+
+pub(crate) fn read_payment_policy(path: &Path) -> PaymentPolicy {
+	if let Ok(file) = File::open(path) {
+		if let Ok(policy) = PaymentPolicy::read(&mut BufReader::new(file)) {
+			return policy;
+		}
+	}
+	PaymentPolicy::default()
+}
+
+pub(crate) fn read_peer_access_lists(path: &Path) -> PeerAccessLists {
+	if let Ok(file) = File::open(path) {
+		if let Ok(lists) = PeerAccessLists::read(&mut BufReader::new(file)) {
+			return lists;
+		}
+	}
+	PeerAccessLists { allowed: Vec::new(), denied: Vec::new() }
+}
+
+/// Writes the YUV proofs which haven't been delivered yet as JSON, as that's the only encoding
+/// YUV transactions support.
+pub(crate) fn persist_yuv_proof_deliveries(
+	path: &Path, deliveries: &HashMap<Txid, (YuvTransaction, ProofDelivery)>,
+) -> std::io::Result<()> {
+	let deliveries = deliveries
+		.values()
+		.map(|(yuv_tx, delivery)| {
+			let (status, attempts) = match delivery {
+				ProofDelivery::Pending { attempts } => ("pending", *attempts),
+				ProofDelivery::Rejected => ("rejected", 0),
+				ProofDelivery::Failed => ("failed", 0),
+			};
+			serde_json::json!({ "status": status, "attempts": attempts, "tx": yuv_tx })
+		})
+		.collect::<Vec<_>>();
+	write_atomically(path, &serde_json::to_vec(&deliveries)?)
+}
+
+/// Reads the YUV proofs written by [`persist_yuv_proof_deliveries`]. A missing file means there's
+/// nothing left to deliver, while a file or entries that fail to parse are logged and skipped.
+pub(crate) fn read_yuv_proof_deliveries(
+	path: &Path, logger: &FilesystemLogger,
+) -> HashMap<Txid, (YuvTransaction, ProofDelivery)> {
+	let contents = match fs::read(path) {
+		Ok(contents) => contents,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+		Err(e) => {
+			log_error!(logger, "Failed to read YUV proof deliveries from {:?}: {}", path, e);
+			return HashMap::new();
+		}
+	};
+	let deliveries = match serde_json::from_slice::<Vec<serde_json::Value>>(&contents) {
+		Ok(deliveries) => deliveries,
+		Err(e) => {
+			log_error!(logger, "Failed to parse YUV proof deliveries in {:?}: {}", path, e);
+			return HashMap::new();
+		}
+	};
+	deliveries
+		.into_iter()
+		.filter_map(|delivery| {
+			let parsed = parse_yuv_proof_delivery(&delivery);
+			if parsed.is_none() {
+				log_error!(logger, "Skipping unparsable YUV proof delivery: {}", delivery);
+			}
+			parsed
+		})
+		.collect()
+}
+
+fn parse_yuv_proof_delivery(
+	delivery: &serde_json::Value,
+) -> Option<(Txid, (YuvTransaction, ProofDelivery))> {
+	let yuv_tx = serde_json::from_value::<YuvTransaction>(delivery["tx"].clone()).ok()?;
+	let status = match delivery["status"].as_str()? {
+		"pending" => ProofDelivery::Pending { attempts: delivery["attempts"].as_u64()? as u32 },
+		"rejected" => ProofDelivery::Rejected,
+		"failed" => ProofDelivery::Failed,
+		_ => return None,
+	};
+	Some((yuv_tx.bitcoin_tx.txid(), (yuv_tx, status)))
+}
+
+/// Writes a backup made by `exportchannelbackup` to `path`, replacing any file already there.
+pub(crate) fn write_channel_backup(path: &Path, backup: &ChannelBackup) -> std::io::Result<()> {
+	fs::write(path, backup.encode())
+}
+
+/// Restores the channel manager and channel monitors of the backup at `path` into `fs_store`,
+/// where they're read from on startup, and returns the number of restored channel monitors.
+///
+/// Every channel monitor is checked to decode with our keys before anything is written, and the
+/// import is refused if `fs_store` already holds any channel state, which is never overwritten.
+pub(crate) fn import_channel_backup(
+	fs_store: &FilesystemStore, keys_manager: &KeysManager, path: &Path,
+) -> Result<usize, String> {
+	let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+	let backup = ChannelBackup::read(&mut BufReader::new(file))
+		.map_err(|e| format!("{} isn't a channel backup: {:?}", path.display(), e))?;
+	if backup.channel_manager.is_empty() {
+		return Err("the backup has no channel manager".to_string());
+	}
+	for monitor in &backup.channel_monitors {
+		let (_, channel_monitor) = <(BlockHash, ChannelMonitor<InMemorySigner>)>::read(
+			&mut &monitor.monitor[..],
+			(keys_manager, keys_manager),
+		)
+		.map_err(|e| format!("failed to decode the channel monitor {}: {:?}", monitor.key, e))?;
+		let (funding_txo, _) = channel_monitor.get_funding_txo();
+		if monitor.key != format!("{}_{}", funding_txo.txid, funding_txo.index) {
+			return Err(format!("the channel monitor {} has the wrong key", monitor.key));
+		}
+	}
+
+	if fs_store
+		.read(
+			persist::CHANNEL_MANAGER_PERSISTENCE_PRIMARY_NAMESPACE,
+			persist::CHANNEL_MANAGER_PERSISTENCE_SECONDARY_NAMESPACE,
+			persist::CHANNEL_MANAGER_PERSISTENCE_KEY,
+		)
+		.is_ok()
+	{
+		return Err("a channel manager already exists in the storage directory".to_string());
+	}
+	let existing_monitors = fs_store
+		.list(
+			persist::CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+			persist::CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE,
+		)
+		.map_err(|e| format!("failed to list the existing channel monitors: {}", e))?;
+	if !existing_monitors.is_empty() {
+		return Err("channel monitors already exist in the storage directory".to_string());
+	}
+
+	// The monitors go first, so that a failed import never leaves a channel manager behind
+	// without the monitors of its channels.
+	for monitor in &backup.channel_monitors {
+		fs_store
+			.write(
+				persist::CHANNEL_MONITOR_PERSISTENCE_PRIMARY_NAMESPACE,
+				persist::CHANNEL_MONITOR_PERSISTENCE_SECONDARY_NAMESPACE,
+				&monitor.key,
+				&monitor.monitor,
+			)
+			.map_err(|e| format!("failed to write the channel monitor {}: {}", monitor.key, e))?;
+	}
+	fs_store
+		.write(
+			persist::CHANNEL_MANAGER_PERSISTENCE_PRIMARY_NAMESPACE,
+			persist::CHANNEL_MANAGER_PERSISTENCE_SECONDARY_NAMESPACE,
+			persist::CHANNEL_MANAGER_PERSISTENCE_KEY,
+			&backup.channel_manager,
+		)
+		.map_err(|e| format!("failed to write the channel manager: {}", e))?;
+
+	Ok(backup.channel_monitors.len())
+}
+
 pub(crate) fn read_scorer(
 	path: &Path, graph: Arc<NetworkGraph>, logger: Arc<FilesystemLogger>,
 ) -> ProbabilisticScorer<Arc<NetworkGraph>, Arc<FilesystemLogger>> {
@@ -107,3 +510,591 @@ pub(crate) fn read_scorer(
 	}
 	ProbabilisticScorer::new(params, graph, logger)
 }
+
+/// Writes the scorer and network graph where the background processor would, so they're read back
+/// by [`read_scorer`] and [`read_network`] on the next start.
+pub(crate) fn persist_scorer_and_network_graph(
+	store: &FilesystemStore,
+	scorer: &RwLock<ProbabilisticScorer<Arc<NetworkGraph>, Arc<FilesystemLogger>>>,
+	network_graph: &NetworkGraph,
+) -> Result<(), std::io::Error> {
+	store.write(
+		persist::SCORER_PERSISTENCE_PRIMARY_NAMESPACE,
+		persist::SCORER_PERSISTENCE_SECONDARY_NAMESPACE,
+		persist::SCORER_PERSISTENCE_KEY,
+		&scorer.read().unwrap().encode(),
+	)?;
+	store.write(
+		persist::NETWORK_GRAPH_PERSISTENCE_PRIMARY_NAMESPACE,
+		persist::NETWORK_GRAPH_PERSISTENCE_SECONDARY_NAMESPACE,
+		persist::NETWORK_GRAPH_PERSISTENCE_KEY,
+		&network_graph.encode(),
+	)
+}
+
+/// A directory under the system temp dir for a test to write into, removed again once dropped.
+#[cfg(test)]
+pub(crate) struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+	/// Starts out empty, even if a previous run of the test left the directory behind.
+	pub(crate) fn new(name: &str) -> Self {
+		let path = std::env::temp_dir().join(name);
+		let _ = fs::remove_dir_all(&path);
+		fs::create_dir_all(&path).unwrap();
+		Self(path)
+	}
+
+	pub(crate) fn path(&self) -> &Path {
+		&self.0
+	}
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.0);
+	}
+}
+
+#[cfg(test)]
+mod log_format_tests {
+	use super::*;
+
+	#[test]
+	fn test_format_log() {
+		let text = format_log(
+			LogFormat::Text,
+			"2024-01-01 00:00:00.000",
+			Level::Info,
+			"ldk_sample::disk",
+			42,
+			"hello",
+		);
+		assert_eq!(text, "2024-01-01 00:00:00.000 INFO  [ldk_sample::disk:42] hello\n");
+
+		let json = format_log(
+			LogFormat::Json,
+			"2024-01-01 00:00:00.000",
+			Level::Warn,
+			"ldk_sample::disk",
+			42,
+			"say \"hi\"\nbye",
+		);
+		assert!(json.ends_with('\n') && json.matches('\n').count() == 1);
+		let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+		assert_eq!(json["timestamp"], "2024-01-01 00:00:00.000");
+		assert_eq!(json["level"], "WARN");
+		assert_eq!(json["module"], "ldk_sample::disk");
+		assert_eq!(json["line"], 42);
+		assert_eq!(json["message"], "say \"hi\"\nbye");
+	}
+}
+
+#[cfg(test)]
+mod log_level_tests {
+	use super::*;
+	use lightning::{log_error, log_info, log_trace, log_warn};
+
+	#[test]
+	fn test_records_below_min_level_are_dropped() {
+		let data_dir = std::env::temp_dir().join("log_level_tests");
+		let _ = fs::remove_dir_all(&data_dir);
+		let logger = FilesystemLogger::new(
+			data_dir.to_str().unwrap().to_string(),
+			LogFormat::Text,
+			Level::Warn,
+			None,
+		);
+
+		log_trace!(logger, "trace record");
+		log_info!(logger, "info record");
+		log_warn!(logger, "warn record");
+		log_error!(logger, "error record");
+
+		let logs = fs::read_to_string(data_dir.join("logs/logs.txt")).unwrap();
+		assert!(!logs.contains("trace record"));
+		assert!(!logs.contains("info record"));
+		assert!(logs.contains("warn record"));
+		assert!(logs.contains("error record"));
+
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+}
+
+#[cfg(test)]
+mod log_rotation_tests {
+	use super::*;
+	use lightning::log_info;
+
+	#[test]
+	fn test_log_files_are_rotated_by_size() {
+		let data_dir = std::env::temp_dir().join("log_rotation_tests");
+		let _ = fs::remove_dir_all(&data_dir);
+		let logger = FilesystemLogger::new(
+			data_dir.to_str().unwrap().to_string(),
+			LogFormat::Text,
+			Level::Gossip,
+			Some(200),
+		);
+
+		for i in 0..50 {
+			log_info!(logger, "record {}", i);
+		}
+
+		let logs_path = data_dir.join("logs");
+		assert!(fs::metadata(logs_path.join("logs.txt")).unwrap().len() <= 200);
+		for index in 1..=MAX_ROTATED_LOG_FILES {
+			let rotated_path = logs_path.join(format!("logs.txt.{}", index));
+			assert!(fs::metadata(rotated_path).unwrap().len() <= 200);
+		}
+		assert!(!logs_path.join(format!("logs.txt.{}", MAX_ROTATED_LOG_FILES + 1)).exists());
+
+		// The newest record is in the active file, and the oldest ones were dropped.
+		let logs = fs::read_to_string(logs_path.join("logs.txt")).unwrap();
+		assert!(logs.contains("record 49"));
+		let oldest_logs =
+			fs::read_to_string(logs_path.join(format!("logs.txt.{}", MAX_ROTATED_LOG_FILES)))
+				.unwrap();
+		assert!(!oldest_logs.contains("record 0\n"));
+
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn test_failed_rotation_keeps_logging() {
+		let data_dir = std::env::temp_dir().join("log_rotation_failure_tests");
+		let _ = fs::remove_dir_all(&data_dir);
+		let logger = FilesystemLogger::new(
+			data_dir.to_str().unwrap().to_string(),
+			LogFormat::Text,
+			Level::Gossip,
+			Some(50),
+		);
+
+		// The oldest rotated file can't be removed to make room, so rotating fails.
+		let logs_path = data_dir.join("logs");
+		let oldest_path = logs_path.join(format!("logs.txt.{}", MAX_ROTATED_LOG_FILES));
+		fs::create_dir_all(oldest_path.join("dir")).unwrap();
+
+		for i in 0..5 {
+			log_info!(logger, "record {}", i);
+		}
+
+		let logs = fs::read_to_string(logs_path.join("logs.txt")).unwrap();
+		for i in 0..5 {
+			assert!(logs.contains(&format!("record {}\n", i)));
+		}
+
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+}
+
+#[cfg(test)]
+mod channel_peer_data_tests {
+	use super::*;
+	use std::str::FromStr;
+
+	const PUBKEY: &str = "02f338eea03393497c2bd2bf0780398d0e4f23ac1e99d1c899f1f7553fe01b54dc";
+	const OTHER_PUBKEY: &str = "033ca6472b079e7c377d94d5b776225a7288e23685ce158b005c25b837c036b995";
+
+	fn addr(addr: &str) -> SocketAddress {
+		SocketAddress::from_str(addr).unwrap()
+	}
+
+	#[test]
+	fn test_persist_channel_peer_updates_address() {
+		let data_dir = std::env::temp_dir().join("channel_peer_data_tests");
+		let _ = fs::remove_dir_all(&data_dir);
+		fs::create_dir_all(&data_dir).unwrap();
+		let path = data_dir.join("channel_peer_data");
+		let pubkey = PublicKey::from_str(PUBKEY).unwrap();
+		let other_pubkey = PublicKey::from_str(OTHER_PUBKEY).unwrap();
+
+		persist_channel_peer(&path, &pubkey, "127.0.0.1:9735").unwrap();
+		persist_channel_peer(&path, &other_pubkey, "127.0.0.1:9736").unwrap();
+		persist_channel_peer(&path, &pubkey, "127.0.0.1:9737").unwrap();
+		persist_channel_peer(&path, &pubkey, "127.0.0.1:9735").unwrap();
+
+		// The most recently used address of a peer is tried first.
+		let peer_data = read_channel_peer_data(&path).unwrap();
+		assert_eq!(peer_data.len(), 2);
+		assert_eq!(peer_data[&pubkey], vec![addr("127.0.0.1:9735"), addr("127.0.0.1:9737")]);
+		assert_eq!(peer_data[&other_pubkey], vec![addr("127.0.0.1:9736")]);
+		assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn test_read_channel_peer_data_legacy_format() {
+		let data_dir = std::env::temp_dir().join("channel_peer_data_legacy_tests");
+		let _ = fs::remove_dir_all(&data_dir);
+		fs::create_dir_all(&data_dir).unwrap();
+		let path = data_dir.join("channel_peer_data");
+		fs::write(
+			&path,
+			format!(
+				"{0}@127.0.0.1:9735\n{1}@127.0.0.1:9736\n{0}@127.0.0.1:9737,127.0.0.1:9735\n",
+				PUBKEY, OTHER_PUBKEY
+			),
+		)
+		.unwrap();
+
+		let pubkey = PublicKey::from_str(PUBKEY).unwrap();
+		let peer_data = read_channel_peer_data(&path).unwrap();
+		assert_eq!(peer_data[&pubkey], vec![addr("127.0.0.1:9735"), addr("127.0.0.1:9737")]);
+
+		// Persisting merges all of the peer's lines into a single one.
+		persist_channel_peer(&path, &pubkey, "127.0.0.1:9738").unwrap();
+		let peer_data = read_channel_peer_data(&path).unwrap();
+		assert_eq!(
+			peer_data[&pubkey],
+			vec![addr("127.0.0.1:9738"), addr("127.0.0.1:9735"), addr("127.0.0.1:9737")]
+		);
+		assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+}
+
+#[cfg(test)]
+mod channel_closures_tests {
+	use super::*;
+	use crate::ChannelClosureInfo;
+	use lightning::ln::ChannelId;
+
+	fn closure(channel_id: ChannelId) -> ChannelClosureInfo {
+		ChannelClosureInfo {
+			timestamp_secs: 1_700_000_000,
+			channel_id,
+			counterparty_node_id: None,
+			channel_capacity_sats: Some(200_000),
+			reason: "counterparty force-closed".to_string(),
+		}
+	}
+
+	#[test]
+	fn test_channel_closures_round_trip() {
+		let data_dir = TempDir::new("channel_closures_tests");
+		let fs_store = FilesystemStore::new(data_dir.path().to_path_buf());
+		let path = data_dir.path().join(CHANNEL_CLOSURES_FNAME);
+
+		// Nothing has been closed yet.
+		assert!(read_channel_closures(&path).closures.is_empty());
+
+		let closures = ChannelClosureStorage { closures: vec![closure(ChannelId([1; 32]))] };
+		persist_channel_closures(&fs_store, &closures).unwrap();
+
+		let closures = read_channel_closures(&path);
+		assert_eq!(closures.closures.len(), 1);
+		let closure = &closures.closures[0];
+		assert_eq!(closure.timestamp_secs, 1_700_000_000);
+		assert_eq!(closure.channel_id, ChannelId([1; 32]));
+		assert_eq!(closure.counterparty_node_id, None);
+		assert_eq!(closure.channel_capacity_sats, Some(200_000));
+		assert_eq!(closure.reason, "counterparty force-closed");
+	}
+
+	#[test]
+	fn test_corrupt_channel_closures_read_as_empty() {
+		let data_dir = TempDir::new("corrupt_channel_closures_tests");
+		let path = data_dir.path().join(CHANNEL_CLOSURES_FNAME);
+
+		let closures = ChannelClosureStorage { closures: vec![closure(ChannelId([1; 32]))] };
+		let encoded = closures.encode();
+		fs::write(&path, &encoded[..encoded.len() / 2]).unwrap();
+		assert!(read_channel_closures(&path).closures.is_empty());
+
+		fs::write(&path, [0xff; 8]).unwrap();
+		assert!(read_channel_closures(&path).closures.is_empty());
+	}
+
+	#[test]
+	fn test_channel_closure_write_error_is_logged() {
+		let data_dir = TempDir::new("channel_closures_write_error_tests");
+		// The store can't create its data dir under a regular file, so every write fails.
+		fs::write(data_dir.path().join("not_a_dir"), []).unwrap();
+		let fs_store = FilesystemStore::new(data_dir.path().join("not_a_dir").join("ldk"));
+		let closures = Mutex::new(ChannelClosureStorage { closures: Vec::new() });
+
+		crate::record_channel_closure(&closures, closure(ChannelId([1; 32])), &fs_store);
+		crate::record_channel_closure(&closures, closure(ChannelId([2; 32])), &fs_store);
+
+		// The closures are still listed until the node restarts.
+		let closures = closures.lock().unwrap();
+		assert_eq!(closures.closures.len(), 2);
+		assert_eq!(closures.closures[1].channel_id, ChannelId([2; 32]));
+	}
+}
+
+#[cfg(test)]
+mod failed_sweeps_tests {
+	use super::*;
+	use crate::FailedSweepInfo;
+	use bitcoin::hashes::Hash;
+	use bitcoin::Txid;
+
+	#[test]
+	fn test_missing_failed_sweeps_read_as_empty() {
+		let data_dir = TempDir::new("missing_failed_sweeps_tests");
+
+		let failed_sweeps = read_failed_sweeps(&data_dir.path().join(FAILED_SWEEPS_FNAME));
+		assert!(failed_sweeps.sweeps.is_empty());
+	}
+
+	#[test]
+	fn test_truncated_failed_sweeps_read_as_empty() {
+		let data_dir = TempDir::new("truncated_failed_sweeps_tests");
+		let path = data_dir.path().join(FAILED_SWEEPS_FNAME);
+
+		let failed_sweeps = FailedSweepStorage {
+			sweeps: vec![FailedSweepInfo {
+				timestamp_secs: 1_700_000_000,
+				outputs_key: "ab".repeat(32),
+				txid: Txid::all_zeros(),
+				reason: "invalid proof".to_string(),
+				proofs: "Transfer".to_string(),
+				abandoned: true,
+			}],
+		};
+		let encoded = failed_sweeps.encode();
+		fs::write(&path, &encoded[..encoded.len() - 1]).unwrap();
+
+		assert!(read_failed_sweeps(&path).sweeps.is_empty());
+	}
+
+	#[test]
+	fn test_empty_failed_sweeps_round_trip() {
+		let data_dir = TempDir::new("empty_failed_sweeps_tests");
+		let fs_store = FilesystemStore::new(data_dir.path().to_path_buf());
+
+		let failed_sweeps = FailedSweepStorage { sweeps: Vec::new() };
+		fs_store.write("", "", FAILED_SWEEPS_FNAME, &failed_sweeps.encode()).unwrap();
+
+		let path = data_dir.path().join(FAILED_SWEEPS_FNAME);
+		assert!(path.exists());
+		assert!(read_failed_sweeps(&path).sweeps.is_empty());
+	}
+}
+
+#[cfg(test)]
+mod update_balance_requests_tests {
+	use super::*;
+	use crate::UpdateBalanceRequest;
+	use lightning::ln::ChannelId;
+	use yuv_pixels::Luma;
+
+	#[test]
+	fn test_missing_update_balance_requests_read_as_empty() {
+		let data_dir = TempDir::new("missing_update_balance_requests_tests");
+
+		let requests =
+			read_update_balance_requests(&data_dir.path().join(UPDATE_BALANCE_REQUESTS_FNAME));
+		assert!(requests.requests.is_empty());
+		assert_eq!(requests.legacy_auto_accept_max_delta_msat, None);
+	}
+
+	#[test]
+	fn test_corrupt_update_balance_requests_read_as_empty() {
+		let data_dir = TempDir::new("corrupt_update_balance_requests_tests");
+		let path = data_dir.path().join(UPDATE_BALANCE_REQUESTS_FNAME);
+
+		let mut requests = UpdateBalanceRequestStorage {
+			requests: HashMap::new(),
+			legacy_auto_accept_max_delta_msat: None,
+		};
+		requests.requests.insert(
+			ChannelId([1; 32]),
+			UpdateBalanceRequest {
+				counterparty_msat: 5_000,
+				counterparty_yuv_luma: Some(Luma::from(42)),
+			},
+		);
+		let encoded = requests.encode();
+		fs::write(&path, &encoded[..encoded.len() / 2]).unwrap();
+
+		assert!(read_update_balance_requests(&path).requests.is_empty());
+	}
+
+	#[test]
+	fn test_legacy_auto_accept_threshold_is_read() {
+		let data_dir = TempDir::new("legacy_update_balance_requests_tests");
+		let fs_store = FilesystemStore::new(data_dir.path().to_path_buf());
+
+		// Files written before the threshold moved to the saved channel config still have it.
+		let requests = UpdateBalanceRequestStorage {
+			requests: HashMap::new(),
+			legacy_auto_accept_max_delta_msat: Some(1_000),
+		};
+		persist_update_balance_requests(&fs_store, &requests).unwrap();
+
+		let requests =
+			read_update_balance_requests(&data_dir.path().join(UPDATE_BALANCE_REQUESTS_FNAME));
+		assert_eq!(requests.legacy_auto_accept_max_delta_msat, Some(1_000));
+	}
+
+	#[test]
+	fn test_removing_last_update_balance_request_persists_empty_storage() {
+		let data_dir = TempDir::new("empty_update_balance_requests_tests");
+		let fs_store = FilesystemStore::new(data_dir.path().to_path_buf());
+		let path = data_dir.path().join(UPDATE_BALANCE_REQUESTS_FNAME);
+
+		let mut requests = UpdateBalanceRequestStorage {
+			requests: HashMap::new(),
+			legacy_auto_accept_max_delta_msat: None,
+		};
+		requests.requests.insert(
+			ChannelId([1; 32]),
+			UpdateBalanceRequest { counterparty_msat: 7_000, counterparty_yuv_luma: None },
+		);
+		persist_update_balance_requests(&fs_store, &requests).unwrap();
+		let requests = Mutex::new(requests);
+
+		crate::remove_update_balance_request(&requests, &ChannelId([1; 32]), &fs_store);
+
+		// An empty storage is written rather than the file being left with the stale request.
+		assert!(path.exists());
+		assert!(read_update_balance_requests(&path).requests.is_empty());
+	}
+}
+
+#[cfg(test)]
+mod channel_backup_tests {
+	use super::*;
+	use crate::ChannelMonitorBackup;
+
+	fn backup(channel_monitors: Vec<ChannelMonitorBackup>) -> ChannelBackup {
+		ChannelBackup { channel_manager: vec![1, 2, 3], channel_monitors }
+	}
+
+	#[test]
+	fn test_import_channel_backup() {
+		let data_dir = std::env::temp_dir().join("channel_backup_tests");
+		let _ = fs::remove_dir_all(&data_dir);
+		fs::create_dir_all(&data_dir).unwrap();
+		let fs_store = FilesystemStore::new(data_dir.join(".ldk"));
+		let keys_manager = KeysManager::new(&[42; 32], 0, 0);
+		let path = data_dir.join("channels.bak");
+
+		write_channel_backup(&path, &backup(Vec::new())).unwrap();
+		assert_eq!(import_channel_backup(&fs_store, &keys_manager, &path), Ok(0));
+		assert_eq!(fs::read(data_dir.join(".ldk").join("manager")).unwrap(), vec![1, 2, 3]);
+
+		// Channel state which already exists is never overwritten.
+		assert!(import_channel_backup(&fs_store, &keys_manager, &path).is_err());
+
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn test_import_invalid_channel_backup() {
+		let data_dir = std::env::temp_dir().join("invalid_channel_backup_tests");
+		let _ = fs::remove_dir_all(&data_dir);
+		fs::create_dir_all(&data_dir).unwrap();
+		let fs_store = FilesystemStore::new(data_dir.join(".ldk"));
+		let keys_manager = KeysManager::new(&[42; 32], 0, 0);
+		let path = data_dir.join("channels.bak");
+
+		fs::write(&path, [0xff; 8]).unwrap();
+		assert!(import_channel_backup(&fs_store, &keys_manager, &path).is_err());
+
+		let monitor =
+			ChannelMonitorBackup { key: "not_a_monitor".to_string(), monitor: vec![0; 8] };
+		write_channel_backup(&path, &backup(vec![monitor])).unwrap();
+		assert!(import_channel_backup(&fs_store, &keys_manager, &path).is_err());
+
+		// Nothing is written unless the whole backup is valid.
+		assert!(!data_dir.join(".ldk").exists());
+
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+}
+
+#[cfg(test)]
+mod payment_info_persistence_tests {
+	use super::*;
+	use crate::{HTLCStatus, MillisatAmount, PaymentInfo};
+	use lightning::ln::PaymentHash;
+
+	fn payments(amt_msat: u64) -> PaymentInfoStorage {
+		let mut payments = HashMap::new();
+		payments.insert(
+			PaymentHash([1; 32]),
+			PaymentInfo {
+				preimage: None,
+				secret: None,
+				status: HTLCStatus::Pending,
+				amt_msat: MillisatAmount(Some(amt_msat)),
+				yuv_pixel: None,
+				max_fee_msat: None,
+				description: None,
+			},
+		);
+		PaymentInfoStorage { payments }
+	}
+
+	#[test]
+	fn test_interrupted_write_keeps_previous_payments() {
+		let data_dir = TempDir::new("payment_info_persistence_tests");
+		let fs_store = FilesystemStore::new(data_dir.path().to_path_buf());
+		let path = data_dir.path().join(INBOUND_PAYMENTS_FNAME);
+		let tmp_path = data_dir.path().join(format!("{}.tmp", INBOUND_PAYMENTS_FNAME));
+
+		persist_payment_info(&fs_store, INBOUND_PAYMENTS_FNAME, &payments(1000)).unwrap();
+		assert!(!tmp_path.exists());
+
+		// A crash while writing the new payments leaves a partially written temporary file, but
+		// the payments file itself is only ever replaced as a whole.
+		let new_payments = payments(2000).encode();
+		fs::write(&tmp_path, &new_payments[..new_payments.len() / 2]).unwrap();
+		let read_payments = read_payment_info(&path);
+		let payment = read_payments.payments.get(&PaymentHash([1; 32])).unwrap();
+		assert_eq!(payment.amt_msat.0, Some(1000));
+
+		// The stale temporary file is overwritten by the next write.
+		persist_payment_info(&fs_store, INBOUND_PAYMENTS_FNAME, &payments(2000)).unwrap();
+		assert!(!tmp_path.exists());
+		let read_payments = read_payment_info(&path);
+		let payment = read_payments.payments.get(&PaymentHash([1; 32])).unwrap();
+		assert_eq!(payment.amt_msat.0, Some(2000));
+	}
+
+	#[test]
+	fn test_write_error_is_returned() {
+		let data_dir = TempDir::new("payment_info_write_error_tests");
+		let fs_store = FilesystemStore::new(data_dir.path().join("missing"));
+
+		assert!(persist_payment_info(&fs_store, INBOUND_PAYMENTS_FNAME, &payments(1000)).is_err());
+	}
+}
+
+#[cfg(test)]
+mod yuv_proof_deliveries_tests {
+	use super::*;
+
+	#[test]
+	fn test_corrupt_deliveries_are_logged() {
+		let data_dir = TempDir::new("yuv_proof_deliveries_tests");
+		let logger = FilesystemLogger::new(
+			data_dir.path().to_str().unwrap().to_string(),
+			LogFormat::Text,
+			Level::Trace,
+			None,
+		);
+		let path = data_dir.path().join(YUV_PROOF_DELIVERIES_FNAME);
+
+		assert!(read_yuv_proof_deliveries(&path, &logger).is_empty());
+
+		fs::write(&path, b"not json").unwrap();
+		assert!(read_yuv_proof_deliveries(&path, &logger).is_empty());
+
+		fs::write(&path, br#"[{"status": "pending", "attempts": 1, "tx": null}]"#).unwrap();
+		assert!(read_yuv_proof_deliveries(&path, &logger).is_empty());
+
+		let logs = fs::read_to_string(data_dir.path().join("logs/logs.txt")).unwrap();
+		assert!(logs.contains("Failed to parse YUV proof deliveries"));
+		assert!(logs.contains("Skipping unparsable YUV proof delivery"));
+	}
+}
@@ -4,37 +4,46 @@ mod cli;
 mod convert;
 mod disk;
 mod hex_utils;
+mod metrics;
+mod onion_messages;
+mod rpc_server;
+mod socks5;
 mod sweep;
 mod wallet;
 mod yuv_client;
 
 use crate::bitcoind_client::BitcoindClient;
 use crate::disk::FilesystemLogger;
+use crate::onion_messages::UserOnionMessageHandler;
 use crate::wallet::Wallet;
-use crate::yuv_client::YuvClient;
+use crate::yuv_client::{YuvClient, YuvClientError};
 use bdk::blockchain::rpc::Auth;
 use bdk::descriptor;
 use bdk::wallet::wallet_name_from_descriptor;
-use bitcoin::secp256k1::Secp256k1;
-use bitcoin::{BlockHash, Network};
-use disk::{INBOUND_PAYMENTS_FNAME, OUTBOUND_PAYMENTS_FNAME};
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::{BlockHash, Network, Txid};
+use disk::{
+	CHANNEL_CLOSURES_FNAME, FAILED_SWEEPS_FNAME, FORWARDS_FNAME, INBOUND_PAYMENTS_FNAME,
+	OUTBOUND_PAYMENTS_FNAME, PAYMENT_POLICY_FNAME, PEER_ACCESS_LISTS_FNAME,
+	UPDATE_BALANCE_REQUESTS_FNAME, USER_CONFIG_FNAME, YUV_PROOF_DELIVERIES_FNAME,
+};
 use lightning::chain::{chainmonitor, ChannelMonitorUpdateStatus, YuvConfirm};
 use lightning::chain::{Filter, Watch};
 use lightning::events::bump_transaction::{BumpTransactionEventHandler, Wallet as LdkWallet};
-use lightning::events::{Event, PaymentFailureReason, PaymentPurpose};
+use lightning::events::{Event, HTLCDestination, PaymentFailureReason, PaymentPurpose};
 use lightning::ln::chan_utils::NewUpdateBalanceRequest;
 use lightning::ln::channelmanager::RecentPaymentDetails;
 use lightning::ln::channelmanager::{
-	ChainParameters, ChannelManagerReadArgs, SimpleArcChannelManager,
+	ChainParameters, ChannelManagerReadArgs, InterceptId, Retry, SimpleArcChannelManager,
 };
 use lightning::ln::msgs::DecodeError;
-use lightning::ln::peer_handler::{IgnoringMessageHandler, MessageHandler, SimpleArcPeerManager};
+use lightning::ln::peer_handler::{IgnoringMessageHandler, MessageHandler};
 use lightning::ln::{ChannelId, PaymentHash, PaymentPreimage, PaymentSecret};
-use lightning::onion_message::messenger::{DefaultMessageRouter, SimpleArcOnionMessenger};
+use lightning::onion_message::messenger::DefaultMessageRouter;
 use lightning::routing::gossip;
 use lightning::routing::gossip::{NodeId, P2PGossipSync};
 use lightning::routing::router::DefaultRouter;
-use lightning::routing::scoring::ProbabilisticScoringFeeParameters;
+use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringFeeParameters};
 use lightning::sign::{EntropySource, InMemorySigner, KeysManager, SpendableOutputDescriptor};
 use lightning::util::config::UserConfig;
 use lightning::util::logger::Logger;
@@ -60,15 +69,20 @@ use std::io::Write;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::Mutex as TokioMutex;
 use ydk::bitcoin_provider::{BitcoinProviderConfig, BitcoinRpcConfig};
 use ydk::wallet::WalletConfig;
 
-use yuv_pixels::Pixel;
+use yuv_pixels::{Luma, Pixel};
+use yuv_types::YuvTransaction;
 
 pub(crate) const PENDING_SPENDABLE_OUTPUT_DIR: &'static str = "pending_spendable_outputs";
 
+/// How many times writing a spendable output to disk is attempted, a second apart, before it's
+/// only printed for the user to keep.
+const SPENDABLE_OUTPUT_PERSIST_ATTEMPTS: u32 = 5;
+
 #[derive(Copy, Clone)]
 pub(crate) enum HTLCStatus {
 	Pending,
@@ -112,6 +126,10 @@ pub(crate) struct PaymentInfo {
 	status: HTLCStatus,
 	amt_msat: MillisatAmount,
 	yuv_pixel: Option<Pixel>,
+	/// The routing fee limit an outbound payment was sent with, if any.
+	max_fee_msat: Option<u64>,
+	/// The description of the invoice the payment was made to, if any.
+	description: Option<String>,
 }
 
 impl_writeable_tlv_based!(PaymentInfo, {
@@ -120,6 +138,8 @@ impl_writeable_tlv_based!(PaymentInfo, {
 	(4, status, required),
 	(6, amt_msat, required),
 	(7, yuv_pixel, option),
+	(9, max_fee_msat, option),
+	(11, description, option),
 });
 
 pub(crate) struct PaymentInfoStorage {
@@ -130,6 +150,406 @@ impl_writeable_tlv_based!(PaymentInfoStorage, {
 	(0, payments, required),
 });
 
+/// A payment we forwarded, kept to report on routing income.
+pub(crate) struct ForwardInfo {
+	timestamp_secs: u64,
+	fee_earned_msat: Option<u64>,
+	amount_forwarded_msat: Option<u64>,
+	/// The YUV amount forwarded, with the chroma of the outbound channel.
+	yuv_pixel: Option<Pixel>,
+}
+
+impl_writeable_tlv_based!(ForwardInfo, {
+	(0, timestamp_secs, required),
+	(2, fee_earned_msat, option),
+	(4, amount_forwarded_msat, option),
+	(6, yuv_pixel, option),
+});
+
+/// How long forwards are kept for, which covers the longest window of `feereport`.
+const FORWARDS_RETENTION_SECS: u64 = 30 * 24 * 60 * 60;
+
+pub(crate) struct ForwardInfoStorage {
+	forwards: Vec<ForwardInfo>,
+	/// The number of forwards dropped once they were older than [`FORWARDS_RETENTION_SECS`].
+	expired_forwards: u64,
+	/// The amount forwarded by the dropped forwards.
+	expired_forwarded_msat: u64,
+}
+
+impl_writeable_tlv_based!(ForwardInfoStorage, {
+	(0, forwards, required_vec),
+	(1, expired_forwards, (default_value, 0)),
+	(3, expired_forwarded_msat, (default_value, 0)),
+});
+
+impl ForwardInfoStorage {
+	/// Records a forward, dropping the ones older than [`FORWARDS_RETENTION_SECS`] so that the
+	/// history doesn't grow without bound. They still count towards the totals.
+	fn record(&mut self, forward: ForwardInfo) {
+		let cutoff_secs = forward.timestamp_secs.saturating_sub(FORWARDS_RETENTION_SECS);
+		let expired = self.forwards.iter().filter(|f| f.timestamp_secs < cutoff_secs);
+		self.expired_forwards += expired.clone().count() as u64;
+		self.expired_forwarded_msat += expired.filter_map(|f| f.amount_forwarded_msat).sum::<u64>();
+		self.forwards.retain(|f| f.timestamp_secs >= cutoff_secs);
+		self.forwards.push(forward);
+	}
+
+	/// The number of forwards ever recorded, including the dropped ones.
+	pub(crate) fn total_forwards(&self) -> u64 {
+		self.expired_forwards + self.forwards.len() as u64
+	}
+
+	/// The amount ever forwarded, including by the dropped forwards.
+	pub(crate) fn total_forwarded_msat(&self) -> u64 {
+		self.expired_forwarded_msat
+			+ self.forwards.iter().filter_map(|f| f.amount_forwarded_msat).sum::<u64>()
+	}
+}
+
+/// A closed channel, kept so that the reasons channels were closed for can be looked into later.
+pub(crate) struct ChannelClosureInfo {
+	timestamp_secs: u64,
+	channel_id: ChannelId,
+	counterparty_node_id: Option<PublicKey>,
+	channel_capacity_sats: Option<u64>,
+	/// The displayed closure reason, which is kept as text so that reasons added by later LDK
+	/// versions don't make the log unreadable.
+	reason: String,
+}
+
+impl_writeable_tlv_based!(ChannelClosureInfo, {
+	(0, timestamp_secs, required),
+	(2, channel_id, required),
+	(4, counterparty_node_id, option),
+	(6, channel_capacity_sats, option),
+	(8, reason, required),
+});
+
+pub(crate) struct ChannelClosureStorage {
+	closures: Vec<ChannelClosureInfo>,
+}
+
+impl_writeable_tlv_based!(ChannelClosureStorage, {
+	(0, closures, required_vec),
+});
+
+/// A sweep of spendable outputs which the YUV node rejected on emulation.
+pub(crate) struct FailedSweepInfo {
+	timestamp_secs: u64,
+	/// The key the set of swept outputs is stored under in the `spendable_outputs` directory.
+	outputs_key: String,
+	txid: Txid,
+	reason: String,
+	/// The debug representation of the YUV proofs of the sweep.
+	proofs: String,
+	/// Whether this was the last rejection before the set was given up on.
+	abandoned: bool,
+}
+
+impl_writeable_tlv_based!(FailedSweepInfo, {
+	(0, timestamp_secs, required),
+	(2, outputs_key, required),
+	(4, txid, required),
+	(6, reason, required),
+	(8, proofs, required),
+	(10, abandoned, required),
+});
+
+pub(crate) struct FailedSweepStorage {
+	sweeps: Vec<FailedSweepInfo>,
+}
+
+impl_writeable_tlv_based!(FailedSweepStorage, {
+	(0, sweeps, required_vec),
+});
+
+/// Which inbound channels are accepted, depending on whether they're funded with YUV tokens.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum InboundChannelKind {
+	#[default]
+	All,
+	Yuv,
+	Bitcoin,
+}
+
+/// The inbound channels the node accepts, so that routing nodes can turn away spam opens.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct InboundChannelPolicy {
+	pub(crate) min_channel_size_sat: Option<u64>,
+	pub(crate) kind: InboundChannelKind,
+}
+
+impl InboundChannelPolicy {
+	/// Returns why a channel of the given value, funded with YUV tokens or not, is rejected, if it
+	/// is.
+	fn rejection_reason(&self, channel_value_satoshis: u64, is_yuv: bool) -> Option<String> {
+		if let Some(min_channel_size_sat) = self.min_channel_size_sat {
+			if channel_value_satoshis < min_channel_size_sat {
+				return Some(format!(
+					"channel value of {} sats is below the minimum of {} sats",
+					channel_value_satoshis, min_channel_size_sat
+				));
+			}
+		}
+		match (self.kind, is_yuv) {
+			(InboundChannelKind::Yuv, false) => Some("only YUV channels are accepted".to_string()),
+			(InboundChannelKind::Bitcoin, true) => Some("YUV channels aren't accepted".to_string()),
+			_ => None,
+		}
+	}
+}
+
+/// The settings of the default [`UserConfig`] which can be changed with `configchannel`, kept so
+/// that they survive restarts, along with the channel settings LDK doesn't know about.
+pub(crate) struct SavedUserConfig {
+	our_htlc_minimum_msat: u64,
+	max_inbound_htlc_value_in_flight_percent_of_channel: u8,
+	support_yuv_payments: bool,
+	forwarding_fee_base_msat: Option<u32>,
+	forwarding_fee_proportional_millionths: Option<u32>,
+	cltv_expiry_delta: Option<u16>,
+	/// Update-balance requests moving the counterparty's balance by less than this are accepted
+	/// automatically. Only requests which leave the YUV luma of the channel unchanged are, as no
+	/// delta is defined for luma.
+	update_balance_max_delta_msat: Option<u64>,
+}
+
+impl_writeable_tlv_based!(SavedUserConfig, {
+	(0, our_htlc_minimum_msat, required),
+	(2, max_inbound_htlc_value_in_flight_percent_of_channel, required),
+	(4, support_yuv_payments, required),
+	(5, forwarding_fee_base_msat, option),
+	(7, forwarding_fee_proportional_millionths, option),
+	(9, cltv_expiry_delta, option),
+	(11, update_balance_max_delta_msat, option),
+});
+
+impl SavedUserConfig {
+	pub(crate) fn from_config(
+		config: &UserConfig, update_balance_max_delta_msat: Option<u64>,
+	) -> Self {
+		Self {
+			our_htlc_minimum_msat: config.channel_handshake_config.our_htlc_minimum_msat,
+			max_inbound_htlc_value_in_flight_percent_of_channel: config
+				.channel_handshake_config
+				.max_inbound_htlc_value_in_flight_percent_of_channel,
+			support_yuv_payments: config.support_yuv_payments,
+			forwarding_fee_base_msat: Some(config.channel_config.forwarding_fee_base_msat),
+			forwarding_fee_proportional_millionths: Some(
+				config.channel_config.forwarding_fee_proportional_millionths,
+			),
+			cltv_expiry_delta: Some(config.channel_config.cltv_expiry_delta),
+			update_balance_max_delta_msat,
+		}
+	}
+
+	fn apply(&self, config: &mut UserConfig) {
+		config.channel_handshake_config.our_htlc_minimum_msat = self.our_htlc_minimum_msat;
+		config.channel_handshake_config.max_inbound_htlc_value_in_flight_percent_of_channel =
+			self.max_inbound_htlc_value_in_flight_percent_of_channel;
+		// YUV payments can't be supported without a YUV node, whatever was saved.
+		config.support_yuv_payments &= self.support_yuv_payments;
+		// Configs saved before the forwarding parameters could be set don't have them.
+		if let Some(fee_base_msat) = self.forwarding_fee_base_msat {
+			config.channel_config.forwarding_fee_base_msat = fee_base_msat;
+		}
+		if let Some(fee_ppm) = self.forwarding_fee_proportional_millionths {
+			config.channel_config.forwarding_fee_proportional_millionths = fee_ppm;
+		}
+		if let Some(cltv_expiry_delta) = self.cltv_expiry_delta {
+			config.channel_config.cltv_expiry_delta = cltv_expiry_delta;
+		}
+	}
+}
+
+/// The peers which may or may not open channels to us, as set with `configchannel`.
+pub(crate) struct PeerAccessLists {
+	/// If not empty, only these peers may open channels to us.
+	allowed: Vec<PublicKey>,
+	denied: Vec<PublicKey>,
+}
+
+impl_writeable_tlv_based!(PeerAccessLists, {
+	(0, allowed, required_vec),
+	(2, denied, required_vec),
+});
+
+impl PeerAccessLists {
+	pub(crate) fn allow(&mut self, node_id: PublicKey) {
+		self.denied.retain(|denied| *denied != node_id);
+		if !self.allowed.contains(&node_id) {
+			self.allowed.push(node_id);
+		}
+	}
+
+	pub(crate) fn deny(&mut self, node_id: PublicKey) {
+		self.allowed.retain(|allowed| *allowed != node_id);
+		if !self.denied.contains(&node_id) {
+			self.denied.push(node_id);
+		}
+	}
+
+	/// Drops `node_id` from both lists, returning whether it was on either.
+	pub(crate) fn remove(&mut self, node_id: &PublicKey) -> bool {
+		let num_entries = self.allowed.len() + self.denied.len();
+		self.allowed.retain(|allowed| allowed != node_id);
+		self.denied.retain(|denied| denied != node_id);
+		self.allowed.len() + self.denied.len() != num_entries
+	}
+
+	/// Returns why a channel opened by `node_id` is rejected, if it is.
+	fn rejection_reason(&self, node_id: &PublicKey) -> Option<String> {
+		if self.denied.contains(node_id) {
+			Some("peer is denied".to_string())
+		} else if !self.allowed.is_empty() && !self.allowed.contains(node_id) {
+			Some("peer isn't allowed".to_string())
+		} else {
+			None
+		}
+	}
+}
+
+/// How payments are sent when the command doesn't say otherwise, as set with `configpayment`.
+#[derive(Clone)]
+pub(crate) struct PaymentPolicy {
+	/// Payments are retried this many times if set, and for `retry_timeout_secs` otherwise.
+	retry_attempts: Option<u32>,
+	retry_timeout_secs: u64,
+	/// The routing fee cap, in parts per million of the amount sent.
+	max_fee_ppm: Option<u32>,
+	max_fee_msat: Option<u64>,
+	/// Whether the routes to the payee are probed before paying, so that the scorer learns about
+	/// their liquidity.
+	probe: bool,
+}
+
+impl_writeable_tlv_based!(PaymentPolicy, {
+	(0, retry_attempts, option),
+	(2, retry_timeout_secs, required),
+	(4, max_fee_ppm, option),
+	(6, max_fee_msat, option),
+	(8, probe, required),
+});
+
+impl Default for PaymentPolicy {
+	fn default() -> Self {
+		Self {
+			retry_attempts: None,
+			retry_timeout_secs: 10,
+			max_fee_ppm: None,
+			max_fee_msat: None,
+			probe: false,
+		}
+	}
+}
+
+impl PaymentPolicy {
+	pub(crate) fn retry(&self) -> Retry {
+		match self.retry_attempts {
+			Some(attempts) => Retry::Attempts(attempts),
+			None => Retry::Timeout(Duration::from_secs(self.retry_timeout_secs)),
+		}
+	}
+
+	/// The routing fee cap for a payment of `amt_msat`, the lower of both limits if both are set.
+	/// Without any limit, routing fees aren't capped.
+	pub(crate) fn fee_limit_msat(&self, amt_msat: u64) -> Option<u64> {
+		let ppm_limit =
+			self.max_fee_ppm.map(|ppm| (amt_msat as u128 * ppm as u128 / 1_000_000) as u64);
+		match (ppm_limit, self.max_fee_msat) {
+			(Some(ppm_limit), Some(max_fee_msat)) => Some(ppm_limit.min(max_fee_msat)),
+			(ppm_limit, max_fee_msat) => ppm_limit.or(max_fee_msat),
+		}
+	}
+}
+
+/// A channel monitor in a [`ChannelBackup`], stored under the same key the monitor persister uses.
+pub(crate) struct ChannelMonitorBackup {
+	/// The funding outpoint of the channel, as `<txid>_<index>`.
+	key: String,
+	monitor: Vec<u8>,
+}
+
+impl_writeable_tlv_based!(ChannelMonitorBackup, {
+	(0, key, required),
+	(2, monitor, required),
+});
+
+/// The state of all channels, as exported by `exportchannelbackup` and restored with
+/// `--import-channel-backup`.
+pub(crate) struct ChannelBackup {
+	channel_manager: Vec<u8>,
+	channel_monitors: Vec<ChannelMonitorBackup>,
+}
+
+impl_writeable_tlv_based!(ChannelBackup, {
+	(0, channel_manager, required),
+	(2, channel_monitors, required_vec),
+});
+
+/// An update-balance request received from the counterparty, which is yet to be responded to.
+pub(crate) struct UpdateBalanceRequest {
+	counterparty_msat: u64,
+	counterparty_yuv_luma: Option<Luma>,
+}
+
+impl Readable for UpdateBalanceRequest {
+	fn read<R: io::Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let counterparty_msat: u64 = Readable::read(r)?;
+		let counterparty_yuv_luma: Option<u128> = Readable::read(r)?;
+		Ok(UpdateBalanceRequest {
+			counterparty_msat,
+			counterparty_yuv_luma: counterparty_yuv_luma.map(Luma::from),
+		})
+	}
+}
+
+impl Writeable for UpdateBalanceRequest {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		self.counterparty_msat.write(w)?;
+		self.counterparty_yuv_luma.map(|luma| luma.amount).write(w)
+	}
+}
+
+/// An HTLC sent to one of our intercept SCIDs, held by LDK until it's forwarded with
+/// `forwardhtlc` or failed with `failhtlc`, or LDK fails it itself as it nears its expiry.
+pub(crate) struct InterceptedHtlc {
+	pub(crate) payment_hash: PaymentHash,
+	pub(crate) requested_next_hop_scid: u64,
+	pub(crate) inbound_amount_msat: u64,
+	pub(crate) expected_outbound_amount_msat: u64,
+	pub(crate) intercepted_at_secs: u64,
+}
+
+/// Forgets an HTLC LDK failed back because it was held at `scid` until nearly expiring. The event
+/// doesn't say which HTLC it was, so the one intercepted first, which is likely to expire first, is
+/// taken to be it.
+fn remove_expired_intercepted_htlc(
+	intercepted_htlcs: &mut HashMap<InterceptId, InterceptedHtlc>, scid: u64,
+) -> Option<InterceptedHtlc> {
+	let intercept_id = *intercepted_htlcs
+		.iter()
+		.filter(|(_, htlc)| htlc.requested_next_hop_scid == scid)
+		.min_by_key(|(_, htlc)| htlc.intercepted_at_secs)?
+		.0;
+	intercepted_htlcs.remove(&intercept_id)
+}
+
+/// The update-balance requests still to be responded to, keyed by channel, persisted so that they
+/// can be responded to after a restart.
+pub(crate) struct UpdateBalanceRequestStorage {
+	requests: HashMap<ChannelId, UpdateBalanceRequest>,
+	/// The auto-accept threshold, as kept here before it moved to the [`SavedUserConfig`]. Only
+	/// read to carry it over on startup, it's never written anymore.
+	legacy_auto_accept_max_delta_msat: Option<u64>,
+}
+
+impl_writeable_tlv_based!(UpdateBalanceRequestStorage, {
+	(0, requests, required),
+	(2, legacy_auto_accept_max_delta_msat, option),
+});
+
 type ChainMonitor = chainmonitor::ChainMonitor<
 	InMemorySigner,
 	Arc<dyn Filter + Send + Sync>,
@@ -154,14 +574,14 @@ pub(crate) type GossipVerifier = lightning_block_sync::gossip::GossipVerifier<
 	Arc<YuvClient>,
 >;
 
-pub(crate) type PeerManager = SimpleArcPeerManager<
+pub(crate) type PeerManager = lightning::ln::peer_handler::PeerManager<
 	SocketDescriptor,
-	ChainMonitor,
-	BitcoindClient,
-	YuvClient,
-	BitcoindClient,
-	GossipVerifier,
-	FilesystemLogger,
+	Arc<ChannelManager>,
+	Arc<P2PGossipSync<Arc<NetworkGraph>, GossipVerifier, Arc<FilesystemLogger>>>,
+	Arc<OnionMessenger>,
+	Arc<FilesystemLogger>,
+	IgnoringMessageHandler,
+	Arc<KeysManager>,
 >;
 
 pub(crate) type ChannelManager = SimpleArcChannelManager<
@@ -174,12 +594,25 @@ pub(crate) type ChannelManager = SimpleArcChannelManager<
 
 pub(crate) type NetworkGraph = gossip::NetworkGraph<Arc<FilesystemLogger>>;
 
-type OnionMessenger = SimpleArcOnionMessenger<
-	ChainMonitor,
-	BitcoindClient,
-	YuvClient,
-	BitcoindClient,
-	FilesystemLogger,
+pub(crate) type Scorer = ProbabilisticScorer<Arc<NetworkGraph>, Arc<FilesystemLogger>>;
+
+pub(crate) type Router = DefaultRouter<
+	Arc<NetworkGraph>,
+	Arc<FilesystemLogger>,
+	Arc<KeysManager>,
+	Arc<RwLock<Scorer>>,
+	ProbabilisticScoringFeeParameters,
+	Scorer,
+>;
+
+type OnionMessenger = lightning::onion_message::messenger::OnionMessenger<
+	Arc<KeysManager>,
+	Arc<KeysManager>,
+	Arc<FilesystemLogger>,
+	Arc<ChannelManager>,
+	Arc<DefaultMessageRouter<Arc<NetworkGraph>, Arc<FilesystemLogger>, Arc<KeysManager>>>,
+	Arc<ChannelManager>,
+	Arc<UserOnionMessageHandler>,
 >;
 
 pub(crate) type BumpTxEventHandler = BumpTransactionEventHandler<
@@ -195,6 +628,11 @@ async fn handle_ldk_events(
 	inbound_payments: Arc<Mutex<PaymentInfoStorage>>,
 	outbound_payments: Arc<Mutex<PaymentInfoStorage>>, fs_store: &Arc<FilesystemStore>,
 	event: Event, wallet: Arc<TokioMutex<Wallet>>, default_config: Arc<Mutex<UserConfig>>,
+	update_balance_requests: Arc<Mutex<UpdateBalanceRequestStorage>>,
+	update_balance_max_delta_msat: Arc<Mutex<Option<u64>>>, yuv_client: Option<Arc<YuvClient>>,
+	forwards: Arc<Mutex<ForwardInfoStorage>>, closures: Arc<Mutex<ChannelClosureStorage>>,
+	inbound_channel_policy: InboundChannelPolicy, peer_access_lists: Arc<Mutex<PeerAccessLists>>,
+	intercepted_htlcs: Arc<Mutex<HashMap<InterceptId, InterceptedHtlc>>>,
 ) {
 	match event {
 		Event::FundingGenerationReady {
@@ -234,14 +672,50 @@ async fn handle_ldk_events(
 					}
 				};
 
+				// Catch invalid proofs before locking funds, rather than having the counterparty
+				// reject the funding later on.
+				if let Some(yuv_client) = yuv_client.as_ref() {
+					let rejection = match yuv_client.emulate_yuv_transaction(yuv_tx.clone()).await {
+						Ok(reason) => reason
+							.map(|reason| format!("Invalid YUV funding transaction: {reason}")),
+						Err(err) => Some(format!("Failed to check YUV funding transaction: {err}")),
+					};
+					if let Some(rejection) = rejection {
+						eprintln!("ERROR: Closing channel. {rejection}");
+						wallet.release_funding_outpoints(&yuv_tx.bitcoin_tx.txid());
+
+						if let Err(err) = channel_manager.force_close_without_broadcasting_txn(
+							&temporary_channel_id,
+							&counterparty_node_id,
+						) {
+							eprintln!("ERROR: failed to force close channel: {err:?}");
+						}
+						return;
+					}
+				}
+
 				(yuv_tx.bitcoin_tx, Some(yuv_tx.tx_type))
 			} else {
-				let tx = wallet.new_funding_tx(output_script, channel_value_satoshis).unwrap();
+				let tx = match wallet.new_funding_tx(output_script, channel_value_satoshis) {
+					Ok(tx) => tx,
+					Err(err) => {
+						eprintln!("ERROR: Closing channel. Failed to create funding transaction: {err:#?}");
+
+						if let Err(err) = channel_manager.force_close_without_broadcasting_txn(
+							&temporary_channel_id,
+							&counterparty_node_id,
+						) {
+							eprintln!("ERROR: failed to force close channel: {err:?}");
+						}
+						return;
+					}
+				};
 
 				(tx, None)
 			};
 
 			// Give the funding transaction back to LDK for opening the channel.
+			let funding_txid = final_tx.txid();
 			match channel_manager.funding_transaction_generated(
 				&temporary_channel_id,
 				&counterparty_node_id,
@@ -250,6 +724,7 @@ async fn handle_ldk_events(
 			) {
 				Ok(()) => {}
 				Err(err) => {
+					wallet.release_funding_outpoints(&funding_txid);
 					println!("\r\nERROR: {:?}", err);
 					print!("\r> ");
 					io::stdout().flush().unwrap();
@@ -274,15 +749,18 @@ async fn handle_ldk_events(
 			);
 
 			if let Some(yuv_amount) = yuv_amount {
+				// The channel may have closed since the payment was received, in which case the
+				// chroma can't be displayed, but the payment is still claimed below.
 				let channels = channel_manager.list_channels();
-				let channel_details =
-					channels.iter().find(|c| Some(c.channel_id) == via_channel_id).unwrap();
-
-				println!(
-					" and YUV {} {}",
-					yuv_amount,
-					channel_details.yuv_holder_pixel.unwrap().chroma
-				);
+				let pixel = channels
+					.iter()
+					.find(|c| Some(c.channel_id) == via_channel_id)
+					.and_then(|c| c.yuv_holder_pixel);
+
+				match pixel {
+					Some(pixel) => println!(" and YUV {} {}", yuv_amount, pixel.chroma),
+					None => println!(" and YUV {} of unknown channel/pixel", yuv_amount),
+				}
 			} else {
 				println!(" and no YUV");
 			};
@@ -291,10 +769,23 @@ async fn handle_ldk_events(
 			io::stdout().flush().unwrap();
 			let payment_preimage = match purpose {
 				PaymentPurpose::Bolt11InvoicePayment { payment_preimage, .. } => payment_preimage,
+				PaymentPurpose::Bolt12OfferPayment { payment_preimage, .. } => payment_preimage,
+				PaymentPurpose::Bolt12RefundPayment { payment_preimage, .. } => payment_preimage,
 				PaymentPurpose::SpontaneousPayment(preimage) => Some(preimage),
-				_ => None,
 			};
-			channel_manager.claim_funds(payment_preimage.unwrap());
+			match payment_preimage {
+				Some(payment_preimage) => channel_manager.claim_funds(payment_preimage),
+				// Without the preimage the payment can't be claimed, it will be failed back once
+				// its claim deadline passes.
+				None => {
+					println!(
+						"\rWARNING: not claiming payment {} as its preimage is unknown",
+						payment_hash
+					);
+					print!("\r> ");
+					io::stdout().flush().unwrap();
+				}
+			}
 		}
 		Event::PaymentClaimed {
 			payment_hash,
@@ -309,16 +800,19 @@ async fn handle_ldk_events(
 				"\rEVENT: claimed payment from payment hash {} of {} millisatoshis",
 				payment_hash, amount_msat,
 			);
-			if let Some(yuv_amount) = sender_intended_total_yuv {
+			// The chroma isn't part of the event, so it's taken from the channel the payment
+			// arrived over.
+			let yuv_pixel = sender_intended_total_yuv.and_then(|yuv_amount| {
+				let channel_id = htlcs.first()?.channel_id;
 				let channels = channel_manager.list_channels();
-				let channel_details =
-					channels.iter().find(|c| c.channel_id == htlcs[0].channel_id).unwrap();
-
-				println!(
-					" and YUV {} {}",
-					yuv_amount,
-					channel_details.yuv_holder_pixel.unwrap().chroma
-				);
+				let channel = channels.iter().find(|c| c.channel_id == channel_id)?;
+				let chroma = channel.yuv_holder_pixel?.chroma;
+				Some(Pixel::new(Luma::from(yuv_amount), chroma))
+			});
+			if let Some(pixel) = yuv_pixel {
+				println!(" and YUV {} {}", pixel.luma.amount, pixel.chroma);
+			} else if let Some(yuv_amount) = sender_intended_total_yuv {
+				println!(" and YUV {}", yuv_amount);
 			} else {
 				println!(" and no YUV");
 			};
@@ -328,6 +822,9 @@ async fn handle_ldk_events(
 				PaymentPurpose::Bolt11InvoicePayment {
 					payment_preimage, payment_secret, ..
 				} => (payment_preimage, Some(payment_secret)),
+				PaymentPurpose::Bolt12OfferPayment { payment_preimage, payment_secret, .. } => {
+					(payment_preimage, Some(payment_secret))
+				}
 				PaymentPurpose::SpontaneousPayment(preimage) => (Some(preimage), None),
 				_ => (None, None),
 			};
@@ -338,6 +835,9 @@ async fn handle_ldk_events(
 					payment.status = HTLCStatus::Succeeded;
 					payment.preimage = payment_preimage;
 					payment.secret = payment_secret;
+					if yuv_pixel.is_some() {
+						payment.yuv_pixel = yuv_pixel;
+					}
 				}
 				Entry::Vacant(e) => {
 					e.insert(PaymentInfo {
@@ -345,13 +845,20 @@ async fn handle_ldk_events(
 						secret: payment_secret,
 						status: HTLCStatus::Succeeded,
 						amt_msat: MillisatAmount(Some(amount_msat)),
-						yuv_pixel: None,
+						yuv_pixel,
+						max_fee_msat: None,
+						description: None,
 					});
 				}
 			}
-			fs_store.write("", "", INBOUND_PAYMENTS_FNAME, &inbound.encode()).unwrap();
+			if let Err(e) = disk::persist_payment_info(&fs_store, INBOUND_PAYMENTS_FNAME, &inbound)
+			{
+				eprintln!("ERROR: failed to persist inbound payments: {e}");
+			}
 		}
-		Event::PaymentSent { payment_preimage, payment_hash, fee_paid_msat, .. } => {
+		Event::PaymentSent {
+			payment_id, payment_preimage, payment_hash, fee_paid_msat, ..
+		} => {
 			let mut outbound = outbound_payments.lock().unwrap();
 			match outbound.payments.get_mut(&payment_hash) {
 				Some(payment) => {
@@ -378,13 +885,78 @@ async fn handle_ldk_events(
 					print!("\r> ");
 					io::stdout().flush().unwrap();
 				}
+				// Offer payments only get a payment hash once the invoice for them arrives, so
+				// they're recorded once sent.
+				None if payment_id.is_some() => {
+					outbound.payments.insert(
+						payment_hash,
+						PaymentInfo {
+							preimage: Some(payment_preimage),
+							secret: None,
+							status: HTLCStatus::Succeeded,
+							amt_msat: MillisatAmount(None),
+							yuv_pixel: None,
+							max_fee_msat: None,
+							description: None,
+						},
+					);
+					println!(
+						"\rEVENT: successfully sent offer payment{} from payment hash {} with preimage {}",
+						fee_paid_msat.map_or(String::new(), |fee| format!(" (fee {} msat)", fee)),
+						payment_hash,
+						payment_preimage
+					);
+					print!("\r> ");
+					io::stdout().flush().unwrap();
+				}
 				None => return,
 			}
-			fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound.encode()).unwrap();
+			if let Err(e) =
+				disk::persist_payment_info(&fs_store, OUTBOUND_PAYMENTS_FNAME, &outbound)
+			{
+				eprintln!("ERROR: failed to persist outbound payments: {e}");
+			}
 		}
 		Event::OpenChannelRequest {
-			ref temporary_channel_id, ref counterparty_node_id, ..
+			ref temporary_channel_id,
+			ref counterparty_node_id,
+			channel_value_satoshis,
+			ref funding_yuv_pixel,
+			..
 		} => {
+			let rejection_reason =
+				peer_access_lists.lock().unwrap().rejection_reason(counterparty_node_id).or_else(
+					|| {
+						inbound_channel_policy
+							.rejection_reason(channel_value_satoshis, funding_yuv_pixel.is_some())
+					},
+				);
+			if let Some(reason) = rejection_reason {
+				// Force-closing a channel which is yet to be accepted rejects it.
+				let res = channel_manager.force_close_without_broadcasting_txn(
+					temporary_channel_id,
+					counterparty_node_id,
+				);
+				if let Err(e) = res {
+					println!(
+						"\rEVENT: Failed to reject inbound channel ({}) from {}: {:?}",
+						temporary_channel_id,
+						hex_utils::hex_str(&counterparty_node_id.serialize()),
+						e,
+					);
+				} else {
+					println!(
+						"\rEVENT: Rejected inbound channel ({}) from {}: {}",
+						temporary_channel_id,
+						hex_utils::hex_str(&counterparty_node_id.serialize()),
+						reason,
+					);
+				}
+				print!("\r> ");
+				io::stdout().flush().unwrap();
+				return;
+			}
+
 			let mut random_bytes = [0u8; 16];
 			random_bytes.copy_from_slice(&keys_manager.get_secure_random_bytes()[..16]);
 			let user_channel_id = u128::from_be_bytes(random_bytes);
@@ -412,25 +984,83 @@ async fn handle_ldk_events(
 			print!("\r> ");
 			io::stdout().flush().unwrap();
 		}
-		Event::PaymentPathSuccessful { .. } => {}
-		Event::PaymentPathFailed { .. } => {}
-		Event::ProbeSuccessful { .. } => {}
-		Event::ProbeFailed { .. } => {}
-		Event::PaymentFailed { payment_hash, reason, .. } => {
-			print!(
-				"\rEVENT: Failed to send payment to payment hash {}: {:?}",
+		Event::PaymentPathSuccessful { payment_hash, path, .. } => {
+			println!(
+				"\rEVENT: payment {} succeeded over a path of {} hops",
+				payment_hash.map(|hash| hash.to_string()).unwrap_or_default(),
+				path.hops.len()
+			);
+			print!("\r> ");
+			io::stdout().flush().unwrap();
+		}
+		Event::PaymentPathFailed {
+			payment_hash,
+			payment_failed_permanently,
+			failure,
+			path,
+			short_channel_id,
+			..
+		} => {
+			// The hop the failing channel leads to, if the failure could be attributed to one.
+			let failing_hop = short_channel_id.and_then(|scid| {
+				path.hops.iter().find(|hop| hop.short_channel_id == scid).map(|hop| hop.pubkey)
+			});
+			println!(
+				"\rEVENT: payment {} failed over a path of {} hops{}{}{}: {:?}",
 				payment_hash,
-				if let Some(r) = reason { r } else { PaymentFailureReason::RetriesExhausted }
+				path.hops.len(),
+				short_channel_id.map(|scid| format!(" at channel {}", scid)).unwrap_or_default(),
+				failing_hop.map(|pubkey| format!(" to {}", pubkey)).unwrap_or_default(),
+				if payment_failed_permanently { " permanently" } else { "" },
+				failure
 			);
 			print!("\r> ");
 			io::stdout().flush().unwrap();
+		}
+		Event::ProbeSuccessful { payment_hash, path, .. } => {
+			println!(
+				"\rEVENT: probe {} to {} succeeded over {} hops",
+				payment_hash,
+				path.hops.last().map(|hop| hop.pubkey.to_string()).unwrap_or_default(),
+				path.hops.len()
+			);
+			print!("\r> ");
+			io::stdout().flush().unwrap();
+		}
+		Event::ProbeFailed { payment_hash, path, short_channel_id, .. } => {
+			println!(
+				"\rEVENT: probe {} to {} failed{}",
+				payment_hash,
+				path.hops.last().map(|hop| hop.pubkey.to_string()).unwrap_or_default(),
+				short_channel_id.map(|scid| format!(" at channel {}", scid)).unwrap_or_default()
+			);
+			print!("\r> ");
+			io::stdout().flush().unwrap();
+		}
+		Event::PaymentFailed { payment_hash, reason, .. } => {
+			let reason = reason.unwrap_or(PaymentFailureReason::RetriesExhausted);
+			print!(
+				"\rEVENT: Failed to send payment to payment hash {}: {:?}",
+				payment_hash, reason
+			);
 
 			let mut outbound = outbound_payments.lock().unwrap();
-			if outbound.payments.contains_key(&payment_hash) {
-				let payment = outbound.payments.get_mut(&payment_hash).unwrap();
+			if let Some(payment) = outbound.payments.get_mut(&payment_hash) {
 				payment.status = HTLCStatus::Failed;
+				if let (PaymentFailureReason::RouteNotFound, Some(max_fee_msat)) =
+					(reason, payment.max_fee_msat)
+				{
+					print!(" (no route found within the fee limit of {} msat)", max_fee_msat);
+				}
+			}
+			println!();
+			print!("\r> ");
+			io::stdout().flush().unwrap();
+			if let Err(e) =
+				disk::persist_payment_info(&fs_store, OUTBOUND_PAYMENTS_FNAME, &outbound)
+			{
+				eprintln!("ERROR: failed to persist outbound payments: {e}");
 			}
-			fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound.encode()).unwrap();
 		}
 		Event::PaymentForwarded {
 			prev_channel_id,
@@ -508,8 +1138,51 @@ async fn handle_ldk_events(
 			}
 			print!("\r> ");
 			io::stdout().flush().unwrap();
+
+			let yuv_pixel = outbound_amount_forwarded_yuv.and_then(|yuv_amount| {
+				let next_channel =
+					channels.iter().find(|c| Some(c.channel_id) == next_channel_id)?;
+				let chroma = next_channel.yuv_holder_pixel?.chroma;
+				Some(Pixel::new(Luma::from(yuv_amount), chroma))
+			});
+			let mut forwards = forwards.lock().unwrap();
+			forwards.record(ForwardInfo {
+				timestamp_secs: SystemTime::now()
+					.duration_since(SystemTime::UNIX_EPOCH)
+					.unwrap()
+					.as_secs(),
+				fee_earned_msat: total_fee_earned_msat,
+				amount_forwarded_msat: outbound_amount_forwarded_msat,
+				yuv_pixel,
+			});
+			if let Err(e) = fs_store.write("", "", FORWARDS_FNAME, &forwards.encode()) {
+				eprintln!("ERROR: failed to persist forwards: {e}");
+			}
+		}
+		Event::HTLCHandlingFailed { prev_channel_id, failed_next_destination, .. } => {
+			println!(
+				"\rEVENT: failed to handle HTLC received over channel {}: {:?}",
+				prev_channel_id, failed_next_destination
+			);
+			// Intercepted HTLCs which `failhtlc` failed back are already forgotten, and show up as
+			// `UnknownNextHop`, while those LDK failed back as they neared expiry show up as
+			// `InvalidForward`.
+			if let HTLCDestination::InvalidForward { requested_forward_scid } =
+				failed_next_destination
+			{
+				let mut intercepted_htlcs = intercepted_htlcs.lock().unwrap();
+				if let Some(htlc) =
+					remove_expired_intercepted_htlc(&mut intercepted_htlcs, requested_forward_scid)
+				{
+					println!(
+						"\rEVENT: intercepted HTLC for payment {} expired and was failed back",
+						htlc.payment_hash
+					);
+				}
+			}
+			print!("\r> ");
+			io::stdout().flush().unwrap();
 		}
-		Event::HTLCHandlingFailed { .. } => {}
 		Event::PendingHTLCsForwardable { time_forwardable } => {
 			let forwarding_channel_manager = channel_manager.clone();
 			tokio::spawn(async move {
@@ -528,11 +1201,26 @@ async fn handle_ldk_events(
 			// to spend them regularly (possibly duplicatively/RBF'ing them). These can just be
 			// treated as normal funds where possible - they are only spendable by us and there is
 			// no rush to claim them.
+			//
+			// LDK won't hand us the event again once we return, so a failed write is retried, and
+			// if it keeps failing the output is printed so that it isn't lost along with the event.
 			for output in outputs {
-				let key = hex_utils::hex_str(&keys_manager.get_secure_random_bytes());
 				// Note that if the type here changes our read code needs to change as well.
 				let output: SpendableOutputDescriptor = output;
-				fs_store.write(PENDING_SPENDABLE_OUTPUT_DIR, "", &key, &output.encode()).unwrap();
+				let mut attempts = 1;
+				while let Err(e) = sweep::persist_pending_spendable_output(fs_store, &output) {
+					if attempts == SPENDABLE_OUTPUT_PERSIST_ATTEMPTS {
+						eprintln!(
+							"ERROR: failed to persist spendable output, save it decoded from hex as a file in {}/{} to have it swept: {}: {e}",
+							fs_store.get_data_dir().display(),
+							PENDING_SPENDABLE_OUTPUT_DIR,
+							hex_utils::hex_str(&output.encode())
+						);
+						break;
+					}
+					attempts += 1;
+					tokio::time::sleep(Duration::from_secs(1)).await;
+				}
 			}
 		}
 		Event::ChannelPending { channel_id, counterparty_node_id, .. } => {
@@ -557,13 +1245,23 @@ async fn handle_ldk_events(
 			);
 			print!("\r> ");
 			io::stdout().flush().unwrap();
+
+			// The funding transaction has confirmed, so the wallet sees its inputs as spent now.
+			let funding_txo = channel_manager
+				.list_channels()
+				.into_iter()
+				.find(|c| c.channel_id == *channel_id)
+				.and_then(|c| c.funding_txo);
+			if let Some(funding_txo) = funding_txo {
+				wallet.lock().await.release_funding_outpoints(&funding_txo.txid);
+			}
 		}
 		Event::ChannelClosed {
 			channel_id,
 			reason,
 			user_channel_id: _,
 			counterparty_node_id,
-			channel_capacity_sats: _,
+			channel_capacity_sats,
 			..
 		} => {
 			println!(
@@ -574,14 +1272,66 @@ async fn handle_ldk_events(
 			);
 			print!("\r> ");
 			io::stdout().flush().unwrap();
+
+			let closure = ChannelClosureInfo {
+				timestamp_secs: SystemTime::now()
+					.duration_since(SystemTime::UNIX_EPOCH)
+					.unwrap()
+					.as_secs(),
+				channel_id,
+				counterparty_node_id,
+				channel_capacity_sats,
+				reason: reason.to_string(),
+			};
+			record_channel_closure(&closures, closure, fs_store);
+
+			remove_update_balance_request(&update_balance_requests, &channel_id, fs_store);
+		}
+		Event::DiscardFunding { transaction, .. } => {
+			// The funding transaction will never be broadcast, so its coins can be spent again.
+			wallet.lock().await.release_funding_outpoints(&transaction.txid());
 		}
-		Event::DiscardFunding { .. } => {
-			// A "real" node should probably "lock" the UTXOs spent in funding transactions until
-			// the funding transaction either confirms, or this event is generated.
+		Event::InvoiceRequestFailed { payment_id } => {
+			println!(
+				"\rEVENT: Failed to get an invoice for offer payment {}",
+				hex_utils::hex_str(&payment_id.0)
+			);
+			print!("\r> ");
+			io::stdout().flush().unwrap();
+		}
+		Event::HTLCIntercepted {
+			intercept_id,
+			requested_next_hop_scid,
+			payment_hash,
+			inbound_amount_msat,
+			expected_outbound_amount_msat,
+		} => {
+			println!(
+				"\rEVENT: intercepted HTLC {} for payment {} to SCID {}, {} msat in and {} msat expected out. Use `forwardhtlc` or `failhtlc` to act on it",
+				hex_utils::hex_str(&intercept_id.0),
+				payment_hash,
+				requested_next_hop_scid,
+				inbound_amount_msat,
+				expected_outbound_amount_msat,
+			);
+			print!("\r> ");
+			io::stdout().flush().unwrap();
+			let intercepted_at_secs =
+				SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+			intercepted_htlcs.lock().unwrap().insert(
+				intercept_id,
+				InterceptedHtlc {
+					payment_hash,
+					requested_next_hop_scid,
+					inbound_amount_msat,
+					expected_outbound_amount_msat,
+					intercepted_at_secs,
+				},
+			);
 		}
-		Event::HTLCIntercepted { .. } => {}
 		Event::BumpTransaction(event) => bump_tx_event_handler.handle_event(&event),
 		Event::UpdateBalanceApplied(channel_id) => {
+			remove_update_balance_request(&update_balance_requests, &channel_id, fs_store);
 			println!("\rEVENT: Channel {} has applied the updated balances", channel_id);
 			print!("\r> ");
 			io::stdout().flush().unwrap();
@@ -596,10 +1346,52 @@ async fn handle_ldk_events(
 					 The new balances are: {} msat, {:?} yuv luma",
 					channel_id, updated_counterparty_msat, updated_counterparty_yuv_luma.map(|luma| luma.amount)
 				);
+
+				let auto_accept = {
+					let mut update_balance_requests = update_balance_requests.lock().unwrap();
+					update_balance_requests.requests.insert(
+						channel_id,
+						UpdateBalanceRequest {
+							counterparty_msat: updated_counterparty_msat,
+							counterparty_yuv_luma: updated_counterparty_yuv_luma,
+						},
+					);
+					if let Err(e) =
+						disk::persist_update_balance_requests(fs_store, &update_balance_requests)
+					{
+						eprintln!("ERROR: failed to persist update-balance requests: {e}");
+					}
+					update_balance_max_delta_msat.lock().unwrap().map_or(false, |max_delta| {
+						update_balance_within_delta(
+							channel_manager,
+							&channel_id,
+							updated_counterparty_msat,
+							updated_counterparty_yuv_luma,
+							max_delta,
+						)
+					})
+				};
+
+				if auto_accept {
+					println!("\rEVENT: Auto-accepting the update-balance request");
+					cli::respond_update_balance(
+						channel_id,
+						true,
+						&update_balance_requests,
+						Arc::clone(channel_manager),
+						fs_store,
+					);
+				} else {
+					println!(
+						"\rUse `respondupdatebalance {} <accept|reject>` to respond to the request",
+						channel_id
+					);
+				}
 				print!("\r> ");
 				io::stdout().flush().unwrap();
 			}
 			NewUpdateBalanceRequest::Revoke => {
+				remove_update_balance_request(&update_balance_requests, &channel_id, fs_store);
 				println!(
 					"\rEVENT: Channel {} has requested to revoke the update balances",
 					channel_id
@@ -612,6 +1404,178 @@ async fn handle_ldk_events(
 	}
 }
 
+/// Forgets the pending update-balance request of the channel, if any, once it's been resolved.
+/// Adds the closure to the listed ones. Failing to persist it is only reported, as the closure is
+/// still listed until the node restarts.
+fn record_channel_closure(
+	closures: &Mutex<ChannelClosureStorage>, closure: ChannelClosureInfo,
+	fs_store: &FilesystemStore,
+) {
+	let mut closures = closures.lock().unwrap();
+	closures.closures.push(closure);
+	if let Err(e) = disk::persist_channel_closures(fs_store, &closures) {
+		eprintln!("ERROR: failed to persist channel closures: {e}");
+	}
+}
+
+fn remove_update_balance_request(
+	update_balance_requests: &Mutex<UpdateBalanceRequestStorage>, channel_id: &ChannelId,
+	fs_store: &FilesystemStore,
+) {
+	let mut update_balance_requests = update_balance_requests.lock().unwrap();
+	if update_balance_requests.requests.remove(channel_id).is_none() {
+		return;
+	}
+	if let Err(e) = disk::persist_update_balance_requests(fs_store, &update_balance_requests) {
+		eprintln!("ERROR: failed to persist update-balance requests: {e}");
+	}
+}
+
+/// Checks whether the requested counterparty balance differs from its current one by less than
+/// `max_delta_msat`. No delta is defined for YUV luma, so only requests which leave the
+/// counterparty's luma unchanged can be within it.
+fn update_balance_within_delta(
+	channel_manager: &ChannelManager, channel_id: &ChannelId, counterparty_msat: u64,
+	counterparty_yuv_luma: Option<Luma>, max_delta_msat: u64,
+) -> bool {
+	let channels = channel_manager.list_channels();
+	let Some(channel) = channels.iter().find(|c| c.channel_id == *channel_id) else {
+		return false;
+	};
+	let Some(amounts) = channel.update_balance_amounts.as_ref() else {
+		return false;
+	};
+
+	if let Some(luma) = counterparty_yuv_luma {
+		let current_luma = channel.yuv_counterparty_pixel.map(|pixel| pixel.luma.amount);
+		if current_luma != Some(luma.amount) {
+			return false;
+		}
+	}
+
+	amounts.counterpartys_msat.abs_diff(counterparty_msat) < max_delta_msat
+}
+
+/// Returns the payment hashes of the given recent payments. Payments which are still awaiting a
+/// BOLT12 invoice don't have a payment hash yet, so they are skipped.
+fn recent_payments_payment_hashes(recent_payments: Vec<RecentPaymentDetails>) -> Vec<PaymentHash> {
+	recent_payments
+		.into_iter()
+		.filter_map(|p| match p {
+			RecentPaymentDetails::Pending { payment_hash, .. } => Some(payment_hash),
+			RecentPaymentDetails::Fulfilled { payment_hash, .. } => payment_hash,
+			RecentPaymentDetails::Abandoned { payment_hash, .. } => Some(payment_hash),
+			RecentPaymentDetails::AwaitingInvoice { .. } => None,
+		})
+		.collect()
+}
+
+/// Returns the txids in either list, each only once.
+fn union_txids(first: &[Txid], second: &[Txid]) -> Vec<Txid> {
+	let mut txids = Vec::with_capacity(first.len() + second.len());
+	for txid in first.iter().chain(second) {
+		if !txids.contains(txid) {
+			txids.push(*txid);
+		}
+	}
+	txids
+}
+
+/// Splits the transactions fetched for the txids pending in the channel manager and in the chain
+/// monitor between both, so that each is only handed the ones it's waiting on. A transaction both
+/// are waiting on goes to both.
+fn split_by_txid<T: Clone>(
+	txs: Vec<T>, txid: impl Fn(&T) -> Txid, manager_txids: &[Txid], monitor_txids: &[Txid],
+) -> (Vec<T>, Vec<T>) {
+	let (mut manager_txs, mut monitor_txs) = (Vec::new(), Vec::new());
+	for tx in txs {
+		let txid = txid(&tx);
+		if monitor_txids.contains(&txid) {
+			monitor_txs.push(tx.clone());
+		}
+		if manager_txids.contains(&txid) {
+			manager_txs.push(tx);
+		}
+	}
+	(manager_txs, monitor_txs)
+}
+
+/// How long pending YUV transactions are polled for after subscribing to confirmed ones failed or
+/// the subscription dropped, before subscribing is tried again. While subscribed, they're still
+/// polled for this often, to catch those the subscription didn't push.
+const YUV_RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Requests the YUV transactions the channel manager and the chain monitor are waiting on from the
+/// YUV node, handing each those which were confirmed.
+async fn poll_pending_yuv_txs(
+	yuv_client: &YuvClient, channel_manager: &ChannelManager, chain_monitor: &ChainMonitor,
+	logger: &FilesystemLogger,
+) {
+	// Both may wait on the same transaction, so it's only requested once.
+	let manager_txids = channel_manager.get_pending_yuv_txs();
+	let monitor_txids = chain_monitor.get_pending_yuv_txs();
+	let tx_ids_to_request = union_txids(&manager_txids, &monitor_txids);
+	if tx_ids_to_request.is_empty() {
+		return;
+	}
+
+	match yuv_client.get_list_raw_yuv_transactions(tx_ids_to_request).await {
+		Ok(pending_txs) => deliver_confirmed_yuv_txs(pending_txs, channel_manager, chain_monitor),
+		Err(YuvClientError::Empty) => {}
+		Err(e) => lightning::log_error!(
+			logger,
+			"Failed to get YUV transactions pending in channels: {}",
+			e
+		),
+	}
+}
+
+/// Hands the confirmed YUV transactions to the channel manager and the chain monitor, each only
+/// getting those it's waiting on, so that the ones pushed by the YUV node for unrelated channels
+/// are ignored.
+fn deliver_confirmed_yuv_txs(
+	yuv_txs: Vec<YuvTransaction>, channel_manager: &ChannelManager, chain_monitor: &ChainMonitor,
+) {
+	let manager_txids = channel_manager.get_pending_yuv_txs();
+	let monitor_txids = chain_monitor.get_pending_yuv_txs();
+	let (manager_txs, monitor_txs) =
+		split_by_txid(yuv_txs, |yuv_tx| yuv_tx.bitcoin_tx.txid(), &manager_txids, &monitor_txids);
+	if !manager_txs.is_empty() {
+		channel_manager.yuv_transactions_confirmed(manager_txs);
+	}
+	if !monitor_txs.is_empty() {
+		chain_monitor.yuv_transactions_confirmed(monitor_txs);
+	}
+}
+
+/// The longest we wait between attempts to reconnect to a channel peer.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// Tracks when each channel peer may next be reconnected to. Every failed attempt doubles the
+/// delay until the next one, up to [`MAX_RECONNECT_BACKOFF`], while a successful one resets it.
+#[derive(Default)]
+struct ReconnectBackoff {
+	peers: HashMap<PublicKey, (Instant, Duration)>,
+}
+
+impl ReconnectBackoff {
+	fn should_attempt(&self, node_id: &PublicKey, now: Instant) -> bool {
+		self.peers.get(node_id).map_or(true, |(next_attempt, _)| now >= *next_attempt)
+	}
+
+	fn record_failure(&mut self, node_id: PublicKey, now: Instant) {
+		let delay = match self.peers.get(&node_id) {
+			Some((_, delay)) => (*delay * 2).min(MAX_RECONNECT_BACKOFF),
+			None => Duration::from_secs(1),
+		};
+		self.peers.insert(node_id, (now + delay, delay));
+	}
+
+	fn record_success(&mut self, node_id: &PublicKey) {
+		self.peers.remove(node_id);
+	}
+}
+
 async fn start_ldk() {
 	let args = match args::parse_startup_args() {
 		Ok(user_args) => user_args,
@@ -624,7 +1588,12 @@ async fn start_ldk() {
 
 	// ## Setup
 	// Step 1: Initialize the Logger
-	let logger = Arc::new(FilesystemLogger::new(ldk_data_dir.clone()));
+	let logger = Arc::new(FilesystemLogger::new(
+		ldk_data_dir.clone(),
+		args.log_format,
+		args.log_level,
+		args.log_max_bytes,
+	));
 
 	let secp_ctx = Secp256k1::new();
 	let wallet_name = wallet_name_from_descriptor(
@@ -672,6 +1641,31 @@ async fn start_ldk() {
 		return;
 	}
 
+	// Check that the YUV node is reachable, as YUV payments can't work without it.
+	let yuv_client_opt = match args.yuv_rpc_url.clone() {
+		Some(yuv_rpc_url) => {
+			let yuv_client = YuvClient::new(
+				yuv_rpc_url.clone(),
+				args.yuv_rpc_timeout,
+				args.yuv_tx_cache_size,
+				args.yuv_txids_chunk_size,
+				args.yuv_max_concurrent_chunks,
+				format!("{}/{}", ldk_data_dir, YUV_PROOF_DELIVERIES_FNAME).into(),
+				tokio::runtime::Handle::current(),
+				Arc::clone(&logger),
+			);
+
+			if let Err(e) = yuv_client.check_connection().await {
+				println!("\rFailed to connect to the YUV node at {}: {}", yuv_rpc_url, e);
+				return;
+			}
+			yuv_client.resume_proof_deliveries();
+
+			Some(Arc::new(yuv_client))
+		}
+		None => None,
+	};
+
 	// Step 2: Initialize the FeeEstimator
 
 	// BitcoindClient implements the FeeEstimator trait, so it'll act as our fee estimator.
@@ -717,9 +1711,12 @@ async fn start_ldk() {
 		bitcoin_provider: BitcoinProviderConfig::BitcoinRpc(BitcoinRpcConfig {
 			url: format!("{}:{}", args.bitcoind_rpc_host, args.bitcoind_rpc_port),
 			network: args.network,
-			auth: Auth::UserPass {
-				username: args.bitcoind_rpc_username,
-				password: args.bitcoind_rpc_password,
+			auth: match args.bitcoind_rpc_cookie.clone() {
+				Some(file) => Auth::Cookie { file },
+				None => Auth::UserPass {
+					username: args.bitcoind_rpc_username,
+					password: args.bitcoind_rpc_password,
+				},
 			},
 			start_time: 0,
 		}),
@@ -727,7 +1724,13 @@ async fn start_ldk() {
 	};
 
 	let (wallet, wallet_source) = {
-		let wallet = Wallet::from_config(wallet_config.clone(), logger.clone()).await.unwrap();
+		let wallet = Wallet::from_config(
+			wallet_config.clone(),
+			Some(Path::new(&ldk_data_dir)),
+			logger.clone(),
+		)
+		.await
+		.unwrap();
 		let wallet_source = wallet.new_wallet_source();
 
 		(Arc::new(TokioMutex::new(wallet)), Arc::new(wallet_source))
@@ -742,6 +1745,21 @@ async fn start_ldk() {
 
 	// Step 5: Initialize Persistence
 	let fs_store = Arc::new(FilesystemStore::new(ldk_data_dir.clone().into()));
+	if let Some(backup_path) = &args.import_channel_backup {
+		println!("\rWARNING: restoring channels from {}. If this backup is older than the latest state of any of its channels, broadcasting that state lets the counterparty claim ALL of the channel's funds with a penalty transaction. Only restore the most recent backup, and never while another node with the same keys is running.", backup_path.display());
+		match disk::import_channel_backup(&fs_store, &keys_manager, backup_path) {
+			Ok(num_monitors) => {
+				println!(
+					"\rSUCCESS: restored the channel manager and {} channel monitors",
+					num_monitors
+				)
+			}
+			Err(e) => {
+				println!("\rERROR: failed to import the channel backup: {}", e);
+				return;
+			}
+		}
+	}
 	let persister = Arc::new(MonitorUpdatingPersister::new(
 		Arc::clone(&fs_store),
 		Arc::clone(&logger),
@@ -753,16 +1771,6 @@ async fn start_ldk() {
 	// larger `ChannelMonitor` update writes (but no deletion or cleanup):
 	//let persister = Arc::clone(&fs_store);
 
-	let yuv_client_opt = match args.yuv_rpc_url.clone() {
-		Some(yuv_rpc_url) => {
-			let yuv_client =
-				YuvClient::new(yuv_rpc_url, tokio::runtime::Handle::current(), Arc::clone(&logger));
-
-			Some(Arc::new(yuv_client))
-		}
-		None => None,
-	};
-
 	// Step 6: Initialize the ChainMonitor
 	let chain_monitor: Arc<ChainMonitor> = Arc::new(ChainMonitor::new(
 		None,
@@ -815,10 +1823,35 @@ async fn start_ldk() {
 	// Step 11: Initialize the ChannelManager
 	let mut user_config = UserConfig::default();
 	user_config.channel_handshake_limits.force_announced_channel_preference = false;
-	user_config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx = true;
-	user_config.manually_accept_inbound_channels = true;
-	user_config.channel_handshake_config.our_htlc_minimum_msat = 4_000_000;
+	user_config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx =
+		args.negotiate_anchors;
+	user_config.manually_accept_inbound_channels = args.manually_accept_inbound_channels;
+	user_config.channel_handshake_config.our_htlc_minimum_msat = args.htlc_minimum_msat;
+	user_config.accept_intercept_htlcs = args.intercept_htlcs;
 	user_config.support_yuv_payments = args.yuv_rpc_url.is_some();
+	if let Some(min_funding_confs) = args.min_funding_confs {
+		user_config.channel_handshake_config.minimum_depth = min_funding_confs;
+	}
+	// Changes made with `configchannel` take precedence over the defaults above.
+	let mut update_balance_max_delta_msat = None;
+	if let Some(saved_config) =
+		disk::read_user_config(Path::new(&format!("{}/{}", ldk_data_dir, USER_CONFIG_FNAME)))
+	{
+		if saved_config.our_htlc_minimum_msat != args.htlc_minimum_msat {
+			println!(
+				"\rNOTE: using the HTLC minimum of {} msat saved with configchannel instead of --htlc-minimum-msat {}",
+				saved_config.our_htlc_minimum_msat, args.htlc_minimum_msat
+			);
+			lightning::log_info!(
+				logger,
+				"Using the HTLC minimum of {} msat saved with configchannel instead of {} msat",
+				saved_config.our_htlc_minimum_msat,
+				args.htlc_minimum_msat
+			);
+		}
+		saved_config.apply(&mut user_config);
+		update_balance_max_delta_msat = saved_config.update_balance_max_delta_msat;
+	}
 	let default_config = Arc::new(Mutex::new(user_config));
 	let mut restarting_node = true;
 	let (channel_manager_blockhash, channel_manager) = {
@@ -837,7 +1870,7 @@ async fn start_ldk() {
 				chain_monitor.clone(),
 				broadcaster.clone(),
 				yuv_client,
-				router,
+				Arc::clone(&router),
 				logger.clone(),
 				user_config,
 				channel_monitor_mut_references,
@@ -857,7 +1890,7 @@ async fn start_ldk() {
 				chain_monitor.clone(),
 				broadcaster.clone(),
 				yuv_client_opt.clone(),
-				router,
+				Arc::clone(&router),
 				logger.clone(),
 				keys_manager.clone(),
 				keys_manager.clone(),
@@ -929,6 +1962,7 @@ async fn start_ldk() {
 
 	// Step 15: Initialize the PeerManager
 	let channel_manager: Arc<ChannelManager> = Arc::new(channel_manager);
+	let onion_message_handler = Arc::new(UserOnionMessageHandler::new(Arc::clone(&logger)));
 	let onion_messenger: Arc<OnionMessenger> = Arc::new(OnionMessenger::new(
 		Arc::clone(&keys_manager),
 		Arc::clone(&keys_manager),
@@ -936,7 +1970,7 @@ async fn start_ldk() {
 		Arc::clone(&channel_manager),
 		Arc::new(DefaultMessageRouter::new(Arc::clone(&network_graph), Arc::clone(&keys_manager))),
 		Arc::clone(&channel_manager),
-		IgnoringMessageHandler {},
+		Arc::clone(&onion_message_handler),
 	));
 	let mut ephemeral_bytes = [0; 32];
 	let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
@@ -1015,16 +2049,27 @@ async fn start_ldk() {
 		"{}/{}",
 		ldk_data_dir, OUTBOUND_PAYMENTS_FNAME
 	)))));
-	let recent_payments_payment_hashes = channel_manager
-		.list_recent_payments()
-		.into_iter()
-		.filter_map(|p| match p {
-			RecentPaymentDetails::Pending { payment_hash, .. } => Some(payment_hash),
-			RecentPaymentDetails::Fulfilled { payment_hash, .. } => payment_hash,
-			RecentPaymentDetails::Abandoned { payment_hash, .. } => Some(payment_hash),
-			RecentPaymentDetails::AwaitingInvoice { payment_id: _ } => todo!(),
-		})
-		.collect::<Vec<PaymentHash>>();
+	let forwards = Arc::new(Mutex::new(disk::read_forwards(Path::new(&format!(
+		"{}/{}",
+		ldk_data_dir, FORWARDS_FNAME
+	)))));
+	let closures = Arc::new(Mutex::new(disk::read_channel_closures(Path::new(&format!(
+		"{}/{}",
+		ldk_data_dir, CHANNEL_CLOSURES_FNAME
+	)))));
+	let failed_sweeps = Arc::new(Mutex::new(disk::read_failed_sweeps(Path::new(&format!(
+		"{}/{}",
+		ldk_data_dir, FAILED_SWEEPS_FNAME
+	)))));
+	let peer_access_lists = Arc::new(Mutex::new(disk::read_peer_access_lists(Path::new(
+		&format!("{}/{}", ldk_data_dir, PEER_ACCESS_LISTS_FNAME),
+	))));
+	let payment_policy = Arc::new(Mutex::new(disk::read_payment_policy(Path::new(&format!(
+		"{}/{}",
+		ldk_data_dir, PAYMENT_POLICY_FNAME
+	)))));
+	let recent_payments_payment_hashes =
+		recent_payments_payment_hashes(channel_manager.list_recent_payments());
 	for (payment_hash, payment_info) in outbound_payments
 		.lock()
 		.unwrap()
@@ -1036,9 +2081,14 @@ async fn start_ldk() {
 			payment_info.status = HTLCStatus::Failed;
 		}
 	}
-	fs_store
-		.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.lock().unwrap().encode())
-		.unwrap();
+	if let Err(e) = disk::persist_payment_info(
+		&fs_store,
+		OUTBOUND_PAYMENTS_FNAME,
+		&outbound_payments.lock().unwrap(),
+	) {
+		println!("ERROR: failed to persist outbound payments: {}", e);
+		return;
+	}
 
 	// Step 18: Handle LDK Events
 	let channel_manager_event_listener = Arc::clone(&channel_manager);
@@ -1047,42 +2097,112 @@ async fn start_ldk() {
 	let inbound_payments_event_listener = Arc::clone(&inbound_payments);
 	let outbound_payments_event_listener = Arc::clone(&outbound_payments);
 	let fs_store_event_listener = Arc::clone(&fs_store);
+	let mut update_balance_requests = disk::read_update_balance_requests(Path::new(&format!(
+		"{}/{}",
+		ldk_data_dir, UPDATE_BALANCE_REQUESTS_FNAME
+	)));
+	// Requests of channels which closed while we were offline can't be responded to anymore.
+	let channel_ids =
+		channel_manager.list_channels().into_iter().map(|c| c.channel_id).collect::<Vec<_>>();
+	update_balance_requests.requests.retain(|channel_id, _| channel_ids.contains(channel_id));
+	// The auto-accept threshold used to be kept with the requests, carry it over into the saved
+	// channel config.
+	if let Some(max_delta_msat) = update_balance_requests.legacy_auto_accept_max_delta_msat.take() {
+		if update_balance_max_delta_msat.is_none() {
+			update_balance_max_delta_msat = Some(max_delta_msat);
+			let saved_config = SavedUserConfig::from_config(
+				&default_config.lock().unwrap(),
+				update_balance_max_delta_msat,
+			);
+			if let Err(e) = fs_store.write("", "", USER_CONFIG_FNAME, &saved_config.encode()) {
+				println!("ERROR: failed to persist channel config: {}", e);
+			}
+		}
+	}
+	let update_balance_requests = Arc::new(Mutex::new(update_balance_requests));
+	let update_balance_requests_event_listener = Arc::clone(&update_balance_requests);
+	let update_balance_max_delta_msat = Arc::new(Mutex::new(update_balance_max_delta_msat));
+	let update_balance_max_delta_msat_event_listener = Arc::clone(&update_balance_max_delta_msat);
+	let intercepted_htlcs = Arc::new(Mutex::new(HashMap::new()));
+	let intercepted_htlcs_event_listener = Arc::clone(&intercepted_htlcs);
 
 	if let Some(yuv_client) = yuv_client_opt.clone() {
+		let yuv_poll_interval = args.yuv_poll_interval;
+		let yuv_subscribe = args.yuv_subscribe;
 		let channel_manager = Arc::clone(&channel_manager);
 		let chain_monitor = Arc::clone(&chain_monitor);
 		let yuv_listener = yuv_client.clone();
+		let yuv_logger = Arc::clone(&logger);
 		tokio::spawn(async move {
+			// Pending transactions are polled for until subscribing succeeds, and again whenever
+			// the subscription drops, in which case subscribing is retried a while later.
+			let mut next_subscribe = Instant::now();
 			loop {
-				let tx_ids_to_request = channel_manager.get_pending_yuv_txs();
-
-				if !tx_ids_to_request.is_empty() {
-					let pending_txs =
-						yuv_listener.get_list_raw_yuv_transactions(tx_ids_to_request).await;
-
-					if !pending_txs.is_empty() {
-						channel_manager.yuv_transactions_confirmed(pending_txs);
-					}
-				}
-
-				let tx_ids_to_request = chain_monitor.get_pending_yuv_txs();
-
-				if !tx_ids_to_request.is_empty() {
-					let pending_txs =
-						yuv_listener.get_list_raw_yuv_transactions(tx_ids_to_request).await;
-
-					if !pending_txs.is_empty() {
-						chain_monitor.yuv_transactions_confirmed(pending_txs);
+				if yuv_subscribe && Instant::now() >= next_subscribe {
+					match yuv_listener.subscribe_confirmed_yuv_transactions().await {
+						Ok(mut subscription) => {
+							lightning::log_info!(
+								yuv_logger,
+								"Subscribed to confirmed YUV transactions"
+							);
+							// Transactions which confirmed before the subscription was opened, or
+							// before a channel started waiting on them, are never pushed, so they're
+							// still polled for once in a while, starting right away.
+							let mut catch_up = tokio::time::interval(YUV_RESUBSCRIBE_INTERVAL);
+							loop {
+								tokio::select! {
+									result = subscription.next() => match result {
+										Some(Ok(yuv_tx)) => deliver_confirmed_yuv_txs(
+											vec![yuv_tx],
+											&channel_manager,
+											&chain_monitor,
+										),
+										Some(Err(e)) => lightning::log_error!(
+											yuv_logger,
+											"Failed to decode confirmed YUV transaction: {}",
+											e
+										),
+										None => break,
+									},
+									_ = catch_up.tick() => {
+										poll_pending_yuv_txs(
+											&yuv_listener,
+											&channel_manager,
+											&chain_monitor,
+											&yuv_logger,
+										)
+										.await
+									}
+								}
+							}
+							lightning::log_error!(
+								yuv_logger,
+								"Subscription to confirmed YUV transactions dropped, polling instead"
+							);
+						}
+						Err(e) => lightning::log_error!(
+							yuv_logger,
+							"Failed to subscribe to confirmed YUV transactions, polling instead: {}",
+							e
+						),
 					}
+					next_subscribe = Instant::now() + YUV_RESUBSCRIBE_INTERVAL;
 				}
 
-				tokio::time::sleep(Duration::from_secs(1)).await;
+				poll_pending_yuv_txs(&yuv_listener, &channel_manager, &chain_monitor, &yuv_logger)
+					.await;
+				tokio::time::sleep(yuv_poll_interval).await;
 			}
 		});
 	}
 
+	let yuv_client_event_listener = yuv_client_opt.clone();
+	let forwards_event_listener = Arc::clone(&forwards);
+	let closures_event_listener = Arc::clone(&closures);
+	let peer_access_lists_event_listener = Arc::clone(&peer_access_lists);
 	let event_handlers_wallet = wallet.clone();
 	let event_jandlers_default_config = default_config.clone();
+	let inbound_channel_policy = args.inbound_channel_policy;
 	let event_handler = move |event: Event| {
 		let channel_manager_event_listener = Arc::clone(&channel_manager_event_listener);
 		let network_graph_event_listener = Arc::clone(&network_graph_event_listener);
@@ -1093,6 +2213,14 @@ async fn start_ldk() {
 		let fs_store_event_listener = Arc::clone(&fs_store_event_listener);
 		let wallet = Arc::clone(&event_handlers_wallet.clone());
 		let default_config = Arc::clone(&event_jandlers_default_config);
+		let update_balance_requests = Arc::clone(&update_balance_requests_event_listener);
+		let update_balance_max_delta_msat =
+			Arc::clone(&update_balance_max_delta_msat_event_listener);
+		let yuv_client = yuv_client_event_listener.clone();
+		let forwards = Arc::clone(&forwards_event_listener);
+		let closures = Arc::clone(&closures_event_listener);
+		let peer_access_lists = Arc::clone(&peer_access_lists_event_listener);
+		let intercepted_htlcs = Arc::clone(&intercepted_htlcs_event_listener);
 
 		async move {
 			handle_ldk_events(
@@ -1106,6 +2234,14 @@ async fn start_ldk() {
 				event,
 				wallet,
 				default_config,
+				update_balance_requests,
+				update_balance_max_delta_msat,
+				yuv_client,
+				forwards,
+				closures,
+				inbound_channel_policy,
+				peer_access_lists,
+				intercepted_htlcs,
 			)
 			.await;
 		}
@@ -1138,19 +2274,53 @@ async fn start_ldk() {
 		|| Some(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap()),
 	));
 
+	// Regularly persist the scorer and network graph, so that what we've learned about the network
+	// survives a crash.
+	let persist_interval = args.persist_interval;
+	let persist_store = Arc::clone(&persister);
+	let persist_scorer = Arc::clone(&scorer);
+	let persist_network_graph = Arc::clone(&network_graph);
+	let persist_logger = Arc::clone(&logger);
+	tokio::spawn(async move {
+		let mut interval = tokio::time::interval_at(
+			tokio::time::Instant::now() + persist_interval,
+			persist_interval,
+		);
+		loop {
+			interval.tick().await;
+			if let Err(e) = disk::persist_scorer_and_network_graph(
+				&persist_store,
+				&persist_scorer,
+				&persist_network_graph,
+			) {
+				lightning::log_error!(
+					persist_logger,
+					"Failed to persist the scorer and network graph: {}",
+					e
+				);
+			}
+		}
+	});
+
 	// Regularly reconnect to channel peers.
 	let connect_cm = Arc::clone(&channel_manager);
 	let connect_pm = Arc::clone(&peer_manager);
 	let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir);
 	let stop_connect = Arc::clone(&stop_listen_connect);
+	let connect_proxy = args.proxy;
 	tokio::spawn(async move {
 		let mut interval = tokio::time::interval(Duration::from_secs(1));
 		interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+		let mut backoff = ReconnectBackoff::default();
 		loop {
 			interval.tick().await;
 			match disk::read_channel_peer_data(Path::new(&peer_data_path)) {
 				Ok(info) => {
 					let peers = connect_pm.list_peers();
+					// Peers which connected to us in the meantime start afresh next time.
+					backoff.peers.retain(|id, _| {
+						!peers.iter().any(|details| details.counterparty_node_id == *id)
+					});
 					for node_id in connect_cm
 						.list_channels()
 						.iter()
@@ -1161,14 +2331,19 @@ async fn start_ldk() {
 						if stop_connect.load(Ordering::Acquire) {
 							return;
 						}
-						for (pubkey, peer_addr) in info.iter() {
-							if *pubkey == node_id {
-								let _ = cli::do_connect_peer(
-									*pubkey,
-									*peer_addr,
+						if let Some(peer_addrs) = info.get(&node_id) {
+							if backoff.should_attempt(&node_id, Instant::now()) {
+								match cli::do_connect_peer_addrs(
+									node_id,
+									peer_addrs,
 									Arc::clone(&connect_pm),
+									connect_proxy,
 								)
-								.await;
+								.await
+								{
+									Ok(()) => backoff.record_success(&node_id),
+									Err(()) => backoff.record_failure(node_id, Instant::now()),
+								}
 							}
 						}
 					}
@@ -1184,12 +2359,20 @@ async fn start_ldk() {
 	let chan_man = Arc::clone(&channel_manager);
 	let network = args.network;
 	let an_logger = Arc::clone(&logger);
+	let announcement_warmup = args.announcement_warmup;
+	let announcement_interval = args.announcement_interval;
+	// Shared with the CLI, so that the alias and addresses can be changed without restarting.
+	let node_alias = Arc::new(Mutex::new(args.ldk_announced_node_name));
+	let an_node_alias = Arc::clone(&node_alias);
+	let announced_addrs = Arc::new(Mutex::new(args.ldk_announced_listen_addr));
+	let an_announced_addrs = Arc::clone(&announced_addrs);
 	tokio::spawn(async move {
-		// First wait a minute until we have some peers and maybe have opened a channel.
-		tokio::time::sleep(Duration::from_secs(60)).await;
-		// Then, update our announcement once an hour to keep it fresh but avoid unnecessary churn
-		// in the global gossip network.
-		let mut interval = tokio::time::interval(Duration::from_secs(30)); // TODO: turn it back to 3600
+		// First wait a while (a minute by default) until we have some peers and maybe have opened
+		// a channel.
+		tokio::time::sleep(announcement_warmup).await;
+		// Then, update our announcement regularly (once an hour by default) to keep it fresh but
+		// avoid unnecessary churn in the global gossip network.
+		let mut interval = tokio::time::interval(announcement_interval);
 		loop {
 			interval.tick().await;
 			// Don't bother trying to announce if we don't have any public channls, though our
@@ -1198,8 +2381,8 @@ async fn start_ldk() {
 			if chan_man.list_channels().iter().any(|chan| chan.is_public) {
 				peer_man.broadcast_node_announcement(
 					[0; 3],
-					args.ldk_announced_node_name,
-					args.ldk_announced_listen_addr.clone(),
+					*an_node_alias.lock().unwrap(),
+					an_announced_addrs.lock().unwrap().clone(),
 				);
 
 				lightning::log_trace!(&an_logger, "Node announcement broadcasted");
@@ -1210,25 +2393,87 @@ async fn start_ldk() {
 	tokio::spawn(sweep::periodic_sweep(
 		ldk_data_dir.clone(),
 		Arc::clone(&keys_manager),
+		keys_seed,
+		args.network,
 		Arc::clone(&logger),
 		Arc::clone(&persister),
 		Arc::clone(&wallet),
-		yuv_client_opt,
+		yuv_client_opt.clone(),
 		Arc::clone(&bitcoind_client),
 		Arc::clone(&channel_manager),
+		args.sweep_conf_target,
+		args.sweep_destination,
+		Arc::clone(&failed_sweeps),
+		args.max_sweep_rejections,
 	));
 
+	// Serve the JSON-RPC API, if requested.
+	let rpc_server_handle = match args.rpc_listen {
+		Some(listen_addr) => {
+			let context = rpc_server::RpcContext {
+				channel_manager: Arc::clone(&channel_manager),
+				peer_manager: Arc::clone(&peer_manager),
+				network_graph: Arc::clone(&network_graph),
+				keys_manager: Arc::clone(&keys_manager),
+				inbound_payments: Arc::clone(&inbound_payments),
+				outbound_payments: Arc::clone(&outbound_payments),
+				fs_store: Arc::clone(&persister),
+				default_config: Arc::clone(&default_config),
+				payment_policy: Arc::clone(&payment_policy),
+				wallet: Arc::clone(&wallet),
+				logger: Arc::clone(&logger),
+				ldk_data_dir: ldk_data_dir.clone(),
+				network,
+				yuv_enabled: yuv_client_opt.is_some(),
+				proxy: args.proxy,
+			};
+			match rpc_server::start_rpc_server(listen_addr, context).await {
+				Ok(handle) => Some((handle, ldk_data_dir.clone())),
+				Err(e) => {
+					println!("ERROR: failed to start the RPC server on {}: {}", listen_addr, e);
+					return;
+				}
+			}
+		}
+		None => None,
+	};
+
+	// Serve the metrics, if requested.
+	let metrics_server = match args.metrics_listen {
+		Some(listen_addr) => {
+			let context = metrics::MetricsContext {
+				channel_manager: Arc::clone(&channel_manager),
+				chain_monitor: Arc::clone(&chain_monitor),
+				peer_manager: Arc::clone(&peer_manager),
+				yuv_client: yuv_client_opt.clone(),
+				forwards: Arc::clone(&forwards),
+			};
+			match metrics::start_metrics_server(listen_addr, context).await {
+				Ok(server) => Some(server),
+				Err(e) => {
+					println!("ERROR: failed to start the metrics server on {}: {}", listen_addr, e);
+					return;
+				}
+			}
+		}
+		None => None,
+	};
+
 	// Start the CLI.
 	let cli_channel_manager = Arc::clone(&channel_manager);
 	let cli_persister = Arc::clone(&persister);
 	let cli_logger = Arc::clone(&logger);
 	let cli_peer_manager = Arc::clone(&peer_manager);
+	let cli_network_graph = Arc::clone(&network_graph);
+	let cli_proxy = args.proxy;
+	let cli_router = Arc::clone(&router);
+	let cli_chain_monitor = Arc::clone(&chain_monitor);
 	let cli_poll = tokio::task::spawn_blocking(move || {
 		cli::poll_for_user_input(
 			cli_peer_manager,
 			cli_channel_manager,
 			keys_manager,
-			network_graph,
+			cli_network_graph,
 			onion_messenger,
 			inbound_payments,
 			outbound_payments,
@@ -1237,19 +2482,49 @@ async fn start_ldk() {
 			cli_logger,
 			cli_persister,
 			default_config,
+			wallet,
+			yuv_client_opt,
+			bitcoind_client,
+			update_balance_requests,
+			update_balance_max_delta_msat,
+			forwards,
+			closures,
+			failed_sweeps,
+			peer_access_lists,
+			cli_router,
+			cli_proxy,
+			node_alias,
+			announced_addrs,
+			cli_chain_monitor,
+			payment_policy,
+			onion_message_handler,
+			intercepted_htlcs,
 		);
 	});
 
-	// Exit if either CLI polling exits or the background processor exits (which shouldn't happen
-	// unless we fail to write to the filesystem).
+	// Exit if either CLI polling exits, we're asked to terminate, or the background processor exits
+	// (which shouldn't happen unless we fail to write to the filesystem).
 	let mut bg_res = Ok(Ok(()));
 	tokio::select! {
 		_ = cli_poll => {},
+		_ = wait_for_sigterm() => {
+			// The CLI is left blocked on reading input, so restore the terminal for it.
+			let _ = crossterm::terminal::disable_raw_mode();
+			println!("\rReceived SIGTERM, shutting down");
+		},
 		bg_exit = &mut background_processor => {
 			bg_res = bg_exit;
 		},
 	}
 
+	if let Some((handle, rpc_data_dir)) = rpc_server_handle {
+		let _ = handle.stop();
+		rpc_server::remove_rpc_cookie(&rpc_data_dir);
+	}
+	if let Some(server) = metrics_server {
+		server.abort();
+	}
+
 	// Disconnect our peers and stop accepting new connections. This ensures we don't continue
 	// updating our channel data after we've stopped the background processor.
 	stop_listen_connect.store(true, Ordering::Release);
@@ -1273,6 +2548,28 @@ async fn start_ldk() {
 		bp_exit.send(()).unwrap();
 		background_processor.await.unwrap().unwrap();
 	}
+
+	if let Err(e) = disk::persist_scorer_and_network_graph(&persister, &scorer, &network_graph) {
+		println!("\rERROR: failed to persist the scorer and network graph: {}", e);
+	}
+}
+
+/// Resolves once the process receives SIGTERM, e.g. when it's stopped by systemd.
+async fn wait_for_sigterm() {
+	#[cfg(not(target_os = "windows"))]
+	{
+		use tokio::signal::unix::{signal, SignalKind};
+
+		match signal(SignalKind::terminate()) {
+			Ok(mut sigterm) => {
+				sigterm.recv().await;
+				return;
+			}
+			Err(e) => println!("\rERROR: failed to listen for SIGTERM: {}", e),
+		}
+	}
+
+	std::future::pending::<()>().await
 }
 
 #[tokio::main]
@@ -1301,4 +2598,394 @@ pub async fn main() {
 	}
 
 	start_ldk().await;
+
+	// After a SIGTERM the CLI may still be blocked on reading input, so don't wait for it to
+	// return before exiting.
+	std::process::exit(0);
+}
+
+#[cfg(test)]
+mod pending_yuv_txs_tests {
+	use super::*;
+	use bitcoin::hashes::Hash;
+
+	fn txid(byte: u8) -> Txid {
+		Txid::from_byte_array([byte; 32])
+	}
+
+	#[test]
+	fn test_union_txids() {
+		assert_eq!(
+			union_txids(&[txid(1), txid(2)], &[txid(2), txid(3)]),
+			vec![txid(1), txid(2), txid(3)]
+		);
+		assert_eq!(union_txids(&[], &[txid(1)]), vec![txid(1)]);
+	}
+
+	#[test]
+	fn test_split_by_txid() {
+		let (manager_txs, monitor_txs) = split_by_txid(
+			vec![txid(1), txid(2), txid(3), txid(4)],
+			|txid| *txid,
+			&[txid(1), txid(2)],
+			&[txid(2), txid(3)],
+		);
+		assert_eq!(manager_txs, vec![txid(1), txid(2)]);
+		assert_eq!(monitor_txs, vec![txid(2), txid(3)]);
+	}
+}
+
+#[cfg(test)]
+mod forward_info_storage_tests {
+	use super::*;
+
+	fn forward(timestamp_secs: u64, amount_forwarded_msat: u64) -> ForwardInfo {
+		ForwardInfo {
+			timestamp_secs,
+			fee_earned_msat: Some(1),
+			amount_forwarded_msat: Some(amount_forwarded_msat),
+			yuv_pixel: None,
+		}
+	}
+
+	#[test]
+	fn test_record_drops_expired_forwards() {
+		let mut forwards = ForwardInfoStorage {
+			forwards: Vec::new(),
+			expired_forwards: 0,
+			expired_forwarded_msat: 0,
+		};
+		forwards.record(forward(1_000, 100));
+		forwards.record(forward(2_000, 200));
+		forwards.record(forward(FORWARDS_RETENTION_SECS + 1_500, 300));
+
+		assert_eq!(forwards.forwards.len(), 2);
+		assert_eq!(forwards.forwards[0].timestamp_secs, 2_000);
+		assert_eq!(forwards.total_forwards(), 3);
+		assert_eq!(forwards.total_forwarded_msat(), 600);
+
+		let decoded = ForwardInfoStorage::read(&mut &forwards.encode()[..]).unwrap();
+		assert_eq!(decoded.forwards.len(), 2);
+		assert_eq!(decoded.total_forwards(), 3);
+		assert_eq!(decoded.total_forwarded_msat(), 600);
+	}
+}
+
+#[cfg(test)]
+mod recent_payments_tests {
+	use super::*;
+	use lightning::ln::channelmanager::PaymentId;
+
+	#[test]
+	fn test_recent_payments_payment_hashes_skips_awaiting_invoice() {
+		let recent_payments = vec![
+			RecentPaymentDetails::AwaitingInvoice { payment_id: PaymentId([0; 32]) },
+			RecentPaymentDetails::Pending {
+				payment_id: PaymentId([1; 32]),
+				payment_hash: PaymentHash([1; 32]),
+				total_msat: 1000,
+			},
+			RecentPaymentDetails::Fulfilled {
+				payment_id: PaymentId([2; 32]),
+				payment_hash: Some(PaymentHash([2; 32])),
+			},
+			RecentPaymentDetails::Fulfilled { payment_id: PaymentId([3; 32]), payment_hash: None },
+			RecentPaymentDetails::Abandoned {
+				payment_id: PaymentId([4; 32]),
+				payment_hash: PaymentHash([4; 32]),
+			},
+		];
+
+		assert_eq!(
+			recent_payments_payment_hashes(recent_payments),
+			vec![PaymentHash([1; 32]), PaymentHash([2; 32]), PaymentHash([4; 32])]
+		);
+	}
+}
+
+#[cfg(test)]
+mod reconnect_backoff_tests {
+	use super::*;
+
+	fn node_id() -> PublicKey {
+		let secp_ctx = Secp256k1::new();
+		let secret_key = bitcoin::secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+		PublicKey::from_secret_key(&secp_ctx, &secret_key)
+	}
+
+	#[test]
+	fn test_reconnect_backoff_doubles_until_capped() {
+		let node_id = node_id();
+		let mut backoff = ReconnectBackoff::default();
+		let now = Instant::now();
+		assert!(backoff.should_attempt(&node_id, now));
+
+		backoff.record_failure(node_id, now);
+		assert!(!backoff.should_attempt(&node_id, now));
+		assert!(backoff.should_attempt(&node_id, now + Duration::from_secs(1)));
+
+		backoff.record_failure(node_id, now);
+		assert!(!backoff.should_attempt(&node_id, now + Duration::from_secs(1)));
+		assert!(backoff.should_attempt(&node_id, now + Duration::from_secs(2)));
+
+		for _ in 0..20 {
+			backoff.record_failure(node_id, now);
+		}
+		assert!(!backoff.should_attempt(&node_id, now + MAX_RECONNECT_BACKOFF / 2));
+		assert!(backoff.should_attempt(&node_id, now + MAX_RECONNECT_BACKOFF));
+	}
+
+	#[test]
+	fn test_reconnect_backoff_resets_on_success() {
+		let node_id = node_id();
+		let mut backoff = ReconnectBackoff::default();
+		let now = Instant::now();
+
+		backoff.record_failure(node_id, now);
+		backoff.record_failure(node_id, now);
+		backoff.record_success(&node_id);
+		assert!(backoff.should_attempt(&node_id, now));
+
+		backoff.record_failure(node_id, now);
+		assert!(backoff.should_attempt(&node_id, now + Duration::from_secs(1)));
+	}
+}
+
+#[cfg(test)]
+mod inbound_channel_policy_tests {
+	use super::*;
+
+	#[test]
+	fn test_default_policy_accepts_everything() {
+		let policy = InboundChannelPolicy::default();
+		assert_eq!(policy.rejection_reason(1, false), None);
+		assert_eq!(policy.rejection_reason(1, true), None);
+	}
+
+	#[test]
+	fn test_channels_below_minimum_size_rejected() {
+		let policy =
+			InboundChannelPolicy { min_channel_size_sat: Some(100_000), ..Default::default() };
+		assert!(policy.rejection_reason(99_999, false).is_some());
+		assert!(policy.rejection_reason(99_999, true).is_some());
+		assert_eq!(policy.rejection_reason(100_000, false), None);
+	}
+
+	#[test]
+	fn test_channels_gated_on_yuv_funding() {
+		let yuv_only = InboundChannelPolicy { kind: InboundChannelKind::Yuv, ..Default::default() };
+		assert!(yuv_only.rejection_reason(100_000, false).is_some());
+		assert_eq!(yuv_only.rejection_reason(100_000, true), None);
+
+		let bitcoin_only =
+			InboundChannelPolicy { kind: InboundChannelKind::Bitcoin, ..Default::default() };
+		assert_eq!(bitcoin_only.rejection_reason(100_000, false), None);
+		assert!(bitcoin_only.rejection_reason(100_000, true).is_some());
+	}
+}
+
+#[cfg(test)]
+mod peer_access_lists_tests {
+	use super::*;
+
+	fn node_id(byte: u8) -> PublicKey {
+		let secp_ctx = Secp256k1::new();
+		let secret_key = bitcoin::secp256k1::SecretKey::from_slice(&[byte; 32]).unwrap();
+		PublicKey::from_secret_key(&secp_ctx, &secret_key)
+	}
+
+	#[test]
+	fn test_empty_lists_accept_everyone() {
+		let lists = PeerAccessLists { allowed: Vec::new(), denied: Vec::new() };
+		assert_eq!(lists.rejection_reason(&node_id(1)), None);
+	}
+
+	#[test]
+	fn test_denied_peer_rejected() {
+		let mut lists = PeerAccessLists { allowed: Vec::new(), denied: Vec::new() };
+		lists.deny(node_id(1));
+		assert!(lists.rejection_reason(&node_id(1)).is_some());
+		assert_eq!(lists.rejection_reason(&node_id(2)), None);
+	}
+
+	#[test]
+	fn test_allow_list_rejects_unlisted_peers() {
+		let mut lists = PeerAccessLists { allowed: Vec::new(), denied: Vec::new() };
+		lists.allow(node_id(1));
+		assert_eq!(lists.rejection_reason(&node_id(1)), None);
+		assert!(lists.rejection_reason(&node_id(2)).is_some());
+	}
+
+	#[test]
+	fn test_allowing_denied_peer_moves_it() {
+		let mut lists = PeerAccessLists { allowed: Vec::new(), denied: Vec::new() };
+		lists.deny(node_id(1));
+		lists.allow(node_id(1));
+		assert_eq!(lists.denied, Vec::new());
+		assert_eq!(lists.allowed, vec![node_id(1)]);
+		assert_eq!(lists.rejection_reason(&node_id(1)), None);
+	}
+
+	#[test]
+	fn test_removed_peer_is_unlisted() {
+		let mut lists = PeerAccessLists { allowed: Vec::new(), denied: Vec::new() };
+		lists.allow(node_id(1));
+		lists.deny(node_id(2));
+		assert!(lists.remove(&node_id(2)));
+		assert!(!lists.remove(&node_id(2)));
+		assert_eq!(lists.denied, Vec::new());
+		assert!(lists.rejection_reason(&node_id(2)).is_some());
+
+		assert!(lists.remove(&node_id(1)));
+		assert_eq!(lists.allowed, Vec::new());
+		assert_eq!(lists.rejection_reason(&node_id(2)), None);
+	}
+}
+
+#[cfg(test)]
+mod payment_policy_tests {
+	use super::*;
+
+	#[test]
+	fn test_fee_limit() {
+		let mut policy = PaymentPolicy::default();
+		assert_eq!(policy.fee_limit_msat(1_000_000), None);
+
+		policy.max_fee_ppm = Some(5_000);
+		assert_eq!(policy.fee_limit_msat(1_000_000), Some(5_000));
+
+		policy.max_fee_msat = Some(2_000);
+		assert_eq!(policy.fee_limit_msat(1_000_000), Some(2_000));
+		assert_eq!(policy.fee_limit_msat(100_000), Some(500));
+
+		policy.max_fee_ppm = None;
+		assert_eq!(policy.fee_limit_msat(100_000), Some(2_000));
+	}
+
+	#[test]
+	fn test_retry() {
+		let mut policy = PaymentPolicy::default();
+		assert_eq!(policy.retry(), Retry::Timeout(Duration::from_secs(10)));
+
+		policy.retry_attempts = Some(3);
+		assert_eq!(policy.retry(), Retry::Attempts(3));
+	}
+
+	#[test]
+	fn test_policy_roundtrip() {
+		let policy = PaymentPolicy {
+			retry_attempts: Some(5),
+			retry_timeout_secs: 30,
+			max_fee_ppm: Some(1_000),
+			max_fee_msat: None,
+			probe: true,
+		};
+		let read_policy = PaymentPolicy::read(&mut &policy.encode()[..]).unwrap();
+		assert_eq!(read_policy.retry_attempts, Some(5));
+		assert_eq!(read_policy.retry_timeout_secs, 30);
+		assert_eq!(read_policy.max_fee_ppm, Some(1_000));
+		assert_eq!(read_policy.max_fee_msat, None);
+		assert!(read_policy.probe);
+	}
+}
+
+#[cfg(test)]
+mod saved_user_config_tests {
+	use super::*;
+
+	#[test]
+	fn test_saved_config_applied_over_defaults() {
+		let mut config = UserConfig::default();
+		config.channel_handshake_config.our_htlc_minimum_msat = 10_000;
+		config.channel_handshake_config.max_inbound_htlc_value_in_flight_percent_of_channel = 50;
+		config.channel_config.forwarding_fee_base_msat = 500;
+		config.channel_config.forwarding_fee_proportional_millionths = 100;
+		config.channel_config.cltv_expiry_delta = 144;
+		let saved_config = SavedUserConfig::from_config(&config, None);
+
+		let mut config = UserConfig::default();
+		saved_config.apply(&mut config);
+		assert_eq!(config.channel_handshake_config.our_htlc_minimum_msat, 10_000);
+		assert_eq!(
+			config.channel_handshake_config.max_inbound_htlc_value_in_flight_percent_of_channel,
+			50
+		);
+		assert_eq!(config.channel_config.forwarding_fee_base_msat, 500);
+		assert_eq!(config.channel_config.forwarding_fee_proportional_millionths, 100);
+		assert_eq!(config.channel_config.cltv_expiry_delta, 144);
+	}
+
+	#[test]
+	fn test_saved_config_without_forwarding_parameters() {
+		let mut saved_config = SavedUserConfig::from_config(&UserConfig::default(), None);
+		saved_config.forwarding_fee_base_msat = None;
+		saved_config.forwarding_fee_proportional_millionths = None;
+		saved_config.cltv_expiry_delta = None;
+
+		let mut config = UserConfig::default();
+		config.channel_config.forwarding_fee_base_msat = 500;
+		config.channel_config.cltv_expiry_delta = 144;
+		saved_config.apply(&mut config);
+		assert_eq!(config.channel_config.forwarding_fee_base_msat, 500);
+		assert_eq!(config.channel_config.cltv_expiry_delta, 144);
+	}
+
+	#[test]
+	fn test_saved_yuv_support_needs_yuv_node() {
+		let mut config = UserConfig::default();
+		config.support_yuv_payments = true;
+		let saved_config = SavedUserConfig::from_config(&config, None);
+
+		config.support_yuv_payments = false;
+		saved_config.apply(&mut config);
+		assert!(!config.support_yuv_payments);
+
+		let mut config = UserConfig::default();
+		config.support_yuv_payments = true;
+		SavedUserConfig { support_yuv_payments: false, ..saved_config }.apply(&mut config);
+		assert!(!config.support_yuv_payments);
+	}
+
+	#[test]
+	fn test_saved_update_balance_max_delta() {
+		let saved_config = SavedUserConfig::from_config(&UserConfig::default(), Some(1_000));
+		let saved_config = SavedUserConfig::read(&mut &saved_config.encode()[..]).unwrap();
+		assert_eq!(saved_config.update_balance_max_delta_msat, Some(1_000));
+
+		// Configs saved before the threshold could be set don't have it.
+		let saved_config = SavedUserConfig::from_config(&UserConfig::default(), None);
+		let saved_config = SavedUserConfig::read(&mut &saved_config.encode()[..]).unwrap();
+		assert_eq!(saved_config.update_balance_max_delta_msat, None);
+	}
+}
+
+#[cfg(test)]
+mod intercepted_htlcs_tests {
+	use super::*;
+
+	fn htlc(scid: u64, intercepted_at_secs: u64) -> InterceptedHtlc {
+		InterceptedHtlc {
+			payment_hash: PaymentHash([intercepted_at_secs as u8; 32]),
+			requested_next_hop_scid: scid,
+			inbound_amount_msat: 2000,
+			expected_outbound_amount_msat: 1000,
+			intercepted_at_secs,
+		}
+	}
+
+	#[test]
+	fn test_oldest_htlc_at_scid_expires() {
+		let mut intercepted_htlcs = HashMap::new();
+		intercepted_htlcs.insert(InterceptId([1; 32]), htlc(42, 20));
+		intercepted_htlcs.insert(InterceptId([2; 32]), htlc(42, 10));
+		intercepted_htlcs.insert(InterceptId([3; 32]), htlc(43, 5));
+
+		let expired = remove_expired_intercepted_htlc(&mut intercepted_htlcs, 42).unwrap();
+		assert_eq!(expired.intercepted_at_secs, 10);
+		assert!(!intercepted_htlcs.contains_key(&InterceptId([2; 32])));
+		assert_eq!(intercepted_htlcs.len(), 2);
+
+		assert!(remove_expired_intercepted_htlc(&mut intercepted_htlcs, 44).is_none());
+		assert_eq!(intercepted_htlcs.len(), 2);
+	}
 }
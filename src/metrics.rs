@@ -0,0 +1,189 @@
+use crate::yuv_client::{YuvClient, YuvClientStats};
+use crate::{ChainMonitor, ChannelManager, ForwardInfoStorage, PeerManager};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Everything the metrics are gathered from.
+pub(crate) struct MetricsContext {
+	pub(crate) channel_manager: Arc<ChannelManager>,
+	pub(crate) chain_monitor: Arc<ChainMonitor>,
+	pub(crate) peer_manager: Arc<PeerManager>,
+	pub(crate) yuv_client: Option<Arc<YuvClient>>,
+	pub(crate) forwards: Arc<Mutex<ForwardInfoStorage>>,
+}
+
+/// A point-in-time reading of the exported metrics.
+struct Metrics {
+	channels: usize,
+	usable_channels: usize,
+	balance_msat: u64,
+	peers: usize,
+	pending_yuv_txs: usize,
+	forwards: u64,
+	forwarded_msat: u64,
+	yuv_stats: Option<YuvClientStats>,
+}
+
+impl MetricsContext {
+	fn gather(&self) -> Metrics {
+		let channels = self.channel_manager.list_channels();
+		let forwards = self.forwards.lock().unwrap();
+		Metrics {
+			channels: channels.len(),
+			usable_channels: channels.iter().filter(|c| c.is_usable).count(),
+			balance_msat: channels.iter().map(|c| c.balance_msat).sum(),
+			peers: self.peer_manager.list_peers().len(),
+			pending_yuv_txs: self.channel_manager.get_pending_yuv_txs().len()
+				+ self.chain_monitor.get_pending_yuv_txs().len(),
+			forwards: forwards.total_forwards(),
+			forwarded_msat: forwards.total_forwarded_msat(),
+			yuv_stats: self.yuv_client.as_ref().map(|client| client.stats()),
+		}
+	}
+}
+
+/// Serves the metrics in the Prometheus text exposition format on `GET /metrics` until the
+/// returned task is aborted.
+pub(crate) async fn start_metrics_server(
+	listen_addr: SocketAddr, context: MetricsContext,
+) -> std::io::Result<JoinHandle<()>> {
+	let listener = TcpListener::bind(listen_addr).await?;
+	let context = Arc::new(context);
+
+	Ok(tokio::spawn(async move {
+		loop {
+			let (stream, _) = match listener.accept().await {
+				Ok(connection) => connection,
+				Err(_) => continue,
+			};
+			let context = Arc::clone(&context);
+			tokio::spawn(async move {
+				let _ = handle_connection(stream, &context).await;
+			});
+		}
+	}))
+}
+
+async fn handle_connection(mut stream: TcpStream, context: &MetricsContext) -> std::io::Result<()> {
+	// Only the request line matters, so there's no need to read the rest of the request.
+	let mut request = [0; 1024];
+	let len = stream.read(&mut request).await?;
+	let request_line = String::from_utf8_lossy(&request[..len]);
+	let mut request_line = request_line.split_whitespace();
+
+	let response = match (request_line.next(), request_line.next()) {
+		(Some("GET"), Some("/metrics")) => {
+			let body = render_metrics(&context.gather());
+			format!(
+				"HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+				body.len(),
+				body
+			)
+		}
+		_ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+	};
+	stream.write_all(response.as_bytes()).await?;
+	stream.shutdown().await
+}
+
+fn render_metrics(metrics: &Metrics) -> String {
+	let mut body = String::new();
+	let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+		let _ = write!(body, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n");
+	};
+
+	metric("ldk_channels", "gauge", "Number of open channels.", metrics.channels as u64);
+	metric(
+		"ldk_usable_channels",
+		"gauge",
+		"Number of channels which can currently be used to send payments.",
+		metrics.usable_channels as u64,
+	);
+	metric(
+		"ldk_balance_msat",
+		"gauge",
+		"Total balance across all channels, in millisatoshis.",
+		metrics.balance_msat,
+	);
+	metric("ldk_peers", "gauge", "Number of connected peers.", metrics.peers as u64);
+	metric(
+		"yuv_pending_transactions",
+		"gauge",
+		"Number of YUV transactions the node is waiting on to confirm.",
+		metrics.pending_yuv_txs as u64,
+	);
+	metric("ldk_forwards_total", "counter", "Number of forwarded payments.", metrics.forwards);
+	metric(
+		"ldk_forwarded_msat_total",
+		"counter",
+		"Total amount of forwarded payments, in millisatoshis.",
+		metrics.forwarded_msat,
+	);
+	if let Some(yuv_stats) = metrics.yuv_stats {
+		let name = "yuv_rpc_requests_total";
+		let _ = write!(
+			body,
+			"# HELP {name} Number of requests to the YUV node by method and result.\n# TYPE {name} counter\n"
+		);
+		for (method, stats) in yuv_stats.methods() {
+			for (result, value) in [("success", stats.successes), ("failure", stats.failures)] {
+				let _ = writeln!(body, "{name}{{method=\"{method}\",result=\"{result}\"}} {value}");
+			}
+		}
+	}
+
+	body
+}
+
+#[cfg(test)]
+mod metrics_tests {
+	use super::*;
+
+	fn metrics(yuv_stats: Option<YuvClientStats>) -> Metrics {
+		Metrics {
+			channels: 3,
+			usable_channels: 2,
+			balance_msat: 150_000_000,
+			peers: 4,
+			pending_yuv_txs: 1,
+			forwards: 5,
+			forwarded_msat: 2_500_000,
+			yuv_stats,
+		}
+	}
+
+	#[test]
+	fn test_render_metrics() {
+		let mut yuv_stats = YuvClientStats::default();
+		yuv_stats.emulate_yuv_transaction.successes = 3;
+		yuv_stats.emulate_yuv_transaction.failures = 7;
+		let body = render_metrics(&metrics(Some(yuv_stats)));
+
+		assert!(body.contains("# TYPE ldk_channels gauge\nldk_channels 3\n"));
+		assert!(body.contains("\nldk_usable_channels 2\n"));
+		assert!(body.contains("\nldk_balance_msat 150000000\n"));
+		assert!(body.contains("\nldk_peers 4\n"));
+		assert!(body.contains("\nyuv_pending_transactions 1\n"));
+		assert!(body.contains("# TYPE ldk_forwards_total counter\nldk_forwards_total 5\n"));
+		assert!(body.contains("\nldk_forwarded_msat_total 2500000\n"));
+		assert!(body.contains(
+			"\nyuv_rpc_requests_total{method=\"emulateyuvtransaction\",result=\"success\"} 3\n"
+		));
+		assert!(body.contains(
+			"\nyuv_rpc_requests_total{method=\"emulateyuvtransaction\",result=\"failure\"} 7\n"
+		));
+		assert!(body.contains(
+			"\nyuv_rpc_requests_total{method=\"provideyuvproof\",result=\"failure\"} 0\n"
+		));
+	}
+
+	#[test]
+	fn test_render_metrics_without_yuv_client() {
+		let body = render_metrics(&metrics(None));
+		assert!(!body.contains("yuv_rpc_requests_total"));
+	}
+}
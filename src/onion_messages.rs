@@ -0,0 +1,170 @@
+use crate::disk::FilesystemLogger;
+use crate::hex_utils;
+use lightning::io::Read;
+use lightning::ln::msgs::DecodeError;
+use lightning::log_info;
+use lightning::onion_message::messenger::{CustomOnionMessageHandler, PendingOnionMessage};
+use lightning::onion_message::packet::OnionMessageContents;
+use lightning::util::logger::Logger;
+use lightning::util::ser::{Writeable, Writer};
+use std::collections::VecDeque;
+use std::io::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// How many received onion messages are kept, the oldest being dropped first.
+const MAX_RECEIVED_ONION_MESSAGES: usize = 100;
+
+/// TLV types below this are reserved for the messages LDK handles itself, such as offers.
+pub(crate) const MIN_CUSTOM_TLV_TYPE: u64 = 64;
+
+/// The payload of an onion message of a type we don't know anything about.
+#[derive(Debug)]
+pub(crate) struct UserOnionMessageContents {
+	pub(crate) tlv_type: u64,
+	pub(crate) data: Vec<u8>,
+}
+
+impl OnionMessageContents for UserOnionMessageContents {
+	fn tlv_type(&self) -> u64 {
+		self.tlv_type
+	}
+}
+
+impl Writeable for UserOnionMessageContents {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), std::io::Error> {
+		w.write_all(&self.data)
+	}
+}
+
+/// An onion message sent to us, as listed by `listonionmessages`.
+pub(crate) struct ReceivedOnionMessage {
+	pub(crate) timestamp_secs: u64,
+	pub(crate) tlv_type: u64,
+	pub(crate) data: Vec<u8>,
+}
+
+/// Keeps the custom onion messages sent to us, rather than ignoring them, so that they can be
+/// looked at from the CLI. Nothing is ever sent in response.
+pub(crate) struct UserOnionMessageHandler {
+	received: Mutex<VecDeque<ReceivedOnionMessage>>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl UserOnionMessageHandler {
+	pub(crate) fn new(logger: Arc<FilesystemLogger>) -> Self {
+		Self { received: Mutex::new(VecDeque::new()), logger }
+	}
+
+	/// Returns the messages received so far, oldest first.
+	pub(crate) fn received_messages(&self) -> Vec<ReceivedOnionMessage> {
+		let received = self.received.lock().unwrap();
+		received
+			.iter()
+			.map(|msg| ReceivedOnionMessage {
+				timestamp_secs: msg.timestamp_secs,
+				tlv_type: msg.tlv_type,
+				data: msg.data.clone(),
+			})
+			.collect()
+	}
+
+	fn push_received(&self, msg: ReceivedOnionMessage) {
+		let mut received = self.received.lock().unwrap();
+		if received.len() == MAX_RECEIVED_ONION_MESSAGES {
+			received.pop_front();
+		}
+		received.push_back(msg);
+	}
+}
+
+impl CustomOnionMessageHandler for UserOnionMessageHandler {
+	type CustomMessage = UserOnionMessageContents;
+
+	fn handle_custom_message(
+		&self, msg: UserOnionMessageContents,
+	) -> Option<UserOnionMessageContents> {
+		log_info!(
+			self.logger,
+			"Received onion message of type {} with {} bytes: {}",
+			msg.tlv_type,
+			msg.data.len(),
+			hex_utils::hex_str(&msg.data)
+		);
+		println!(
+			"\rEVENT: received onion message of type {} with {} bytes",
+			msg.tlv_type,
+			msg.data.len()
+		);
+		print!("\r> ");
+		std::io::stdout().flush().unwrap();
+
+		let timestamp_secs =
+			SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+		self.push_received(ReceivedOnionMessage {
+			timestamp_secs,
+			tlv_type: msg.tlv_type,
+			data: msg.data,
+		});
+		None
+	}
+
+	fn read_custom_message<R: Read>(
+		&self, message_type: u64, buffer: &mut R,
+	) -> Result<Option<UserOnionMessageContents>, DecodeError> {
+		if message_type < MIN_CUSTOM_TLV_TYPE {
+			return Ok(None);
+		}
+		let mut data = Vec::new();
+		buffer.read_to_end(&mut data).map_err(|e| DecodeError::Io(e.kind()))?;
+		Ok(Some(UserOnionMessageContents { tlv_type: message_type, data }))
+	}
+
+	fn release_pending_custom_messages(
+		&self,
+	) -> Vec<PendingOnionMessage<UserOnionMessageContents>> {
+		Vec::new()
+	}
+}
+
+#[cfg(test)]
+mod onion_message_handler_tests {
+	use super::*;
+	use crate::disk::LogFormat;
+	use lightning::util::logger::Level;
+
+	fn handler() -> UserOnionMessageHandler {
+		let data_dir = std::env::temp_dir().join("onion_message_handler_tests");
+		let logger = FilesystemLogger::new(
+			data_dir.to_str().unwrap().to_string(),
+			LogFormat::Text,
+			Level::Info,
+			None,
+		);
+		UserOnionMessageHandler::new(Arc::new(logger))
+	}
+
+	#[test]
+	fn test_read_custom_message() {
+		let handler = handler();
+		assert!(handler.read_custom_message(8, &mut &[1, 2][..]).unwrap().is_none());
+
+		let msg = handler.read_custom_message(65, &mut &[1, 2, 3][..]).unwrap().unwrap();
+		assert_eq!(msg.tlv_type, 65);
+		assert_eq!(msg.data, vec![1, 2, 3]);
+	}
+
+	#[test]
+	fn test_received_messages_are_capped() {
+		let handler = handler();
+		for i in 0..MAX_RECEIVED_ONION_MESSAGES + 5 {
+			let msg = UserOnionMessageContents { tlv_type: 64 + i as u64, data: vec![i as u8] };
+			assert!(handler.handle_custom_message(msg).is_none());
+		}
+
+		let received = handler.received_messages();
+		assert_eq!(received.len(), MAX_RECEIVED_ONION_MESSAGES);
+		assert_eq!(received[0].tlv_type, 69);
+		assert_eq!(received.last().unwrap().tlv_type, 64 + MAX_RECEIVED_ONION_MESSAGES as u64 + 4);
+	}
+}
@@ -0,0 +1,360 @@
+use crate::cli::{self, parse_pixel_word};
+use crate::disk::{self, FilesystemLogger, INBOUND_PAYMENTS_FNAME, RPC_COOKIE_FNAME};
+use crate::hex_utils;
+use crate::wallet::Wallet;
+use crate::{ChannelManager, NetworkGraph, PaymentInfoStorage, PaymentPolicy, PeerManager};
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::Network;
+use jsonrpsee::server::{RpcModule, Server, ServerHandle};
+use jsonrpsee::types::error::{
+	ErrorObject, ErrorObjectOwned, CALL_EXECUTION_FAILED_CODE, INVALID_PARAMS_CODE,
+};
+use jsonrpsee::types::Params;
+use lightning::sign::KeysManager;
+use lightning::util::config::UserConfig;
+use lightning_invoice::Bolt11Invoice;
+use lightning_persister::fs_store::FilesystemStore;
+use rand::{thread_rng, RngCore};
+use serde_json::{json, Map, Value};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Mutex as TokioMutex;
+use tower::ServiceBuilder;
+use tower_http::validate_request::ValidateRequestHeaderLayer;
+
+type RpcResult<T> = Result<T, ErrorObjectOwned>;
+
+/// The username of the HTTP basic auth credentials written to the cookie file, as with bitcoind.
+const RPC_COOKIE_USER: &str = "__cookie__";
+
+/// Everything the RPC methods need to drive the node, mirroring the arguments of
+/// [`cli::poll_for_user_input`].
+pub(crate) struct RpcContext {
+	pub(crate) channel_manager: Arc<ChannelManager>,
+	pub(crate) peer_manager: Arc<PeerManager>,
+	pub(crate) network_graph: Arc<NetworkGraph>,
+	pub(crate) keys_manager: Arc<KeysManager>,
+	pub(crate) inbound_payments: Arc<Mutex<PaymentInfoStorage>>,
+	pub(crate) outbound_payments: Arc<Mutex<PaymentInfoStorage>>,
+	pub(crate) fs_store: Arc<FilesystemStore>,
+	pub(crate) default_config: Arc<Mutex<UserConfig>>,
+	pub(crate) payment_policy: Arc<Mutex<PaymentPolicy>>,
+	pub(crate) wallet: Arc<TokioMutex<Wallet>>,
+	pub(crate) logger: Arc<FilesystemLogger>,
+	pub(crate) ldk_data_dir: String,
+	pub(crate) network: Network,
+	pub(crate) yuv_enabled: bool,
+	/// The SOCKS5 proxy to connect to peers through, if any.
+	pub(crate) proxy: Option<SocketAddr>,
+}
+
+/// Starts serving the JSON-RPC methods on `listen_addr`. The server is stopped once the returned
+/// handle is stopped or dropped.
+///
+/// Like bitcoind, a fresh password is written to the `.rpc_cookie` file in the LDK data dir on
+/// every start, and requests must carry it as HTTP basic auth credentials in the
+/// `__cookie__:<password>` form the file holds.
+pub(crate) async fn start_rpc_server(
+	listen_addr: SocketAddr, context: RpcContext,
+) -> eyre::Result<ServerHandle> {
+	let cookie_path = format!("{}/{}", context.ldk_data_dir, RPC_COOKIE_FNAME);
+	let password = write_rpc_cookie(Path::new(&cookie_path))?;
+	let auth =
+		ServiceBuilder::new().layer(ValidateRequestHeaderLayer::basic(RPC_COOKIE_USER, &password));
+	let server = Server::builder().set_middleware(auth).build(listen_addr).await?;
+
+	let mut module = RpcModule::new(context);
+	module.register_async_method("openchannel", |params, ctx| async move {
+		open_channel(params, &ctx).await
+	})?;
+	module.register_method("closechannel", |params, ctx| close_channel(params, ctx))?;
+	module.register_method("sendpayment", |params, ctx| send_payment(params, ctx))?;
+	module.register_method("getinvoice", |params, ctx| get_invoice(params, ctx))?;
+	module.register_method("listchannels", |_, ctx| {
+		RpcResult::Ok(cli::list_channels_json(
+			&ctx.channel_manager,
+			&ctx.network_graph,
+			ctx.network,
+		))
+	})?;
+	module.register_method("getinfo", |_, ctx| {
+		let support_yuv_payments = ctx.default_config.lock().unwrap().support_yuv_payments;
+		RpcResult::Ok(cli::get_info_json(
+			&ctx.channel_manager,
+			&ctx.peer_manager,
+			ctx.network,
+			support_yuv_payments,
+			ctx.yuv_enabled,
+		))
+	})?;
+	module.register_method("nodeinfo", |_, ctx| {
+		RpcResult::Ok(cli::node_info_json(&ctx.channel_manager, &ctx.peer_manager))
+	})?;
+	module.register_async_method("yuvbalance", |_, ctx| async move { yuv_balance(&ctx).await })?;
+
+	Ok(server.start(module))
+}
+
+async fn open_channel(params: Params<'static>, ctx: &RpcContext) -> RpcResult<Value> {
+	let params = named_params(params)?;
+	let peer_pubkey = required_param::<String>(&params, "peer_pubkey")?;
+	let peer_pubkey = hex_utils::to_compressed_pubkey(&peer_pubkey)
+		.ok_or_else(|| invalid_params("invalid peer_pubkey parameter"))?;
+	let amount_sat = required_param::<u64>(&params, "amount_sat")?;
+	let yuv_pixel = match optional_param::<String>(&params, "pixel")? {
+		Some(pixel) => Some(
+			parse_pixel_word(&pixel)
+				.map_err(|e| invalid_params(format!("invalid pixel parameter: {}", e)))?,
+		),
+		None => None,
+	};
+	let announce_channel = optional_param::<bool>(&params, "public")?.unwrap_or(false);
+	let with_anchors = optional_param::<bool>(&params, "with_anchors")?.unwrap_or(false);
+	let push_msat = optional_param::<u64>(&params, "push_msat")?.unwrap_or(0);
+	let push_yuv_luma = optional_param::<u128>(&params, "push_yuv")?;
+
+	let mut config = ctx.default_config.lock().unwrap().clone();
+	config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx = with_anchors;
+	config.channel_handshake_config.announced_channel = announce_channel;
+
+	// As with the CLI, channels can only be opened to peers we've connected to before.
+	let peer_data_path = format!("{}/channel_peer_data", ctx.ldk_data_dir);
+	let peers_data = disk::read_channel_peer_data(Path::new(&peer_data_path))
+		.map_err(|e| execution_failed(format!("failed to read peer data: {}", e)))?;
+	let peer_addrs = peers_data
+		.get(&peer_pubkey)
+		.ok_or_else(|| execution_failed(format!("unknown peer: {}", peer_pubkey)))?;
+
+	cli::connect_peer_if_necessary(
+		peer_pubkey,
+		peer_addrs,
+		Arc::clone(&ctx.peer_manager),
+		ctx.proxy,
+	)
+	.await
+	.map_err(|()| execution_failed("failed to connect to peer"))?;
+
+	if let Some(pixel) = &yuv_pixel {
+		cli::check_yuv_funding_balance(&ctx.wallet, &ctx.channel_manager, pixel, ctx.network)
+			.await
+			.map_err(execution_failed)?;
+	}
+
+	let channel_id = cli::open_channel(
+		peer_pubkey,
+		amount_sat,
+		push_msat,
+		push_yuv_luma,
+		config,
+		Arc::clone(&ctx.channel_manager),
+		yuv_pixel,
+	)
+	.map_err(execution_failed)?;
+
+	Ok(json!({ "channel_id": channel_id.to_string() }))
+}
+
+fn close_channel(params: Params, ctx: &RpcContext) -> RpcResult<Value> {
+	let params = named_params(params)?;
+	let channel_id = required_param::<String>(&params, "channel_id")?;
+	let channel_id = match hex_utils::to_vec(&channel_id) {
+		Some(channel_id) if channel_id.len() == 32 => {
+			let mut id = [0; 32];
+			id.copy_from_slice(&channel_id);
+			id
+		}
+		_ => return Err(invalid_params("invalid channel_id parameter")),
+	};
+	let peer_pubkey = required_param::<PublicKey>(&params, "peer_pubkey")?;
+	let feerate_sat_per_vb = optional_param::<u32>(&params, "feerate_sat_per_vb")?;
+
+	cli::close_channel(
+		channel_id,
+		peer_pubkey,
+		feerate_sat_per_vb,
+		Arc::clone(&ctx.channel_manager),
+	)
+	.map_err(execution_failed)?;
+
+	Ok(Value::Null)
+}
+
+fn send_payment(params: Params, ctx: &RpcContext) -> RpcResult<Value> {
+	let params = named_params(params)?;
+	let invoice = required_param::<Bolt11Invoice>(&params, "invoice")?;
+	let amount_msat = optional_param::<u64>(&params, "amount_msat")?;
+	let yuv_amount = optional_param::<u128>(&params, "yuv_amount")?;
+	let max_fee_msat = optional_param::<u64>(&params, "max_fee_msat")?;
+
+	let payment_hash = cli::send_payment(
+		&ctx.channel_manager,
+		&invoice,
+		amount_msat,
+		yuv_amount,
+		max_fee_msat,
+		None,
+		&ctx.payment_policy.lock().unwrap(),
+		&mut ctx.outbound_payments.lock().unwrap(),
+		Arc::clone(&ctx.fs_store),
+	)
+	.map_err(execution_failed)?;
+
+	Ok(json!({ "payment_hash": payment_hash.to_string() }))
+}
+
+fn get_invoice(params: Params, ctx: &RpcContext) -> RpcResult<Value> {
+	let params = named_params(params)?;
+	let amount_msat = required_param::<u64>(&params, "amount_msat")?;
+	let expiry_secs = required_param::<u32>(&params, "expiry_secs")?;
+	let yuv_pixel = match optional_param::<String>(&params, "pixel")? {
+		Some(pixel) => Some(
+			parse_pixel_word(&pixel)
+				.map_err(|e| invalid_params(format!("invalid pixel parameter: {}", e)))?,
+		),
+		None => None,
+	};
+
+	let mut inbound_payments = ctx.inbound_payments.lock().unwrap();
+	let invoice = cli::get_invoice(
+		amount_msat,
+		&mut inbound_payments,
+		&ctx.channel_manager,
+		Arc::clone(&ctx.keys_manager),
+		ctx.network,
+		expiry_secs,
+		yuv_pixel,
+		Arc::clone(&ctx.logger),
+	)
+	.map_err(execution_failed)?;
+	disk::persist_payment_info(&ctx.fs_store, INBOUND_PAYMENTS_FNAME, &inbound_payments)
+		.map_err(|e| execution_failed(format!("failed to persist inbound payments: {}", e)))?;
+
+	Ok(json!({
+		"invoice": invoice.to_string(),
+		"payment_hash": invoice.payment_hash().to_string(),
+	}))
+}
+
+async fn yuv_balance(ctx: &RpcContext) -> RpcResult<Value> {
+	if !ctx.yuv_enabled {
+		return Err(execution_failed("YUV node is not configured"));
+	}
+
+	let balances = cli::yuv_balances(&ctx.wallet, ctx.network)
+		.await
+		.map_err(|e| execution_failed(format!("failed to get YUV balances: {:?}", e)))?;
+
+	// Balances are `u128`s, which don't fit into a JSON number, so they are encoded as strings.
+	let balances = balances
+		.into_iter()
+		.map(|(chroma, amount)| (chroma, Value::String(amount.to_string())))
+		.collect::<Map<_, _>>();
+
+	Ok(Value::Object(balances))
+}
+
+/// Methods only take their parameters by name, so that optional ones can be left out.
+fn named_params(params: Params) -> RpcResult<Map<String, Value>> {
+	if params.as_str().is_none() {
+		return Ok(Map::new());
+	}
+	params.parse::<Map<String, Value>>()
+}
+
+fn optional_param<T: FromStr>(params: &Map<String, Value>, name: &str) -> RpcResult<Option<T>> {
+	let raw = match params.get(name) {
+		None | Some(Value::Null) => return Ok(None),
+		Some(Value::String(raw)) => raw.clone(),
+		Some(value) => value.to_string(),
+	};
+
+	match T::from_str(&raw) {
+		Ok(param) => Ok(Some(param)),
+		Err(_) => Err(invalid_params(format!("invalid {} parameter", name))),
+	}
+}
+
+fn required_param<T: FromStr>(params: &Map<String, Value>, name: &str) -> RpcResult<T> {
+	optional_param(params, name)?
+		.ok_or_else(|| invalid_params(format!("missing {} parameter", name)))
+}
+
+/// Removes the cookie file written by [`start_rpc_server`], once the server has been stopped.
+pub(crate) fn remove_rpc_cookie(ldk_data_dir: &str) {
+	let _ = fs::remove_file(format!("{}/{}", ldk_data_dir, RPC_COOKIE_FNAME));
+}
+
+/// Writes `__cookie__:<password>` with a random password to `path`, readable only by the current
+/// user, and returns the password.
+fn write_rpc_cookie(path: &Path) -> io::Result<String> {
+	let mut password = [0; 32];
+	thread_rng().fill_bytes(&mut password);
+	let password = hex_utils::hex_str(&password);
+
+	let mut options = OpenOptions::new();
+	options.write(true).create(true).truncate(true);
+	#[cfg(unix)]
+	options.mode(0o600);
+	let mut file = options.open(path)?;
+	file.write_all(format!("{}:{}", RPC_COOKIE_USER, password).as_bytes())?;
+	file.sync_all()?;
+
+	Ok(password)
+}
+
+fn invalid_params(message: impl Into<String>) -> ErrorObjectOwned {
+	ErrorObject::owned(INVALID_PARAMS_CODE, message.into(), None::<()>)
+}
+
+fn execution_failed(message: impl Into<String>) -> ErrorObjectOwned {
+	ErrorObject::owned(CALL_EXECUTION_FAILED_CODE, message.into(), None::<()>)
+}
+
+#[cfg(test)]
+mod rpc_params_tests {
+	use super::*;
+
+	#[test]
+	fn test_optional_param_accepts_strings_and_numbers() {
+		let params =
+			json!({ "amount_msat": 1000, "yuv_amount": "340282366920938463463374607431768211455" });
+		let params = params.as_object().unwrap();
+
+		assert_eq!(optional_param::<u64>(params, "amount_msat").unwrap(), Some(1000));
+		assert_eq!(optional_param::<u128>(params, "yuv_amount").unwrap(), Some(u128::MAX));
+		assert_eq!(optional_param::<u64>(params, "expiry_secs").unwrap(), None);
+	}
+
+	#[test]
+	fn test_required_param_errors() {
+		let params = json!({ "amount_msat": "not a number" });
+		let params = params.as_object().unwrap();
+
+		let err = required_param::<u64>(params, "amount_msat").unwrap_err();
+		assert_eq!(err.code(), INVALID_PARAMS_CODE);
+		assert_eq!(err.message(), "invalid amount_msat parameter");
+
+		let err = required_param::<u64>(params, "expiry_secs").unwrap_err();
+		assert_eq!(err.message(), "missing expiry_secs parameter");
+	}
+
+	#[test]
+	fn test_write_rpc_cookie() {
+		let path = std::env::temp_dir().join("rpc_params_tests_rpc_cookie");
+		let password = write_rpc_cookie(&path).unwrap();
+		assert_eq!(password.len(), 64);
+		assert_eq!(fs::read_to_string(&path).unwrap(), format!("__cookie__:{}", password));
+
+		// Every start gets a new password.
+		let new_password = write_rpc_cookie(&path).unwrap();
+		assert_ne!(new_password, password);
+		assert_eq!(fs::read_to_string(&path).unwrap(), format!("__cookie__:{}", new_password));
+		fs::remove_file(&path).unwrap();
+	}
+}
@@ -0,0 +1,142 @@
+use lightning::ln::msgs::SocketAddress;
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Opens a connection to `target` through the SOCKS5 proxy at `proxy`, e.g. a Tor daemon. Onion
+/// addresses and hostnames are passed on to the proxy to resolve, so they aren't looked up
+/// locally.
+pub(crate) async fn connect(proxy: SocketAddr, target: &SocketAddress) -> io::Result<TcpStream> {
+	let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+	encode_address(target, &mut request)?;
+
+	let mut stream = TcpStream::connect(proxy).await?;
+	stream.write_all(&[SOCKS_VERSION, 1, AUTH_NONE]).await?;
+	let mut method = [0; 2];
+	stream.read_exact(&mut method).await?;
+	if method != [SOCKS_VERSION, AUTH_NONE] {
+		return Err(proxy_error("the proxy requires authentication"));
+	}
+
+	stream.write_all(&request).await?;
+	let mut reply = [0; 4];
+	stream.read_exact(&mut reply).await?;
+	if reply[0] != SOCKS_VERSION {
+		return Err(proxy_error("the proxy replied with an unexpected SOCKS version"));
+	}
+	if reply[1] != 0x00 {
+		return Err(proxy_error(&format!(
+			"the proxy failed to connect: {}",
+			reply_error(reply[1])
+		)));
+	}
+	// The reply ends with the address the proxy connected from, which isn't needed.
+	let bound_addr_len = match reply[3] {
+		ATYP_IPV4 => 4,
+		ATYP_IPV6 => 16,
+		ATYP_DOMAIN => stream.read_u8().await? as usize,
+		_ => return Err(proxy_error("the proxy replied with an unknown address type")),
+	};
+	let mut bound_addr = vec![0; bound_addr_len + 2];
+	stream.read_exact(&mut bound_addr).await?;
+
+	Ok(stream)
+}
+
+/// Appends the address type, address and port of a SOCKS5 request for `addr`.
+fn encode_address(addr: &SocketAddress, buf: &mut Vec<u8>) -> io::Result<()> {
+	let port = match addr {
+		SocketAddress::TcpIpV4 { addr, port } => {
+			buf.push(ATYP_IPV4);
+			buf.extend_from_slice(addr);
+			port
+		}
+		SocketAddress::TcpIpV6 { addr, port } => {
+			buf.push(ATYP_IPV6);
+			buf.extend_from_slice(addr);
+			port
+		}
+		SocketAddress::OnionV3 { port, .. } | SocketAddress::Hostname { port, .. } => {
+			let addr_str = addr.to_string();
+			let host = addr_str.rsplit_once(':').map_or(addr_str.as_str(), |(host, _)| host);
+			buf.push(ATYP_DOMAIN);
+			buf.push(host.len() as u8);
+			buf.extend_from_slice(host.as_bytes());
+			port
+		}
+		SocketAddress::OnionV2(_) => {
+			return Err(io::Error::new(
+				io::ErrorKind::Unsupported,
+				"Tor v2 onion addresses are no longer supported",
+			));
+		}
+	};
+	buf.extend_from_slice(&port.to_be_bytes());
+	Ok(())
+}
+
+fn reply_error(code: u8) -> &'static str {
+	match code {
+		0x01 => "general failure",
+		0x02 => "connection not allowed by ruleset",
+		0x03 => "network unreachable",
+		0x04 => "host unreachable",
+		0x05 => "connection refused",
+		0x06 => "TTL expired",
+		0x07 => "command not supported",
+		0x08 => "address type not supported",
+		_ => "unknown error",
+	}
+}
+
+fn proxy_error(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::Other, msg.to_string())
+}
+
+#[cfg(test)]
+mod socks5_tests {
+	use super::*;
+	use std::str::FromStr;
+
+	const ONION_HOST: &str = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+
+	fn encode(addr: &str) -> Vec<u8> {
+		let mut buf = Vec::new();
+		encode_address(&SocketAddress::from_str(addr).unwrap(), &mut buf).unwrap();
+		buf
+	}
+
+	#[test]
+	fn test_encode_ip_address() {
+		assert_eq!(encode("127.0.0.1:9735"), vec![ATYP_IPV4, 127, 0, 0, 1, 0x26, 0x07]);
+
+		let mut expected = vec![ATYP_IPV6];
+		expected.extend_from_slice(&[0; 15]);
+		expected.extend_from_slice(&[1, 0x26, 0x07]);
+		assert_eq!(encode("[::1]:9735"), expected);
+	}
+
+	#[test]
+	fn test_encode_onion_address() {
+		let mut expected = vec![ATYP_DOMAIN, ONION_HOST.len() as u8];
+		expected.extend_from_slice(ONION_HOST.as_bytes());
+		expected.extend_from_slice(&[0x26, 0x07]);
+		assert_eq!(encode(&format!("{}:9735", ONION_HOST)), expected);
+	}
+
+	#[test]
+	fn test_encode_hostname() {
+		let mut expected = vec![ATYP_DOMAIN, 11];
+		expected.extend_from_slice(b"example.com");
+		expected.extend_from_slice(&[0x26, 0x07]);
+		assert_eq!(encode("example.com:9735"), expected);
+	}
+}
@@ -1,11 +1,16 @@
+use crate::disk::FAILED_SWEEPS_FNAME;
 use crate::hex_utils;
 use crate::wallet::Wallet;
 use crate::yuv_client::YuvClient;
 use crate::BitcoindClient;
 use crate::ChannelManager;
 use crate::FilesystemLogger;
+use crate::{FailedSweepInfo, FailedSweepStorage};
 use bitcoin::absolute::LockTime;
-use bitcoin::secp256k1::Secp256k1;
+use bitcoin::bip32::{ChildNumber, ExtendedPrivKey};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::{Network, OutPoint, PrivateKey, ScriptBuf, Txid};
 use bitcoin_client::RawTx;
 use lightning::chain::chaininterface::{
 	BroadcasterInterface, ConfirmationTarget, FeeEstimator, YuvBroadcaster,
@@ -17,25 +22,251 @@ use lightning::util::logger::Logger;
 use lightning::util::persist::KVStore;
 use lightning::util::ser::{Readable, WithoutLength, Writeable};
 use lightning_persister::fs_store::FilesystemStore;
+use std::cmp;
 use std::io::{Read, Seek, SeekFrom};
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use std::{fs, io};
 
-/// If we have any pending claimable outputs, we should slowly sweep them to our Bitcoin Core
-/// wallet. We technically don't need to do this - they're ours to spend when we want and can just
-/// use them to build new transactions instead, but we cannot feed them direclty into Bitcoin
-/// Core's wallet so we have to sweep.
+/// The directory where the txids of the transactions sweeping each set of spendable outputs are
+/// stored, under the same key as the set itself.
+const SPENDABLE_OUTPUTS_SWEEPS_DIR: &str = "spendable_outputs_sweeps";
+
+/// The directory where the number of times each set of spendable outputs was swept is stored,
+/// under the same key as the set itself.
+const SPENDABLE_OUTPUTS_SWEEP_ATTEMPTS_DIR: &str = "spendable_outputs_sweep_attempts";
+
+/// The directory where sets of spendable outputs which won't be swept anymore are moved to, e.g.
+/// because the YUV node keeps rejecting their sweep, so that they're kept for the user to look into
+/// without being gone over on every pass.
+pub(crate) const FAILED_SPENDABLE_OUTPUTS_DIR: &str = "failed_spendable_outputs";
+
+/// The minimum feerate increase bitcoind requires of a replacement transaction, in satoshis per
+/// 1000 weight units.
+const INCREMENTAL_RELAY_FEE_SAT_PER_1000_WEIGHT: u32 = 250;
+
+/// The highest feerate bumping takes a sweep to, 100 sat/vB, in satoshis per 1000 weight units.
+const MAX_SWEEP_FEERATE_SAT_PER_1000_WEIGHT: u32 = 25_000;
+
+fn persist_sweep_txids(path: &Path, txids: &[Txid]) -> io::Result<()> {
+	let contents = txids.iter().map(|txid| format!("{}\n", txid)).collect::<String>();
+	fs::write(path, contents)
+}
+
+fn read_sweep_txids(path: &Path) -> Vec<Txid> {
+	match fs::read_to_string(path) {
+		Ok(contents) => contents.lines().filter_map(|line| Txid::from_str(line).ok()).collect(),
+		Err(_) => Vec::new(),
+	}
+}
+
+fn persist_sweep_attempts(path: &Path, attempts: u32) -> io::Result<()> {
+	fs::write(path, attempts.to_string())
+}
+
+fn read_sweep_attempts(path: &Path) -> u32 {
+	fs::read_to_string(path).ok().and_then(|contents| contents.trim().parse().ok()).unwrap_or(0)
+}
+
+/// The keys [`KeysManager`] pays [`SpendableOutputDescriptor::StaticOutput`]s to, i.e. those of its
+/// destination and shutdown scripts, which it derives at `m/1'` and `m/2'` of its seed.
+fn static_output_keys(keys_seed: &[u8; 32]) -> Vec<SecretKey> {
+	let secp_ctx = Secp256k1::new();
+	// `KeysManager` derives from a testnet master key whichever network it's used on.
+	let master_key = ExtendedPrivKey::new_master(Network::Testnet, keys_seed).unwrap();
+	[1, 2]
+		.iter()
+		.map(|idx| {
+			let child = ChildNumber::from_hardened_idx(*idx).unwrap();
+			master_key.ckd_priv(&secp_ctx, child).unwrap().private_key
+		})
+		.collect()
+}
+
+/// Returns the key of `output` if it's a static output paying to a plain P2WPKH script of one of
+/// `keys`. Outputs carrying YUV pixels pay to tweaked keys, so they never match and keep being
+/// swept.
+fn static_output_key(output: &SpendableOutputDescriptor, keys: &[SecretKey]) -> Option<SecretKey> {
+	let SpendableOutputDescriptor::StaticOutput { output, .. } = output else {
+		return None;
+	};
+	let secp_ctx = Secp256k1::new();
+	keys.iter().copied().find(|key| {
+		let pubkey = bitcoin::PublicKey::new(PublicKey::from_secret_key(&secp_ctx, key));
+		pubkey
+			.wpubkey_hash()
+			.map_or(false, |hash| ScriptBuf::new_v0_p2wpkh(&hash) == output.script_pubkey)
+	})
+}
+
+/// Where an output being swept was spent, going by bitcoind's UTXO set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputSpend {
+	Unspent,
+	InMempool,
+	InChain,
+}
+
+/// How far the sweep of a set of spendable outputs got.
+#[derive(Debug, PartialEq)]
+enum SweepStatus {
+	/// Some of the outputs aren't spent, not even in the mempool, so they have to be swept.
+	Unswept,
+	/// All of the outputs are spent, but some only by transactions in the mempool.
+	Pending,
+	/// All of the outputs are spent on chain.
+	Confirmed,
+}
+
+fn sweep_status(spends: &[OutputSpend]) -> SweepStatus {
+	if spends.iter().all(|spend| *spend == OutputSpend::InChain) {
+		SweepStatus::Confirmed
+	} else if spends.iter().any(|spend| *spend == OutputSpend::Unspent) {
+		SweepStatus::Unswept
+	} else {
+		SweepStatus::Pending
+	}
+}
+
+/// Looks up whether `outpoint` was spent. The outputs handed to us by LDK are always confirmed, so
+/// one missing from the UTXO set was spent on chain.
+async fn output_spend(
+	bitcoind_client: &BitcoindClient, outpoint: &OutPoint,
+) -> io::Result<OutputSpend> {
+	if !bitcoind_client.is_output_unspent(outpoint, false).await? {
+		return Ok(OutputSpend::InChain);
+	}
+	if !bitcoind_client.is_output_unspent(outpoint, true).await? {
+		return Ok(OutputSpend::InMempool);
+	}
+	Ok(OutputSpend::Unspent)
+}
+
+fn spendable_outpoint(output: &SpendableOutputDescriptor) -> OutPoint {
+	match output {
+		SpendableOutputDescriptor::StaticOutput { outpoint, .. } => {
+			outpoint.into_bitcoin_outpoint()
+		}
+		SpendableOutputDescriptor::DelayedPaymentOutput(descriptor) => {
+			descriptor.outpoint.into_bitcoin_outpoint()
+		}
+		SpendableOutputDescriptor::StaticPaymentOutput(descriptor) => {
+			descriptor.outpoint.into_bitcoin_outpoint()
+		}
+	}
+}
+
+/// Reads a set of spendable outputs as written by [`periodic_sweep`], i.e. the descriptors one
+/// after the other.
+fn read_spendable_outputs(path: &Path) -> io::Result<Vec<SpendableOutputDescriptor>> {
+	let mut outputs = Vec::new();
+	let mut file = fs::File::open(path)?;
+	loop {
+		// Check if there are any bytes left to read, and if so read a descriptor.
+		match file.read_exact(&mut [0; 1]) {
+			Ok(_) => {
+				file.seek(SeekFrom::Current(-1))?;
+			}
+			Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+			Err(e) => return Err(e),
+		}
+		let output = Readable::read(&mut file).map_err(|e| {
+			io::Error::new(io::ErrorKind::InvalidData, format!("invalid descriptor: {:?}", e))
+		})?;
+		outputs.push(output);
+	}
+	Ok(outputs)
+}
+
+/// The feerate to sweep a set of outputs at after `attempts` previous sweeps of it were dropped
+/// from the mempool. Each dropped sweep raises it by a quarter of the estimate, and by at least
+/// enough for the new sweep to replace the previous one in the mempool, up to
+/// [`MAX_SWEEP_FEERATE_SAT_PER_1000_WEIGHT`], so that fees can't eat the outputs. An estimate above
+/// that is used as is.
+fn bumped_sweep_feerate(estimated_feerate: u32, attempts: u32) -> u32 {
+	let bump = cmp::max(estimated_feerate / 4, INCREMENTAL_RELAY_FEE_SAT_PER_1000_WEIGHT);
+	let bumped_feerate = estimated_feerate.saturating_add(bump.saturating_mul(attempts));
+	cmp::min(bumped_feerate, cmp::max(estimated_feerate, MAX_SWEEP_FEERATE_SAT_PER_1000_WEIGHT))
+}
+
+/// How many times the YUV node rejected sweeping the set of outputs stored under `outputs_key`.
+fn num_rejections(failed_sweeps: &FailedSweepStorage, outputs_key: &str) -> u32 {
+	failed_sweeps.sweeps.iter().filter(|sweep| sweep.outputs_key == outputs_key).count() as u32
+}
+
+/// Moves the sets of spendable outputs set aside in [`FAILED_SPENDABLE_OUTPUTS_DIR`] back for
+/// [`periodic_sweep`] to pick up, forgetting the rejections of their earlier sweeps so that they're
+/// retried up to `max_sweep_rejections` times again. Returns how many sets were moved back.
+pub(crate) fn retry_failed_sweeps(
+	ldk_data_dir: &str, fs_store: &FilesystemStore, failed_sweeps: &mut FailedSweepStorage,
+) -> io::Result<usize> {
+	let failed_spendables_dir = Path::new(ldk_data_dir).join(FAILED_SPENDABLE_OUTPUTS_DIR);
+	let spendables_dir = Path::new(ldk_data_dir).join("spendable_outputs");
+	let dir_iter = match fs::read_dir(&failed_spendables_dir) {
+		Ok(dir_iter) => dir_iter,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+		Err(e) => return Err(e),
+	};
+	fs::create_dir_all(&spendables_dir)?;
+
+	let mut retried_keys = Vec::new();
+	let mut move_res = Ok(());
+	for file_res in dir_iter {
+		let moved = file_res.and_then(|file| {
+			fs::rename(file.path(), spendables_dir.join(file.file_name()))?;
+			Ok(file.file_name().to_string_lossy().to_string())
+		});
+		match moved {
+			Ok(outputs_key) => retried_keys.push(outputs_key),
+			Err(e) => {
+				move_res = Err(e);
+				break;
+			}
+		}
+	}
+
+	// The sets which were moved back would be set aside again right away if their rejections were
+	// kept, even if moving another one failed.
+	if !retried_keys.is_empty() {
+		failed_sweeps.sweeps.retain(|sweep| !retried_keys.contains(&sweep.outputs_key));
+		fs_store.write("", "", FAILED_SWEEPS_FNAME, &failed_sweeps.encode())?;
+	}
+	move_res.map(|_| retried_keys.len())
+}
+
+/// Stores a spendable output for [`periodic_sweep`] to pick up. It's keyed by the hash of its
+/// contents rather than at random, so that the same output being handed to us again, e.g. when an
+/// event is replayed after a restart, is only written, and so swept, once.
+pub(crate) fn persist_pending_spendable_output(
+	fs_store: &FilesystemStore, output: &SpendableOutputDescriptor,
+) -> io::Result<()> {
+	let encoded = output.encode();
+	let key = hex_utils::hex_str(&sha256::Hash::hash(&encoded).to_byte_array());
+	fs_store.write(crate::PENDING_SPENDABLE_OUTPUT_DIR, "", &key, &encoded)
+}
+
+/// If we have any pending claimable outputs, we should slowly sweep them to our wallet. We
+/// technically don't need to do this - they're ours to spend when we want and can just use them
+/// to build new transactions instead, but we cannot feed them directly into the BDK wallet so we
+/// have to sweep.
 ///
-/// Note that this is unececssary for [`SpendableOutputDescriptor::StaticOutput`]s, which *do* have
-/// an associated secret key we could simply import into Bitcoin Core's wallet, but for consistency
-/// we don't do that here either.
+/// The exception are [`SpendableOutputDescriptor::StaticOutput`]s paying to the [`KeysManager`]'s
+/// destination and shutdown scripts, which *do* have an associated secret key. Unless a
+/// `sweep_destination` is configured, that key is imported into the node's BDK wallet instead,
+/// which then spends the outputs like its own. If importing fails they're swept like any other
+/// output, as are static outputs holding YUV pixels, which pay to tweaked keys.
+///
+/// Every sweep the YUV node rejects is recorded in `failed_sweeps`, and a set of outputs is no
+/// longer swept once it was rejected `max_sweep_rejections` times.
 pub(crate) async fn periodic_sweep(
-	ldk_data_dir: String, keys_manager: Arc<KeysManager>, logger: Arc<FilesystemLogger>,
-	persister: Arc<FilesystemStore>, wallet: Arc<tokio::sync::Mutex<Wallet>>,
-	yuv_client: Option<Arc<YuvClient>>, bitcoind_client: Arc<BitcoindClient>,
-	channel_manager: Arc<ChannelManager>,
+	ldk_data_dir: String, keys_manager: Arc<KeysManager>, keys_seed: [u8; 32], network: Network,
+	logger: Arc<FilesystemLogger>, persister: Arc<FilesystemStore>,
+	wallet: Arc<tokio::sync::Mutex<Wallet>>, yuv_client: Option<Arc<YuvClient>>,
+	bitcoind_client: Arc<BitcoindClient>, channel_manager: Arc<ChannelManager>,
+	conf_target: ConfirmationTarget, sweep_destination: Option<PublicKey>,
+	failed_sweeps: Arc<Mutex<FailedSweepStorage>>, max_sweep_rejections: u32,
 ) {
 	// Regularly claim outputs which are exclusively spendable by us and send them to Bitcoin Core.
 	// Note that if you more tightly integrate your wallet with LDK you may not need to do this -
@@ -44,6 +275,10 @@ pub(crate) async fn periodic_sweep(
 		format!("{}/{}", ldk_data_dir, crate::PENDING_SPENDABLE_OUTPUT_DIR);
 	let processing_spendables_dir = format!("{}/processing_spendable_outputs", ldk_data_dir);
 	let spendables_dir = format!("{}/spendable_outputs", ldk_data_dir);
+	let sweeps_dir = format!("{}/{}", ldk_data_dir, SPENDABLE_OUTPUTS_SWEEPS_DIR);
+	let sweep_attempts_dir = format!("{}/{}", ldk_data_dir, SPENDABLE_OUTPUTS_SWEEP_ATTEMPTS_DIR);
+	let failed_spendables_dir = format!("{}/{}", ldk_data_dir, FAILED_SPENDABLE_OUTPUTS_DIR);
+	let static_output_keys = static_output_keys(&keys_seed);
 
 	// We batch together claims of all spendable outputs generated each day, however only after
 	// batching any claims of spendable outputs which were generated prior to restart. On a mobile
@@ -90,35 +325,233 @@ pub(crate) async fn periodic_sweep(
 		}
 		// Iterate over all the sets of spendable outputs in `spendables_dir` and try to claim
 		// them.
-		// Note that here we try to claim each set of spendable outputs over and over again
-		// forever, even long after its been claimed. While this isn't an issue per se, in practice
-		// you may wish to track when the claiming transaction has confirmed and remove the
-		// spendable outputs set. You may also wish to merge groups of unspent spendable outputs to
+		// Whether a set was swept is looked up in bitcoind's UTXO set: it's removed once all of
+		// its outputs are spent on chain, left alone while some are only spent in the mempool, and
+		// swept again if some aren't spent at all, e.g. because its sweep was dropped from the
+		// mempool. This works without `-txindex`, unlike looking up the sweeping transactions. The
+		// txids of the sweeps are still kept per set in `sweeps_dir`, so that a set being swept
+		// again can be told from one being swept for the first time. Each time a set has to be
+		// swept again the feerate is bumped, so that sweeps evicted for paying too little
+		// eventually confirm. You may also wish to merge groups of unspent spendable outputs to
 		// combine batches.
 		if let Ok(dir_iter) = fs::read_dir(&spendables_dir) {
 			for file_res in dir_iter {
-				let mut outputs: Vec<SpendableOutputDescriptor> = Vec::new();
-				let mut file = fs::File::open(file_res.unwrap().path()).unwrap();
-				loop {
-					// Check if there are any bytes left to read, and if so read a descriptor.
-					match file.read_exact(&mut [0; 1]) {
-						Ok(_) => {
-							file.seek(SeekFrom::Current(-1)).unwrap();
+				let Ok(file) = file_res else { continue };
+				let file_path = file.path();
+				let sweep_txids_path = Path::new(&sweeps_dir).join(file.file_name());
+				let sweep_attempts_path = Path::new(&sweep_attempts_dir).join(file.file_name());
+				let outputs_key = file.file_name().to_string_lossy().to_string();
+				let set_aside = |reason: &str| {
+					lightning::log_error!(
+						logger,
+						"Setting aside spendable outputs {}: {}",
+						file_path.display(),
+						reason
+					);
+					let failed_path = Path::new(&failed_spendables_dir).join(&outputs_key);
+					let res = fs::create_dir_all(&failed_spendables_dir)
+						.and_then(|_| fs::rename(&file_path, failed_path));
+					if let Err(e) = res {
+						lightning::log_error!(
+							logger,
+							"Failed to move spendable outputs {}: {}",
+							file_path.display(),
+							e
+						);
+					}
+					let _ = fs::remove_file(&sweep_txids_path);
+					let _ = fs::remove_file(&sweep_attempts_path);
+				};
+
+				let outputs = match read_spendable_outputs(&file_path) {
+					Ok(outputs) => outputs,
+					Err(e) => {
+						set_aside(&format!("failed to read them: {}", e));
+						continue;
+					}
+				};
+
+				let mut spends = Vec::with_capacity(outputs.len());
+				for output in outputs.iter() {
+					match output_spend(&bitcoind_client, &spendable_outpoint(output)).await {
+						Ok(spend) => spends.push(spend),
+						Err(e) => {
+							lightning::log_error!(
+								logger,
+								"Failed to look up outputs {} in bitcoind: {}",
+								file_path.display(),
+								e
+							);
+							break;
 						}
-						Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
-						Err(e) => Err(e).unwrap(),
 					}
-					outputs.push(Readable::read(&mut file).unwrap());
+				}
+				// Bitcoind couldn't be asked about all of them, so try again on the next pass.
+				if spends.len() < outputs.len() {
+					continue;
+				}
+				match sweep_status(&spends) {
+					SweepStatus::Confirmed => {
+						log_info!(
+							logger,
+							"Sweep of spendable outputs {} confirmed, removing them",
+							file_path.display()
+						);
+						if let Err(e) = fs::remove_file(&file_path) {
+							lightning::log_error!(
+								logger,
+								"Failed to remove spendable outputs {}: {}",
+								file_path.display(),
+								e
+							);
+						}
+						let _ = fs::remove_file(&sweep_txids_path);
+						let _ = fs::remove_file(&sweep_attempts_path);
+						continue;
+					}
+					// Still waiting for the sweeping transactions in the mempool to confirm.
+					SweepStatus::Pending => continue,
+					SweepStatus::Unswept => {}
 				}
 
-				let wallet = wallet.lock().await;
-				let Ok(destination_pubkey) = wallet.get_change_yuv_pubkey() else {
-					lightning::log_error!(logger, "Failed to get change YUV pubkey");
+				// The YUV node keeps rejecting this set, so it's left for the user to look into.
+				if num_rejections(&failed_sweeps.lock().unwrap(), &outputs_key)
+					>= max_sweep_rejections
+				{
+					set_aside("the YUV node keeps rejecting their sweep");
 					continue;
+				}
+
+				// Outputs which some earlier sweep already got on chain can't be spent again.
+				let mut outputs = outputs
+					.into_iter()
+					.zip(spends)
+					.filter(|(_, spend)| *spend == OutputSpend::Unspent)
+					.map(|(output, _)| output)
+					.collect::<Vec<_>>();
+
+				// Drop the static outputs whose key the BDK wallet has, importing it first if
+				// needed. Importing a key twice is a no-op, so this is safe to redo after a restart
+				// which happened before the set was rewritten.
+				if sweep_destination.is_none() {
+					let num_outputs = outputs.len();
+					let mut remaining = Vec::with_capacity(num_outputs);
+					for output in outputs {
+						let Some(key) = static_output_key(&output, &static_output_keys) else {
+							remaining.push(output);
+							continue;
+						};
+						let private_key = PrivateKey::new(key, network);
+						match wallet.lock().await.import_private_key(private_key) {
+							Ok(newly_imported) => {
+								if newly_imported {
+									log_info!(
+										logger,
+										"Imported key {} of static spendable outputs",
+										private_key.public_key(&Secp256k1::new())
+									);
+								}
+							}
+							Err(e) => {
+								lightning::log_error!(
+									logger,
+									"Failed to import key {} of static spendable outputs, sweeping them instead: {}",
+									private_key.public_key(&Secp256k1::new()),
+									e
+								);
+								remaining.push(output);
+							}
+						}
+					}
+
+					if remaining.is_empty() {
+						log_info!(
+							logger,
+							"Spendable outputs {} are all in the BDK wallet, removing them",
+							file_path.display()
+						);
+						if let Err(e) = fs::remove_file(&file_path) {
+							lightning::log_error!(
+								logger,
+								"Failed to remove spendable outputs {}: {}",
+								file_path.display(),
+								e
+							);
+						}
+						let _ = fs::remove_file(&sweep_txids_path);
+						let _ = fs::remove_file(&sweep_attempts_path);
+						continue;
+					}
+					if remaining.len() < num_outputs {
+						let encoded = remaining
+							.iter()
+							.flat_map(|output| output.encode())
+							.collect::<Vec<u8>>();
+						if let Err(e) =
+							persister.write("spendable_outputs", "", &outputs_key, &encoded)
+						{
+							lightning::log_error!(
+								logger,
+								"Failed to persist remaining spendable outputs {}: {}",
+								file_path.display(),
+								e
+							);
+						}
+					}
+					outputs = remaining;
+				}
+				// Only a sweep which was broadcast and then dropped, neither confirming nor staying
+				// in the mempool, counts as a failed attempt. Its txids are forgotten, so that it's
+				// only counted once however long it takes to broadcast the next one.
+				let mut attempts = read_sweep_attempts(&sweep_attempts_path);
+				let sweep_txids = read_sweep_txids(&sweep_txids_path);
+				if !sweep_txids.is_empty() {
+					attempts += 1;
+					log_info!(
+						logger,
+						"Sweep {:?} of spendable outputs {} is gone from the mempool, sweeping them again",
+						sweep_txids,
+						file_path.display()
+					);
+					let res = fs::create_dir_all(&sweep_attempts_dir)
+						.and_then(|_| persist_sweep_attempts(&sweep_attempts_path, attempts))
+						.and_then(|_| fs::remove_file(&sweep_txids_path));
+					if let Err(e) = res {
+						lightning::log_error!(
+							logger,
+							"Failed to persist sweep attempts of {}: {}",
+							file_path.display(),
+							e
+						);
+					}
+				}
+
+				let wallet = wallet.lock().await;
+				// Unless an external destination is configured, the outputs go back to our wallet.
+				let destination_pubkey = match sweep_destination {
+					Some(pubkey) => pubkey,
+					None => {
+						let Ok(pubkey) = wallet.get_change_yuv_pubkey() else {
+							lightning::log_error!(logger, "Failed to get change YUV pubkey");
+							continue;
+						};
+						pubkey
+					}
 				};
 				let output_descriptors = &outputs.iter().collect::<Vec<_>>();
-				let tx_feerate = bitcoind_client
-					.get_est_sat_per_1000_weight(ConfirmationTarget::ChannelCloseMinimum);
+				let tx_feerate = bumped_sweep_feerate(
+					bitcoind_client.get_est_sat_per_1000_weight(conf_target),
+					attempts,
+				);
+				if attempts > 0 {
+					log_info!(
+						logger,
+						"Retrying sweep of spendable outputs {} at {} sat/kw after {} attempts",
+						file_path.display(),
+						tx_feerate,
+						attempts
+					);
+				}
 
 				// We set nLockTime to the current height to discourage fee sniping.
 				let cur_height = channel_manager.current_best_block().height;
@@ -134,14 +567,31 @@ pub(crate) async fn periodic_sweep(
 					&Secp256k1::new(),
 				) {
 					Ok(yuv_spending_txs) => {
-						// Note that, most likely, we've already sweeped this set of outputs
-						// and they're already confirmed on-chain, so this broadcast will fail.
+						let mut broadcasted_txids = Vec::new();
 						for yuv_tx in yuv_spending_txs {
-							let emulate_result = yuv_client
-								.as_ref()
-								.unwrap()
-								.emulate_yuv_transaction(yuv_tx.clone())
-								.await;
+							// Without a YUV node the node holds no tokens, so there's nothing
+							// to check or prove and only the bitcoin transaction is broadcast.
+							let emulate_result = match yuv_client.as_ref() {
+								Some(yuv_client) => {
+									yuv_client.emulate_yuv_transaction(yuv_tx.clone()).await
+								}
+								None => Ok(None),
+							};
+
+							// Without an answer from the YUV node the sweep is neither broadcast
+							// nor counted as rejected, and is tried again on the next round.
+							let emulate_result = match emulate_result {
+								Ok(emulate_result) => emulate_result,
+								Err(err) => {
+									lightning::log_error!(
+										logger,
+										"Failed to check spending YUV tx {} with the YUV node: {}",
+										yuv_tx.bitcoin_tx.txid(),
+										err
+									);
+									continue;
+								}
+							};
 
 							if let Some(reason) = emulate_result {
 								lightning::log_error!(
@@ -150,13 +600,47 @@ pub(crate) async fn periodic_sweep(
 									yuv_tx.bitcoin_tx.raw_hex(),
 									yuv_tx.tx_type,
 								);
+
+								let mut failed_sweeps = failed_sweeps.lock().unwrap();
+								let abandoned = num_rejections(&failed_sweeps, &outputs_key) + 1
+									>= max_sweep_rejections;
+								if abandoned {
+									lightning::log_error!(
+										logger,
+										"Giving up on sweeping spendable outputs {} after {} rejections",
+										file_path.display(),
+										max_sweep_rejections
+									);
+								}
+								failed_sweeps.sweeps.push(FailedSweepInfo {
+									timestamp_secs: SystemTime::now()
+										.duration_since(SystemTime::UNIX_EPOCH)
+										.unwrap()
+										.as_secs(),
+									outputs_key: outputs_key.clone(),
+									txid: yuv_tx.bitcoin_tx.txid(),
+									reason,
+									proofs: format!("{:?}", yuv_tx.tx_type),
+									abandoned,
+								});
+								if let Err(e) = persister.write(
+									"",
+									"",
+									FAILED_SWEEPS_FNAME,
+									&failed_sweeps.encode(),
+								) {
+									lightning::log_error!(
+										logger,
+										"Failed to persist failed sweeps: {}",
+										e
+									);
+								}
 								continue;
 							}
 
-							yuv_client
-								.as_ref()
-								.unwrap()
-								.broadcast_transactions_proofs(yuv_tx.clone());
+							if let Some(yuv_client) = yuv_client.as_ref() {
+								yuv_client.broadcast_transactions_proofs(yuv_tx.clone());
+							}
 
 							bitcoind_client.broadcast_transactions(&[&yuv_tx.bitcoin_tx]);
 
@@ -165,6 +649,21 @@ pub(crate) async fn periodic_sweep(
 								"Broadcasted YUV sweep tx: {}",
 								yuv_tx.bitcoin_tx.txid()
 							);
+							broadcasted_txids.push(yuv_tx.bitcoin_tx.txid());
+						}
+
+						if !broadcasted_txids.is_empty() {
+							let res = fs::create_dir_all(&sweeps_dir).and_then(|_| {
+								persist_sweep_txids(&sweep_txids_path, &broadcasted_txids)
+							});
+							if let Err(e) = res {
+								lightning::log_error!(
+									logger,
+									"Failed to persist sweep txids of {}: {}",
+									file_path.display(),
+									e
+								);
+							}
 						}
 					}
 					Err(err) => {
@@ -176,8 +675,192 @@ pub(crate) async fn periodic_sweep(
 					}
 				}
 			}
+		}
+	}
+}
 
-			fs::remove_dir_all(&spendables_dir).unwrap();
+#[cfg(test)]
+mod pending_spendable_output_tests {
+	use super::*;
+	use bitcoin::{ScriptBuf, TxOut};
+	use lightning::chain::transaction::OutPoint;
+
+	fn static_output(value: u64) -> SpendableOutputDescriptor {
+		SpendableOutputDescriptor::StaticOutput {
+			outpoint: OutPoint { txid: Txid::all_zeros(), index: 0 },
+			output: TxOut { value, script_pubkey: ScriptBuf::new() },
+			channel_keys_id: None,
 		}
 	}
+
+	fn num_pending_outputs(data_dir: &Path) -> usize {
+		fs::read_dir(data_dir.join(crate::PENDING_SPENDABLE_OUTPUT_DIR)).unwrap().count()
+	}
+
+	#[test]
+	fn test_persisting_same_output_twice_writes_one_file() {
+		let data_dir = std::env::temp_dir().join("pending_spendable_output_tests");
+		let _ = fs::remove_dir_all(&data_dir);
+		let fs_store = FilesystemStore::new(data_dir.clone());
+
+		persist_pending_spendable_output(&fs_store, &static_output(1000)).unwrap();
+		persist_pending_spendable_output(&fs_store, &static_output(1000)).unwrap();
+		assert_eq!(num_pending_outputs(&data_dir), 1);
+
+		persist_pending_spendable_output(&fs_store, &static_output(2000)).unwrap();
+		assert_eq!(num_pending_outputs(&data_dir), 2);
+
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+}
+
+#[cfg(test)]
+mod sweep_status_tests {
+	use super::*;
+
+	#[test]
+	fn test_all_spent_on_chain_is_confirmed() {
+		let spends = [OutputSpend::InChain, OutputSpend::InChain];
+		assert_eq!(sweep_status(&spends), SweepStatus::Confirmed);
+	}
+
+	#[test]
+	fn test_spent_in_mempool_is_pending() {
+		let spends = [OutputSpend::InChain, OutputSpend::InMempool];
+		assert_eq!(sweep_status(&spends), SweepStatus::Pending);
+	}
+
+	#[test]
+	fn test_any_unspent_is_unswept() {
+		let spends = [OutputSpend::InChain, OutputSpend::Unspent];
+		assert_eq!(sweep_status(&spends), SweepStatus::Unswept);
+		assert_eq!(sweep_status(&[OutputSpend::Unspent]), SweepStatus::Unswept);
+	}
+}
+
+#[cfg(test)]
+mod sweep_feerate_tests {
+	use super::*;
+
+	#[test]
+	fn test_first_attempt_uses_estimate() {
+		assert_eq!(bumped_sweep_feerate(2000, 0), 2000);
+	}
+
+	#[test]
+	fn test_feerate_bumped_per_attempt() {
+		assert_eq!(bumped_sweep_feerate(2000, 1), 2500);
+		assert_eq!(bumped_sweep_feerate(2000, 3), 3500);
+	}
+
+	#[test]
+	fn test_low_feerate_bumped_by_incremental_relay_fee() {
+		assert_eq!(bumped_sweep_feerate(253, 1), 503);
+		assert_eq!(bumped_sweep_feerate(253, 2), 753);
+	}
+
+	#[test]
+	fn test_bump_is_capped() {
+		assert_eq!(bumped_sweep_feerate(2000, 1000), MAX_SWEEP_FEERATE_SAT_PER_1000_WEIGHT);
+		assert_eq!(bumped_sweep_feerate(2000, u32::MAX), MAX_SWEEP_FEERATE_SAT_PER_1000_WEIGHT);
+	}
+
+	#[test]
+	fn test_estimate_above_cap_is_not_bumped() {
+		assert_eq!(bumped_sweep_feerate(30_000, 0), 30_000);
+		assert_eq!(bumped_sweep_feerate(30_000, 5), 30_000);
+	}
+}
+
+#[cfg(test)]
+mod failed_sweep_tests {
+	use super::*;
+
+	fn failed_sweep(outputs_key: &str) -> FailedSweepInfo {
+		FailedSweepInfo {
+			timestamp_secs: 0,
+			outputs_key: outputs_key.to_string(),
+			txid: Txid::all_zeros(),
+			reason: "invalid proof".to_string(),
+			proofs: String::new(),
+			abandoned: false,
+		}
+	}
+
+	#[test]
+	fn test_num_rejections_counts_only_matching_set() {
+		let failed_sweeps = FailedSweepStorage {
+			sweeps: vec![failed_sweep("aa"), failed_sweep("bb"), failed_sweep("aa")],
+		};
+		assert_eq!(num_rejections(&failed_sweeps, "aa"), 2);
+		assert_eq!(num_rejections(&failed_sweeps, "bb"), 1);
+		assert_eq!(num_rejections(&failed_sweeps, "cc"), 0);
+	}
+
+	#[test]
+	fn test_retry_failed_sweeps() {
+		let data_dir = std::env::temp_dir().join("failed_sweep_tests");
+		let _ = fs::remove_dir_all(&data_dir);
+		let fs_store = FilesystemStore::new(data_dir.clone());
+		let ldk_data_dir = data_dir.to_str().unwrap();
+		let mut failed_sweeps = FailedSweepStorage {
+			sweeps: vec![failed_sweep("aa"), failed_sweep("bb"), failed_sweep("aa")],
+		};
+
+		// Nothing was set aside yet.
+		assert_eq!(retry_failed_sweeps(ldk_data_dir, &fs_store, &mut failed_sweeps).unwrap(), 0);
+		assert_eq!(failed_sweeps.sweeps.len(), 3);
+
+		let failed_spendables_dir = data_dir.join(FAILED_SPENDABLE_OUTPUTS_DIR);
+		fs::create_dir_all(&failed_spendables_dir).unwrap();
+		fs::write(failed_spendables_dir.join("aa"), [1, 2, 3]).unwrap();
+
+		assert_eq!(retry_failed_sweeps(ldk_data_dir, &fs_store, &mut failed_sweeps).unwrap(), 1);
+		assert_eq!(fs::read(data_dir.join("spendable_outputs").join("aa")).unwrap(), vec![1, 2, 3]);
+		assert_eq!(fs::read_dir(&failed_spendables_dir).unwrap().count(), 0);
+		assert_eq!(num_rejections(&failed_sweeps, "aa"), 0);
+		assert_eq!(num_rejections(&failed_sweeps, "bb"), 1);
+
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+}
+
+#[cfg(test)]
+mod static_output_tests {
+	use super::*;
+	use bitcoin::TxOut;
+	use lightning::chain::transaction::OutPoint;
+	use lightning::sign::SignerProvider;
+
+	const SEED: [u8; 32] = [42; 32];
+
+	fn static_output(script_pubkey: ScriptBuf) -> SpendableOutputDescriptor {
+		SpendableOutputDescriptor::StaticOutput {
+			outpoint: OutPoint { txid: Txid::all_zeros(), index: 0 },
+			output: TxOut { value: 1000, script_pubkey },
+			channel_keys_id: None,
+		}
+	}
+
+	#[test]
+	fn test_static_output_keys_match_keys_manager_scripts() {
+		let keys_manager = KeysManager::new(&SEED, 0, 0);
+		let keys = static_output_keys(&SEED);
+
+		let destination_script = keys_manager.get_destination_script([0; 32]).unwrap();
+		assert_eq!(static_output_key(&static_output(destination_script), &keys), Some(keys[0]));
+
+		let shutdown_script = keys_manager.get_shutdown_scriptpubkey().unwrap().into_inner();
+		assert_eq!(static_output_key(&static_output(shutdown_script), &keys), Some(keys[1]));
+	}
+
+	#[test]
+	fn test_static_output_with_unknown_script_has_no_key() {
+		let keys = static_output_keys(&SEED);
+		assert_eq!(static_output_key(&static_output(ScriptBuf::new()), &keys), None);
+
+		let other_script =
+			KeysManager::new(&[7; 32], 0, 0).get_destination_script([0; 32]).unwrap();
+		assert_eq!(static_output_key(&static_output(other_script), &keys), None);
+	}
 }
@@ -1,18 +1,22 @@
 use crate::disk::FilesystemLogger;
-use bdk::blockchain::AnyBlockchain;
-use bdk::wallet::AddressIndex;
-use bdk::SignOptions;
+use bdk::blockchain::rpc::{RpcBlockchain, RpcConfig, RpcSyncParams};
+use bdk::blockchain::{AnyBlockchain, ConfigurableBlockchain};
+use bdk::database::{AnyDatabase, Database, MemoryDatabase};
+use bdk::wallet::{wallet_name_from_descriptor, AddressIndex};
+use bdk::{Balance, KeychainKind, SignOptions};
+use bitcoin::bip32::ExtendedPrivKey;
 use bitcoin::blockdata::constants::WITNESS_SCALE_FACTOR;
 use bitcoin::psbt::PartiallySignedTransaction;
-use bitcoin::secp256k1::PublicKey;
-use bitcoin::{ScriptBuf, Transaction};
-use eyre::Context;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::{Address, Network, OutPoint, PrivateKey, ScriptBuf, Transaction, Txid};
+use eyre::{bail, Context};
 use lightning::events::bump_transaction::{Utxo, WalletSource};
 use lightning::log_error;
 use lightning::util::logger::Logger;
 use std::collections::HashMap;
-use std::sync::Arc;
-use ydk::bitcoin_provider::BitcoinProvider;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use ydk::bitcoin_provider::{BitcoinProvider, BitcoinProviderConfig};
 use ydk::types::FeeRateStrategy;
 use ydk::wallet::WalletConfig as MemoryWalletConfig;
 use ydk::wallet::{MemoryWallet, SyncOptions};
@@ -20,18 +24,222 @@ use ydk::AnyBitcoinProvider;
 use yuv_pixels::{Chroma, Pixel};
 use yuv_types::YuvTransaction;
 
+/// The directory under the LDK data dir the state of the BDK wallets is kept in.
+pub(crate) const BDK_WALLET_DIR: &str = "bdk_wallet";
+
+/// The tree of the [`WalletStore`] listing the keys imported with [`Wallet::import_private_key`].
+const IMPORTED_KEYS_TREE: &str = "imported_keys";
+
 #[derive(Clone)]
 pub(crate) struct Wallet {
+	/// Only used for YUV, as its BDK wallet is kept in memory and so synced from scratch on every
+	/// start. The wallet's bitcoin is tracked by `bitcoin_wallet` instead.
 	ydk_wallet: MemoryWallet,
+	/// Shared between the clones of the wallet, so that a rescan is seen by the wallet sources too.
+	bitcoin_wallet: Arc<Mutex<SyncedWallet>>,
+	/// The wallets of the keys imported with [`Wallet::import_private_key`], by public key. Always
+	/// locked after `bitcoin_wallet`, if both are.
+	imported_wallets: Arc<Mutex<HashMap<PublicKey, SyncedWallet>>>,
+	store: WalletStore,
 	blockchain: Arc<AnyBlockchain>,
 	logger: Arc<FilesystemLogger>,
+	/// Shared between the clones of the wallet, so that the wallet source used for bumping
+	/// transactions doesn't spend them either.
+	reserved_outpoints: Arc<Mutex<ReservedOutpoints>>,
+	/// Kept to rebuild the wallet from scratch on a rescan.
+	config: MemoryWalletConfig,
+	sync_yuv_wallet: bool,
+}
+
+/// Outpoints spent by funding transactions which haven't confirmed yet, keyed by the funding
+/// txid, so that concurrent channel opens don't select the same coins.
+#[derive(Default)]
+struct ReservedOutpoints {
+	by_funding_txid: HashMap<Txid, Vec<OutPoint>>,
+}
+
+impl ReservedOutpoints {
+	/// Reserves the inputs of `funding_tx`, failing if any of them is already reserved by another
+	/// funding transaction.
+	fn reserve(&mut self, funding_tx: &Transaction) -> bool {
+		if funding_tx.input.iter().any(|input| self.is_reserved(&input.previous_output)) {
+			return false;
+		}
+		let outpoints = funding_tx.input.iter().map(|input| input.previous_output).collect();
+		self.by_funding_txid.insert(funding_tx.txid(), outpoints);
+		true
+	}
+
+	fn release(&mut self, funding_txid: &Txid) {
+		self.by_funding_txid.remove(funding_txid);
+	}
+
+	fn is_reserved(&self, outpoint: &OutPoint) -> bool {
+		self.by_funding_txid.values().any(|outpoints| outpoints.contains(outpoint))
+	}
+
+	fn outpoints(&self) -> Vec<OutPoint> {
+		self.by_funding_txid.values().flatten().copied().collect()
+	}
+}
+
+/// Where the state of the BDK wallets is kept: in a database under the LDK data dir, so that it
+/// survives restarts and only what happened since is synced, or in memory if there's no data dir.
+#[derive(Clone)]
+enum WalletStore {
+	Memory,
+	Disk(bdk::sled::Db),
+}
+
+impl WalletStore {
+	fn open(data_dir: Option<&Path>) -> eyre::Result<Self> {
+		let Some(data_dir) = data_dir else {
+			return Ok(Self::Memory);
+		};
+		let db = bdk::sled::open(data_dir.join(BDK_WALLET_DIR))
+			.wrap_err("failed to open wallet database")?;
+		Ok(Self::Disk(db))
+	}
+
+	/// Opens the database of the BDK wallet called `name`, each wallet being kept in a tree of its
+	/// own.
+	fn database(&self, name: &str) -> eyre::Result<AnyDatabase> {
+		match self {
+			Self::Memory => Ok(AnyDatabase::Memory(MemoryDatabase::new())),
+			Self::Disk(db) => {
+				let tree = db.open_tree(name).wrap_err("failed to open wallet database tree")?;
+				Ok(AnyDatabase::Sled(tree))
+			}
+		}
+	}
+
+	/// Forgets the state of the BDK wallet called `name`, so that it's synced from scratch.
+	fn clear(&self, name: &str) -> eyre::Result<()> {
+		if let Self::Disk(db) = self {
+			let tree = db.open_tree(name).wrap_err("failed to open wallet database tree")?;
+			tree.clear().wrap_err("failed to clear wallet database tree")?;
+		}
+		Ok(())
+	}
+
+	/// Returns the keys which were imported, which are forgotten on restart when kept in memory.
+	fn imported_keys(&self) -> eyre::Result<Vec<PrivateKey>> {
+		let Self::Disk(db) = self else {
+			return Ok(Vec::new());
+		};
+		let tree =
+			db.open_tree(IMPORTED_KEYS_TREE).wrap_err("failed to open imported keys tree")?;
+		tree.iter()
+			.keys()
+			.map(|key| {
+				let key = key.wrap_err("failed to read imported key")?;
+				let wif = std::str::from_utf8(&key).wrap_err("invalid imported key")?;
+				PrivateKey::from_wif(wif).wrap_err("invalid imported key")
+			})
+			.collect()
+	}
+
+	fn add_imported_key(&self, private_key: &PrivateKey) -> eyre::Result<()> {
+		if let Self::Disk(db) = self {
+			let tree =
+				db.open_tree(IMPORTED_KEYS_TREE).wrap_err("failed to open imported keys tree")?;
+			tree.insert(private_key.to_wif(), &[]).wrap_err("failed to persist imported key")?;
+			tree.flush().wrap_err("failed to persist imported key")?;
+		}
+		Ok(())
+	}
+}
+
+/// Opens the BDK wallet of the given descriptors from `store`, under the name bitcoind's
+/// watch-only wallet for them is given too.
+fn open_bdk_wallet(
+	store: &WalletStore, descriptor: &str, change_descriptor: Option<&str>, network: Network,
+) -> eyre::Result<(String, bdk::Wallet<AnyDatabase>)> {
+	let name =
+		wallet_name_from_descriptor(descriptor, change_descriptor, network, &Secp256k1::new())
+			.wrap_err("failed to name wallet")?;
+	let wallet = bdk::Wallet::new(descriptor, change_descriptor, network, store.database(&name)?)
+		.wrap_err("failed to open wallet")?;
+	Ok((name, wallet))
+}
+
+/// A BDK wallet synced with bitcoind through a watch-only wallet of its own. As its state is kept
+/// in the [`WalletStore`], each sync only picks up the transactions since the previous one.
+struct SyncedWallet {
+	name: String,
+	wallet: bdk::Wallet<AnyDatabase>,
+	blockchain: RpcBlockchain,
+}
+
+impl SyncedWallet {
+	fn open(
+		store: &WalletStore, descriptor: &str, change_descriptor: Option<&str>,
+		config: &MemoryWalletConfig,
+	) -> eyre::Result<Self> {
+		let BitcoinProviderConfig::BitcoinRpc(rpc_config) = &config.bitcoin_provider else {
+			bail!("the wallet can only be synced with bitcoind's RPC");
+		};
+		let (name, wallet) = open_bdk_wallet(store, descriptor, change_descriptor, config.network)?;
+		let rpc_config = RpcConfig {
+			url: rpc_config.url.clone(),
+			auth: rpc_config.auth.clone(),
+			network: rpc_config.network,
+			wallet_name: name.clone(),
+			sync_params: Some(RpcSyncParams {
+				start_time: rpc_config.start_time,
+				..Default::default()
+			}),
+		};
+		let blockchain =
+			RpcBlockchain::from_config(&rpc_config).wrap_err("failed to connect to bitcoind")?;
+		Ok(Self { name, wallet, blockchain })
+	}
+
+	fn sync(&self) -> eyre::Result<()> {
+		self.wallet
+			.sync(&self.blockchain, bdk::SyncOptions::default())
+			.wrap_err("failed to sync wallet")
+	}
+}
+
+/// The descriptors of the wallet's bitcoin. Coins are received on the key the YUV wallet uses too,
+/// while change goes to fresh keys derived from it, so that change outputs can't be told apart by
+/// their script.
+fn bitcoin_descriptors(private_key: &PrivateKey) -> eyre::Result<(String, String)> {
+	let change_key =
+		ExtendedPrivKey::new_master(private_key.network, &private_key.inner.secret_bytes())
+			.wrap_err("failed to derive change key")?;
+	Ok((format!("wpkh({})", private_key), format!("wpkh({}/1/*)", change_key)))
+}
+
+/// The descriptor of a key imported with [`Wallet::import_private_key`].
+fn imported_key_descriptor(private_key: &PrivateKey) -> String {
+	format!("wpkh({})", private_key)
+}
+
+/// Sums the balances of the wallet and of its imported keys.
+fn add_balances(balance: Balance, other: Balance) -> Balance {
+	Balance {
+		immature: balance.immature + other.immature,
+		trusted_pending: balance.trusted_pending + other.trusted_pending,
+		untrusted_pending: balance.untrusted_pending + other.untrusted_pending,
+		confirmed: balance.confirmed + other.confirmed,
+	}
+}
+
+/// Reveals the next change script of `wallet`, so that each call returns a script not handed out
+/// before.
+fn new_change_script(wallet: &bdk::Wallet<AnyDatabase>) -> Result<ScriptBuf, bdk::Error> {
+	Ok(wallet.get_internal_address(AddressIndex::New)?.script_pubkey())
 }
 
 pub const DUMMY_YUV_URL: &str = "http://localhost:8080";
 
 impl Wallet {
+	/// The wallet's bitcoin is kept under `data_dir`, if given, so that only what happened since
+	/// the last run is synced. Without it, the wallet is synced from scratch on every start.
 	pub async fn from_config(
-		mut config: MemoryWalletConfig, logger: Arc<FilesystemLogger>,
+		mut config: MemoryWalletConfig, data_dir: Option<&Path>, logger: Arc<FilesystemLogger>,
 	) -> eyre::Result<Self> {
 		let sync_yuv_wallet = !config.yuv_url.is_empty();
 
@@ -42,14 +250,161 @@ impl Wallet {
 		let bitcoin_provider: AnyBitcoinProvider =
 			BitcoinProvider::from_config(config.clone().try_into()?)?;
 
-		let ydk_wallet =
-			ydk::Wallet::from_config(config).await.wrap_err("failed to initialize wallet")?;
+		let ydk_wallet = ydk::Wallet::from_config(config.clone())
+			.await
+			.wrap_err("failed to initialize wallet")?;
 
 		let options = SyncOptions { sync_yuv_wallet, ..Default::default() };
 
 		ydk_wallet.sync(options).await.wrap_err("failed to sync wallet")?;
 
-		Ok(Self { ydk_wallet, logger, blockchain: bitcoin_provider.blockchain() })
+		let store = WalletStore::open(data_dir)?;
+		let (descriptor, change_descriptor) = bitcoin_descriptors(&config.privkey)?;
+		let bitcoin_wallet =
+			SyncedWallet::open(&store, &descriptor, Some(&change_descriptor), &config)?;
+		bitcoin_wallet.sync()?;
+
+		let mut imported_wallets = HashMap::new();
+		for private_key in store.imported_keys()? {
+			let imported_wallet =
+				SyncedWallet::open(&store, &imported_key_descriptor(&private_key), None, &config)?;
+			imported_wallet.sync()?;
+			imported_wallets
+				.insert(private_key.public_key(&Secp256k1::new()).inner, imported_wallet);
+		}
+
+		Ok(Self {
+			ydk_wallet,
+			bitcoin_wallet: Arc::new(Mutex::new(bitcoin_wallet)),
+			imported_wallets: Arc::new(Mutex::new(imported_wallets)),
+			store,
+			logger,
+			blockchain: bitcoin_provider.blockchain(),
+			reserved_outpoints: Arc::new(Mutex::new(ReservedOutpoints::default())),
+			config,
+			sync_yuv_wallet,
+		})
+	}
+
+	/// Drops the wallet state and syncs it again from scratch, including the YUV wallet if the
+	/// node is connected to a YUV node, for when it has drifted from the chain.
+	///
+	/// Note that wallet sources created before the rescan keep using the previous YUV state.
+	pub async fn full_rescan(&mut self) -> eyre::Result<()> {
+		let ydk_wallet = ydk::Wallet::from_config(self.config.clone())
+			.await
+			.wrap_err("failed to initialize wallet")?;
+
+		let options = SyncOptions { sync_yuv_wallet: self.sync_yuv_wallet, ..Default::default() };
+		ydk_wallet.sync(options).await.wrap_err("failed to sync wallet")?;
+
+		let mut bitcoin_wallet = self.bitcoin_wallet.lock().unwrap();
+		self.store.clear(&bitcoin_wallet.name)?;
+		let (descriptor, change_descriptor) = bitcoin_descriptors(&self.config.privkey)?;
+		let rescanned_wallet =
+			SyncedWallet::open(&self.store, &descriptor, Some(&change_descriptor), &self.config)?;
+		rescanned_wallet.sync()?;
+
+		let mut imported_wallets = self.imported_wallets.lock().unwrap();
+		for private_key in self.store.imported_keys()? {
+			let public_key = private_key.public_key(&Secp256k1::new()).inner;
+			let Some(imported_wallet) = imported_wallets.get_mut(&public_key) else {
+				continue;
+			};
+			self.store.clear(&imported_wallet.name)?;
+			let descriptor = imported_key_descriptor(&private_key);
+			*imported_wallet = SyncedWallet::open(&self.store, &descriptor, None, &self.config)?;
+			imported_wallet.sync()?;
+		}
+
+		*bitcoin_wallet = rescanned_wallet;
+		self.ydk_wallet = ydk_wallet;
+		Ok(())
+	}
+
+	/// Syncs the wallet, including the YUV wallet if the node is connected to a YUV node.
+	pub async fn sync(&self) -> eyre::Result<()> {
+		let options = SyncOptions { sync_yuv_wallet: self.sync_yuv_wallet, ..Default::default() };
+		self.ydk_wallet.sync(options).await.wrap_err("failed to sync wallet")?;
+		self.bitcoin_wallet.lock().unwrap().sync()?;
+		for imported_wallet in self.imported_wallets.lock().unwrap().values() {
+			imported_wallet.sync()?;
+		}
+		Ok(())
+	}
+
+	/// Returns the height of the block the wallet was last synced up to, if it was ever synced.
+	pub fn sync_status(&self) -> eyre::Result<Option<u32>> {
+		let bitcoin_wallet = self.bitcoin_wallet.lock().unwrap();
+
+		let sync_time =
+			bitcoin_wallet.wallet.database().get_sync_time().wrap_err("failed to get sync time")?;
+
+		Ok(sync_time.map(|sync_time| sync_time.block_time.height))
+	}
+
+	/// Imports `private_key`, so that the bitcoin it holds, and receives later, is spent like the
+	/// wallet's own: a BDK wallet watching and signing for its P2WPKH output is kept next to ours,
+	/// and bitcoind rescans the chain for it. Returns `false` if the key was already imported.
+	///
+	/// The YUV wallet can't take on other keys, so the key's YUV holdings are moved to our key with
+	/// [`Wallet::transfer_imported_yuv`] instead.
+	pub fn import_private_key(&self, private_key: PrivateKey) -> eyre::Result<bool> {
+		let public_key = private_key.public_key(&Secp256k1::new()).inner;
+		if public_key == self.public_key() {
+			bail!("the key is the wallet's own");
+		}
+		let mut imported_wallets = self.imported_wallets.lock().unwrap();
+		if imported_wallets.contains_key(&public_key) {
+			return Ok(false);
+		}
+
+		let descriptor = imported_key_descriptor(&private_key);
+		let imported_wallet = SyncedWallet::open(&self.store, &descriptor, None, &self.config)?;
+		imported_wallet.sync()?;
+		self.store.add_imported_key(&private_key)?;
+		imported_wallets.insert(public_key, imported_wallet);
+		Ok(true)
+	}
+
+	/// Syncs the wallet of an imported key and returns its bitcoin balance.
+	pub fn imported_key_balance(&self, public_key: &PublicKey) -> eyre::Result<Balance> {
+		let imported_wallets = self.imported_wallets.lock().unwrap();
+		let Some(imported_wallet) = imported_wallets.get(public_key) else {
+			bail!("the key wasn't imported");
+		};
+		imported_wallet.sync()?;
+		imported_wallet.wallet.get_balance().wrap_err("failed to get imported key balance")
+	}
+
+	/// Builds a transfer of the key's holdings of a single chroma to our key, if it holds any. Call
+	/// this again, once the previous transfer confirmed, until it returns `None`.
+	pub async fn transfer_imported_yuv(
+		&self, private_key: PrivateKey,
+	) -> eyre::Result<Option<YuvTransaction>> {
+		if !self.sync_yuv_wallet {
+			return Ok(None);
+		}
+		let config = MemoryWalletConfig { privkey: private_key, ..self.config.clone() };
+		let imported_yuv_wallet = ydk::Wallet::from_config(config)
+			.await
+			.wrap_err("failed to initialize YUV wallet of imported key")?;
+
+		let options = SyncOptions { sync_yuv_wallet: true, ..Default::default() };
+		imported_yuv_wallet
+			.sync(options)
+			.await
+			.wrap_err("failed to sync YUV wallet of imported key")?;
+
+		let balances = imported_yuv_wallet.balances().await?;
+		let Some((chroma, amount)) = balances.yuv.into_iter().find(|(_, amount)| *amount > 0)
+		else {
+			return Ok(None);
+		};
+		let yuv_tx = imported_yuv_wallet
+			.create_transfer(Pixel::new(amount, chroma), self.public_key(), None)
+			.await?;
+		Ok(Some(yuv_tx))
 	}
 }
 
@@ -65,7 +420,8 @@ impl Wallet {
 	) -> eyre::Result<YuvTransaction> {
 		self.ydk_wallet.sync(SyncOptions::default()).await.wrap_err("failed to sync wallet")?;
 
-		self.ydk_wallet
+		let funding_tx = self
+			.ydk_wallet
 			.lightning_funding_tx(
 				funding_pixel,
 				funding_holder_pubkey,
@@ -73,33 +429,86 @@ impl Wallet {
 				channel_value_satoshis,
 				Some(FeeRateStrategy::TryEstimate { fee_rate: 1.1, target: 2 }),
 			)
-			.await
+			.await?;
+
+		// The YUV coin selection can't be told to skip outpoints, so the best we can do is to
+		// refuse funding transactions which collide with a pending one.
+		if !self.reserved_outpoints.lock().unwrap().reserve(&funding_tx.bitcoin_tx) {
+			bail!("funding tx spends outputs reserved by another pending channel open");
+		}
+
+		Ok(funding_tx)
 	}
 
 	pub fn new_funding_tx(
 		&self, output_script: ScriptBuf, channel_value_satoshis: u64,
 	) -> eyre::Result<Transaction> {
-		// SAFETY: it's okay as we are not accessing the wallet's DB directly as
-		// suggested by the note in ydk.
-		let bdk_wallet = unsafe { self.ydk_wallet.bitcoin_wallet() };
-		let bdk_wallet_guard = bdk_wallet.read().unwrap();
+		let bitcoin_wallet = self.bitcoin_wallet.lock().unwrap();
 
-		let mut tx_builder = bdk_wallet_guard.build_tx();
+		let mut tx_builder = bitcoin_wallet.wallet.build_tx();
 
 		let fee_rate_strategy = FeeRateStrategy::TryEstimate { fee_rate: 1.1, target: 2 };
 		let fee_rate = fee_rate_strategy
 			.get_fee_rate(&self.blockchain)
 			.wrap_err("failed to estimate fee rate")?;
 
-		tx_builder.add_recipient(output_script, channel_value_satoshis).fee_rate(fee_rate);
+		let mut reserved_outpoints = self.reserved_outpoints.lock().unwrap();
+		tx_builder
+			.add_recipient(output_script, channel_value_satoshis)
+			.fee_rate(fee_rate)
+			.unspendable(reserved_outpoints.outpoints());
+
+		// BDK can only select coins of its own wallet, so those of the imported keys are added as
+		// foreign ones. These are always spent, so the next funding tx consolidates them, with the
+		// change going to the wallet.
+		let imported_wallets = self.imported_wallets.lock().unwrap();
+		for imported_wallet in imported_wallets.values() {
+			let satisfaction_weight = imported_wallet
+				.wallet
+				.get_descriptor_for_keychain(KeychainKind::External)
+				.max_satisfaction_weight()
+				.wrap_err("failed to get imported key satisfaction weight")?;
+			let utxos =
+				imported_wallet.wallet.list_unspent().wrap_err("failed to list imported coins")?;
+			for utxo in utxos {
+				if reserved_outpoints.is_reserved(&utxo.outpoint) {
+					continue;
+				}
+				let outpoint = utxo.outpoint;
+				let psbt_input = imported_wallet
+					.wallet
+					.get_psbt_input(utxo, None, false)
+					.wrap_err("failed to get imported coin")?;
+				tx_builder
+					.add_foreign_utxo(outpoint, psbt_input, satisfaction_weight)
+					.wrap_err("failed to add imported coin")?;
+			}
+		}
 
 		let (mut psbt, _tx_details) = tx_builder.finish().wrap_err("failed to build funding tx")?;
 
-		bdk_wallet_guard
-			.sign(&mut psbt, SignOptions { trust_witness_utxo: true, ..Default::default() })
+		let sign_options = SignOptions { trust_witness_utxo: true, ..Default::default() };
+		bitcoin_wallet
+			.wallet
+			.sign(&mut psbt, sign_options.clone())
 			.wrap_err("failed to sign funding tx")?;
+		for imported_wallet in imported_wallets.values() {
+			imported_wallet
+				.wallet
+				.sign(&mut psbt, sign_options.clone())
+				.wrap_err("failed to sign funding tx with imported key")?;
+		}
 
-		Ok(psbt.extract_tx())
+		let funding_tx = psbt.extract_tx();
+		reserved_outpoints.reserve(&funding_tx);
+
+		Ok(funding_tx)
+	}
+
+	/// Makes the coins spent by the given funding transaction available again, once it either
+	/// confirmed or was discarded.
+	pub fn release_funding_outpoints(&self, funding_txid: &Txid) {
+		self.reserved_outpoints.lock().unwrap().release(funding_txid);
 	}
 
 	pub async fn get_yuv_balances(&self) -> eyre::Result<HashMap<Chroma, u128>> {
@@ -110,6 +519,59 @@ impl Wallet {
 		Ok(balances.yuv)
 	}
 
+	/// Returns the wallet's balance of `chroma`, leaving out the UTXOs spent by funding
+	/// transactions which haven't confirmed yet.
+	pub async fn get_unreserved_yuv_balance(&self, chroma: &Chroma) -> eyre::Result<u128> {
+		self.ydk_wallet.sync(SyncOptions::default()).await.wrap_err("failed to sync ydk wallet")?;
+
+		let utxos = self.ydk_wallet.yuv_utxos().await;
+		let reserved_outpoints = self.reserved_outpoints.lock().unwrap();
+		let balance = utxos
+			.into_iter()
+			.filter(|(outpoint, _)| !reserved_outpoints.is_reserved(outpoint))
+			.map(|(_, utxo)| utxo.proof.pixel())
+			.filter(|pixel| pixel.chroma == *chroma)
+			.map(|pixel| pixel.luma.amount)
+			.sum();
+
+		Ok(balance)
+	}
+
+	/// Lists the wallet's YUV UTXOs along with the pixel each of them holds, sorted by outpoint.
+	pub async fn list_yuv_utxos(&self) -> eyre::Result<Vec<(OutPoint, Pixel)>> {
+		self.ydk_wallet.sync(SyncOptions::default()).await.wrap_err("failed to sync ydk wallet")?;
+
+		let mut utxos = self
+			.ydk_wallet
+			.yuv_utxos()
+			.await
+			.into_iter()
+			.map(|(outpoint, utxo)| (outpoint, utxo.proof.pixel()))
+			.collect::<Vec<_>>();
+		utxos.sort_by_key(|(outpoint, _)| *outpoint);
+
+		Ok(utxos)
+	}
+
+	/// Returns the balance of the wallet, including the bitcoin of the imported keys.
+	pub async fn get_onchain_balance(&self) -> eyre::Result<Balance> {
+		let bitcoin_wallet = self.bitcoin_wallet.lock().unwrap();
+		bitcoin_wallet.sync()?;
+		let mut balance =
+			bitcoin_wallet.wallet.get_balance().wrap_err("failed to get on-chain balance")?;
+
+		for imported_wallet in self.imported_wallets.lock().unwrap().values() {
+			imported_wallet.sync()?;
+			let imported_balance = imported_wallet
+				.wallet
+				.get_balance()
+				.wrap_err("failed to get imported key balance")?;
+			balance = add_balances(balance, imported_balance);
+		}
+
+		Ok(balance)
+	}
+
 	pub async fn new_yuv_transfer(
 		&self, recepient: PublicKey, chroma: Chroma, amount: u128,
 	) -> eyre::Result<YuvTransaction> {
@@ -118,6 +580,17 @@ impl Wallet {
 		self.ydk_wallet.create_transfer(Pixel::new(amount, chroma), recepient, None).await
 	}
 
+	pub fn get_new_address(&self) -> eyre::Result<Address> {
+		let bitcoin_wallet = self.bitcoin_wallet.lock().unwrap();
+
+		let address_info = bitcoin_wallet
+			.wallet
+			.get_address(AddressIndex::New)
+			.wrap_err("failed to get new address")?;
+
+		Ok(address_info.address)
+	}
+
 	pub fn public_key(&self) -> PublicKey {
 		self.ydk_wallet.public_key().inner
 	}
@@ -125,15 +598,21 @@ impl Wallet {
 
 impl WalletSource for Wallet {
 	fn list_confirmed_utxos(&self) -> Result<Vec<Utxo>, ()> {
-		let bdk_wallet = unsafe { self.ydk_wallet.bitcoin_wallet() };
-		let bdk_wallet_guard = bdk_wallet.read().unwrap();
+		let bitcoin_wallet = self.bitcoin_wallet.lock().unwrap();
 
-		let utxos = bdk_wallet_guard.list_unspent().map_err(|err| {
+		let mut utxos = bitcoin_wallet.wallet.list_unspent().map_err(|err| {
 			log_error!(&self.logger, "Failed to get list unspent utxos: {err}");
 		})?;
+		for imported_wallet in self.imported_wallets.lock().unwrap().values() {
+			utxos.extend(imported_wallet.wallet.list_unspent().map_err(|err| {
+				log_error!(&self.logger, "Failed to get list unspent imported utxos: {err}");
+			})?);
+		}
 
+		let reserved_outpoints = self.reserved_outpoints.lock().unwrap();
 		let ldk_utxos = utxos
 			.into_iter()
+			.filter(|utxo| !reserved_outpoints.is_reserved(&utxo.outpoint))
 			.map(|utxo| {
 				Utxo {
 					outpoint: utxo.outpoint,
@@ -148,14 +627,11 @@ impl WalletSource for Wallet {
 	}
 
 	fn get_change_script(&self) -> Result<ScriptBuf, ()> {
-		let bdk_wallet = unsafe { self.ydk_wallet.bitcoin_wallet() };
-		let bdk_wallet_guard = bdk_wallet.read().unwrap();
-
-		let address_info = bdk_wallet_guard.get_address(AddressIndex::Peek(0)).map_err(|err| {
-			log_error!(&self.logger, "Failed to get address indo: {err}");
-		})?;
+		let bitcoin_wallet = self.bitcoin_wallet.lock().unwrap();
 
-		Ok(address_info.script_pubkey())
+		new_change_script(&bitcoin_wallet.wallet).map_err(|err| {
+			log_error!(&self.logger, "Failed to get change script: {err}");
+		})
 	}
 
 	fn get_change_yuv_pubkey(&self) -> Result<PublicKey, ()> {
@@ -163,13 +639,172 @@ impl WalletSource for Wallet {
 	}
 
 	fn sign_psbt(&self, mut psbt: PartiallySignedTransaction) -> Result<Transaction, ()> {
-		let bdk_wallet = unsafe { self.ydk_wallet.bitcoin_wallet() };
-		let bdk_wallet_guard = bdk_wallet.read().unwrap();
+		let bitcoin_wallet = self.bitcoin_wallet.lock().unwrap();
 
-		bdk_wallet_guard.sign(&mut psbt, SignOptions::default()).map_err(|err| {
+		bitcoin_wallet.wallet.sign(&mut psbt, SignOptions::default()).map_err(|err| {
 			log_error!(&self.logger, "Failed to sign psbt: {err}");
 		})?;
+		// Inputs spending coins of the imported keys are signed by their wallets.
+		for imported_wallet in self.imported_wallets.lock().unwrap().values() {
+			imported_wallet.wallet.sign(&mut psbt, SignOptions::default()).map_err(|err| {
+				log_error!(&self.logger, "Failed to sign psbt with imported key: {err}");
+			})?;
+		}
 
 		Ok(psbt.extract_tx())
 	}
 }
+
+#[cfg(test)]
+mod reserved_outpoints_tests {
+	use super::*;
+	use bitcoin::absolute::LockTime;
+	use bitcoin::hashes::Hash;
+	use bitcoin::TxIn;
+
+	fn funding_tx(vouts: &[u32]) -> Transaction {
+		Transaction {
+			version: 2,
+			lock_time: LockTime::ZERO,
+			input: vouts
+				.iter()
+				.map(|vout| TxIn {
+					previous_output: OutPoint::new(Txid::all_zeros(), *vout),
+					..Default::default()
+				})
+				.collect(),
+			output: vec![],
+		}
+	}
+
+	#[test]
+	fn test_reserve_rejects_colliding_funding_tx() {
+		let mut reserved_outpoints = ReservedOutpoints::default();
+		let first_tx = funding_tx(&[0, 1]);
+
+		assert!(reserved_outpoints.reserve(&first_tx));
+		assert!(reserved_outpoints.is_reserved(&OutPoint::new(Txid::all_zeros(), 1)));
+		assert!(!reserved_outpoints.reserve(&funding_tx(&[1, 2])));
+		assert!(!reserved_outpoints.is_reserved(&OutPoint::new(Txid::all_zeros(), 2)));
+		assert!(reserved_outpoints.reserve(&funding_tx(&[2])));
+		assert_eq!(reserved_outpoints.outpoints().len(), 3);
+	}
+
+	#[test]
+	fn test_release_frees_outpoints() {
+		let mut reserved_outpoints = ReservedOutpoints::default();
+		let first_tx = funding_tx(&[0, 1]);
+		assert!(reserved_outpoints.reserve(&first_tx));
+
+		reserved_outpoints.release(&first_tx.txid());
+		assert!(reserved_outpoints.outpoints().is_empty());
+		assert!(reserved_outpoints.reserve(&funding_tx(&[1, 2])));
+	}
+}
+
+#[cfg(test)]
+mod wallet_store_tests {
+	use super::*;
+	use crate::disk::TempDir;
+	use bdk::database::BatchOperations;
+	use bdk::LocalUtxo;
+	use bitcoin::hashes::Hash;
+	use bitcoin::TxOut;
+	use std::str::FromStr;
+
+	const PRIVATE_KEY: &str = "cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9";
+	const IMPORTED_PRIVATE_KEY: &str = "cMec2DGaTXkYJYfi7x3ZGjRXkeqmAvYAoWzMAcWj5fdLaqudWsNi";
+
+	fn open_wallet(store: &WalletStore) -> (String, bdk::Wallet<AnyDatabase>) {
+		let private_key = PrivateKey::from_str(PRIVATE_KEY).unwrap();
+		let (descriptor, change_descriptor) = bitcoin_descriptors(&private_key).unwrap();
+		open_bdk_wallet(store, &descriptor, Some(&change_descriptor), Network::Regtest).unwrap()
+	}
+
+	#[test]
+	fn test_wallet_state_survives_restart() {
+		let data_dir = TempDir::new("wallet_store_tests");
+		let utxo = |script_pubkey| LocalUtxo {
+			outpoint: OutPoint::new(Txid::all_zeros(), 0),
+			txout: TxOut { value: 10_000, script_pubkey },
+			keychain: KeychainKind::Internal,
+			is_spent: false,
+		};
+
+		let change_script = {
+			let store = WalletStore::open(Some(data_dir.path())).unwrap();
+			let (name, wallet) = open_wallet(&store);
+			new_change_script(&wallet).unwrap();
+			let change_script = new_change_script(&wallet).unwrap();
+			drop(wallet);
+
+			// What a sync finds is written to the wallet's database.
+			store.database(&name).unwrap().set_utxo(&utxo(change_script.clone())).unwrap();
+			change_script
+		};
+
+		let store = WalletStore::open(Some(data_dir.path())).unwrap();
+		let (_, wallet) = open_wallet(&store);
+		assert_eq!(wallet.database().get_last_index(KeychainKind::Internal).unwrap(), Some(1));
+		assert_eq!(wallet.list_unspent().unwrap(), vec![utxo(change_script.clone())]);
+		// Change scripts handed out before the restart aren't handed out again.
+		assert_ne!(new_change_script(&wallet).unwrap(), change_script);
+	}
+
+	#[test]
+	fn test_memory_wallet_state_is_dropped() {
+		let store = WalletStore::open(None).unwrap();
+
+		let (_, wallet) = open_wallet(&store);
+		new_change_script(&wallet).unwrap();
+
+		let (_, wallet) = open_wallet(&store);
+		assert_eq!(wallet.database().get_last_index(KeychainKind::Internal).unwrap(), None);
+	}
+
+	#[test]
+	fn test_change_scripts_are_fresh() {
+		let store = WalletStore::open(None).unwrap();
+		let (_, wallet) = open_wallet(&store);
+
+		let first_script = new_change_script(&wallet).unwrap();
+		let second_script = new_change_script(&wallet).unwrap();
+		assert_ne!(first_script, second_script);
+		// Change never goes to the wallet's receiving address.
+		let address = wallet.get_address(AddressIndex::Peek(0)).unwrap();
+		assert_ne!(first_script, address.script_pubkey());
+		assert!(wallet.is_mine(&first_script).unwrap());
+	}
+
+	#[test]
+	fn test_imported_keys_survive_restart() {
+		let data_dir = TempDir::new("imported_keys_survive_restart");
+		let private_key = PrivateKey::from_str(IMPORTED_PRIVATE_KEY).unwrap();
+		{
+			let store = WalletStore::open(Some(data_dir.path())).unwrap();
+			assert!(store.imported_keys().unwrap().is_empty());
+			store.add_imported_key(&private_key).unwrap();
+			store.add_imported_key(&private_key).unwrap();
+		}
+
+		let store = WalletStore::open(Some(data_dir.path())).unwrap();
+		assert_eq!(store.imported_keys().unwrap(), vec![private_key]);
+	}
+
+	#[test]
+	fn test_imported_key_wallet_is_separate() {
+		let store = WalletStore::open(None).unwrap();
+		let (name, _) = open_wallet(&store);
+
+		let private_key = PrivateKey::from_str(IMPORTED_PRIVATE_KEY).unwrap();
+		let (imported_name, imported_wallet) =
+			open_bdk_wallet(&store, &imported_key_descriptor(&private_key), None, Network::Regtest)
+				.unwrap();
+		assert_ne!(name, imported_name);
+
+		let address = imported_wallet.get_address(AddressIndex::Peek(0)).unwrap();
+		let expected =
+			Address::p2wpkh(&private_key.public_key(&Secp256k1::new()), Network::Regtest).unwrap();
+		assert_eq!(address.address, expected);
+	}
+}
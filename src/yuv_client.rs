@@ -1,57 +1,422 @@
-use crate::disk::FilesystemLogger;
+use crate::disk::{self, FilesystemLogger};
+use bitcoin::hashes::Hash;
 use bitcoin::Txid;
+use futures::stream::{FuturesUnordered, StreamExt};
+use jsonrpsee::core::client::{Subscription, SubscriptionClientT};
+use jsonrpsee::core::Error as RpcError;
 use jsonrpsee::http_client::{HttpClient, HttpClientBuilder};
+use jsonrpsee::rpc_params;
+use jsonrpsee::types::ErrorObjectOwned;
+use jsonrpsee::ws_client::{WsClient, WsClientBuilder};
 use lightning::chain::chaininterface::YuvBroadcaster;
 use lightning::util::logger::Logger;
-use lightning::{log_error, log_info};
+use lightning::{log_error, log_info, log_warn};
 use lightning_block_sync::gossip::YuvTransactionSource;
 use lightning_block_sync::AsyncYuvSourceResult;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
 use std::io::ErrorKind;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use yuv_rpc_api::transactions::EmulateYuvTransactionResponse;
 use yuv_rpc_api::transactions::{GetRawYuvTransactionResponseHex, YuvTransactionsRpcClient};
 use yuv_types::YuvTransaction;
 
+/// The number of times a failed YUV RPC request is retried before giving up.
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// The delay before the first retry, which is multiplied by 4 on each subsequent attempt.
+const DEFAULT_RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// The number of times delivering a YUV proof is retried before giving up on it, which with
+/// [`MAX_PROOF_RETRY_DELAY`] amounts to about half an hour.
+const MAX_PROOF_RETRIES: u32 = 12;
+
+/// The longest delay between two attempts at delivering a YUV proof.
+const MAX_PROOF_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// The YUV node's subscription to the transactions it confirms, each pushed in the same form as
+/// `getlistrawyuvtransactions` returns them.
+const SUBSCRIBE_CONFIRMED_METHOD: &str = "subscribeconfirmedyuvtransactions";
+const UNSUBSCRIBE_CONFIRMED_METHOD: &str = "unsubscribeconfirmedyuvtransactions";
+
+#[derive(Debug, thiserror::Error)]
+pub enum YuvClientError {
+	#[error("failed to reach the YUV node: {0}")]
+	Transport(RpcError),
+	#[error("failed to decode the YUV node response: {0}")]
+	Decode(RpcError),
+	#[error("none of the requested transactions are known to the YUV node")]
+	Empty,
+}
+
+impl From<RpcError> for YuvClientError {
+	fn from(err: RpcError) -> Self {
+		match err {
+			RpcError::ParseError(_) => YuvClientError::Decode(err),
+			err => YuvClientError::Transport(err),
+		}
+	}
+}
+
 pub struct YuvClient {
 	client: HttpClient,
+	/// The YUV node URL with a WebSocket scheme, which subscriptions are opened to.
+	ws_url: String,
+	request_timeout: Duration,
 	handle: tokio::runtime::Handle,
 	logger: Arc<FilesystemLogger>,
+	max_retries: u32,
+	retry_base_delay: Duration,
+	txids_chunk_size: usize,
+	max_concurrent_chunks: usize,
+	counters: Arc<RpcCounters>,
+	proof_deliveries: Arc<ProofDeliveries>,
+	/// Transactions already fetched from the YUV node, so that they aren't requested again while
+	/// they're still pending in the channel manager or chain monitor.
+	fetched_txs: Mutex<LruCache<YuvTransaction>>,
+}
+
+/// A map bounded to `capacity` entries, evicting the least recently used one once full.
+struct LruCache<T> {
+	capacity: usize,
+	entries: HashMap<Txid, T>,
+	/// The keys of `entries`, from the least to the most recently used.
+	order: VecDeque<Txid>,
+}
+
+impl<T: Clone> LruCache<T> {
+	fn new(capacity: usize) -> Self {
+		Self { capacity, entries: HashMap::new(), order: VecDeque::new() }
+	}
+
+	fn get(&mut self, txid: &Txid) -> Option<T> {
+		let entry = self.entries.get(txid)?.clone();
+		self.touch(txid);
+		Some(entry)
+	}
+
+	fn insert(&mut self, txid: Txid, entry: T) {
+		if self.capacity == 0 {
+			return;
+		}
+		if self.entries.insert(txid, entry).is_some() {
+			self.touch(&txid);
+			return;
+		}
+		self.order.push_back(txid);
+		if self.order.len() > self.capacity {
+			if let Some(evicted) = self.order.pop_front() {
+				self.entries.remove(&evicted);
+			}
+		}
+	}
+
+	fn touch(&mut self, txid: &Txid) {
+		if let Some(position) = self.order.iter().position(|key| key == txid) {
+			self.order.remove(position);
+		}
+		self.order.push_back(*txid);
+	}
+}
+
+/// What became of a proof given to the YUV node, until it's accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofDelivery {
+	/// The proof is still being retried after this many failed attempts.
+	Pending { attempts: u32 },
+	/// The YUV node refused the proof, so it isn't retried.
+	Rejected,
+	/// The YUV node couldn't be reached after [`MAX_PROOF_RETRIES`] retries, so delivering the
+	/// proof is only attempted again once the node is restarted.
+	Failed,
+}
+
+/// The proofs given to the YUV node which it hasn't accepted yet, by transaction id. They're
+/// written to disk on every change, so that their delivery can be resumed after a restart.
+struct ProofDeliveries {
+	path: PathBuf,
+	deliveries: Mutex<HashMap<Txid, (YuvTransaction, ProofDelivery)>>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl ProofDeliveries {
+	fn read(path: PathBuf, logger: Arc<FilesystemLogger>) -> Self {
+		let deliveries = Mutex::new(disk::read_yuv_proof_deliveries(&path, &logger));
+		Self { path, deliveries, logger }
+	}
+
+	fn set(&self, yuv_tx: &YuvTransaction, delivery: ProofDelivery) {
+		let mut deliveries = self.deliveries.lock().unwrap();
+		deliveries.insert(yuv_tx.bitcoin_tx.txid(), (yuv_tx.clone(), delivery));
+		self.persist(&deliveries);
+	}
+
+	fn remove(&self, txid: &Txid) {
+		let mut deliveries = self.deliveries.lock().unwrap();
+		if deliveries.remove(txid).is_some() {
+			self.persist(&deliveries);
+		}
+	}
+
+	fn persist(&self, deliveries: &HashMap<Txid, (YuvTransaction, ProofDelivery)>) {
+		if let Err(e) = disk::persist_yuv_proof_deliveries(&self.path, deliveries) {
+			log_error!(self.logger, "Failed to persist YUV proof deliveries: {}", e);
+		}
+	}
+}
+
+/// How many requests of a single YUV RPC method succeeded and failed, after any retries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MethodStats {
+	pub successes: u64,
+	pub failures: u64,
+}
+
+/// A snapshot of the request counters of each YUV RPC method the client calls.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct YuvClientStats {
+	pub get_list_raw_yuv_transactions: MethodStats,
+	pub emulate_yuv_transaction: MethodStats,
+	pub provide_yuv_proof: MethodStats,
+	pub get_raw_yuv_transaction: MethodStats,
+}
+
+impl YuvClientStats {
+	/// Returns the stats of each method along with its RPC method name.
+	pub fn methods(&self) -> [(&'static str, MethodStats); 4] {
+		[
+			("getlistrawyuvtransactions", self.get_list_raw_yuv_transactions),
+			("emulateyuvtransaction", self.emulate_yuv_transaction),
+			("provideyuvproof", self.provide_yuv_proof),
+			("getrawyuvtransaction", self.get_raw_yuv_transaction),
+		]
+	}
+}
+
+#[derive(Default)]
+struct MethodCounters {
+	successes: AtomicU64,
+	failures: AtomicU64,
+}
+
+impl MethodCounters {
+	fn record<T, E>(&self, result: &Result<T, E>) {
+		let counter = if result.is_ok() { &self.successes } else { &self.failures };
+		counter.fetch_add(1, Ordering::Relaxed);
+	}
+
+	fn snapshot(&self) -> MethodStats {
+		MethodStats {
+			successes: self.successes.load(Ordering::Relaxed),
+			failures: self.failures.load(Ordering::Relaxed),
+		}
+	}
+}
+
+#[derive(Default)]
+struct RpcCounters {
+	get_list_raw_yuv_transactions: MethodCounters,
+	emulate_yuv_transaction: MethodCounters,
+	provide_yuv_proof: MethodCounters,
+	get_raw_yuv_transaction: MethodCounters,
 }
 
 impl YuvClient {
+	/// Requests to the YUV node fail once they take longer than `request_timeout`, so that a stalled
+	/// node can't block the callers indefinitely. Up to `tx_cache_size` fetched transactions are
+	/// kept around, so that they aren't requested again, and at most `txids_chunk_size` txids are
+	/// requested in a single call, with up to `max_concurrent_chunks` such calls at once. The proofs
+	/// the YUV node hasn't accepted yet are kept in the `proof_deliveries_path` file.
 	pub(crate) fn new(
-		yuv_node_url: String, handle: tokio::runtime::Handle, logger: Arc<FilesystemLogger>,
+		yuv_node_url: String, request_timeout: Duration, tx_cache_size: usize,
+		txids_chunk_size: usize, max_concurrent_chunks: usize, proof_deliveries_path: PathBuf,
+		handle: tokio::runtime::Handle, logger: Arc<FilesystemLogger>,
 	) -> Self {
-		let http_client =
-			HttpClientBuilder::new().build(yuv_node_url).expect("invalid yuv node url");
+		let ws_url = ws_url(&yuv_node_url);
+		let http_client = HttpClientBuilder::new()
+			.request_timeout(request_timeout)
+			.build(yuv_node_url)
+			.expect("invalid yuv node url");
 
-		Self { client: http_client, handle: handle.clone(), logger: logger.clone() }
+		Self {
+			client: http_client,
+			ws_url,
+			request_timeout,
+			handle: handle.clone(),
+			logger: logger.clone(),
+			max_retries: DEFAULT_MAX_RETRIES,
+			retry_base_delay: DEFAULT_RETRY_BASE_DELAY,
+			txids_chunk_size,
+			max_concurrent_chunks,
+			counters: Arc::new(RpcCounters::default()),
+			proof_deliveries: Arc::new(ProofDeliveries::read(
+				proof_deliveries_path,
+				logger.clone(),
+			)),
+			fetched_txs: Mutex::new(LruCache::new(tx_cache_size)),
+		}
 	}
 
-	pub async fn get_list_raw_yuv_transactions(&self, txids: Vec<Txid>) -> Vec<YuvTransaction> {
+	/// Returns how many requests of each method to the YUV node have succeeded and failed so far.
+	pub fn stats(&self) -> YuvClientStats {
+		YuvClientStats {
+			get_list_raw_yuv_transactions: self.counters.get_list_raw_yuv_transactions.snapshot(),
+			emulate_yuv_transaction: self.counters.emulate_yuv_transaction.snapshot(),
+			provide_yuv_proof: self.counters.provide_yuv_proof.snapshot(),
+			get_raw_yuv_transaction: self.counters.get_raw_yuv_transaction.snapshot(),
+		}
+	}
+
+	/// Returns the proofs which the YUV node hasn't accepted yet, so that a channel whose funding
+	/// or transfer proof never made it can be spotted.
+	pub fn undelivered_proofs(&self) -> Vec<(Txid, ProofDelivery)> {
+		let deliveries = self.proof_deliveries.deliveries.lock().unwrap();
+		deliveries.iter().map(|(txid, (_, delivery))| (*txid, *delivery)).collect()
+	}
+
+	/// Starts delivering the proofs which were still pending, or which were given up on, when the
+	/// node was last stopped. The ones the YUV node rejected are left alone.
+	pub fn resume_proof_deliveries(&self) {
+		let yuv_txs = {
+			let deliveries = self.proof_deliveries.deliveries.lock().unwrap();
+			deliveries
+				.values()
+				.filter(|(_, delivery)| *delivery != ProofDelivery::Rejected)
+				.map(|(yuv_tx, _)| yuv_tx.clone())
+				.collect::<Vec<_>>()
+		};
+		for yuv_tx in yuv_txs {
+			self.broadcast_transactions_proofs(yuv_tx);
+		}
+	}
+
+	/// Checks that the YUV node is reachable by requesting a transaction that doesn't exist. Any
+	/// response from the node, including a "not found" error, means the node is up.
+	pub async fn check_connection(&self) -> Result<(), RpcError> {
+		match self.client.get_yuv_transaction(Txid::all_zeros()).await {
+			Ok(_) | Err(RpcError::Call(_)) => Ok(()),
+			Err(err) => Err(err),
+		}
+	}
+
+	/// Fetches the YUV transactions with the given ids, splitting them into chunks so that a single
+	/// oversized request doesn't fail the whole batch. Chunks that fail are skipped as long as
+	/// another one returns transactions, otherwise the last error is returned, or
+	/// [`YuvClientError::Empty`] if none of the transactions are known to the YUV node yet.
+	///
+	/// Up to `max_concurrent_chunks` chunks are requested at the same time, so the transactions
+	/// are returned in no particular order. Transactions which were already fetched are served
+	/// from the cache instead.
+	pub async fn get_list_raw_yuv_transactions(
+		&self, txids: Vec<Txid>,
+	) -> Result<Vec<YuvTransaction>, YuvClientError> {
+		let mut yuv_txs = Vec::with_capacity(txids.len());
+		let mut missing_txids = Vec::with_capacity(txids.len());
+		{
+			let mut fetched_txs = self.fetched_txs.lock().unwrap();
+			for txid in txids {
+				match fetched_txs.get(&txid) {
+					Some(yuv_tx) => yuv_txs.push(yuv_tx),
+					None => missing_txids.push(txid),
+				}
+			}
+		}
+
+		let (chunk_txs, last_err) = fetch_chunks(
+			&missing_txids,
+			self.txids_chunk_size,
+			self.max_concurrent_chunks,
+			|chunk| self.get_list_raw_yuv_transactions_chunk(chunk),
+		)
+		.await;
+		{
+			let mut fetched_txs = self.fetched_txs.lock().unwrap();
+			for yuv_tx in &chunk_txs {
+				fetched_txs.insert(yuv_tx.bitcoin_tx.txid(), yuv_tx.clone());
+			}
+		}
+		yuv_txs.extend(chunk_txs);
+
+		if yuv_txs.is_empty() {
+			return Err(last_err.unwrap_or(YuvClientError::Empty));
+		}
+		Ok(yuv_txs)
+	}
+
+	/// Transport errors are retried with an exponential backoff, while decode errors and empty
+	/// responses are returned right away, as retrying wouldn't change them.
+	async fn get_list_raw_yuv_transactions_chunk(
+		&self, txids: Vec<Txid>,
+	) -> Result<Vec<YuvTransaction>, YuvClientError> {
 		let logger = self.logger.clone();
-		match self.client.get_list_raw_yuv_transactions(txids.clone()).await {
-			Ok(yuv_txs) => yuv_txs
-				.into_iter()
-				.map(|tx_raw| YuvTransaction::new(tx_raw.bitcoin_tx.bitcoin_tx, tx_raw.tx_type))
-				.collect(),
-			Err(err) => {
-				log_error!(
-					logger,
-					"Error, failed to getlistrawtransactions: {err}\nTx ids: {:?}",
-					txids,
-				);
-				vec![]
+		let mut attempt = 0;
+		let counters = &self.counters.get_list_raw_yuv_transactions;
+		loop {
+			let result = self.client.get_list_raw_yuv_transactions(txids.clone()).await;
+			// Failed attempts are only counted once they're no longer retried.
+			if result.is_ok() {
+				counters.successes.fetch_add(1, Ordering::Relaxed);
+			}
+			match result {
+				Ok(yuv_txs) if yuv_txs.is_empty() => return Err(YuvClientError::Empty),
+				Ok(yuv_txs) => {
+					return Ok(yuv_txs
+						.into_iter()
+						.map(|tx_raw| {
+							YuvTransaction::new(tx_raw.bitcoin_tx.bitcoin_tx, tx_raw.tx_type)
+						})
+						.collect())
+				}
+				Err(err @ RpcError::ParseError(_)) => {
+					counters.failures.fetch_add(1, Ordering::Relaxed);
+					log_error!(
+						logger,
+						"Error, failed to decode getlistrawtransactions response: {err}\nTx ids: {:?}",
+						txids,
+					);
+					return Err(err.into());
+				}
+				Err(err) if attempt < self.max_retries => {
+					let delay = self.retry_base_delay * 4u32.pow(attempt);
+					attempt += 1;
+					log_warn!(
+						logger,
+						"Failed to getlistrawtransactions, retrying in {:?} ({}/{}): {err}",
+						delay,
+						attempt,
+						self.max_retries,
+					);
+					tokio::time::sleep(delay).await;
+				}
+				Err(err) => {
+					counters.failures.fetch_add(1, Ordering::Relaxed);
+					log_error!(
+						logger,
+						"Error, failed to getlistrawtransactions after {} attempts: {err}\nTx ids: {:?}",
+						attempt + 1,
+						txids,
+					);
+					return Err(err.into());
+				}
 			}
 		}
 	}
 
-	pub async fn emulate_yuv_transaction(&self, yuv_tx: YuvTransaction) -> Option<String> {
+	/// Returns why the YUV node rejects `yuv_tx`, if it does. An error means the transaction
+	/// couldn't be checked, and so mustn't be taken as valid.
+	pub async fn emulate_yuv_transaction(
+		&self, yuv_tx: YuvTransaction,
+	) -> Result<Option<String>, YuvClientError> {
 		let logger = self.logger.clone();
-		match self.client.emulate_yuv_transaction(yuv_tx.clone()).await {
+		let result = self.client.emulate_yuv_transaction(yuv_tx.clone()).await;
+		self.counters.emulate_yuv_transaction.record(&result);
+		match result {
 			Ok(response) => match response {
-				EmulateYuvTransactionResponse::Valid => None,
-				EmulateYuvTransactionResponse::Invalid { reason } => Some(reason),
+				EmulateYuvTransactionResponse::Valid => Ok(None),
+				EmulateYuvTransactionResponse::Invalid { reason } => Ok(Some(reason)),
 			},
 			Err(err) => {
 				log_error!(
@@ -59,35 +424,183 @@ impl YuvClient {
 					"Error, failed to emulateyuvtransaction: {err}\nTransaction: {:?}",
 					yuv_tx,
 				);
-				None
+				Err(err.into())
 			}
 		}
 	}
+
+	/// Opens a WebSocket to the YUV node and subscribes to the transactions it confirms, which
+	/// fails if the node doesn't support subscriptions. The subscription stays open until the
+	/// returned [`ConfirmedYuvTransactions`] is dropped, or the connection is lost.
+	pub async fn subscribe_confirmed_yuv_transactions(
+		&self,
+	) -> Result<ConfirmedYuvTransactions, YuvClientError> {
+		let client = WsClientBuilder::default()
+			.request_timeout(self.request_timeout)
+			.build(&self.ws_url)
+			.await?;
+		let subscription = client
+			.subscribe(SUBSCRIBE_CONFIRMED_METHOD, rpc_params![], UNSUBSCRIBE_CONFIRMED_METHOD)
+			.await?;
+		Ok(ConfirmedYuvTransactions { _client: client, subscription })
+	}
+}
+
+/// The transactions the YUV node pushes as it confirms them, see
+/// [`YuvClient::subscribe_confirmed_yuv_transactions`].
+pub struct ConfirmedYuvTransactions {
+	/// The subscription is closed along with its client, so the client is kept alive with it.
+	_client: WsClient,
+	subscription: Subscription<GetRawYuvTransactionResponseHex>,
+}
+
+impl ConfirmedYuvTransactions {
+	/// Waits for the next confirmed transaction, or returns `None` once the subscription dropped.
+	pub async fn next(&mut self) -> Option<Result<YuvTransaction, YuvClientError>> {
+		let result = self.subscription.next().await?;
+		Some(
+			result
+				.map(|tx_raw| YuvTransaction::new(tx_raw.bitcoin_tx.bitcoin_tx, tx_raw.tx_type))
+				.map_err(YuvClientError::from),
+		)
+	}
+}
+
+/// Returns the WebSocket URL of the YUV node at `url`, i.e. the same address with a `ws` or `wss`
+/// scheme in place of `http` or `https`. URLs without either are used as is.
+fn ws_url(url: &str) -> String {
+	if let Some(rest) = url.strip_prefix("https://") {
+		format!("wss://{}", rest)
+	} else if let Some(rest) = url.strip_prefix("http://") {
+		format!("ws://{}", rest)
+	} else {
+		url.to_string()
+	}
+}
+
+/// Returns the delay before the next attempt at delivering a proof, after `attempts` failed ones.
+fn proof_retry_delay(base_delay: Duration, attempts: u32) -> Duration {
+	let multiplier = 4u32.saturating_pow(attempts.saturating_sub(1));
+	base_delay.saturating_mul(multiplier).min(MAX_PROOF_RETRY_DELAY)
+}
+
+/// Calls `fetch` with each chunk of `txids`, keeping at most `max_in_flight` of the calls running
+/// at once, and merges what they return in the order they complete, along with the error of the
+/// last chunk which failed, if any.
+async fn fetch_chunks<T, F, Fut>(
+	txids: &[Txid], chunk_size: usize, max_in_flight: usize, fetch: F,
+) -> (Vec<T>, Option<YuvClientError>)
+where
+	F: Fn(Vec<Txid>) -> Fut,
+	Fut: Future<Output = Result<Vec<T>, YuvClientError>>,
+{
+	let mut chunks = txids.chunks(chunk_size.max(1));
+	let mut in_flight = FuturesUnordered::new();
+	let mut fetched = Vec::with_capacity(txids.len());
+	let mut last_err = None;
+	loop {
+		while in_flight.len() < max_in_flight.max(1) {
+			match chunks.next() {
+				Some(chunk) => in_flight.push(fetch(chunk.to_vec())),
+				None => break,
+			}
+		}
+		match in_flight.next().await {
+			Some(Ok(chunk_fetched)) => fetched.extend(chunk_fetched),
+			Some(Err(err)) => last_err = Some(err),
+			None => break,
+		}
+	}
+	(fetched, last_err)
+}
+
+/// Whether the YUV node refused a proof because it already has it, e.g. when it's provided
+/// again after a restart, which counts as delivered rather than rejected.
+fn is_already_provided(err: &ErrorObjectOwned) -> bool {
+	err.message().to_lowercase().contains("already")
 }
 
 impl YuvBroadcaster for YuvClient {
+	/// Transport errors are retried with an exponential backoff, capped at
+	/// [`MAX_PROOF_RETRY_DELAY`], up to [`MAX_PROOF_RETRIES`] times. The proof only counts as
+	/// delivered once the YUV node returns the transaction when queried for it, and as failed once
+	/// it's rejected or given up on.
 	fn broadcast_transactions_proofs(&self, yuv_tx: YuvTransaction) {
 		let logger = self.logger.clone();
 		let client = self.client.clone();
+		let counters = Arc::clone(&self.counters);
+		let proof_deliveries = Arc::clone(&self.proof_deliveries);
+		let retry_base_delay = self.retry_base_delay;
+		let txid = yuv_tx.bitcoin_tx.txid();
+		proof_deliveries.set(&yuv_tx, ProofDelivery::Pending { attempts: 0 });
 		self.handle.spawn(async move {
-			match client.provide_yuv_proof(yuv_tx.clone()).await {
-				Ok(_) => {
-					log_info!(logger, "Successfully broadcasted a YUV transaction")
-				}
-				Err(err) => {
+			let mut attempts = 0;
+			loop {
+				let provided = match client.provide_yuv_proof(yuv_tx.clone()).await {
+					Ok(_) => Ok(()),
+					Err(RpcError::Call(err)) if is_already_provided(&err) => Ok(()),
+					Err(err) => Err(err),
+				};
+				let err = match provided {
+					Ok(()) => match client.get_yuv_transaction(txid).await {
+						Ok(_) => {
+							counters.provide_yuv_proof.successes.fetch_add(1, Ordering::Relaxed);
+							proof_deliveries.remove(&txid);
+							log_info!(logger, "Successfully broadcasted YUV transaction {}", txid);
+							return;
+						}
+						Err(err) => err,
+					},
+					Err(err @ RpcError::Call(_)) => {
+						counters.provide_yuv_proof.failures.fetch_add(1, Ordering::Relaxed);
+						proof_deliveries.set(&yuv_tx, ProofDelivery::Rejected);
+						log_error!(
+							logger,
+							"Error, the YUV node rejected the proof of {}: {err}\nTransaction: {:?}",
+							txid,
+							yuv_tx,
+						);
+						return;
+					}
+					Err(err) => err,
+				};
+
+				if attempts == MAX_PROOF_RETRIES {
+					counters.provide_yuv_proof.failures.fetch_add(1, Ordering::Relaxed);
+					proof_deliveries.set(&yuv_tx, ProofDelivery::Failed);
 					log_error!(
 						logger,
-						"Error, failed to provideyuvproof: {err}\nTransaction: {:?}",
-						yuv_tx,
-					)
+						"Error, failed to deliver the proof of YUV transaction {} after {} attempts: {err}",
+						txid,
+						attempts + 1,
+					);
+					return;
 				}
+				attempts += 1;
+				proof_deliveries.set(&yuv_tx, ProofDelivery::Pending { attempts });
+				let delay = proof_retry_delay(retry_base_delay, attempts);
+				log_warn!(
+					logger,
+					"Failed to deliver the proof of YUV transaction {}, retrying in {:?} ({}/{}): {err}",
+					txid,
+					delay,
+					attempts,
+					MAX_PROOF_RETRIES,
+				);
+				tokio::time::sleep(delay).await;
 			}
 		});
 	}
 
 	fn emulate_yuv_transaction(&self, yuv_tx: YuvTransaction) -> Option<String> {
+		// The trait only has room for a rejection reason, and a transaction the YUV node couldn't
+		// check mustn't pass as valid.
 		tokio::task::block_in_place(move || {
-			self.handle.block_on(async move { self.emulate_yuv_transaction(yuv_tx).await })
+			self.handle.block_on(async move {
+				self.emulate_yuv_transaction(yuv_tx)
+					.await
+					.unwrap_or_else(|err| Some(err.to_string()))
+			})
 		})
 	}
 }
@@ -98,9 +611,12 @@ impl YuvTransactionSource for YuvClient {
 	) -> AsyncYuvSourceResult<'a, GetRawYuvTransactionResponseHex> {
 		let logger = self.logger.clone();
 		let client = self.client.clone();
+		let counters = Arc::clone(&self.counters);
 
 		Box::pin(async move {
-			client.get_yuv_transaction(*txid).await.map_err(|err| {
+			let result = client.get_yuv_transaction(*txid).await;
+			counters.get_raw_yuv_transaction.record(&result);
+			result.map_err(|err| {
 				log_error!(
 					logger,
 					"Error, failed to getrawyuvtransaction: {err}\nTx id: {:?}",
@@ -112,3 +628,162 @@ impl YuvTransactionSource for YuvClient {
 		})
 	}
 }
+
+#[cfg(test)]
+mod yuv_client_error_tests {
+	use super::*;
+
+	#[test]
+	fn test_yuv_client_error_from_rpc_error() {
+		let parse_err = serde_json::from_str::<serde_json::Value>("not json").unwrap_err();
+		let err = YuvClientError::from(RpcError::ParseError(parse_err));
+		assert!(matches!(err, YuvClientError::Decode(_)));
+
+		let err = YuvClientError::from(RpcError::RequestTimeout);
+		assert!(matches!(err, YuvClientError::Transport(_)));
+	}
+}
+
+#[cfg(test)]
+mod ws_url_tests {
+	use super::*;
+
+	#[test]
+	fn test_ws_url() {
+		assert_eq!(ws_url("http://127.0.0.1:18333"), "ws://127.0.0.1:18333");
+		assert_eq!(ws_url("https://yuv.example.com/rpc"), "wss://yuv.example.com/rpc");
+		assert_eq!(ws_url("ws://127.0.0.1:18333"), "ws://127.0.0.1:18333");
+	}
+}
+
+#[cfg(test)]
+mod yuv_client_stats_tests {
+	use super::*;
+
+	#[test]
+	fn test_method_counters() {
+		let counters = MethodCounters::default();
+		counters.record(&Ok::<(), ()>(()));
+		counters.record(&Ok::<(), ()>(()));
+		counters.record(&Err::<(), ()>(()));
+
+		assert_eq!(counters.snapshot(), MethodStats { successes: 2, failures: 1 });
+	}
+}
+
+#[cfg(test)]
+mod lru_cache_tests {
+	use super::*;
+
+	fn txid(n: u8) -> Txid {
+		Txid::from_byte_array([n; 32])
+	}
+
+	#[test]
+	fn test_lru_cache_evicts_least_recently_used() {
+		let mut cache = LruCache::new(2);
+		cache.insert(txid(1), 1);
+		cache.insert(txid(2), 2);
+		// Using the first entry makes the second one the least recently used.
+		assert_eq!(cache.get(&txid(1)), Some(1));
+		cache.insert(txid(3), 3);
+
+		assert_eq!(cache.get(&txid(1)), Some(1));
+		assert_eq!(cache.get(&txid(2)), None);
+		assert_eq!(cache.get(&txid(3)), Some(3));
+		assert_eq!(cache.order.len(), 2);
+	}
+
+	#[test]
+	fn test_lru_cache_reinsert_and_zero_capacity() {
+		let mut cache = LruCache::new(2);
+		cache.insert(txid(1), 1);
+		cache.insert(txid(1), 10);
+		assert_eq!(cache.get(&txid(1)), Some(10));
+		assert_eq!(cache.order.len(), 1);
+
+		let mut cache = LruCache::new(0);
+		cache.insert(txid(1), 1);
+		assert_eq!(cache.get(&txid(1)), None);
+	}
+}
+
+#[cfg(test)]
+mod proof_retry_tests {
+	use super::*;
+	use jsonrpsee::types::error::CALL_EXECUTION_FAILED_CODE;
+
+	#[test]
+	fn test_proof_retry_delay_grows_until_capped() {
+		let base_delay = Duration::from_millis(100);
+		assert_eq!(proof_retry_delay(base_delay, 1), Duration::from_millis(100));
+		assert_eq!(proof_retry_delay(base_delay, 2), Duration::from_millis(400));
+		assert_eq!(proof_retry_delay(base_delay, 3), Duration::from_millis(1600));
+		assert_eq!(proof_retry_delay(base_delay, 10), MAX_PROOF_RETRY_DELAY);
+		assert_eq!(proof_retry_delay(base_delay, u32::MAX), MAX_PROOF_RETRY_DELAY);
+	}
+
+	#[test]
+	fn test_is_already_provided() {
+		let err = ErrorObjectOwned::owned(
+			CALL_EXECUTION_FAILED_CODE,
+			"Proof is already provided",
+			None::<()>,
+		);
+		assert!(is_already_provided(&err));
+
+		let err = ErrorObjectOwned::owned(CALL_EXECUTION_FAILED_CODE, "Invalid proof", None::<()>);
+		assert!(!is_already_provided(&err));
+	}
+}
+
+#[cfg(test)]
+mod fetch_chunks_tests {
+	use super::*;
+	use std::sync::atomic::AtomicUsize;
+
+	fn txids(n: u8) -> Vec<Txid> {
+		(0..n).map(|i| Txid::from_byte_array([i; 32])).collect()
+	}
+
+	#[tokio::test]
+	async fn test_fetch_chunks_bounds_requests_in_flight() {
+		let txids = txids(25);
+		let (in_flight, max_in_flight) = (AtomicUsize::new(0), AtomicUsize::new(0));
+		let (mut fetched, last_err) = fetch_chunks(&txids, 2, 3, |chunk| {
+			let (in_flight, max_in_flight) = (&in_flight, &max_in_flight);
+			async move {
+				let now_in_flight = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+				max_in_flight.fetch_max(now_in_flight, Ordering::SeqCst);
+				tokio::task::yield_now().await;
+				in_flight.fetch_sub(1, Ordering::SeqCst);
+				Ok(chunk)
+			}
+		})
+		.await;
+
+		assert!(last_err.is_none());
+		assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+		fetched.sort();
+		let mut expected = txids;
+		expected.sort();
+		assert_eq!(fetched, expected);
+	}
+
+	#[tokio::test]
+	async fn test_fetch_chunks_merges_around_failed_chunks() {
+		let txids = txids(4);
+		let failing_txid = txids[0];
+		let (fetched, last_err) = fetch_chunks(&txids, 2, 8, |chunk| async move {
+			if chunk.contains(&failing_txid) {
+				Err(YuvClientError::Empty)
+			} else {
+				Ok(chunk)
+			}
+		})
+		.await;
+
+		assert_eq!(fetched.len(), 2);
+		assert!(matches!(last_err, Some(YuvClientError::Empty)));
+	}
+}